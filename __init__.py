@@ -18,10 +18,12 @@ import mathutils
 import numpy
 import os
 import subprocess
+import tempfile
 import time
 
 from .bfm_external import FiducialMarkerDetectorExternal
 from .bfm_native import FiducialMarkerDetectorNative
+from .bfm_tracks import inject_tracks
 
 MARKER_PREFIX = "BFM_MARKER_"
 
@@ -56,6 +58,8 @@ AR_DICTIONARIES = [
 	"ARUCO_MIP_25H7",
 	"APRILTAG_36H9",
 	"APRILTAG_16H5",
+	"QR",
+	"ARUCO+QR",
 ]
 
 # UI Configuration (Blender-side):
@@ -256,8 +260,16 @@ class BFM_OT_Track(bpy.types.Operator):
 		else:
 			bfm_system = FiducialMarkerDetectorExternal(config.dictionary, config.marker_size_mm, config.footage_focal_length_mm)
 		
+		# The external tracker can write the 2D tracks for us. The OpenCV path doesn't support this yet.
+		tracks_path = None
+		if config.generate_2d_tracks and not config.use_opencv:
+			tracks_path = os.path.join(tempfile.gettempdir(), f"bfm_tracks_{os.getpid()}.json")
+			detections_by_frame = bfm_system.detect_markers(clip_path, tracks_path=tracks_path)
+		else:
+			detections_by_frame = bfm_system.detect_markers(clip_path)
+
 		self.report({'INFO'}, f"Reading from movie clip at {clip_path}")
-		for frame_idx, detections in bfm_system.detect_markers(clip_path):
+		for frame_idx, detections in detections_by_frame:
 			self.report({'INFO'}, f"Processing frame {frame_idx}... ")
 			context.scene.frame_set(frame_idx)  # Not strictly necessary for setting keyframes, but updates the UI.
 			visible_marker_ids = set()  # Visible in this frame.
@@ -278,7 +290,8 @@ class BFM_OT_Track(bpy.types.Operator):
 				# https://docs.blender.org/api/current/info_quickstart.html#animation
 				empty.location = opencv_to_blender_coordinates(mathutils.Vector(marker.poses[0].position) / 1000.0) # Scale back to meters.
 				empty.rotation_quaternion = opencv_to_blender_coordinates(mat3_to_quaternion(marker.poses[0].rotation))
-				empty.bfm_detection_confidence = 1.0
+				# Never quite zero for a detection, since zero means 'not seen this frame'.
+				empty.bfm_detection_confidence = max(marker.confidence, 1e-3)
 				empty.empty_display_size = config.marker_size_mm / 1000.0  # Again, mm to m.
 
 				empty.keyframe_insert(data_path="location", frame=int(frame_idx))  # index=2 would set only z, for example.
@@ -289,15 +302,9 @@ class BFM_OT_Track(bpy.types.Operator):
 				visible_marker_ids.add(marker.marker_id)
 				all_marker_ids.add(marker.marker_id)
 
-				# Possibly create tracking markers.
-				# See https://docs.blender.org/api/current/bpy.types.MovieTrackingMarkers.html and https://docs.blender.org/api/current/bpy.types.MovieTrackingMarker.html#bpy.types.MovieTrackingMarker
-				if config.generate_2d_tracks:
-					if config.tracks_at_corners:
-						# TODO: Add the markers at the center instead of the top-left.
-						clip_width = clip.size[0]
-						clip_height = clip.size[1]
-					#bpy.ops.clip.add_marker_slide(CLIP_OT_add_marker={"location":(0.324317, 0.554497)}, TRANSFORM_OT_translate={"value":(0, 0, 0), "orient_type":'GLOBAL', "orient_matrix":((1, 0, 0), (0, 1, 0), (0, 0, 1)), "orient_matrix_type":'GLOBAL', "constraint_axis":(True, True, True), "mirror":False, "use_proportional_edit":False, "proportional_edit_falloff":'SMOOTH', "proportional_size":1, "use_proportional_connected":False, "use_proportional_projected":False, "snap":False, "snap_elements":{'INCREMENT'}, "use_snap_project":False, "snap_target":'CLOSEST', "use_snap_self":True, "use_snap_edit":True, "use_snap_nonedit":True, "use_snap_selectable":False, "snap_point":(0, 0, 0), "snap_align":False, "snap_normal":(0, 0, 0), "gpencil_strokes":False, "cursor_transform":False, "texture_space":False, "remove_on_cancel":False, "use_duplicated_keyframes":False, "view2d_edge_pan":False, "release_confirm":True, "use_accurate":False, "use_automerge_and_split":False})
-					#bpy.ops.clip.add_marker(location=(0.324317, 0.554497))
+				# Tracking markers get created from the exported tracks once the whole clip is done. See inject_tracks.
+				#bpy.ops.clip.add_marker_slide(CLIP_OT_add_marker={"location":(0.324317, 0.554497)}, TRANSFORM_OT_translate={"value":(0, 0, 0), "orient_type":'GLOBAL', "orient_matrix":((1, 0, 0), (0, 1, 0), (0, 0, 1)), "orient_matrix_type":'GLOBAL', "constraint_axis":(True, True, True), "mirror":False, "use_proportional_edit":False, "proportional_edit_falloff":'SMOOTH', "proportional_size":1, "use_proportional_connected":False, "use_proportional_projected":False, "snap":False, "snap_elements":{'INCREMENT'}, "use_snap_project":False, "snap_target":'CLOSEST', "use_snap_self":True, "use_snap_edit":True, "use_snap_nonedit":True, "use_snap_selectable":False, "snap_point":(0, 0, 0), "snap_align":False, "snap_normal":(0, 0, 0), "gpencil_strokes":False, "cursor_transform":False, "texture_space":False, "remove_on_cancel":False, "use_duplicated_keyframes":False, "view2d_edge_pan":False, "release_confirm":True, "use_accurate":False, "use_automerge_and_split":False})
+				#bpy.ops.clip.add_marker(location=(0.324317, 0.554497))
 			
 			# Now that we've done all the detections, we need to go over the unseen ones.
 			for mid in all_marker_ids:
@@ -311,6 +318,14 @@ class BFM_OT_Track(bpy.types.Operator):
 			# TODO: Go back over the first ones and insert the first frame where they're visible.
 
 			self.report({'INFO'}, f" ... Done processeing frame {frame_idx}. Found/updated {len(detections)} markers.")
+
+		if tracks_path is not None:
+			if os.path.exists(tracks_path):
+				added = inject_tracks(clip, tracks_path, at_corners=config.tracks_at_corners)
+				os.remove(tracks_path)
+				self.report({'INFO'}, f"Added {added} 2D tracks to {clip.name}.")
+			else:
+				self.report({'WARNING'}, "The tracker didn't write any 2D tracks.")
 		
 		# Get the camera, invert its motion, and apply that to all the other markers.
 		if config.bake_relative_to_camera:
@@ -318,7 +333,7 @@ class BFM_OT_Track(bpy.types.Operator):
 				context.scene.frame_set(frame_idx)
 				for m in all_marker_ids:
 					empty = marker_id_to_empty[m]
-					if empty.bfm_detection_confidence == 1.0:  # There was a keyframe for this marker.
+					if empty.bfm_detection_confidence > 0.0:  # There was a keyframe for this marker.
 						# Rotation needs a little extra handling because we're not sure what the camera mode is:
 						# While the 'rotate' method on quaternion will accept euler or quaternion, we're converting everything to quaternions to avoid footguns later.
 						if camera.rotation_mode == 'QUATERNION':
@@ -351,13 +366,13 @@ class BFM_OT_Track(bpy.types.Operator):
 						if m == config.origin_marker:
 							continue
 						to_transform = marker_id_to_empty[m]
-						if to_transform.bfm_detection_confidence == 1.0:
+						if to_transform.bfm_detection_confidence > 0.0:
 							to_transform.rotation_quaternion.rotate(inverse_rotation)
 							to_transform.keyframe_insert(data_path="rotation_quaternion", frame=int(frame_idx))
 							to_transform.location += inverse_translation
 							to_transform.keyframe_insert(data_path="location", frame=int(frame_idx))  # index=2 would set only z, for example.
 					# Also apply to the camera if the marker is visible.
-					if camera and origin_marker.bfm_detection_confidence == 1.0:
+					if camera and origin_marker.bfm_detection_confidence > 0.0:
 						camera.rotation_quaternion.rotate(inverse_rotation)
 						camera.keyframe_insert(data_path="rotation_quaternion", frame=frame_idx)
 						camera.location = inverse_translation
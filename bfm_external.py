@@ -12,9 +12,12 @@ class FiducialMarkerDetectorExternal:
 		self.marker_size_mm = marker_size_mm
 		self.focal_length_mm = focal_length_mm
 		
-	def detect_markers(self, filepath: str) -> list[tuple[int, list[MarkerDetection]]]:
+	def detect_markers(self, filepath: str, tracks_path: str | None = None) -> list[tuple[int, list[MarkerDetection]]]:
+		"""If tracks_path is set, also have the tracker write 2D tracks there for bfm_tracks.inject_tracks once it's done."""
 		executable_path = os.path.join(os.path.dirname(__file__), EXECUTABLE_NAME)
 		args=[executable_path, filepath, self.dictionary_name, str(self.marker_size_mm)]
+		if tracks_path:
+			args += ["--export-blender-tracks", tracks_path]
 
 		# On Windows we need to set process flags to ensure that we run async.  Also, if shell=True then we need to pass a string of args rather than a list.
 		# Also, if shell=True then we need to do " ".join(args)
@@ -30,6 +33,9 @@ class FiducialMarkerDetectorExternal:
 			for line in proc.stdout:
 				print(line)
 				data = json.loads(line)
+				if data.get("type") == "header":
+					# Units and axis conventions for the rest of the run. We ask for the defaults, so there's nothing to do.
+					continue
 				frame_idx = data.pop("frame_id")
 				raw_detections = data.pop("detections")
 				markers = list()
@@ -42,10 +48,23 @@ class FiducialMarkerDetectorExternal:
 							MarkerPose(
 								position=p["translation"],
 								rotation=p["rotation"],
-								error=p["error"]
+								error=p["error"],
+								reprojection_error=p.get("reprojection_error"),
 							)
 						)
-					markers.append(MarkerDetection(marker_id=marker_id, corners=corners, poses=poses))
+					confidence = d.get("confidence", {}).get("score", 1.0)
+					markers.append(
+						MarkerDetection(
+							marker_id=marker_id,
+							corners=corners,
+							poses=poses,
+							payload=d.get("payload"),
+							hamming_distance=d.get("hamming_distance"),
+							confidence=confidence,
+							velocity=d.get("velocity"),
+							angular_velocity=d.get("angular_velocity"),
+						)
+					)
 				yield frame_idx, markers
 		finally:
 			proc.wait()
\ No newline at end of file
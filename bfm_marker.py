@@ -6,11 +6,17 @@ class MarkerPose:
     position: list[float]  # Vector 1x3
     rotation: list[float]  # Matrix 3x3 -- row-major.
     error: float
+    reprojection_error: float | None = None  # RMS corner reprojection error in pixels, if known.
 
 @dataclass
 class MarkerDetection:
     marker_id: int = -1  # Can be thought of as the 'index' of the marker.
     corners: list[tuple[int, int]] = field(default_factory=list)  # Left, Top, Right, Bottom
     poses: list[MarkerPose] = field(default_factory=list)
+    payload: str | None = None  # Decoded contents for markers that carry data, like QR codes.
+    hamming_distance: int | None = None  # Bits corrected to match the dictionary code, if the detector says.
+    confidence: float = 1.0  # 0 to 1. How clean the detection looked; 1.0 when the detector doesn't say.
+    velocity: list[float] | None = None  # Vector 1x3, mm/s in camera space, if seen in the previous frame.
+    angular_velocity: list[float] | None = None  # Vector 1x3, axis * rad/s in camera space.
 
 
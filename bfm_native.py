@@ -88,6 +88,8 @@ class FiducialMarkerDetectorNative:
 				# Blender uses a right-handed +Z up coordinate system.
 				# OpenCV uses a right-handed +Z forward coordinate system.
 				rot, _jacobian = cv2.Rodrigues(rotation)
+				projected, _jacobian = cv2.projectPoints(self.marker_points, rotation, translation, intrinsics, self.distortion_coefficients)
+				reprojection_error = float(numpy.sqrt(numpy.mean(numpy.sum((projected.reshape(4, 2) - c.reshape(4, 2))**2, axis=1))))
 
 				# Transforms points from the _model coordinate system_ to the _camera coordinate system_.
 				pose1 = MarkerPose(
@@ -98,6 +100,7 @@ class FiducialMarkerDetectorNative:
 						rot[2,0], rot[2,1], rot[2,2], 
 					],
 					error=0.0,
+					reprojection_error=reprojection_error,
 				)
 				marker.poses = [pose1,]
 				detections.append(marker)
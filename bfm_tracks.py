@@ -0,0 +1,35 @@
+import json
+
+import bpy
+
+def inject_tracks(clip: bpy.types.MovieClip, tracks_path: str, at_corners: bool = False) -> int:
+	"""
+	Add the tracks written by `fiducial_track_video --export-blender-tracks` to a movie clip. Returns how many tracks were added.
+	If at_corners is set, add a track for each corner of each fiducial. Otherwise add one per fiducial at its center,
+	with the pattern area set to the fiducial's outline so it can be used for plane tracks.
+	Existing tracks with the same name are replaced.
+	"""
+	with open(tracks_path, 'rt') as fin:
+		data = json.load(fin)
+
+	tracking_tracks = clip.tracking.tracks
+	added = 0
+	for t in data["tracks"]:
+		if (t["corner"] is not None) != at_corners:
+			continue
+		existing = tracking_tracks.get(t["name"])
+		if existing is not None:
+			tracking_tracks.remove(existing)
+		# See https://docs.blender.org/api/current/bpy.types.MovieTrackingTracks.html
+		track = tracking_tracks.new(name=t["name"], frame=t["markers"][0]["frame"])
+		for m in t["markers"]:
+			if m.get("mute"):
+				marker = track.markers.insert(m["frame"])
+				marker.mute = True
+				continue
+			marker = track.markers.insert(m["frame"], co=m["co"])
+			if "pattern_corners" in m:
+				pc = m["pattern_corners"]
+				marker.pattern_corners = [pc[0:2], pc[2:4], pc[4:6], pc[6:8]]
+		added += 1
+	return added
@@ -0,0 +1,51 @@
+/* The fiducial tracker's C interface. Link against libfiducial (cargo build --release -p fiducial_capi).
+ *
+ * Results come back through callbacks as JSON frame records, the same as one line of the command line tool's
+ * output. A record's string is only valid until the callback returns. Functions that can fail return null or -1,
+ * and fiducial_last_error() then says why.
+ */
+#ifndef FIDUCIAL_H
+#define FIDUCIAL_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct FiducialDetector FiducialDetector;
+typedef struct FiducialVideo FiducialVideo;
+
+typedef void (*FiducialRecordCallback)(const char *record_json, void *user_data);
+
+/* 1 for this header. */
+uint32_t fiducial_abi_version(void);
+
+/* The last error on the calling thread, or null. Valid until the next call that fails. */
+const char *fiducial_last_error(void);
+
+/* A detector for single frames, e.g. from an engine's camera feed. The dictionary is named as on the command line
+ * ("ARUCO", "APRILTAG_36H11+QR", ...) and the focal length is in pixels. */
+FiducialDetector *fiducial_detector_new(const char *dictionary, float marker_size_mm, float focal_length_px);
+void fiducial_detector_free(FiducialDetector *detector);
+
+/* Find the markers in one frame of 8-bit pixels with 1 (gray), 3 (RGB), or 4 (RGBA) channels and `stride` bytes
+ * per row. The callback gets the frame's record before this returns. */
+int fiducial_detect_frame(FiducialDetector *detector, const uint8_t *pixels, uint32_t width, uint32_t height,
+	size_t stride, uint32_t channels, double timestamp, FiducialRecordCallback callback, void *user_data);
+
+/* Start tracking a video file on a background thread. `options` are the command line tool's arguments after the
+ * path, e.g. {"ARUCO", "50", "--focal-length-mm", "900"}. The callback gets every frame record, from the tracking
+ * thread. Pass the result to fiducial_video_finish. */
+FiducialVideo *fiducial_open_video(const char *path, const char *const *options, size_t option_count,
+	FiducialRecordCallback callback, void *user_data);
+
+/* Wait for a video to finish tracking, then free it. */
+int fiducial_video_finish(FiducialVideo *video);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
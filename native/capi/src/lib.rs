@@ -0,0 +1,326 @@
+// A C interface to the tracker, for game engines and plugins that can't link Rust. include/fiducial.h is the header
+// to build against, and the two have to be changed together: this is a stable interface, so functions get added but
+// never change their signatures.
+//
+// Results come back through callbacks as JSON, the same records the command line tool writes, so hosts can use
+// whatever JSON reader they already have and new fields don't break anyone. Strings passed to a callback only live
+// until it returns.
+
+use clap::Parser;
+use fiducial_track_video::cli::{self, Args};
+use fiducial_track_video::detect::{FrameDetector, intrinsics_for};
+use fiducial_track_video::dictionary::parse_dictionaries;
+use fiducial_track_video::frame::FrameView;
+use fiducial_track_video::geometry::Pinhole;
+use fiducial_track_video::output::{Outputs, Sink};
+use fiducial_track_video::record::FrameRecord;
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::io;
+use std::thread::JoinHandle;
+
+/// Bumped if the interface ever has to change incompatibly.
+const ABI_VERSION: u32 = 1;
+
+pub type RecordCallback = extern "C" fn(record_json: *const c_char, user_data: *mut c_void);
+
+thread_local! {
+	static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message: impl std::fmt::Display) {
+	let message = CString::new(message.to_string().replace('\0', " ")).expect("Nuls were just replaced.");
+	LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// What a panic said, if it said it with a string.
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+	if let Some(message) = panic.downcast_ref::<&str>() {
+		message
+	} else if let Some(message) = panic.downcast_ref::<String>() {
+		message
+	} else {
+		"no message"
+	}
+}
+
+/// Run an entry point's body, catching a panic so it fails the call like any other error instead of aborting the
+/// host.
+fn guard<T>(failed: T, body: impl FnOnce() -> T) -> T {
+	match std::panic::catch_unwind(std::panic::AssertUnwindSafe(body)) {
+		Ok(result) => result,
+		Err(panic) => {
+			set_error(format!("The tracker panicked: {}", panic_message(panic.as_ref())));
+			failed
+		},
+	}
+}
+
+/// The host's pointer, handed back to its callback from whichever thread is tracking.
+struct UserData(*mut c_void);
+
+// The header tells the host its callback can be called from another thread.
+unsafe impl Send for UserData {}
+
+fn call(callback: RecordCallback, record: &FrameRecord, user_data: &UserData) {
+	let json = CString::new(record.to_json()).expect("JSON escapes control characters.");
+	callback(json.as_ptr(), user_data.0);
+}
+
+/// # Safety
+/// `text` must be null or a valid nul-terminated string.
+unsafe fn string_arg(text: *const c_char, name: &str) -> Option<String> {
+	if text.is_null() {
+		set_error(format!("{name} is null."));
+		return None;
+	}
+	match unsafe { CStr::from_ptr(text) }.to_str() {
+		Ok(text) => Some(text.to_string()),
+		Err(_) => {
+			set_error(format!("{name} isn't UTF-8."));
+			None
+		},
+	}
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn fiducial_abi_version() -> u32 {
+	ABI_VERSION
+}
+
+/// The last error on this thread, or null. Valid until the next call that fails.
+#[unsafe(no_mangle)]
+pub extern "C" fn fiducial_last_error() -> *const c_char {
+	LAST_ERROR.with(|last| last.borrow().as_ref().map_or(std::ptr::null(), |message| message.as_ptr()))
+}
+
+pub struct FiducialDetector {
+	detector: FrameDetector,
+	focal_length_px: f32,
+	frame_id: usize,
+}
+
+/// A detector for single frames. The dictionary is named as on the command line and the focal length is in pixels.
+/// Null on error.
+///
+/// # Safety
+/// `dictionary` must be a valid nul-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fiducial_detector_new(dictionary: *const c_char, marker_size_mm: f32, focal_length_px: f32) -> *mut FiducialDetector {
+	guard(std::ptr::null_mut(), || {
+		let Some(dictionary) = (unsafe { string_arg(dictionary, "The dictionary") }) else {
+			return std::ptr::null_mut();
+		};
+		let dictionaries = match parse_dictionaries(&dictionary) {
+			Ok(dictionaries) => dictionaries,
+			Err(e) => {
+				set_error(e);
+				return std::ptr::null_mut();
+			},
+		};
+		if !focal_length_px.is_finite() || focal_length_px <= 0.0 {
+			set_error("The focal length must be greater than zero.");
+			return std::ptr::null_mut();
+		}
+		let detector = FrameDetector::new(dictionaries, marker_size_mm, marker_size_mm, None);
+		Box::into_raw(Box::new(FiducialDetector { detector, focal_length_px, frame_id: 0 }))
+	})
+}
+
+/// # Safety
+/// `detector` must be null or from fiducial_detector_new, and not used again.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fiducial_detector_free(detector: *mut FiducialDetector) {
+	if !detector.is_null() {
+		drop(unsafe { Box::from_raw(detector) });
+	}
+}
+
+/// Find the markers in one frame of 8-bit gray (1 channel), RGB (3), or RGBA (4) pixels, with `stride` bytes from
+/// one row to the next. The frame's record goes to `callback` before this returns. 0 on success, -1 on error.
+///
+/// # Safety
+/// `detector` must be from fiducial_detector_new, and `pixels` must hold `height` rows of `stride` bytes.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn fiducial_detect_frame(
+	detector: *mut FiducialDetector,
+	pixels: *const u8,
+	width: u32,
+	height: u32,
+	stride: usize,
+	channels: u32,
+	timestamp: f64,
+	callback: Option<RecordCallback>,
+	user_data: *mut c_void,
+) -> c_int {
+	guard(-1, || {
+		let Some(detector) = (unsafe { detector.as_mut() }) else {
+			set_error("The detector is null.");
+			return -1;
+		};
+		let row_bytes = width as usize * channels as usize;
+		if pixels.is_null() || stride < row_bytes || !matches!(channels, 1 | 3 | 4) {
+			set_error(format!("Expected {width}x{height} pixels of 1, 3, or 4 channels with a stride of at least {row_bytes} bytes."));
+			return -1;
+		}
+		// The last row doesn't have to be padded out to the stride.
+		let len = if height == 0 { 0 } else { stride * (height as usize - 1) + row_bytes };
+		let view = FrameView::new(unsafe { std::slice::from_raw_parts(pixels, len) }, width, height, stride, channels).expect("Checked above.");
+		let gray = view.to_luma8();
+
+		let f = detector.focal_length_px;
+		let pinhole = Pinhole { width, height, fx: f, fy: f, cx: width as f32 / 2.0, cy: height as f32 / 2.0 };
+		let found = detector.detector.find(None, &gray, (0.0, 0.0), &pinhole);
+		let record = detector.detector.record(found, detector.frame_id, timestamp, &gray, (0.0, 0.0), &intrinsics_for(&pinhole), &pinhole, false);
+		detector.frame_id += 1;
+		if let Some(callback) = callback {
+			call(callback, &record, &UserData(user_data));
+		}
+		0
+	})
+}
+
+pub struct FiducialVideo {
+	worker: JoinHandle<Result<(), String>>,
+}
+
+struct CallbackSink {
+	callback: RecordCallback,
+	user_data: UserData,
+}
+
+impl Sink for CallbackSink {
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		call(self.callback, record, &self.user_data);
+		Ok(())
+	}
+}
+
+/// Start tracking a video in the background, with `options` as the command line tool's arguments after the path,
+/// e.g. {"ARUCO", "50", "--focal-length-mm", "900"}. Each frame record goes to `callback`, from the tracking thread,
+/// instead of stdout. Null on error, otherwise finish with fiducial_video_finish.
+///
+/// # Safety
+/// `path` and the `option_count` strings in `options` must be valid nul-terminated strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fiducial_open_video(
+	path: *const c_char,
+	options: *const *const c_char,
+	option_count: usize,
+	callback: Option<RecordCallback>,
+	user_data: *mut c_void,
+) -> *mut FiducialVideo {
+	guard(std::ptr::null_mut(), || {
+		let Some(path) = (unsafe { string_arg(path, "The path") }) else {
+			return std::ptr::null_mut();
+		};
+		let mut argv = vec!["fiducial_track_video".to_string(), path];
+		for idx in 0..option_count {
+			let Some(option) = (unsafe { string_arg(*options.add(idx), "An option") }) else {
+				return std::ptr::null_mut();
+			};
+			argv.push(option);
+		}
+		let args = match Args::try_parse_from(argv) {
+			Ok(args) => args,
+			Err(e) => {
+				set_error(e.to_string().trim_end());
+				return std::ptr::null_mut();
+			},
+		};
+		let user_data = UserData(user_data);
+		let worker = std::thread::spawn(move || {
+			let mut outputs = Outputs::default();
+			if let Some(callback) = callback {
+				outputs.add("callback", Box::new(CallbackSink { callback, user_data }));
+			}
+			cli::run(&args, outputs).map_err(|e| e.to_string())
+		});
+		Box::into_raw(Box::new(FiducialVideo { worker }))
+	})
+}
+
+/// Wait for a video to finish tracking and free it. 0 on success, -1 if tracking failed.
+///
+/// # Safety
+/// `video` must be from fiducial_open_video, and not used again.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn fiducial_video_finish(video: *mut FiducialVideo) -> c_int {
+	guard(-1, || {
+		if video.is_null() {
+			set_error("The video is null.");
+			return -1;
+		}
+		let video = unsafe { Box::from_raw(video) };
+		match video.worker.join() {
+			Ok(Ok(())) => 0,
+			Ok(Err(e)) => {
+				set_error(e);
+				-1
+			},
+			Err(panic) => {
+				set_error(format!("The tracker panicked: {}", panic_message(panic.as_ref())));
+				-1
+			},
+		}
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use fiducial_track_video::synthetic::{self, PlacedMarker};
+
+	extern "C" fn collect(record_json: *const c_char, user_data: *mut c_void) {
+		let records = unsafe { &mut *(user_data as *mut Vec<String>) };
+		records.push(unsafe { CStr::from_ptr(record_json) }.to_str().unwrap().to_string());
+	}
+
+	fn last_error() -> String {
+		unsafe { CStr::from_ptr(fiducial_last_error()) }.to_str().unwrap().to_string()
+	}
+
+	#[test]
+	fn test_detect_frame() {
+		let pinhole = Pinhole { width: 320, height: 240, fx: 500.0, fy: 500.0, cx: 160.0, cy: 120.0 };
+		let marker = PlacedMarker { id: 7, rotation: synthetic::FACING, translation: [0.0, 0.0, 700.0] };
+		let gray = synthetic::render(&pinhole, &[marker], 100.0, 128);
+		let dictionary = CString::new(synthetic::DICTIONARY).unwrap();
+		let detector = unsafe { fiducial_detector_new(dictionary.as_ptr(), 100.0, 500.0) };
+		assert!(!detector.is_null());
+		let mut records: Vec<String> = vec![];
+		let user_data = &mut records as *mut Vec<String> as *mut c_void;
+		let status = unsafe { fiducial_detect_frame(detector, gray.as_raw().as_ptr(), 320, 240, 320, 1, 0.0, Some(collect), user_data) };
+		unsafe { fiducial_detector_free(detector) };
+		assert_eq!(status, 0);
+		assert_eq!(records.len(), 1);
+		assert!(records[0].contains("\"marker_id\":7"), "{}", records[0]);
+	}
+
+	#[test]
+	fn test_null_arguments() {
+		let null = std::ptr::null_mut::<c_void>();
+		assert!(unsafe { fiducial_detector_new(std::ptr::null(), 100.0, 500.0) }.is_null());
+		assert_eq!(last_error(), "The dictionary is null.");
+		assert_eq!(unsafe { fiducial_detect_frame(std::ptr::null_mut(), std::ptr::null(), 320, 240, 320, 1, 0.0, None, null) }, -1);
+		assert_eq!(last_error(), "The detector is null.");
+
+		let dictionary = CString::new(synthetic::DICTIONARY).unwrap();
+		let detector = unsafe { fiducial_detector_new(dictionary.as_ptr(), 100.0, 500.0) };
+		assert_eq!(unsafe { fiducial_detect_frame(detector, std::ptr::null(), 320, 240, 320, 1, 0.0, None, null) }, -1);
+		assert!(last_error().starts_with("Expected 320x240 pixels"));
+		unsafe { fiducial_detector_free(detector) };
+
+		assert!(unsafe { fiducial_open_video(std::ptr::null(), std::ptr::null(), 0, None, null) }.is_null());
+		assert_eq!(last_error(), "The path is null.");
+		assert_eq!(unsafe { fiducial_video_finish(std::ptr::null_mut()) }, -1);
+		assert_eq!(last_error(), "The video is null.");
+	}
+
+	#[test]
+	fn test_panics_become_errors() {
+		assert_eq!(guard(-1, || panic!("out of cheese")), -1);
+		assert_eq!(last_error(), "The tracker panicked: out of cheese");
+	}
+}
@@ -0,0 +1,246 @@
+// Python bindings, so the Blender addon can run the tracker in-process instead of shelling out to the binary and
+// parsing its output. Build a wheel with `maturin build --release` in this directory, then:
+//   import pyfiducial
+//   markers = pyfiducial.detect(image, "ARUCO", 50.0, 900.0)
+//   for record in pyfiducial.process_video("take1.mp4", {"dictionary": "ARUCO", "marker_size_mm": 50, "focal_length_mm": 900}):
+//       ...
+// Records and detections are plain dicts and lists shaped like the tool's JSON output.
+
+use fiducial_track_video::cli::{self, Args};
+use fiducial_track_video::detect::{FrameDetector, intrinsics_for};
+use fiducial_track_video::dictionary::parse_dictionaries;
+use fiducial_track_video::frame::FrameView;
+use fiducial_track_video::geometry::Pinhole;
+use fiducial_track_video::output::{Outputs, Sink};
+use fiducial_track_video::record::FrameRecord;
+use fiducial_track_video::value::Value;
+use clap::Parser;
+use numpy::{PyReadonlyArrayDyn, PyUntypedArrayMethods};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyList, PyString};
+use std::io;
+use std::sync::Mutex;
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::thread::JoinHandle;
+
+/// Find the markers in one image: a height x width (x 1, 3 for RGB, or 4 for RGBA) array of uint8. `dictionary` is
+/// named as on the command line and the focal length is in pixels. Returns the frame's detections.
+#[pyfunction]
+fn detect<'py>(py: Python<'py>, image: PyReadonlyArrayDyn<'py, u8>, dictionary: &str, marker_size_mm: f32, focal_length_px: f32) -> PyResult<Bound<'py, PyAny>> {
+	let dictionaries = parse_dictionaries(dictionary).map_err(PyValueError::new_err)?;
+	let shape = image.shape().to_vec();
+	let (height, width, channels) = match shape[..] {
+		[height, width] => (height as u32, width as u32, 1),
+		[height, width, channels] => (height as u32, width as u32, channels as u32),
+		_ => return Err(PyValueError::new_err(format!("Expected an image of 2 or 3 dimensions, got {}.", shape.len()))),
+	};
+	if !matches!(channels, 1 | 3 | 4) {
+		return Err(PyValueError::new_err(format!("Expected 1, 3, or 4 channels, got {channels}.")));
+	}
+	// Read a C-contiguous array in place, and only copy out ones that are sliced or transposed.
+	let copied;
+	let pixels = match image.as_slice() {
+		Ok(pixels) => pixels,
+		Err(_) => {
+			copied = image.as_array().iter().copied().collect::<Vec<u8>>();
+			&copied[..]
+		},
+	};
+	let gray = FrameView::new(pixels, width, height, width as usize * channels as usize, channels)
+		.expect("The buffer came from an array of this shape.")
+		.to_luma8();
+	let record = py.allow_threads(|| {
+		let pinhole = Pinhole { width, height, fx: focal_length_px, fy: focal_length_px, cx: width as f32 / 2.0, cy: height as f32 / 2.0 };
+		let mut detector = FrameDetector::new(dictionaries, marker_size_mm, marker_size_mm, None);
+		let found = detector.find(None, &gray, (0.0, 0.0), &pinhole);
+		detector.record(found, 0, 0.0, &gray, (0.0, 0.0), &intrinsics_for(&pinhole), &pinhole, false)
+	});
+	let Value::Map(fields) = record.to_value() else {
+		unreachable!("Frame records are maps.");
+	};
+	let detections = fields.into_iter().find(|(key, _)| key == "detections").map_or(Value::Array(vec![]), |(_, value)| value);
+	to_python(py, &detections)
+}
+
+/// Track a whole video the way the command line tool would, and iterate over its frame records as they come.
+/// `config` maps the tool's option names (with underscores) to values, plus "dictionary" and "marker_size_mm", e.g.
+/// {"dictionary": "ARUCO", "marker_size_mm": 50, "start_frame": 100, "marker_map": "set.json"}. True turns a flag
+/// on, and a list repeats an option. Records go to this iterator instead of stdout, though other outputs (exports,
+/// --serve, and so on) still happen.
+#[pyfunction]
+#[pyo3(signature = (path, config = None))]
+fn process_video(path: &str, config: Option<&Bound<'_, PyDict>>) -> PyResult<VideoRecords> {
+	let mut positional = [None, None];
+	let mut options = vec![];
+	for (key, value) in config.into_iter().flat_map(|config| config.iter()) {
+		let key: String = key.extract()?;
+		match key.as_str() {
+			"dictionary" => positional[0] = Some(value.str()?.to_string()),
+			"marker_size_mm" => positional[1] = Some(value.str()?.to_string()),
+			_ => {
+				let flag = format!("--{}", key.replace('_', "-"));
+				if let Ok(on) = value.downcast::<PyBool>() {
+					if on.is_true() {
+						options.push(flag);
+					}
+				} else if let Ok(items) = value.downcast::<PyList>() {
+					for item in items {
+						options.extend([flag.clone(), item.str()?.to_string()]);
+					}
+				} else {
+					options.extend([flag, value.str()?.to_string()]);
+				}
+			},
+		}
+	}
+	let [Some(dictionary), Some(marker_size_mm)] = positional else {
+		return Err(PyValueError::new_err("The config needs a 'dictionary' and a 'marker_size_mm'."));
+	};
+	let argv = ["fiducial_track_video".to_string(), path.to_string(), dictionary, marker_size_mm].into_iter().chain(options);
+	let args = Args::try_parse_from(argv).map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+	// Bounded so a slow consumer holds the tracker back rather than the whole video piling up in memory.
+	let (sender, receiver) = sync_channel(64);
+	let worker = std::thread::spawn(move || {
+		let mut outputs = Outputs::default();
+		outputs.add("Python", Box::new(ChannelSink { sender }));
+		cli::run(&args, outputs).map_err(|e| e.to_string())
+	});
+	Ok(VideoRecords { receiver: Mutex::new(receiver), worker: Mutex::new(Some(worker)) })
+}
+
+/// The frame records of a video being tracked in the background. Stopping early leaves the tracker to finish the
+/// video on its own, with nowhere to send the rest.
+#[pyclass]
+struct VideoRecords {
+	receiver: Mutex<Receiver<Value>>,
+	worker: Mutex<Option<JoinHandle<Result<(), String>>>>,
+}
+
+#[pymethods]
+impl VideoRecords {
+	fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+		slf
+	}
+
+	fn __next__<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> {
+		if let Ok(record) = py.allow_threads(|| self.receiver.lock().expect("Only we lock it.").recv()) {
+			return to_python(py, &record).map(Some);
+		}
+		// The tracker hung up, so it's finished. Any error it hit is raised once, at the end.
+		let Some(worker) = self.worker.lock().expect("Only we lock it.").take() else {
+			return Ok(None);
+		};
+		match py.allow_threads(|| worker.join()) {
+			Ok(Ok(())) => Ok(None),
+			Ok(Err(e)) => Err(PyRuntimeError::new_err(e)),
+			Err(_) => Err(PyRuntimeError::new_err("The tracker panicked.")),
+		}
+	}
+}
+
+struct ChannelSink {
+	sender: SyncSender<Value>,
+}
+
+impl Sink for ChannelSink {
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		self.sender.send(record.to_value()).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Nothing is iterating over the records any more."))
+	}
+}
+
+fn to_python<'py>(py: Python<'py>, value: &Value) -> PyResult<Bound<'py, PyAny>> {
+	Ok(match value {
+		Value::Null => py.None().into_bound(py),
+		Value::Bool(b) => PyBool::new(py, *b).to_owned().into_any(),
+		Value::Int(i) => i.into_pyobject(py)?.into_any(),
+		Value::F32(f) => PyFloat::new(py, *f as f64).into_any(),
+		Value::F64(f) => PyFloat::new(py, *f).into_any(),
+		Value::Str(s) => PyString::new(py, s).into_any(),
+		Value::Array(items) => {
+			let list = PyList::empty(py);
+			for item in items {
+				list.append(to_python(py, item)?)?;
+			}
+			list.into_any()
+		},
+		Value::Map(fields) => {
+			let dict = PyDict::new(py);
+			for (key, item) in fields {
+				dict.set_item(key, to_python(py, item)?)?;
+			}
+			dict.into_any()
+		},
+	})
+}
+
+#[pymodule]
+fn pyfiducial(m: &Bound<'_, PyModule>) -> PyResult<()> {
+	m.add_function(wrap_pyfunction!(detect, m)?)?;
+	m.add_function(wrap_pyfunction!(process_video, m)?)?;
+	m.add_class::<VideoRecords>()?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use fiducial_track_video::synthetic::{self, PlacedMarker};
+	use numpy::{PyArray1, PyArrayMethods};
+
+	#[test]
+	fn test_detect() {
+		pyo3::prepare_freethreaded_python();
+		let pinhole = Pinhole { width: 320, height: 240, fx: 500.0, fy: 500.0, cx: 160.0, cy: 120.0 };
+		let marker = PlacedMarker { id: 7, rotation: synthetic::FACING, translation: [0.0, 0.0, 700.0] };
+		let gray = synthetic::render(&pinhole, &[marker], 100.0, 128);
+		Python::with_gil(|py| {
+			let image = PyArray1::from_vec(py, gray.into_raw()).reshape([240, 320]).unwrap();
+			let detections = detect(py, image.to_dyn().readonly(), synthetic::DICTIONARY, 100.0, 500.0).unwrap();
+			let detections = detections.downcast::<PyList>().unwrap();
+			assert_eq!(detections.len(), 1);
+			let marker_id: usize = detections.get_item(0).unwrap().get_item("marker_id").unwrap().extract().unwrap();
+			assert_eq!(marker_id, 7);
+
+			let two_channels = PyArray1::from_vec(py, vec![0u8; 8]).reshape([2, 2, 2]).unwrap();
+			assert!(detect(py, two_channels.to_dyn().readonly(), synthetic::DICTIONARY, 100.0, 500.0).unwrap_err().is_instance_of::<PyValueError>(py));
+		});
+	}
+
+	#[test]
+	fn test_process_video_config() {
+		pyo3::prepare_freethreaded_python();
+		Python::with_gil(|py| {
+			let config = PyDict::new(py);
+			config.set_item("marker_size_mm", 50).unwrap();
+			assert!(process_video("take1.mp4", Some(&config)).is_err_and(|e| e.is_instance_of::<PyValueError>(py)));
+			config.set_item("dictionary", "ARUCO").unwrap();
+			config.set_item("not_an_option", true).unwrap();
+			assert!(process_video("take1.mp4", Some(&config)).is_err_and(|e| e.is_instance_of::<PyValueError>(py)));
+
+			// A video that isn't there fails in the tracker, and that comes out of the iterator.
+			config.del_item("not_an_option").unwrap();
+			let records = process_video("no such video.mp4", Some(&config)).unwrap();
+			assert!(records.__next__(py).is_err_and(|e| e.is_instance_of::<PyRuntimeError>(py)));
+			assert!(records.__next__(py).unwrap().is_none());
+		});
+	}
+
+	#[test]
+	fn test_to_python() {
+		pyo3::prepare_freethreaded_python();
+		Python::with_gil(|py| {
+			let value = Value::Map(vec![
+				("frame_id".to_string(), Value::Int(3)),
+				("corners".to_string(), Value::Array(vec![Value::F32(1.5), Value::Null])),
+			]);
+			let dict = to_python(py, &value).unwrap();
+			let frame_id: i64 = dict.get_item("frame_id").unwrap().extract().unwrap();
+			let corners = dict.get_item("corners").unwrap();
+			let first: f64 = corners.get_item(0).unwrap().extract().unwrap();
+			assert_eq!((frame_id, first), (3, 1.5));
+			assert!(corners.get_item(1).unwrap().is_none());
+		});
+	}
+}
@@ -0,0 +1,70 @@
+// Poses relative to one marker instead of the camera, for --anchor-marker. With the anchor on the set floor, props
+// come out in a frame that doesn't move when the camera does.
+//
+// Every 3D quantity is moved into the anchor's marker frame (+X right, +Y up, +Z out of the marker's face; see
+// geometry::marker_corners), so the anchor itself sits at the origin with no rotation. When a camera loses sight of
+// the anchor we keep using the last pose it had in that camera (see transform::HeldTransform), which is right for a
+// locked-off shot and drifts for a moving one.
+
+use crate::geometry;
+use crate::record::FrameRecord;
+use crate::transform::HeldTransform;
+
+pub struct Anchor {
+	marker_id: usize,
+	held: HeldTransform,
+}
+
+impl Anchor {
+	pub fn new(marker_id: usize) -> Self {
+		Anchor { marker_id, held: HeldTransform::default() }
+	}
+
+	pub fn apply(&mut self, record: &mut FrameRecord) {
+		let measured = record.markers.iter().find(|m| m.marker_id == self.marker_id).and_then(|m| m.best_pose()).map(|pose| {
+			// x_anchor = R^T (x_camera - t)
+			let rotation = geometry::transpose(&pose.rotation);
+			(rotation, geometry::scale(&geometry::mat_mul_vec(&rotation, &pose.translation), -1.0))
+		});
+		self.held.apply(record, measured);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::geometry::{Mat3, Vec3};
+	use crate::record::{MarkerRecord, PoseRecord};
+
+	fn marker(marker_id: usize, rotation: Mat3, translation: Vec3) -> MarkerRecord {
+		MarkerRecord { marker_id, poses: vec![PoseRecord { translation, rotation, ..Default::default() }], ..Default::default() }
+	}
+
+	#[test]
+	fn test_relative_to_anchor() {
+		let mut anchor = Anchor::new(0);
+		// The anchor faces the camera from 1m away, and the prop sits 100mm to its right, turned a quarter turn about the
+		// anchor's normal.
+		let facing: Mat3 = [[1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, -1.0]];
+		let quarter = geometry::rodrigues(&[0.0, 0.0, std::f32::consts::FRAC_PI_2]);
+		let mut record = FrameRecord {
+			markers: vec![
+				marker(0, facing, [0.0, 0.0, 1000.0]),
+				marker(1, geometry::mat_mul(&facing, &quarter), [100.0, 0.0, 1000.0]),
+			],
+			..Default::default()
+		};
+		anchor.apply(&mut record);
+		let (a, p) = (&record.markers[0].poses[0], &record.markers[1].poses[0]);
+		assert!(geometry::length(&a.translation) < 1e-4);
+		assert!(geometry::length(&geometry::sub(&p.translation, &[100.0, 0.0, 0.0])) < 1e-4, "{:?}", p.translation);
+		for (row, expected) in p.rotation.iter().zip(quarter.iter()) {
+			assert!(geometry::length(&geometry::sub(row, expected)) < 1e-5, "{:?}", p.rotation);
+		}
+
+		// Another camera that hasn't seen the anchor has nothing to go on.
+		let mut other = FrameRecord { camera_id: Some("1".to_string()), markers: vec![marker(1, facing, [0.0; 3])], ..Default::default() };
+		anchor.apply(&mut other);
+		assert!(other.markers[0].poses.is_empty());
+	}
+}
@@ -0,0 +1,80 @@
+// Other marker detectors behind one interface, so a binding to OpenCV's aruco module or the AprilTag C library (or a
+// learned detector, one day) can stand in for aruco3 without the pipeline knowing. A backend only has to find square
+// markers in a gray image and say which code each one is; FrameDetector offsets the corners into the full frame,
+// solves poses with our own solver, and scores them like any other marker.
+//
+// The built-in aruco3 detector doesn't go through this, since its own solver returns both pose candidates and it gets
+// a second look on dropouts. New backends go in Backend, each behind the cargo feature that pulls in its library.
+
+use clap::ValueEnum;
+use image::GrayImage;
+
+/// One marker as a backend found it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Marker {
+	pub id: usize,
+	/// In the image's pixels, starting from the marker's top-left and going clockwise.
+	pub corners: [(f32, f32); 4],
+	/// How many bits differed from the matched code, if the backend knows.
+	pub hamming_distance: Option<u32>,
+}
+
+pub trait FiducialDetector: Send {
+	fn detect(&mut self, gray: &GrayImage) -> Vec<Marker>;
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+	/// The built-in aruco3 detector.
+	#[default]
+	Aruco3,
+}
+
+impl Backend {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Backend::Aruco3 => "aruco3",
+		}
+	}
+
+	/// The detector for `dictionary`, or None for the built-in one.
+	pub fn create(self, _dictionary: &str) -> Result<Option<Box<dyn FiducialDetector>>, String> {
+		match self {
+			Backend::Aruco3 => Ok(None),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::detect::FrameDetector;
+	use crate::dictionary::Dictionaries;
+	use crate::geometry::Pinhole;
+
+	struct Fixed(Vec<Marker>);
+
+	impl FiducialDetector for Fixed {
+		fn detect(&mut self, _gray: &GrayImage) -> Vec<Marker> {
+			self.0.clone()
+		}
+	}
+
+	#[test]
+	fn test_backend_markers_are_recorded() {
+		let near = Marker { id: 4, corners: [(10.0, 10.0), (50.0, 10.0), (50.0, 50.0), (10.0, 50.0)], hamming_distance: Some(0) };
+		let noisy = Marker { id: 9, hamming_distance: Some(3), ..near.clone() };
+		let dictionaries = Dictionaries { aruco: Some("ARUCO".to_string()), ..Default::default() };
+		let mut detector = FrameDetector::new(dictionaries, 50.0, 50.0, Some(1)).with_backend(Box::new(Fixed(vec![near, noisy])));
+		let gray = GrayImage::new(64, 64);
+		let pinhole = Pinhole { width: 164, height: 164, fx: 160.0, fy: 160.0, cx: 82.0, cy: 82.0 };
+		let offset = (100.0, 100.0);
+		let found = detector.find(None, &gray, offset, &pinhole);
+		assert!(found.aruco.is_none());
+		let record = detector.record(found, 0, 0.0, &gray, offset, &crate::detect::intrinsics_for(&pinhole), &pinhole, false);
+		assert_eq!(record.markers.len(), 1);
+		let m = &record.markers[0];
+		assert_eq!((m.marker_id, m.hamming_distance, m.corners[0]), (4, Some(0), (110.0, 110.0)));
+		assert_eq!(m.poses.len(), 1);
+	}
+}
@@ -0,0 +1,1211 @@
+// The command line tool: arguments, setting up the outputs, and the per-record processing between the trackers and
+// the outputs. main.rs is just a call to main() here, and the Python and C bindings call run() with outputs of their
+// own, so they behave exactly like the tool given the same flags.
+// This is lifting heavily from https://github.com/shssoichiro/ffmpeg-the-third/blob/master/examples/dump-frames.rs
+use ffmpeg_the_third as ffmpeg;
+
+mod audio_sync;
+mod batch;
+mod bench;
+mod compress;
+mod debug_dump;
+mod deinterlace;
+mod hwaccel;
+mod osc;
+mod overlay;
+mod pipeline;
+mod probe;
+mod refine;
+mod serve;
+mod sqlite;
+mod synth;
+
+use crate::{
+	anchor, backend, colmap, diamond, dictionary, distortion, geometry, ground, instances, intrinsics_track, map_builder, marker_map, mask,
+	motion, msgpack, output, preprocess, qr, record, report, schema, stats, stereo, timecode, track2d, transform, value,
+};
+
+use anchor::Anchor;
+use aruco3::ARDictionary;
+use backend::Backend;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap::error::ErrorKind;
+use colmap::ColmapSink;
+use compress::Compression;
+use deinterlace::DeinterlaceMode;
+use diamond::DiamondFinder;
+use hwaccel::HwAccel;
+use ground::GroundPlane;
+use instances::InstanceTracker;
+use intrinsics_track::{IntrinsicsTrack, parse_intrinsics_track_file};
+use dictionary::{Dictionaries, parse_dictionaries};
+use geometry::Pinhole;
+use distortion::Distortion;
+use map_builder::MapBuilderSink;
+use marker_map::{MarkerMap, parse_marker_map_file};
+use mask::{MaskSource, parse_mask};
+use motion::VelocityEstimator;
+use msgpack::MsgPackSink;
+use osc::OscSink;
+use output::{Finish, JsonLinesSink, Outputs, Sink};
+use pipeline::{Camera, sample_frames, track_cameras, track_video};
+use preprocess::{Preprocess, parse_preprocess};
+use record::{FrameRecord, MarkerRecord, NormalizedCoords, RotationFormat};
+use report::ReportSink;
+use serve::{ServeAddress, SocketServer, WebSocketServer, parse_serve_address};
+use sqlite::SqliteSink;
+use stats::StatsSink;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use stereo::{StereoRig, parse_extrinsics};
+use timecode::{TimecodeStart, parse_timecode};
+use track2d::{Track2dSink, TrackFormat};
+use transform::{Convention, Units, scale_record, transform_record};
+use value::Value;
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+pub struct Args {
+	#[command(subcommand)]
+	command: Option<Command>,
+
+	/// The path to the video to read.
+	#[arg(required_unless_present_any = ["print_supported_dictionaries", "emit_schema"])]
+	filename: Option<String>,
+
+	/// The type of fiducial markers to use. Join several kinds with '+', e.g. 'ARUCO+QR'. QR codes are 'QR', and
+	/// 'AUTO' tries every ArUco-style dictionary on the first frames and tracks with whichever finds the most.
+	#[arg(required_unless_present_any = ["print_supported_dictionaries", "emit_schema"], value_parser = parse_dictionaries)]
+	fiducial_dictionary: Option<Dictionaries>,
+
+	/// The length of the edge of the fiducial markers in mm.
+	#[arg(required_unless_present_any = ["print_supported_dictionaries", "emit_schema"])]
+	marker_size_mm: Option<f32>,
+
+	/// If 'true', print lots of information.
+	#[arg(short, long, default_value_t = false)]
+	verbose: bool,
+
+	/// If 'true', print all supported dictionaries and halt.
+	#[arg(long, default_value_t = false)]
+	print_supported_dictionaries: bool,
+
+	/// If 'true', print a JSON Schema describing the header and frame records and halt.
+	#[arg(long, default_value_t = false)]
+	emit_schema: bool,
+
+	/// The starting frame from which to dump fiducial tracks.
+	#[arg(long, default_value_t = 0)]
+	start_frame: u64,
+
+	/// The ending frame (exclusive) to which fiducial markers should be detected.
+	#[arg(long, default_value_t = 0)]
+	end_frame: u64,
+
+	/// Stop once this many markers have been found, counting each marker in each frame. With several cameras, each
+	/// stops on its own.
+	#[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+	stop_after_detections: Option<u64>,
+
+	/// Stop once no markers have been seen for this many seconds, counting from when the first ones were.
+	#[arg(long)]
+	stop_after_gap: Option<f64>,
+
+	/// The horizontal focal length of the camera in mm.
+	#[arg(long, default_value_t = 1f32)]
+	focal_length_mm: f32,
+
+	/// The size of the sensor (diagonal) in mm.
+	#[arg(long)]
+	sensor_size_mm: Option<f32>,
+
+	/// The field of view of the camera in radians.
+	#[arg(long)]
+	fov_h_radians: Option<f32>,
+
+	/// A CSV of intrinsics by frame, for a zoom lens whose focal length changes during the shot. Columns are frame plus
+	/// one of fx (pixels), focal_length_mm (needs --sensor-size-mm), or fov_h_radians, and optionally fy, cx, cy.
+	/// See intrinsics_track.rs.
+	#[arg(long, value_parser = parse_intrinsics_track_file)]
+	intrinsics_track: Option<IntrinsicsTrack>,
+
+	/// Fit the focal length to the markers in this many frames from --start-frame, starting from the lens settings
+	/// above, then track the whole video with it. For when those are only a rough guess. Needs markers seen at an angle;
+	/// ones square on to the camera don't say anything about the focal length. See focal.rs.
+	#[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+	refine_focal: Option<u32>,
+
+	/// With --refine-focal, fit the principal point too instead of keeping it in the middle of the frame. Needs markers
+	/// spread out over the frame.
+	#[arg(long, default_value_t = false, requires = "refine_focal")]
+	refine_principal_point: bool,
+
+	/// The main video's timecode at its first frame, as hh:mm:ss:ff (or hh:mm:ss;ff for drop frame). Overrides any
+	/// timecode the camera embedded. Every frame record gets its own timecode when either is known.
+	#[arg(long, value_parser = parse_timecode)]
+	timecode_start: Option<TimecodeStart>,
+
+	/// If 'true', ignore the rotation (display matrix) metadata and process frames as they are stored.
+	#[arg(long, default_value_t = false)]
+	no_autorotate: bool,
+
+	/// If set, resample the tracks onto a constant frame rate grid, interpolating poses between decoded frames. Frame
+	/// ids count samples on that grid from the start of the video, so with a Blender scene's rate as the target every
+	/// keyframe lands on a scene frame.
+	#[arg(long, visible_alias = "resample-fps")]
+	target_fps: Option<f64>,
+
+	/// How to bring high bit depth or HDR (PQ/HLG) footage down to what the detector sees.
+	#[arg(long, value_enum, default_value_t = ToneMap::Auto)]
+	tonemap: ToneMap,
+
+	/// If 'true', convert 8-bit frames to RGB before detection instead of scaling straight to grayscale.
+	#[arg(long, default_value_t = false)]
+	rgb: bool,
+
+	/// The edge length of QR codes in mm, if they're a different size from the other markers.
+	#[arg(long)]
+	qr_size_mm: Option<f32>,
+
+	/// Also look for ChArUco diamonds: four ArUco markers around a chessboard square this many mm across. Each is
+	/// written as one more marker, with the four ids as diamond_ids and a pose solved from all sixteen corners.
+	#[arg(long)]
+	diamond_square_mm: Option<f32>,
+
+	/// Drop detections whose bits differ from the matched dictionary code in more than this many places.
+	/// Lower is stricter. Useful on noisy footage where a wrong id is worse than a missing one.
+	#[arg(long)]
+	max_hamming: Option<u32>,
+
+	/// Which detector looks for the ArUco-style markers. Other backends are built in with their cargo features.
+	/// See backend.rs.
+	#[arg(long, value_enum, default_value_t = Backend::Aruco3)]
+	backend: Backend,
+
+	/// How many frames an AUTO dictionary tries every dictionary on before picking one.
+	#[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..))]
+	auto_dictionary_frames: u32,
+
+	/// The dictionaries with AUTO resolved, once it has been.
+	#[arg(skip)]
+	resolved_dictionaries: OnceLock<Dictionaries>,
+
+	/// When a marker from the last frame doesn't decode, look for its border where it was and write it anyway with
+	/// "partial": true and which corners could be seen, so a hand passing over it doesn't end its track. See occlusion.rs.
+	#[arg(long, default_value_t = false)]
+	partial_markers: bool,
+
+	/// Clean up each frame before detection with these stages, in order: clahe[=clip], denoise[=radius], gamma=<g>.
+	/// E.g. 'denoise,clahe,gamma=1.8' for dark, noisy footage. See preprocess.rs.
+	#[arg(long, value_parser = parse_preprocess)]
+	preprocess: Option<Preprocess>,
+
+	/// The sensor's rolling shutter readout time in ms, i.e. how long after the top row the bottom row is captured.
+	/// Corners are moved back to where they'd be at the middle of the readout before solving poses. Typically 10 to 30.
+	#[arg(long)]
+	rolling_shutter_ms: Option<f32>,
+
+	/// Estimate the lens's radial distortion (k1, k2) and principal point from the markers themselves, by straightening
+	/// their edges over the whole video, then solve every pose again through the corrected lens. For footage with no
+	/// calibration. Nothing is written until the video is done, and the estimate goes in the header.
+	#[arg(long, default_value_t = false, conflicts_with = "crop_local_coords")]
+	self_calibrate: bool,
+
+	/// Whether to deinterlace frames before detection.
+	#[arg(long, value_enum, default_value_t = DeinterlaceMode::Auto)]
+	deinterlace: DeinterlaceMode,
+
+	/// Decode on the GPU, copying each frame back for detection. Worth it for H.265 and AV1, which are slow to decode
+	/// in software. Falls back to software if the device isn't there or can't handle the codec.
+	#[arg(long, value_enum)]
+	hwaccel: Option<HwAccel>,
+
+	/// Stop at the first frame that can't be read or decoded, instead of skipping it with a record saying why.
+	#[arg(long, default_value_t = false)]
+	strict: bool,
+
+	/// Only search for markers inside this region of the upright frame, given as x,y,w,h in pixels.
+	#[arg(long, value_parser = parse_crop)]
+	crop: Option<CropRegion>,
+
+	/// A black and white image over the upright frame: markers are only looked for where it's white. A path with a
+	/// frame number in it, like masks/shot.%04d.png, is a sequence, each mask holding until the next. See mask.rs.
+	#[arg(long, value_parser = parse_mask)]
+	mask: Option<MaskSource>,
+
+	/// If 'true', report corners relative to the crop region instead of the full frame. Poses are unaffected.
+	#[arg(long, default_value_t = false)]
+	crop_local_coords: bool,
+
+	/// Also write each marker's corners as fractions of the frame, so the same track lines up on a proxy and on the
+	/// full-res plate: 'unit' for 0 to 1 from the top left, 'ndc' for -1 to 1 from the center with y up.
+	#[arg(long, value_enum, conflicts_with = "crop_local_coords")]
+	normalized_coords: Option<NormalizedCoords>,
+
+	/// Also write each marker's plane normal and the tips of its X, Y, and Z axes, each a marker edge long, from the
+	/// best pose. For drawing orientation gizmos without working them out from the rotation. In the poses' space and units.
+	#[arg(long, default_value_t = false)]
+	pose_axes: bool,
+
+	/// Track an additional camera alongside the main video, as comma-separated key=value pairs.
+	/// Keys: file (required), id, focal_length_mm, sensor_size_mm, fov_h_radians, intrinsics_track, timecode_start,
+	/// frame_offset, mask. Lens settings default to the main camera's, except for the intrinsics track and the mask.
+	#[arg(long = "camera", value_parser = parse_camera)]
+	cameras: Vec<CameraSpec>,
+
+	/// Work out each --camera's frame_offset by cross-correlating its audio with the main video's, e.g. for takes
+	/// synced with a clap. A frame_offset in the camera spec is added on top.
+	#[arg(long, default_value_t = false, requires = "cameras")]
+	audio_sync: bool,
+
+	/// Pose of the --camera video relative to the main one, as rx,ry,rz,tx,ty,tz (Rodrigues vector and mm) or a row-major
+	/// rotation matrix followed by the translation. When set, markers seen by both cameras are triangulated.
+	#[arg(long, value_parser = parse_extrinsics, allow_hyphen_values = true)]
+	stereo_extrinsics: Option<StereoRig>,
+
+	/// Known world poses (and optionally sizes) of some or all of the markers. See marker_map.rs for the file format.
+	/// Each frame that sees a mapped marker gets a camera_pose placing the camera in that world.
+	#[arg(long, value_parser = parse_marker_map_file)]
+	marker_map: Option<MarkerMap>,
+
+	/// Which way the camera's axes point in every 3D output: poses, triangulated corners, and velocities.
+	#[arg(long, value_enum, default_value_t = Convention::Opencv)]
+	convention: Convention,
+
+	/// Report every 3D output relative to this marker instead of the camera, in the marker's own axes (+Z out of its
+	/// face). When the anchor is out of view, its last pose in that camera is used.
+	#[arg(long, conflicts_with_all = ["convention", "export_colmap", "build_map"])]
+	anchor_marker: Option<usize>,
+
+	/// Ids of markers lying flat on the floor, e.g. 0,1,2. Every 3D output is moved into a Z-up frame with the floor
+	/// at Z = 0, centered on the lowest numbered one. The floor is fixed the first time three or more are in view.
+	#[arg(long, value_delimiter = ',', conflicts_with_all = ["convention", "anchor_marker", "export_colmap", "build_map"])]
+	floor_markers: Vec<usize>,
+
+	/// The length unit for translations, triangulated corners, and velocities in every output.
+	#[arg(long, value_enum, default_value_t = Units::Mm)]
+	output_units: Units,
+
+	/// An extra factor on every output length, on top of --output-units. E.g. for a miniature set.
+	#[arg(long, default_value_t = 1.0)]
+	scale: f32,
+
+	/// How to write pose rotations. Each format has its own key in the pose: rotation, quaternion, euler_xyz, or axis_angle.
+	#[arg(long, value_enum, default_value_t = RotationFormat::Matrix)]
+	rotation_format: RotationFormat,
+
+	/// How to write records to stdout or --output. The live outputs (--serve and friends) always use JSON.
+	#[arg(long, value_enum, default_value_t = OutputFormat::Jsonl)]
+	format: OutputFormat,
+
+	/// Write records to this file instead of stdout. A .gz or .zst extension compresses it.
+	#[arg(long)]
+	output: Option<PathBuf>,
+
+	/// Compress the records, whatever --output is called, or on stdout.
+	#[arg(long, value_enum)]
+	compress: Option<Compression>,
+
+	/// Also write a copy of the video with marker outlines, ids, and pose axes drawn on each processed frame.
+	/// In multi-camera runs the camera id is added to the file name.
+	#[arg(long)]
+	render_overlay: Option<PathBuf>,
+
+	/// Write images of the detector's input, an approximate threshold map, candidate regions, and accepted markers
+	/// into this directory for every frame where nothing was found or a marker from the previous frame was lost.
+	#[arg(long)]
+	debug_dump: Option<PathBuf>,
+
+	/// When done, write every marker's center and corner tracks to this file for the Blender addon to load onto a MovieClip.
+	#[arg(long)]
+	export_blender_tracks: Option<PathBuf>,
+
+	/// When done, write a Nuke script with a Tracker node for each marker's corners to this file.
+	#[arg(long)]
+	export_nuke: Option<PathBuf>,
+
+	/// When done, write After Effects keyframe clipboard text for each marker (position and corner pin) into this directory.
+	#[arg(long)]
+	export_after_effects: Option<PathBuf>,
+
+	/// When done, write a COLMAP text model (cameras.txt, images.txt, points3D.txt) into this directory.
+	/// With --marker-map, mapped corners become known 3D points and frames that see them are posed.
+	#[arg(long)]
+	export_colmap: Option<PathBuf>,
+
+	/// When done, write a marker map built from the footage to this file, with the first marker seen at the origin (or
+	/// extending --marker-map if given). The footage has to link the markers together by showing them in pairs.
+	#[arg(long)]
+	build_map: Option<PathBuf>,
+
+	/// If 'true', print per-marker detection counts, gaps, errors, and stage timings to stderr when done.
+	#[arg(long, default_value_t = false)]
+	stats: bool,
+
+	/// Write the end-of-run statistics to this file as JSON.
+	#[arg(long)]
+	stats_file: Option<PathBuf>,
+
+	/// Write a visibility timeline when done: each marker's frame ranges, gaps, and mean reprojection error. An HTML
+	/// page, or one row per range if the path ends in .csv.
+	#[arg(long)]
+	report: Option<PathBuf>,
+
+	/// Also push records to connected clients as they're produced. Accepts unix:/path/to.sock, host:port, or a port number.
+	#[arg(long, value_parser = parse_serve_address)]
+	serve: Option<ServeAddress>,
+
+	/// Also send each frame as an OSC bundle over UDP to this host:port.
+	#[arg(long)]
+	osc: Option<String>,
+
+	/// Also serve records as JSON messages to WebSocket clients connecting to this host:port, e.g. 0.0.0.0:9000.
+	#[arg(long)]
+	websocket: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+	/// Convert a MessagePack track file back into JSON lines.
+	Convert {
+		/// The .msgpack file written with --format msgpack. A .gz or .zst one is decompressed first.
+		input: PathBuf,
+
+		/// Where to write the JSON lines. Defaults to stdout. A .gz or .zst extension compresses them.
+		#[arg(long)]
+		output: Option<PathBuf>,
+	},
+	/// Time each stage of the pipeline, e.g. `bench --runs 5 -- clip.mp4 ARUCO 50 --preprocess clahe`.
+	Bench {
+		/// How many times to track the clip.
+		#[arg(long, default_value_t = 3)]
+		runs: u32,
+
+		/// Time the detector on this many rendered frames of ARUCO markers instead of a clip. Leaves decoding out.
+		#[arg(long)]
+		synthetic: Option<usize>,
+
+		/// The size of the rendered frames.
+		#[arg(long, default_value = "1920x1080", value_parser = bench::parse_size)]
+		synthetic_size: (u32, u32),
+
+		/// The arguments of a normal run, after a '--'.
+		#[arg(last = true)]
+		tracking: Vec<String>,
+	},
+	/// Track every video in a directory, or each one named, into its own file with the same settings, e.g.
+	/// `batch day1/ --jobs 4 --output-dir tracks/ -- ARUCO 50 --format msgpack`.
+	Batch {
+		/// Videos, and directories to track every video in.
+		#[arg(required = true)]
+		inputs: Vec<PathBuf>,
+
+		/// Where to write each clip's records, named after the clip. Defaults to next to the clip.
+		#[arg(long)]
+		output_dir: Option<PathBuf>,
+
+		/// How many clips to track at once. Each one already decodes on several threads.
+		#[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+		jobs: u32,
+
+		/// Keep watching the directories and track each new clip once it's finished copying in, into a sidecar file.
+		/// Clips with an up to date sidecar are skipped. Runs until stopped.
+		#[arg(long, default_value_t = false)]
+		watch: bool,
+
+		/// The arguments of a normal run without the video, after a '--'.
+		#[arg(last = true, required = true)]
+		tracking: Vec<String>,
+	},
+	/// Print what the tracker would make of a video as JSON: its size, rate, length, codec, rotation, and lens metadata.
+	Probe {
+		/// The video to look at.
+		input: PathBuf,
+	},
+	/// Clean up a JSON lines track that's already been written: fix pose flips, remove spikes, fill short gaps, and
+	/// smooth, without tracking the video again.
+	Refine(refine::RefineArgs),
+	/// Render ARUCO markers moving along known paths, with their true poses, for testing the tracker's accuracy.
+	Synth(synth::SynthArgs),
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+	/// One JSON object per line.
+	Jsonl,
+	/// The same records as back-to-back MessagePack maps. Much smaller and faster to parse for long runs.
+	Msgpack,
+	/// An indexed SQLite database with frames, markers, and poses tables, to query instead of read through. Needs --output.
+	Sqlite,
+}
+
+impl OutputFormat {
+	fn extension(&self) -> &'static str {
+		match self {
+			OutputFormat::Jsonl => "jsonl",
+			OutputFormat::Msgpack => "msgpack",
+			OutputFormat::Sqlite => "sqlite",
+		}
+	}
+}
+
+impl Args {
+	// The positionals are only optional so subcommands can go without them. Clap makes sure they're set otherwise.
+	fn filename(&self) -> &str {
+		self.filename.as_deref().unwrap_or_default()
+	}
+
+	fn dictionaries(&self) -> &Dictionaries {
+		static NONE: Dictionaries = Dictionaries { aruco: None, auto: false, qr: false };
+		self.resolved_dictionaries.get().or(self.fiducial_dictionary.as_ref()).unwrap_or(&NONE)
+	}
+
+	fn marker_size(&self) -> f32 {
+		self.marker_size_mm.unwrap_or_default()
+	}
+
+	/// Output length units per mm.
+	fn length_scale(&self) -> f32 {
+		self.output_units.per_mm() * self.scale
+	}
+
+	/// The first record of a file output, describing how to read the rest.
+	/// `cameras` are from camera_header, in the order they were given.
+	fn header(&self, lenses: &BTreeMap<Option<String>, Distortion>, cameras: Vec<Value>) -> Value {
+		let mut out = vec![
+			("type".to_string(), Value::Str("header".to_string())),
+			("version".to_string(), Value::Str(env!("CARGO_PKG_VERSION").to_string())),
+			("convention".to_string(), Value::Str(self.convention.name().to_string())),
+			("units".to_string(), Value::Str(self.output_units.name().to_string())),
+			("scale".to_string(), Value::F32(self.scale)),
+			("rotation_format".to_string(), Value::Str(self.rotation_format.key().to_string())),
+		];
+		if let Some(anchor_marker) = self.anchor_marker {
+			out.push(("anchor_marker".to_string(), Value::Int(anchor_marker as i64)));
+		}
+		if !self.floor_markers.is_empty() {
+			out.push(("floor_markers".to_string(), Value::Array(self.floor_markers.iter().map(|id| Value::Int(*id as i64)).collect())));
+		}
+		if let Some(coords) = self.normalized_coords {
+			out.push(("normalized_coords".to_string(), Value::Str(coords.name().to_string())));
+		}
+		if let Some(fps) = self.target_fps {
+			out.push(("target_fps".to_string(), Value::F64(fps)));
+		}
+		if !lenses.is_empty() {
+			out.push(("distortion".to_string(), Value::Array(lenses.iter().map(|(camera_id, lens)| lens.to_value(camera_id.as_deref())).collect())));
+		}
+		let dictionaries = self.dictionaries();
+		let mut dictionary = vec![];
+		if let Some(aruco) = &dictionaries.aruco {
+			dictionary.push(("aruco".to_string(), Value::Str(aruco.clone())));
+		}
+		dictionary.push(("qr".to_string(), Value::Bool(dictionaries.qr)));
+		out.push(("dictionary".to_string(), Value::Map(dictionary)));
+		out.push(("marker_size_mm".to_string(), Value::F32(self.marker_size())));
+		if let Some(qr_size_mm) = self.qr_size_mm {
+			out.push(("qr_size_mm".to_string(), Value::F32(qr_size_mm)));
+		}
+		if let Some(square_mm) = self.diamond_square_mm {
+			out.push(("diamond_square_mm".to_string(), Value::F32(square_mm)));
+		}
+		out.push(("detector".to_string(), self.detector_header()));
+		out.push(("cameras".to_string(), Value::Array(cameras)));
+		Value::Map(out)
+	}
+
+	/// The settings that change what the detector finds, so a run can be told apart from one with different ones.
+	fn detector_header(&self) -> Value {
+		let mut out = vec![("backend".to_string(), Value::Str(self.backend.name().to_string()))];
+		if let Some(max_hamming) = self.max_hamming {
+			out.push(("max_hamming".to_string(), Value::Int(max_hamming as i64)));
+		}
+		if let Some(preprocess) = &self.preprocess {
+			out.push(("preprocess".to_string(), Value::Str(preprocess.to_string())));
+		}
+		if let Some(crop) = self.crop {
+			out.push(("crop".to_string(), Value::Array([crop.x, crop.y, crop.width, crop.height].map(|v| Value::Int(v as i64)).to_vec())));
+		}
+		if let Some(mask) = &self.mask {
+			out.push(("mask".to_string(), Value::Str(mask.path().to_string())));
+		}
+		out.push(("partial_markers".to_string(), Value::Bool(self.partial_markers)));
+		if let Some(readout_ms) = self.rolling_shutter_ms {
+			out.push(("rolling_shutter_ms".to_string(), Value::F32(readout_ms)));
+		}
+		Value::Map(out)
+	}
+
+	fn qr_size(&self) -> f32 {
+		self.qr_size_mm.unwrap_or(self.marker_size())
+	}
+}
+
+/// An extra camera from the command line, before missing lens settings are filled in from the main camera.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct CameraSpec {
+	id: Option<String>,
+	filename: String,
+	focal_length_mm: Option<f32>,
+	sensor_size_mm: Option<f32>,
+	fov_h_radians: Option<f32>,
+	intrinsics_track: Option<IntrinsicsTrack>,
+	timecode_start: Option<TimecodeStart>,
+	frame_offset: i64,
+	mask: Option<MaskSource>,
+}
+
+impl CameraSpec {
+	fn resolve(&self, args: &Args) -> Camera {
+		Camera {
+			id: self.id.clone(),
+			filename: self.filename.clone(),
+			focal_length_mm: self.focal_length_mm.unwrap_or(args.focal_length_mm),
+			sensor_size_mm: self.sensor_size_mm.or(args.sensor_size_mm),
+			fov_h_radians: self.fov_h_radians.or(args.fov_h_radians),
+			// A zoom track belongs to one lens, so it's never borrowed from the main camera.
+			intrinsics_track: self.intrinsics_track.clone(),
+			// Each camera runs its own clock.
+			timecode_start: self.timecode_start,
+			frame_offset: self.frame_offset,
+			refined: None,
+			mask: self.mask.clone(),
+		}
+	}
+}
+
+fn parse_camera(s: &str) -> Result<CameraSpec, String> {
+	let mut spec = CameraSpec::default();
+	for pair in s.split(',') {
+		let (key, value) = pair.split_once('=').ok_or_else(|| format!("Expected key=value in camera spec, got '{pair}'."))?;
+		let value = value.trim();
+		let number_error = |e: std::num::ParseFloatError| format!("Bad value for {key}: {e}");
+		match key.trim() {
+			"file" => spec.filename = value.to_string(),
+			"id" => spec.id = Some(value.to_string()),
+			"focal_length_mm" => spec.focal_length_mm = Some(value.parse().map_err(number_error)?),
+			"sensor_size_mm" => spec.sensor_size_mm = Some(value.parse().map_err(number_error)?),
+			"fov_h_radians" => spec.fov_h_radians = Some(value.parse().map_err(number_error)?),
+			"intrinsics_track" => spec.intrinsics_track = Some(parse_intrinsics_track_file(value)?),
+			"timecode_start" => spec.timecode_start = Some(parse_timecode(value)?),
+			"frame_offset" => spec.frame_offset = value.parse().map_err(|e| format!("Bad value for frame_offset: {e}"))?,
+			"mask" => spec.mask = Some(parse_mask(value)?),
+			other => return Err(format!("Unknown camera setting '{other}'.")),
+		}
+	}
+	if spec.filename.is_empty() {
+		return Err("Camera spec needs a file=... entry.".to_string());
+	}
+	Ok(spec)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct CropRegion {
+	x: u32,
+	y: u32,
+	width: u32,
+	height: u32,
+}
+
+impl CropRegion {
+	/// Shrink the region so it fits inside a frame of the given size, keeping at least one pixel.
+	fn clamped(&self, width: u32, height: u32) -> Self {
+		let x = self.x.min(width.saturating_sub(1));
+		let y = self.y.min(height.saturating_sub(1));
+		CropRegion {
+			x,
+			y,
+			width: self.width.min(width - x).max(1),
+			height: self.height.min(height - y).max(1),
+		}
+	}
+}
+
+fn parse_crop(s: &str) -> Result<CropRegion, String> {
+	let values = s.split(',').map(|v| v.trim().parse::<u32>()).collect::<Result<Vec<u32>, _>>().map_err(|e| format!("Crop values must be whole pixel counts: {e}"))?;
+	match values[..] {
+		[x, y, width, height] if width > 0 && height > 0 => Ok(CropRegion { x, y, width, height }),
+		[_, _, _, _] => Err("Crop width and height must be greater than zero.".to_string()),
+		_ => Err("Expected a crop region as x,y,w,h.".to_string()),
+	}
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum ToneMap {
+	/// Tone-map and stretch >8-bit or HDR footage into 8-bit luma. 8-bit SDR footage is untouched.
+	Auto,
+	/// Always use the plain 8-bit conversion.
+	Off,
+	/// Hand >8-bit or HDR footage to the detector as 16-bit luma without tone-mapping.
+	Luma16,
+}
+
+/// Why a run couldn't go ahead: bad arguments (or outputs that can't be opened), or trouble reading the video.
+#[derive(Debug)]
+pub enum Error {
+	Usage(clap::Error),
+	Video(ffmpeg::Error),
+}
+
+impl From<ffmpeg::Error> for Error {
+	fn from(e: ffmpeg::Error) -> Self {
+		Error::Video(e)
+	}
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::Usage(e) => write!(f, "{}", e.to_string().trim_end()),
+			Error::Video(e) => write!(f, "{e}"),
+		}
+	}
+}
+
+fn usage(kind: ErrorKind, message: impl std::fmt::Display) -> Error {
+	Error::Usage(Args::command().error(kind, message))
+}
+
+pub fn main() -> Result<(), ffmpeg::Error> {
+	let args = Args::parse(); // Better than env::args().nth(1).expect("Cannot open file.")
+
+	match &args.command {
+		Some(Command::Convert { input, output }) => {
+			convert(input, output.as_deref());
+			return Ok(());
+		},
+		Some(Command::Bench { runs, synthetic, synthetic_size, tracking }) => {
+			return exit_on_usage_error(bench::bench(*runs, *synthetic, *synthetic_size, tracking));
+		},
+		Some(Command::Batch { inputs, output_dir, jobs, watch, tracking }) => {
+			return exit_on_usage_error(batch::batch(inputs, output_dir.as_deref(), *jobs as usize, *watch, tracking));
+		},
+		Some(Command::Probe { input }) => {
+			return exit_on_usage_error(probe::probe(input));
+		},
+		Some(Command::Refine(refine_args)) => {
+			return exit_on_usage_error(refine::refine(refine_args));
+		},
+		Some(Command::Synth(synth_args)) => {
+			return exit_on_usage_error(synth::synth(synth_args));
+		},
+		None => {},
+	}
+
+	if args.print_supported_dictionaries {
+		for d in ARDictionary::get_dictionary_names() {
+			println!("{}", d);
+		}
+		println!("{}", qr::QR);
+		println!("{}", dictionary::AUTO);
+		return Ok(());
+	}
+
+	if args.emit_schema {
+		println!("{}", schema::schema().to_json());
+		return Ok(());
+	}
+
+	let mut outputs = Outputs::default();
+	match main_sink(&args) {
+		Ok((name, sink)) => outputs.add_primary(&name, sink),
+		Err(e) => return exit_on_usage_error(Err(e)),
+	}
+	exit_on_usage_error(run(&args, outputs))
+}
+
+/// The records' own output, the one the bindings replace with theirs: --output or stdout, in --format.
+fn main_sink(args: &Args) -> Result<(String, Box<dyn Sink>), Error> {
+	let sink: Box<dyn Sink> = match (args.format, &args.output) {
+		(OutputFormat::Jsonl, _) => Box::new(JsonLinesSink::new(create_output(args.output.as_deref(), args.compress).map_err(Error::Usage)?)),
+		(OutputFormat::Msgpack, _) => Box::new(MsgPackSink::new(create_output(args.output.as_deref(), args.compress).map_err(Error::Usage)?)),
+		(OutputFormat::Sqlite, None) => return Err(usage(ErrorKind::MissingRequiredArgument, "--format sqlite writes a database file, so it needs --output.")),
+		(OutputFormat::Sqlite, Some(_)) if args.compress.is_some() => return Err(usage(ErrorKind::ArgumentConflict, "--compress doesn't apply to --format sqlite.")),
+		(OutputFormat::Sqlite, Some(path)) => match SqliteSink::create(path) {
+			Ok(sink) => Box::new(sink),
+			Err(e) => return Err(usage(ErrorKind::Io, format!("Couldn't create {}: {e}", path.display()))),
+		},
+	};
+	let name = args.output.as_ref().map_or("stdout".to_string(), |path| path.display().to_string());
+	Ok((name, sink))
+}
+
+/// Bad arguments exit with clap's usage message, like they would have while parsing. Video errors go back to main.
+fn exit_on_usage_error(result: Result<(), Error>) -> Result<(), ffmpeg::Error> {
+	match result {
+		Ok(()) => Ok(()),
+		Err(Error::Usage(e)) => e.exit(),
+		Err(Error::Video(e)) => Err(e),
+	}
+}
+
+/// Track the video (and any extra cameras) and write the records to `outputs`, along with everything the arguments
+/// ask for besides the main output, which is left to the caller.
+pub fn run(args: &Args, mut outputs: Outputs) -> Result<(), Error> {
+	ffmpeg::init()?;
+
+	let mut cameras = vec![Camera {
+		id: None,
+		filename: args.filename().to_string(),
+		focal_length_mm: args.focal_length_mm,
+		sensor_size_mm: args.sensor_size_mm,
+		fov_h_radians: args.fov_h_radians,
+		intrinsics_track: args.intrinsics_track.clone(),
+		timecode_start: args.timecode_start,
+		frame_offset: 0,
+		refined: None,
+		mask: args.mask.clone(),
+	}];
+	cameras.extend(args.cameras.iter().map(|spec| spec.resolve(args)));
+	if cameras.len() > 1 {
+		for (idx, camera) in cameras.iter_mut().enumerate() {
+			camera.id.get_or_insert_with(|| idx.to_string());
+		}
+	}
+	if args.audio_sync {
+		audio_sync::sync_cameras(&mut cameras, args.verbose)?;
+	}
+	if let Some(camera) = cameras.iter().find(|c| c.sensor_size_mm.is_none() && c.intrinsics_track.as_ref().is_some_and(IntrinsicsTrack::needs_sensor_size)) {
+		return Err(usage(ErrorKind::MissingRequiredArgument, format!("The intrinsics track for {} is in mm, which needs a sensor size to turn into pixels.", camera.filename)));
+	}
+	if args.stereo_extrinsics.is_some() && cameras.len() != 2 {
+		return Err(usage(ErrorKind::ArgumentConflict, "--stereo-extrinsics needs exactly one --camera alongside the main video."));
+	}
+	if args.dictionaries().auto {
+		pick_dictionary(args, &cameras[0])?;
+	}
+	match &args.dictionaries().aruco {
+		Some(dictionary) => if let Err(e) = args.backend.create(dictionary) {
+			return Err(usage(ErrorKind::InvalidValue, format!("Couldn't set up the --backend: {e}")));
+		},
+		None if args.backend != Backend::Aruco3 => {
+			return Err(usage(ErrorKind::ArgumentConflict, "--backend only looks for ArUco-style markers, so it needs one of their dictionaries."));
+		},
+		None => {},
+	}
+	if let Some(frames) = args.refine_focal {
+		for camera in cameras.iter_mut() {
+			refine_focal(args, camera, frames)?;
+		}
+	}
+	if !(args.scale > 0.0 && args.scale.is_finite()) {
+		return Err(usage(ErrorKind::InvalidValue, "--scale needs to be a positive number."));
+	}
+	if args.target_fps.is_some_and(|fps| !(fps > 0.0 && fps.is_finite())) {
+		return Err(usage(ErrorKind::InvalidValue, "--target-fps needs to be a positive number of frames per second."));
+	}
+	if args.diamond_square_mm.is_some_and(|square| !(square > args.marker_size() && square.is_finite())) {
+		return Err(usage(ErrorKind::InvalidValue, "--diamond-square-mm needs to be bigger than the markers inside the squares."));
+	}
+	if args.stop_after_gap.is_some_and(|gap| !(gap > 0.0 && gap.is_finite())) {
+		return Err(usage(ErrorKind::InvalidValue, "--stop-after-gap needs to be a positive number of seconds."));
+	}
+
+	for (name, format, path) in [
+		("Blender tracks", TrackFormat::Blender, &args.export_blender_tracks),
+		("Nuke script", TrackFormat::Nuke, &args.export_nuke),
+		("After Effects keyframes", TrackFormat::AfterEffects, &args.export_after_effects),
+	] {
+		if let Some(path) = path {
+			outputs.add(name, Box::new(Track2dSink::new(format, path.clone())));
+		}
+	}
+	if let Some(directory) = &args.export_colmap {
+		outputs.add("COLMAP model", Box::new(ColmapSink::new(directory.clone(), args.marker_size(), args.marker_map.clone(), args.convention, args.length_scale())));
+	}
+	if let Some(path) = &args.build_map {
+		outputs.add("marker map", Box::new(MapBuilderSink::new(path.clone(), args.marker_size(), args.marker_map.clone(), args.convention, args.length_scale())));
+	}
+	if args.stats || args.stats_file.is_some() {
+		outputs.add("statistics", Box::new(StatsSink::new(args.marker_size(), args.stats, args.stats_file.clone())));
+	}
+	if let Some(path) = &args.report {
+		outputs.add("visibility report", Box::new(ReportSink::new(path.clone(), args.marker_size())));
+	}
+	if let Some(address) = &args.serve {
+		match SocketServer::bind(address) {
+			Ok(server) => outputs.add("socket server", Box::new(server)),
+			Err(e) => return Err(usage(ErrorKind::Io, format!("Couldn't listen on {address:?}: {e}"))),
+		}
+	}
+	if let Some(address) = &args.osc {
+		match OscSink::connect(address) {
+			Ok(sink) => outputs.add("OSC", Box::new(sink)),
+			Err(e) => return Err(usage(ErrorKind::Io, format!("Couldn't set up OSC output to {address}: {e}"))),
+		}
+	}
+	if let Some(address) = &args.websocket {
+		match WebSocketServer::bind(address) {
+			Ok(server) => outputs.add("WebSocket server", Box::new(server)),
+			Err(e) => return Err(usage(ErrorKind::Io, format!("Couldn't listen for WebSockets on {address}: {e}"))),
+		}
+	}
+
+	// --self-calibrate can't solve a pose until it's seen the whole video, so that first pass only collects records.
+	let mut lenses = BTreeMap::new();
+	let mut calibrated = None;
+	if args.self_calibrate {
+		let mut records = vec![];
+		let result = track(args, &cameras, &mut |record| records.push(record));
+		lenses = distortion::estimate(&records, args.marker_size());
+		for camera in &cameras {
+			match lenses.get(&camera.id) {
+				Some(lens) if args.verbose => eprintln!("Estimated distortion for {}: k1 = {}, k2 = {}, principal point ({}, {}).", camera.filename, lens.k1, lens.k2, lens.cx, lens.cy),
+				Some(_) => {},
+				None => eprintln!("Not enough large, sharp markers in {} to estimate its distortion. Leaving it uncorrected.", camera.filename),
+			}
+		}
+		calibrated = Some((result, records));
+	}
+	let mut pending = PendingHeader::new(args, &lenses, &cameras);
+
+	// In stereo mode, hang on to the left camera's latest frame until the right camera's matching frame shows up.
+	let left_id = cameras[0].id.clone();
+	let mut pending_left: Option<FrameRecord> = None;
+	let mut anchor = args.anchor_marker.map(Anchor::new);
+	let mut ground = (!args.floor_markers.is_empty()).then(|| GroundPlane::new(args.floor_markers.clone()));
+	let mut instances = InstanceTracker::default();
+	let mut velocities = VelocityEstimator::default();
+	let basis = args.convention.basis();
+	let length_scale = args.length_scale();
+	let diamonds = args.diamond_square_mm.map(|square| DiamondFinder::new(args.marker_size(), square));
+	// Each marker's axes are as long as its edge, in output units.
+	let axis_length = |m: &MarkerRecord| length_scale * match (m.diamond, &m.payload) {
+		(Some(_), _) => args.diamond_square_mm.unwrap_or(args.marker_size()),
+		(None, Some(_)) => args.qr_size(),
+		(None, None) => args.marker_size(),
+	};
+	let mut emit = |mut record: FrameRecord| {
+		if let Some(diamonds) = &diamonds {
+			diamonds.apply(&mut record);
+		}
+		instances.apply(&mut record);
+		if let Some(map) = &args.marker_map {
+			record.camera_pose = map.camera_pose(&record, args.marker_size());
+		}
+		if let Some(anchor) = anchor.as_mut() {
+			anchor.apply(&mut record);
+		}
+		if let Some(ground) = ground.as_mut() {
+			ground.apply(&mut record);
+		}
+		velocities.apply(&mut record);
+		if let Some(coords) = args.normalized_coords {
+			record.normalize_corners(coords);
+		}
+		transform_record(&mut record, &basis, &[0.0; 3]);
+		scale_record(&mut record, length_scale);
+		if args.pose_axes {
+			record.add_axes(&axis_length);
+		}
+		record.rotation_format = args.rotation_format;
+		pending.write(&mut outputs, &record);
+		let Some(rig) = &args.stereo_extrinsics else {
+			return;
+		};
+		if record.camera_id == left_id {
+			pending_left = Some(record);
+		} else if let Some(left) = pending_left.take_if(|left| left.frame_id == record.frame_id)
+			&& let Some(mut combined) = rig.triangulate_frames(&left, &record, args.marker_size()) {
+			combined.camera_id = Some("stereo".to_string());
+			instances.apply(&mut combined);
+			if let Some(map) = &args.marker_map {
+				combined.camera_pose = map.camera_pose(&combined, args.marker_size());
+			}
+			if let Some(anchor) = anchor.as_mut() {
+				anchor.apply(&mut combined);
+			}
+			if let Some(ground) = ground.as_mut() {
+				ground.apply(&mut combined);
+			}
+			velocities.apply(&mut combined);
+			if let Some(coords) = args.normalized_coords {
+				combined.normalize_corners(coords);
+			}
+			transform_record(&mut combined, &basis, &[0.0; 3]);
+			scale_record(&mut combined, length_scale);
+			if args.pose_axes {
+				combined.add_axes(&axis_length);
+			}
+			combined.rotation_format = args.rotation_format;
+			pending.write(&mut outputs, &combined);
+		}
+	};
+
+	let result = match calibrated {
+		Some((result, records)) => {
+			for mut record in records {
+				if let Some(lens) = lenses.get(&record.camera_id) {
+					lens.apply(&mut record, args.marker_size(), args.qr_size());
+				}
+				emit(record);
+			}
+			result
+		},
+		None => track(args, &cameras, &mut emit),
+	};
+	pending.release(&mut outputs);
+	let finished = outputs.finish();
+	result.map_err(Error::Video)?;
+	finished.map_err(|e| usage(ErrorKind::Io, e))
+}
+
+/// Resolve an AUTO dictionary from the main video's first frames, saying how each dictionary did.
+fn pick_dictionary(args: &Args, main: &Camera) -> Result<(), Error> {
+	let frames: Vec<_> = sample_frames(args, main, args.auto_dictionary_frames as usize)?.into_iter().map(|s| s.gray).collect();
+	let scores = dictionary::identify(&frames, &ARDictionary::get_dictionary_names());
+	eprintln!("Markers found in the first {} frames by each dictionary:", frames.len());
+	for score in scores.iter().filter(|s| s.detections > 0) {
+		eprintln!("{:>24} {:>6} detections, {:>6} without corrections, in {} frames", score.name, score.detections, score.exact, score.frames);
+	}
+	let Some(best) = scores.first().filter(|s| s.detections > 0) else {
+		return Err(usage(ErrorKind::ValueValidation, format!("None of the dictionaries found a marker in the first {} frames.", frames.len())));
+	};
+	eprintln!("Tracking with {}.", best.name);
+	let mut dictionaries = args.dictionaries().clone();
+	dictionaries.aruco = Some(best.name.clone());
+	dictionaries.auto = false;
+	// Batch runs each clip with its own arguments, so this is only ever set once.
+	let _ = args.resolved_dictionaries.set(dictionaries);
+	Ok(())
+}
+
+/// Fit the camera's lens to its first frames for --refine-focal, and say how it went.
+fn refine_focal(args: &Args, camera: &mut Camera, frames: u32) -> Result<(), Error> {
+	if camera.intrinsics_track.is_some() {
+		eprintln!("{} already has an intrinsics track, so its focal length is left alone.", camera.filename);
+		return Ok(());
+	}
+	match pipeline::refine_focal(args, camera, frames as usize)? {
+		Some(fit) => {
+			eprintln!("Refined the focal length for {} to {:.1} px over {} markers, with principal point ({:.1}, {:.1}). Reprojection error went from {:.3} to {:.3} px.",
+				camera.filename, fit.pinhole.fx, fit.markers, fit.pinhole.cx, fit.pinhole.cy, fit.rms_before, fit.rms_after);
+			camera.refined = Some(fit.pinhole);
+		},
+		None => eprintln!("Not enough markers seen at an angle in the first {frames} frames of {} to refine its focal length. Keeping the lens settings.", camera.filename),
+	}
+	Ok(())
+}
+
+/// The header gives each camera's lens as its first tracked frame was solved with, which isn't known until that frame
+/// has been decoded. Records are held back until every camera has had one, which is only a frame or two since they
+/// start together, rather than opening and decoding every video a second time just for the header.
+struct PendingHeader<'a> {
+	args: &'a Args,
+	lenses: &'a BTreeMap<Option<String>, Distortion>,
+	cameras: &'a [Camera],
+	pinholes: Vec<Option<Pinhole>>,
+	/// None once the header's been written.
+	held: Option<Vec<FrameRecord>>,
+}
+
+impl<'a> PendingHeader<'a> {
+	fn new(args: &'a Args, lenses: &'a BTreeMap<Option<String>, Distortion>, cameras: &'a [Camera]) -> Self {
+		PendingHeader { args, lenses, cameras, pinholes: vec![None; cameras.len()], held: Some(vec![]) }
+	}
+
+	fn write(&mut self, outputs: &mut Outputs, record: &FrameRecord) {
+		let Some(held) = self.held.as_mut() else {
+			outputs.write(record);
+			return;
+		};
+		if let Some(idx) = self.cameras.iter().position(|camera| camera.id == record.camera_id) {
+			self.pinholes[idx] = self.pinholes[idx].or(record.intrinsics);
+		}
+		held.push(record.clone());
+		if self.pinholes.iter().all(Option::is_some) {
+			self.release(outputs);
+		}
+	}
+
+	/// Write the header and everything held back. At the end of a run that's with whichever lenses turned up, so a
+	/// video with no frames to track still gets a header.
+	fn release(&mut self, outputs: &mut Outputs) {
+		let Some(held) = self.held.take() else {
+			return;
+		};
+		let cameras = self.cameras.iter().zip(&self.pinholes).map(|(camera, pinhole)| camera_header(camera, *pinhole)).collect();
+		outputs.header(&self.args.header(self.lenses, cameras));
+		for record in &held {
+			outputs.write(record);
+		}
+	}
+}
+
+/// A camera's entry in the header: its file, and the lens its first frame is solved with.
+fn camera_header(camera: &Camera, pinhole: Option<Pinhole>) -> Value {
+	let mut out = vec![];
+	if let Some(id) = &camera.id {
+		out.push(("camera_id".to_string(), Value::Str(id.clone())));
+	}
+	out.push(("file".to_string(), Value::Str(camera.filename.clone())));
+	match input_fingerprint(Path::new(&camera.filename)) {
+		Ok(fingerprint) => out.push(("input_fingerprint".to_string(), Value::Str(fingerprint))),
+		Err(e) => eprintln!("Couldn't read {} to fingerprint it: {e}", camera.filename),
+	}
+	if let Some(pinhole) = pinhole {
+		let source = match (&camera.intrinsics_track, camera.refined) {
+			(Some(_), _) => "intrinsics_track",
+			(None, Some(refined)) if (refined.width, refined.height) == (pinhole.width, pinhole.height) => "refined",
+			_ => "lens",
+		};
+		out.extend([
+			("width".to_string(), Value::Int(pinhole.width as i64)),
+			("height".to_string(), Value::Int(pinhole.height as i64)),
+			("fx".to_string(), Value::F32(pinhole.fx)),
+			("fy".to_string(), Value::F32(pinhole.fy)),
+			("cx".to_string(), Value::F32(pinhole.cx)),
+			("cy".to_string(), Value::F32(pinhole.cy)),
+			("intrinsics_source".to_string(), Value::Str(source.to_string())),
+		]);
+	}
+	Value::Map(out)
+}
+
+/// A fingerprint of a file that's quick to take on a big video: 64-bit FNV-1a over its length and its first and last
+/// MiB, in hex. Enough to tell whether a track came from the file in hand, not a checksum: the middle of the file isn't
+/// read, so a change there goes unnoticed.
+fn input_fingerprint(path: &Path) -> std::io::Result<String> {
+	const CHUNK: u64 = 1 << 20;
+	let mut file = File::open(path)?;
+	let len = file.metadata()?.len();
+	let mut hash: u64 = 0xcbf29ce484222325;
+	let mut feed = |bytes: &[u8]| {
+		for byte in bytes {
+			hash ^= *byte as u64;
+			hash = hash.wrapping_mul(0x100000001b3);
+		}
+	};
+	feed(&len.to_le_bytes());
+	let mut buffer = vec![];
+	(&mut file).take(CHUNK).read_to_end(&mut buffer)?;
+	if len > CHUNK {
+		file.seek(SeekFrom::Start(len.saturating_sub(CHUNK).max(CHUNK)))?;
+		file.take(CHUNK).read_to_end(&mut buffer)?;
+	}
+	feed(&buffer);
+	Ok(format!("{hash:016x}"))
+}
+
+fn track(args: &Args, cameras: &[Camera], emit: &mut dyn FnMut(FrameRecord)) -> Result<(), ffmpeg::Error> {
+	if cameras.len() == 1 {
+		track_video(args, &cameras[0], emit)
+	} else {
+		track_cameras(args, cameras, emit)
+	}
+}
+
+/// A buffered file if a path was given, otherwise stdout, compressed if asked or if the path ends in .gz or .zst.
+fn create_output(path: Option<&Path>, compression: Option<Compression>) -> Result<Box<dyn Finish>, clap::Error> {
+	let compression = compression.or_else(|| path.and_then(Compression::from_path));
+	let writer: Box<dyn Finish> = match path {
+		Some(path) => match File::create(path) {
+			Ok(file) => Box::new(BufWriter::new(file)),
+			Err(e) => return Err(Args::command().error(ErrorKind::Io, format!("Couldn't create {}: {e}", path.display()))),
+		},
+		None => Box::new(std::io::stdout()),
+	};
+	match compression {
+		// Compressing a few bytes at a time costs more than the buffer, so buffer in front of the compressor too.
+		Some(compression) => match compression.writer(writer) {
+			Ok(compressed) => Ok(Box::new(BufWriter::new(compressed))),
+			Err(e) => Err(Args::command().error(ErrorKind::Io, format!("Couldn't start compressing: {e}"))),
+		},
+		None => Ok(writer),
+	}
+}
+
+fn convert(input: &Path, output: Option<&Path>) {
+	let file = match File::open(input) {
+		Ok(file) => BufReader::new(file),
+		Err(e) => Args::command().error(ErrorKind::Io, format!("Couldn't open {}: {e}", input.display())).exit(),
+	};
+	let mut reader: Box<dyn Read> = match Compression::from_path(input).map(|compression| compression.reader(file)) {
+		Some(Ok(reader)) => reader,
+		Some(Err(e)) => Args::command().error(ErrorKind::Io, format!("Couldn't decompress {}: {e}", input.display())).exit(),
+		None => Box::new(file),
+	};
+	let mut writer = create_output(output, None).unwrap_or_else(|e| e.exit());
+	if let Err(e) = msgpack::convert_to_json_lines(&mut reader, &mut writer).and_then(|_| writer.finish()) {
+		eprintln!("Failed to convert {}: {e}", input.display());
+		std::process::exit(1);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_sanity() {
+	}
+
+	#[test]
+	fn test_args_are_consistent() {
+		Args::command().debug_assert();
+	}
+
+	#[test]
+	fn test_parse_camera() {
+		let spec = parse_camera("id=left,file=left.mp4,focal_length_mm=28,frame_offset=-3").unwrap();
+		assert_eq!(spec.id.as_deref(), Some("left"));
+		assert_eq!(spec.filename, "left.mp4");
+		assert_eq!(spec.focal_length_mm, Some(28.0));
+		assert_eq!(spec.frame_offset, -3);
+		assert!(parse_camera("id=left").is_err());
+		assert!(parse_camera("file=a.mp4,zoom=2").is_err());
+	}
+
+	#[test]
+	fn test_parse_crop() {
+		assert_eq!(parse_crop("10, 20,300,400"), Ok(CropRegion { x: 10, y: 20, width: 300, height: 400 }));
+		assert!(parse_crop("10,20,0,400").is_err());
+		assert!(parse_crop("10,20,300").is_err());
+		let clamped = parse_crop("1900,0,100,100").unwrap().clamped(1920, 1080);
+		assert_eq!(clamped.width, 20);
+	}
+
+	#[test]
+	fn test_header_is_documented() {
+		let args = Args::try_parse_from(["track", "shot.mp4", "DICT_4X4_50+QR", "50", "--qr-size-mm", "80", "--max-hamming", "1",
+			"--preprocess", "clahe", "--crop", "0,0,640,360", "--rolling-shutter-ms", "20"]).unwrap();
+		let camera = Camera {
+			id: Some("left".to_string()),
+			filename: "shot.mp4".to_string(),
+			focal_length_mm: 900.0,
+			sensor_size_mm: None,
+			fov_h_radians: None,
+			intrinsics_track: None,
+			timecode_start: None,
+			frame_offset: 0,
+			refined: None,
+			mask: None,
+		};
+		let pinhole = Pinhole { width: 1280, height: 720, fx: 900.0, fy: 900.0, cx: 640.0, cy: 360.0 };
+		let header = args.header(&BTreeMap::new(), vec![camera_header(&camera, Some(pinhole))]);
+		let schema = schema::schema();
+		let definition = |name: &str| schema.get("$defs").and_then(|defs| defs.get(name)).and_then(|def| def.get("properties")).unwrap().clone();
+		let documented = |value: &Value, properties: &Value| {
+			let Value::Map(fields) = value else {
+				panic!("expected a map");
+			};
+			for (key, _) in fields {
+				assert!(properties.get(key).is_some(), "{key} isn't in the schema");
+			}
+		};
+		let properties = definition("header");
+		documented(&header, &properties);
+		for key in ["dictionary", "detector"] {
+			documented(header.get(key).unwrap(), properties.get(key).and_then(|p| p.get("properties")).unwrap());
+		}
+		let Some(Value::Array(cameras)) = header.get("cameras") else {
+			panic!("no cameras");
+		};
+		documented(&cameras[0], &definition("camera"));
+		assert_eq!(cameras[0].get("intrinsics_source"), Some(&Value::Str("lens".to_string())));
+		assert_eq!(header.get("detector").and_then(|d| d.get("preprocess")), Some(&Value::Str("clahe=2".to_string())));
+	}
+}
@@ -0,0 +1,232 @@
+// Automatic frame offsets between cameras, for --audio-sync, so takes synced with a clap (or anything loud) line up
+// without counting frames by hand.
+//
+// Each file's first audio track is boiled down to a loudness envelope at 1kHz, then to its onsets (how much louder
+// each millisecond is than the last), since a clap is a sudden rise whatever the microphone's gain or the room's echo.
+// Every other camera's onsets are cross-correlated against the main camera's, and the best lag becomes that camera's
+// frame offset, at the main video's frame rate. A frame_offset given with --camera is added on top, as a nudge.
+//
+// This assumes each file's audio starts with its video, which is true of anything straight off a camera.
+
+use ffmpeg_the_third as ffmpeg;
+
+use crate::ffmpeg::format::{Sample, input};
+use crate::ffmpeg::media::Type;
+use crate::ffmpeg::util::frame::audio::Audio;
+use crate::cli::pipeline::{Camera, frame_rate};
+use std::f64::consts::PI;
+
+/// Envelope samples per second. A millisecond is much finer than a frame.
+const ENVELOPE_RATE: u32 = 1000;
+/// The best lag has to beat any other this far from it by this much before we'd call it unambiguous.
+const MIN_SEPARATION_S: f64 = 0.1;
+const MIN_CLARITY: f32 = 1.2;
+
+/// Mean absolute level over each millisecond, fed a frame at a time.
+#[derive(Default)]
+struct Envelope {
+	values: Vec<f32>,
+	sum: f32,
+	count: usize,
+	/// Audio samples so far, which says which millisecond the next one falls in. Rates like 44.1kHz don't divide into
+	/// whole samples per millisecond, so counting a fixed number per bin would run the envelope slow.
+	samples: u64,
+}
+
+impl Envelope {
+	fn push(&mut self, samples: &[f32], rate: u32) {
+		for s in samples {
+			let bin = (self.samples * ENVELOPE_RATE as u64 / rate.max(1) as u64) as usize;
+			// Below 1kHz a sample can span several bins, which all get its level.
+			while bin > self.values.len() {
+				let level = if self.count > 0 { self.sum / self.count as f32 } else { self.values.last().copied().unwrap_or(0.0) };
+				self.values.push(level);
+				(self.sum, self.count) = (0.0, 0);
+			}
+			self.sum += s.abs();
+			self.count += 1;
+			self.samples += 1;
+		}
+	}
+}
+
+/// The first channel of a decoded audio frame, as floats from -1 to 1.
+fn first_channel(frame: &Audio) -> Vec<f32> {
+	// Packed formats interleave the channels in the first plane, planar ones give each channel its own.
+	let stride = if frame.is_packed() { frame.ch_layout().channels().max(1) as usize } else { 1 };
+	let data = frame.data(0);
+	let samples = frame.samples().min(data.len() / stride);
+	let at = |i: usize, size: usize| &data[i * stride * size..][..size];
+	match frame.format() {
+		Sample::U8(_) => (0..samples).map(|i| (at(i, 1)[0] as f32 - 128.0) / 128.0).collect(),
+		Sample::I16(_) => (0..samples).map(|i| i16::from_ne_bytes(at(i, 2).try_into().unwrap()) as f32 / 32768.0).collect(),
+		Sample::I32(_) => (0..samples).map(|i| i32::from_ne_bytes(at(i, 4).try_into().unwrap()) as f32 / 2147483648.0).collect(),
+		Sample::F32(_) => (0..samples).map(|i| f32::from_ne_bytes(at(i, 4).try_into().unwrap())).collect(),
+		Sample::F64(_) => (0..samples).map(|i| f64::from_ne_bytes(at(i, 8).try_into().unwrap()) as f32).collect(),
+		_ => vec![],
+	}
+}
+
+/// A file's audio envelope and its video frame rate, or None for the envelope if it has no audio.
+fn load(filename: &str) -> Result<(Option<Vec<f32>>, Option<f64>), ffmpeg::Error> {
+	let mut ictx = input(filename)?;
+	let fps = ictx.streams().best(Type::Video).and_then(|stream| frame_rate(stream.avg_frame_rate()).or(frame_rate(stream.rate())));
+	let Some(stream) = ictx.streams().best(Type::Audio) else {
+		return Ok((None, fps));
+	};
+	let audio_stream_index = stream.index();
+	let mut decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?.decoder().audio()?;
+	let mut envelope = Envelope::default();
+	let mut decoded = Audio::empty();
+	for (stream, packet) in ictx.packets().filter_map(Result::ok) {
+		if stream.index() == audio_stream_index {
+			decoder.send_packet(&packet)?;
+			while decoder.receive_frame(&mut decoded).is_ok() {
+				envelope.push(&first_channel(&decoded), decoded.rate());
+			}
+		}
+	}
+	decoder.send_eof()?;
+	while decoder.receive_frame(&mut decoded).is_ok() {
+		envelope.push(&first_channel(&decoded), decoded.rate());
+	}
+	Ok((Some(envelope.values), fps))
+}
+
+/// How much louder each envelope sample is than the one before, ignoring the falls.
+fn onsets(envelope: &[f32]) -> Vec<f32> {
+	std::iter::once(0.0).chain(envelope.windows(2).map(|w| (w[1] - w[0]).max(0.0))).collect()
+}
+
+fn mul(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+	(a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0)
+}
+
+/// In-place radix-2 FFT. The length must be a power of two. The inverse isn't scaled.
+fn fft(data: &mut [(f64, f64)], inverse: bool) {
+	let n = data.len();
+	let mut j = 0;
+	for i in 1..n {
+		let mut bit = n >> 1;
+		while j & bit != 0 {
+			j ^= bit;
+			bit >>= 1;
+		}
+		j |= bit;
+		if i < j {
+			data.swap(i, j);
+		}
+	}
+	let mut len = 2;
+	while len <= n {
+		let angle = if inverse { 2.0 } else { -2.0 } * PI / len as f64;
+		let step = (angle.cos(), angle.sin());
+		for start in (0..n).step_by(len) {
+			let mut w = (1.0, 0.0);
+			for k in start..start + len / 2 {
+				let (a, b) = (data[k], mul(w, data[k + len / 2]));
+				data[k] = (a.0 + b.0, a.1 + b.1);
+				data[k + len / 2] = (a.0 - b.0, a.1 - b.1);
+				w = mul(w, step);
+			}
+		}
+		len <<= 1;
+	}
+}
+
+/// The lag L, in samples, that best lines up other[t] with reference[t + L], and how far above any other candidate
+/// its correlation stands.
+fn best_lag(reference: &[f32], other: &[f32]) -> Option<(i64, f32)> {
+	if reference.is_empty() || other.is_empty() {
+		return None;
+	}
+	let n = (reference.len() + other.len()).next_power_of_two();
+	let spectrum = |signal: &[f32]| {
+		let mean = signal.iter().sum::<f32>() / signal.len() as f32;
+		let mut data: Vec<(f64, f64)> = signal.iter().map(|v| ((v - mean) as f64, 0.0)).collect();
+		data.resize(n, (0.0, 0.0));
+		fft(&mut data, false);
+		data
+	};
+	let (a, b) = (spectrum(reference), spectrum(other));
+	let mut correlation: Vec<_> = a.iter().zip(&b).map(|(x, y)| mul(*x, (y.0, -y.1))).collect();
+	fft(&mut correlation, true);
+	// With this much padding nothing wraps onto itself: the low indices are positive lags, the high ones negative.
+	let lag = |i: usize| if i < reference.len() { i as i64 } else { i as i64 - n as i64 };
+	let (best, peak) = correlation.iter().enumerate().map(|(i, c)| (i, c.0)).max_by(|x, y| x.1.total_cmp(&y.1))?;
+	let separation = (MIN_SEPARATION_S * ENVELOPE_RATE as f64) as i64;
+	let runner_up = correlation.iter().enumerate()
+		.filter(|(i, _)| (lag(*i) - lag(best)).abs() > separation)
+		.map(|(_, c)| c.0)
+		.fold(f64::MIN_POSITIVE, f64::max);
+	Some((lag(best), (peak / runner_up) as f32))
+}
+
+/// Work out every extra camera's frame offset from its audio. Cameras we can't line up keep the offset they were
+/// given.
+pub fn sync_cameras(cameras: &mut [Camera], verbose: bool) -> Result<(), ffmpeg::Error> {
+	let Some((first, rest)) = cameras.split_first_mut() else {
+		return Ok(());
+	};
+	let (reference, fps) = load(&first.filename)?;
+	let (Some(reference), Some(fps)) = (reference, fps) else {
+		eprintln!("{} has no audio or frame rate to sync against. Leaving the camera offsets alone.", first.filename);
+		return Ok(());
+	};
+	let reference = onsets(&reference);
+	for camera in rest {
+		let Some((lag, clarity)) = load(&camera.filename)?.0.and_then(|envelope| best_lag(&reference, &onsets(&envelope))) else {
+			eprintln!("{} has no audio to sync with. Keeping its frame offset of {}.", camera.filename, camera.frame_offset);
+			continue;
+		};
+		let seconds = lag as f64 / ENVELOPE_RATE as f64;
+		let offset = (seconds * fps).round() as i64;
+		if clarity < MIN_CLARITY {
+			eprintln!("The audio of {} doesn't line up clearly with {}. Check its offset of {offset} frames.", camera.filename, first.filename);
+		} else if verbose {
+			eprintln!("{} starts {seconds:.3}s after {}, an offset of {offset} frames.", camera.filename, first.filename);
+		}
+		camera.frame_offset += offset;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_finds_clap_offset() {
+		// Three claps and some quiet noise, then the same heard by a camera that started 1.234s later.
+		let mut seed = 7u32;
+		let mut noise = || {
+			seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+			(seed >> 16) as f32 / 65536.0 * 0.05
+		};
+		let reference: Vec<f32> = (0..8000).map(|t| noise() + if [2000, 3100, 5600].contains(&t) { 1.0 } else { 0.0 }).collect();
+		let other: Vec<f32> = reference[1234..].iter().map(|v| v + noise()).collect();
+		let (lag, clarity) = best_lag(&onsets(&reference), &onsets(&other)).unwrap();
+		assert_eq!(lag, 1234);
+		assert!(clarity > MIN_CLARITY, "{clarity}");
+		// And the other way round.
+		assert_eq!(best_lag(&onsets(&other), &onsets(&reference)).unwrap().0, -1234);
+	}
+
+	#[test]
+	fn test_mixed_sample_rates() {
+		// The same claps recorded at 44.1kHz, and at 48kHz by a camera that started 1.234s later.
+		let level = |t: f64| if [2.0, 3.1, 5.6].iter().any(|clap| (0.0..0.002).contains(&(t - clap))) { 1.0 } else { 0.01 };
+		let record = |rate: u32, start: f64| {
+			let mut envelope = Envelope::default();
+			let samples: Vec<f32> = (0..(7.0 * rate as f64) as usize).map(|i| level(start + i as f64 / rate as f64)).collect();
+			// In frames of 1024, the way a decoder hands them over.
+			for frame in samples.chunks(1024) {
+				envelope.push(frame, rate);
+			}
+			envelope.values
+		};
+		let (reference, other) = (record(44100, 0.0), record(48000, 1.234));
+		assert!((reference.len() as i64 - 7000).abs() <= 1, "{}", reference.len());
+		assert_eq!(best_lag(&onsets(&reference), &onsets(&other)).unwrap().0, 1234);
+	}
+}
@@ -0,0 +1,196 @@
+// The batch subcommand: track a folder of clips (or a list of them) with the same settings, each into its own file,
+// several at a time. For leaving a whole shoot to track overnight. e.g.
+//   fiducial_track_video batch day1/ --jobs 4 --output-dir tracks/ -- ARUCO 50 --focal-length-mm 35 --format msgpack
+// writes tracks/A001_C002.msgpack and so on. Anything else a run writes, like --render-overlay or --export-nuke, gets
+// the clip's name added, the way multi-camera runs add the camera's: overlay.mp4 becomes overlay_A001_C002.mp4.
+//
+// A clip that fails is reported and skipped, and the rest carry on. The exit status says whether any failed.
+//
+// With --watch it becomes an ingest station: it keeps looking in the directories for clips that land there (from a
+// camera offload, say) and tracks each into a sidecar file once it's finished copying. Clips that already have a
+// newer sidecar are left alone, so it can be stopped and started again.
+
+use crate::cli::overlay::path_for_camera;
+use crate::cli::{Args, Error, main_sink, run, usage};
+use crate::output::Outputs;
+use clap::Parser;
+use clap::error::ErrorKind;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::time::{Duration, SystemTime};
+
+/// How often --watch looks for new clips. A clip is tracked once it's the same size on two looks in a row.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What a directory's clips are recognised by, lowercased.
+const VIDEO_EXTENSIONS: [&str; 10] = ["mp4", "mov", "m4v", "mkv", "avi", "mxf", "webm", "mts", "m2ts", "braw"];
+
+/// Every clip to track, in order: the files named, and the videos in each directory named, sorted by name.
+fn clips(inputs: &[PathBuf]) -> Result<Vec<PathBuf>, Error> {
+	let mut clips = vec![];
+	for input in inputs {
+		if !input.is_dir() {
+			clips.push(input.clone());
+			continue;
+		}
+		let entries = std::fs::read_dir(input).map_err(|e| usage(ErrorKind::Io, format!("Couldn't list {}: {e}", input.display())))?;
+		let mut found: Vec<PathBuf> = entries.filter_map(|entry| Some(entry.ok()?.path()))
+			.filter(|path| path.is_file() && is_video(path))
+			.collect();
+		found.sort();
+		clips.extend(found);
+	}
+	Ok(clips)
+}
+
+fn is_video(path: &Path) -> bool {
+	let extension = path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+	extension.is_some_and(|e| VIDEO_EXTENSIONS.contains(&e.as_str()))
+}
+
+/// The arguments for one clip: the shared ones with the clip in front and every output renamed for it.
+fn clip_args(clip: &Path, output_dir: Option<&Path>, tracking: &[String]) -> Result<Args, Error> {
+	let argv = [OsString::from("fiducial_track_video"), clip.as_os_str().to_owned()].into_iter().chain(tracking.iter().map(OsString::from));
+	let mut args = Args::try_parse_from(argv).map_err(Error::Usage)?;
+	if args.output.is_some() {
+		return Err(usage(ErrorKind::ArgumentConflict, "batch names each clip's output itself. Use --output-dir to say where they go."));
+	}
+	if !args.cameras.is_empty() {
+		return Err(usage(ErrorKind::ArgumentConflict, "batch tracks each clip on its own, so it can't take --camera."));
+	}
+	if args.serve.is_some() || args.osc.is_some() || args.websocket.is_some() {
+		return Err(usage(ErrorKind::ArgumentConflict, "Several clips at once can't share --serve, --osc, or --websocket."));
+	}
+	let stem = clip.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+	let mut name = format!("{stem}.{}", args.format.extension());
+	if let Some(compression) = args.compress {
+		name = format!("{name}.{}", compression.extension());
+	}
+	args.output = Some(match output_dir {
+		Some(directory) => directory.join(name),
+		None => clip.with_file_name(name),
+	});
+	for path in [&mut args.render_overlay, &mut args.debug_dump, &mut args.export_blender_tracks, &mut args.export_nuke,
+		&mut args.export_after_effects, &mut args.export_colmap, &mut args.build_map, &mut args.stats_file, &mut args.report] {
+		if let Some(path) = path.as_mut() {
+			*path = path_for_camera(path, Some(&stem));
+		}
+	}
+	Ok(args)
+}
+
+fn track_clip(clip: &Path, output_dir: Option<&Path>, tracking: &[String]) -> Result<(), Error> {
+	let args = clip_args(clip, output_dir, tracking)?;
+	let mut outputs = Outputs::default();
+	let (name, sink) = main_sink(&args)?;
+	outputs.add_primary(&name, sink);
+	run(&args, outputs)
+}
+
+/// A clip's size and modification time. A clip still being copied in changes from one look to the next.
+fn file_state(path: &Path) -> Option<(u64, SystemTime)> {
+	let metadata = std::fs::metadata(path).ok()?;
+	Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// Whether the clip's records were already written after it last changed, by an earlier watch that was stopped.
+fn already_tracked(clip: &Path, output_dir: Option<&Path>, tracking: &[String]) -> bool {
+	let Some(output) = clip_args(clip, output_dir, tracking).ok().and_then(|args| args.output) else {
+		return false;
+	};
+	matches!((file_state(clip), file_state(&output)), (Some((_, clip)), Some((_, output))) if output >= clip)
+}
+
+/// With `watch`, keep looking in the directories for new clips and track each once it stops growing. That never
+/// returns: stop it with Ctrl-C.
+pub fn batch(inputs: &[PathBuf], output_dir: Option<&Path>, jobs: usize, watch: bool, tracking: &[String]) -> Result<(), Error> {
+	let mut found = clips(inputs)?;
+	if found.is_empty() && !watch {
+		return Err(usage(ErrorKind::ValueValidation, "No videos to track."));
+	}
+	// Catch bad arguments before anything starts, rather than once per clip.
+	clip_args(found.first().map_or(Path::new("clip.mp4"), PathBuf::as_path), output_dir, tracking)?;
+	if let Some(directory) = output_dir {
+		std::fs::create_dir_all(directory).map_err(|e| usage(ErrorKind::Io, format!("Couldn't create {}: {e}", directory.display())))?;
+	}
+
+	let (sender, receiver) = mpsc::channel::<PathBuf>();
+	let receiver = Mutex::new(receiver);
+	let (tracked, failed) = (AtomicUsize::new(0), Mutex::new(vec![]));
+	std::thread::scope(|scope| -> Result<(), Error> {
+		for _ in 0..jobs.max(1) {
+			scope.spawn(|| {
+				// Only one worker waits on the queue at a time, and the rest wait for it.
+				while let Ok(clip) = receiver.lock().expect("Nothing panics holding it.").recv() {
+					eprintln!("Tracking {}.", clip.display());
+					match track_clip(&clip, output_dir, tracking) {
+						Ok(()) => {
+							tracked.fetch_add(1, Ordering::Relaxed);
+							eprintln!("Finished {}.", clip.display());
+						},
+						Err(e) => {
+							eprintln!("Failed to track {}: {e}", clip.display());
+							failed.lock().expect("Nothing panics holding it.").push(clip);
+						},
+					}
+				}
+			});
+		}
+		if !watch {
+			for clip in found.drain(..) {
+				sender.send(clip).expect("The workers are still waiting.");
+			}
+			drop(sender);
+			return Ok(());
+		}
+
+		eprintln!("Watching for new clips. Ctrl-C to stop.");
+		let mut queued = BTreeSet::new();
+		let mut last_seen = BTreeMap::new();
+		loop {
+			for clip in found.drain(..) {
+				if queued.contains(&clip) {
+					continue;
+				}
+				let state = file_state(&clip);
+				if state.is_some() && last_seen.get(&clip) == state.as_ref() {
+					if !already_tracked(&clip, output_dir, tracking) {
+						sender.send(clip.clone()).expect("The workers are still waiting.");
+					}
+					queued.insert(clip);
+				} else if let Some(state) = state {
+					last_seen.insert(clip, state);
+				}
+			}
+			std::thread::sleep(POLL_INTERVAL);
+			found = clips(inputs)?;
+		}
+	})?;
+
+	let (tracked, failed) = (tracked.into_inner(), failed.into_inner().expect("Nothing panics holding it."));
+	eprintln!("Tracked {tracked} of {} clips.", tracked + failed.len());
+	if !failed.is_empty() {
+		let names: Vec<_> = failed.iter().map(|clip| clip.display().to_string()).collect();
+		return Err(usage(ErrorKind::Io, format!("{} clips failed: {}", failed.len(), names.join(", "))));
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_clip_outputs() {
+		let tracking = ["ARUCO", "50", "--format", "msgpack", "--compress", "zstd", "--render-overlay", "previews/overlay.mp4"].map(String::from);
+		let args = clip_args(Path::new("day1/A001_C002.MOV"), Some(Path::new("tracks")), &tracking).unwrap();
+		assert_eq!(args.output, Some(PathBuf::from("tracks/A001_C002.msgpack.zst")));
+		assert_eq!(args.render_overlay, Some(PathBuf::from("previews/overlay_A001_C002.mp4")));
+		assert!(is_video(Path::new("day1/A001_C002.MOV")));
+		assert!(!is_video(Path::new("day1/notes.txt")));
+		assert!(clip_args(Path::new("a.mp4"), None, &["ARUCO", "50", "--output", "x.jsonl"].map(String::from)).is_err());
+	}
+}
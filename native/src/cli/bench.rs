@@ -0,0 +1,147 @@
+// The bench subcommand: run the whole pipeline on a clip a few times and report where the time goes per frame, so a
+// performance change or a choice of options (--preprocess, --rgb, a crop) can be measured instead of guessed at.
+//
+// With --synthetic it times the detector on rendered frames instead (see synthetic.rs), which leaves decoding out
+// and needs no footage at all.
+
+use crate::cli::{Args, Error, run, usage};
+use crate::detect::{FrameDetector, intrinsics_for};
+use crate::dictionary::parse_dictionaries;
+use crate::geometry::Pinhole;
+use crate::output::{Outputs, Sink};
+use crate::record::FrameRecord;
+use crate::stats::StageTimings;
+use crate::synthetic;
+use clap::Parser;
+use clap::error::ErrorKind;
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// The marker size for synthetic frames. It only changes the units of the poses.
+const SYNTHETIC_MARKER_MM: f32 = 50.0;
+
+pub fn parse_size(s: &str) -> Result<(u32, u32), String> {
+	let (width, height) = s.split_once(['x', 'X']).ok_or("Expected a size like 1920x1080.")?;
+	let parse = |v: &str| v.trim().parse::<u32>().ok().filter(|v| *v > 0).ok_or(format!("Bad size '{s}'."));
+	Ok((parse(width)?, parse(height)?))
+}
+
+#[derive(Default)]
+struct Totals {
+	frames: u32,
+	stages: StageTimings,
+	serialize: Duration,
+}
+
+impl Totals {
+	fn push(&mut self, record: &FrameRecord) {
+		// Triangulated records aren't frames we decoded.
+		if record.camera_id.as_deref() == Some("stereo") {
+			return;
+		}
+		self.frames += 1;
+		if let Some(timings) = &record.timings {
+			self.stages.add(timings);
+		}
+		let start = Instant::now();
+		std::hint::black_box(record.to_json());
+		self.serialize += start.elapsed();
+	}
+}
+
+struct BenchSink(Rc<RefCell<Totals>>);
+
+impl Sink for BenchSink {
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		self.0.borrow_mut().push(record);
+		Ok(())
+	}
+}
+
+fn time_synthetic(frames: usize, (width, height): (u32, u32)) -> Totals {
+	let dictionaries = parse_dictionaries(synthetic::DICTIONARY).expect("The synthetic dictionary is a known name.");
+	let mut detector = FrameDetector::new(dictionaries, SYNTHETIC_MARKER_MM, SYNTHETIC_MARKER_MM, None);
+	let f = width as f32;
+	let pinhole = Pinhole { width, height, fx: f, fy: f, cx: width as f32 / 2.0, cy: height as f32 / 2.0 };
+	let intrinsics = intrinsics_for(&pinhole);
+	let mut totals = Totals::default();
+	for frame in 0..frames {
+		let gray = synthetic::render(&pinhole, &synthetic::scene(4, 0, frame as f32 / 30.0), SYNTHETIC_MARKER_MM, 128);
+		let detect_start = Instant::now();
+		let found = detector.find(None, &gray, (0.0, 0.0), &pinhole);
+		let pose_start = Instant::now();
+		let mut record = detector.record(found, frame, frame as f64 / 30.0, &gray, (0.0, 0.0), &intrinsics, &pinhole, false);
+		record.timings = Some(StageTimings { detect: pose_start - detect_start, pose: pose_start.elapsed(), ..Default::default() });
+		totals.push(&record);
+	}
+	totals
+}
+
+/// Run the benchmark and print the report to stdout. `tracking` is the command line for a normal run, without the
+/// program name.
+pub fn bench(runs: u32, synthetic: Option<usize>, size: (u32, u32), tracking: &[String]) -> Result<(), Error> {
+	let args = match synthetic {
+		Some(_) => None,
+		None if tracking.is_empty() => return Err(usage(ErrorKind::MissingRequiredArgument, "bench needs a clip to track after '--', or --synthetic.")),
+		None => Some(Args::try_parse_from(std::iter::once("fiducial_track_video").chain(tracking.iter().map(String::as_str))).map_err(Error::Usage)?),
+	};
+	let mut results = vec![];
+	for idx in 0..runs.max(1) {
+		let (totals, elapsed) = match &args {
+			Some(args) => {
+				let totals = Rc::new(RefCell::new(Totals::default()));
+				let mut outputs = Outputs::default();
+				outputs.add("benchmark", Box::new(BenchSink(totals.clone())));
+				let start = Instant::now();
+				run(args, outputs)?;
+				(totals.take(), start.elapsed())
+			},
+			// Rendering takes longer than tracking, so only count the parts we timed.
+			None => {
+				let totals = time_synthetic(synthetic.unwrap_or_default(), size);
+				let elapsed = totals.stages.detect + totals.stages.pose + totals.serialize;
+				(totals, elapsed)
+			},
+		};
+		println!("Run {}: {} frames in {:.2}s, {:.1} fps.", idx + 1, totals.frames, elapsed.as_secs_f64(), totals.frames as f64 / elapsed.as_secs_f64());
+		results.push((totals, elapsed));
+	}
+
+	let frames: u32 = results.iter().map(|(totals, _)| totals.frames).sum();
+	if frames == 0 {
+		println!("No frames were tracked.");
+		return Ok(());
+	}
+	let mut stages = StageTimings::default();
+	let mut serialize = Duration::ZERO;
+	for (totals, _) in &results {
+		stages.add(&totals.stages);
+		serialize += totals.serialize;
+	}
+	let ms = |d: Duration| d.as_secs_f64() * 1000.0 / frames as f64;
+	println!("Mean per frame over {} runs:", results.len());
+	if synthetic.is_none() {
+		println!("  decode     {:8.3} ms", ms(stages.decode));
+		println!("  convert    {:8.3} ms", ms(stages.convert));
+	}
+	println!("  detect     {:8.3} ms", ms(stages.detect));
+	println!("  pose       {:8.3} ms", ms(stages.pose));
+	println!("  serialize  {:8.3} ms", ms(serialize));
+	let elapsed: Duration = results.iter().map(|(_, elapsed)| *elapsed).sum();
+	println!("Throughput: {:.1} fps.", frames as f64 / elapsed.as_secs_f64());
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_size() {
+		assert_eq!(parse_size("1920x1080"), Ok((1920, 1080)));
+		assert!(parse_size("1920").is_err());
+		assert!(parse_size("0x1080").is_err());
+	}
+}
@@ -0,0 +1,118 @@
+// Compressed record files. Hours of footage with a dozen markers in view is gigabytes of JSON lines, most of it the
+// same keys over and over, so it compresses about tenfold. Streams are compressed as they're written and the trailer
+// goes out when the sink is finished, so a disk that fills up at the last moment is an error instead of a file that
+// ends early.
+
+use crate::output::Finish;
+
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+	/// gzip, readable everywhere (zcat, Python's gzip module).
+	Gzip,
+	/// Zstandard, smaller and several times faster to write and read.
+	Zstd,
+}
+
+impl Compression {
+	/// What a file's extension asks for: .gz or .zst.
+	pub fn from_path(path: &Path) -> Option<Self> {
+		match path.extension()?.to_str()? {
+			"gz" => Some(Compression::Gzip),
+			"zst" => Some(Compression::Zstd),
+			_ => None,
+		}
+	}
+
+	/// The extension from_path takes back, without the dot.
+	pub fn extension(&self) -> &'static str {
+		match self {
+			Compression::Gzip => "gz",
+			Compression::Zstd => "zst",
+		}
+	}
+
+	pub fn writer(&self, inner: impl Finish + 'static) -> io::Result<Box<dyn Finish>> {
+		Ok(match self {
+			Compression::Gzip => Box::new(GzEncoder::new(inner, flate2::Compression::default())),
+			Compression::Zstd => Box::new(zstd::Encoder::new(inner, 0)?),
+		})
+	}
+
+	pub fn reader(&self, inner: impl Read + 'static) -> io::Result<Box<dyn Read>> {
+		Ok(match self {
+			Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(inner))),
+			Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::new(inner)?)),
+		})
+	}
+}
+
+impl<W: Finish> Finish for GzEncoder<W> {
+	fn finish(&mut self) -> io::Result<()> {
+		self.try_finish()?;
+		self.get_mut().finish()
+	}
+}
+
+impl<W: Finish> Finish for zstd::Encoder<'static, W> {
+	fn finish(&mut self) -> io::Result<()> {
+		self.do_finish()?;
+		self.get_mut().finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::cell::RefCell;
+	use std::rc::Rc;
+
+	// The writer takes its inner stream by value, so the test keeps a second handle on the bytes.
+	#[derive(Clone, Default)]
+	struct Shared(Rc<RefCell<Vec<u8>>>);
+
+	impl Write for Shared {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.0.borrow_mut().write(buf)
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	impl Finish for Shared {}
+
+	#[test]
+	fn test_compression_round_trip() {
+		let lines: String = (0..1000).map(|i| format!("{{\"frame\":{i},\"markers\":[]}}\n")).collect();
+		for compression in Compression::value_variants() {
+			let shared = Shared::default();
+			let mut writer = compression.writer(shared.clone()).unwrap();
+			writer.write_all(lines.as_bytes()).unwrap();
+			writer.finish().unwrap();
+			let compressed = shared.0.borrow().clone();
+			assert!(compressed.len() < lines.len() / 4, "{compression:?} wrote {} bytes", compressed.len());
+
+			let mut decompressed = String::new();
+			compression.reader(io::Cursor::new(compressed)).unwrap().read_to_string(&mut decompressed).unwrap();
+			assert_eq!(decompressed, lines, "{compression:?}");
+		}
+	}
+
+	#[test]
+	fn test_compression_from_path() {
+		assert_eq!(Compression::from_path(Path::new("take1.jsonl.gz")), Some(Compression::Gzip));
+		assert_eq!(Compression::from_path(Path::new("take1.msgpack.zst")), Some(Compression::Zstd));
+		assert_eq!(Compression::from_path(Path::new("take1.jsonl")), None);
+		for compression in Compression::value_variants() {
+			assert_eq!(Compression::from_path(Path::new(&format!("take1.jsonl.{}", compression.extension()))), Some(*compression));
+		}
+	}
+}
@@ -0,0 +1,76 @@
+// Images of what the detector was looking at on frames where it came up short, to help with tuning.
+//
+// For each such frame we write, into the dump directory:
+//   frame_000123_gray.png        the luma the detector was given (after rotation, crop, and tone-mapping)
+//   frame_000123_threshold.png   a local-mean adaptive threshold like the detector's first stage
+//   frame_000123_candidates.png  dark regions that could be markers, with a rough quad fitted to each
+//   frame_000123_markers.png     the markers that were accepted, if any
+// aruco3 doesn't hand back its own threshold image or rejected candidates, so the middle two are our approximation
+// of them (see quads.rs). They're still good for spotting a marker that's too small, too blurry, or washed out.
+
+use crate::cli::overlay::{draw_line, draw_record};
+use crate::quads::{adaptive_threshold, candidate_quads};
+use crate::record::FrameRecord;
+use image::{DynamicImage, GrayImage, Rgb};
+use std::collections::BTreeSet;
+use std::io;
+use std::path::PathBuf;
+
+const CANDIDATE: Rgb<u8> = Rgb([255, 128, 0]);
+
+pub struct DebugDumper {
+	directory: PathBuf,
+	/// Markers from the previous frame of each camera, so losing one counts as a failure.
+	previous: Vec<(Option<String>, BTreeSet<usize>)>,
+}
+
+impl DebugDumper {
+	pub fn new(directory: PathBuf) -> io::Result<Self> {
+		std::fs::create_dir_all(&directory)?;
+		Ok(DebugDumper { directory, previous: vec![] })
+	}
+
+	/// Dump the frame if nothing was found or a marker from the previous frame went missing.
+	/// `record` should have its corners relative to `gray`, i.e. crop-local.
+	pub fn dump_if_failed(&mut self, gray: &GrayImage, record: &FrameRecord, marker_size_mm: f32) -> io::Result<bool> {
+		let found: BTreeSet<usize> = record.markers.iter().map(|m| m.marker_id).collect();
+		let idx = match self.previous.iter().position(|(camera_id, _)| *camera_id == record.camera_id) {
+			Some(idx) => idx,
+			None => {
+				self.previous.push((record.camera_id.clone(), BTreeSet::new()));
+				self.previous.len() - 1
+			},
+		};
+		let previous = &mut self.previous[idx].1;
+		let failed = found.is_empty() || !previous.is_subset(&found);
+		*previous = found;
+		if !failed {
+			return Ok(false);
+		}
+
+		let prefix = match &record.camera_id {
+			Some(camera_id) => format!("{camera_id}_frame_{:06}", record.frame_id),
+			None => format!("frame_{:06}", record.frame_id),
+		};
+		let save = |name: &str, img: DynamicImage| img.save(self.directory.join(format!("{prefix}_{name}.png"))).map_err(io::Error::other);
+
+		save("gray", DynamicImage::ImageLuma8(gray.clone()))?;
+		// Roughly the window the detector uses relative to the frame, with a small fixed offset.
+		let threshold = adaptive_threshold(gray, (gray.width().max(gray.height()) / 100).max(3), 7.0);
+		let quads = candidate_quads(&threshold, 8);
+		save("threshold", DynamicImage::ImageLuma8(threshold))?;
+
+		let mut candidates = DynamicImage::ImageLuma8(gray.clone()).to_rgb8();
+		for quad in quads {
+			for idx in 0..4 {
+				draw_line(&mut candidates, quad[idx], quad[(idx + 1) % 4], 2, CANDIDATE);
+			}
+		}
+		save("candidates", DynamicImage::ImageRgb8(candidates))?;
+
+		let mut markers = DynamicImage::ImageLuma8(gray.clone()).to_rgb8();
+		draw_record(&mut markers, record, marker_size_mm);
+		save("markers", DynamicImage::ImageRgb8(markers))?;
+		Ok(true)
+	}
+}
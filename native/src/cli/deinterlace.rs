@@ -0,0 +1,83 @@
+// A one-filter ffmpeg graph that sits between the decoder and the scaler.
+// Interlaced frames have comb artifacts along every moving edge, which is exactly where the marker borders are.
+
+use ffmpeg_the_third as ffmpeg;
+
+use crate::ffmpeg::codec::FieldOrder;
+use crate::ffmpeg::filter;
+use crate::ffmpeg::format::Pixel;
+use crate::ffmpeg::util::frame::video::Video;
+use clap::ValueEnum;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeinterlaceMode {
+	/// Deinterlace with bwdif if the stream says it's interlaced.
+	Auto,
+	/// Never deinterlace.
+	Off,
+	/// Always run yadif.
+	Yadif,
+	/// Always run bwdif.
+	Bwdif,
+}
+
+impl DeinterlaceMode {
+	/// The filter to use for a stream with the given field order, or None if we should leave it alone.
+	pub fn filter_name(&self, field_order: FieldOrder) -> Option<&'static str> {
+		match self {
+			DeinterlaceMode::Auto => match field_order {
+				FieldOrder::Progressive | FieldOrder::Unknown => None,
+				_ => Some("bwdif"),
+			},
+			DeinterlaceMode::Off => None,
+			DeinterlaceMode::Yadif => Some("yadif"),
+			DeinterlaceMode::Bwdif => Some("bwdif"),
+		}
+	}
+}
+
+pub struct Deinterlacer {
+	graph: filter::Graph,
+}
+
+impl Deinterlacer {
+	/// `format` is the frames' as they'll be pushed, which after a hardware download isn't the decoder's.
+	pub fn new(filter_name: &str, decoder: &ffmpeg::decoder::Video, format: Pixel, time_base: ffmpeg::Rational, only_flagged_frames: bool) -> Result<Self, ffmpeg::Error> {
+		let aspect = decoder.aspect_ratio();
+		let aspect = if aspect.numerator() > 0 { aspect } else { ffmpeg::Rational::new(1, 1) };
+		let source_args = format!(
+			"video_size={}x{}:pix_fmt={}:time_base={}/{}:pixel_aspect={}/{}",
+			decoder.width(),
+			decoder.height(),
+			ffmpeg::ffi::AVPixelFormat::from(format) as i32,
+			time_base.numerator(),
+			time_base.denominator(),
+			aspect.numerator(),
+			aspect.denominator(),
+		);
+
+		let mut graph = filter::Graph::new();
+		graph.add(&filter::find("buffer").ok_or(ffmpeg::Error::FilterNotFound)?, "in", &source_args)?;
+		graph.add(&filter::find("buffersink").ok_or(ffmpeg::Error::FilterNotFound)?, "out", "")?;
+		// send_frame keeps one output frame per input frame so our frame indices still line up with the source.
+		let deint = if only_flagged_frames { "interlaced" } else { "all" };
+		graph.output("in", 0)?.input("out", 0)?.parse(&format!("{filter_name}=mode=send_frame:parity=auto:deint={deint}"))?;
+		graph.validate()?;
+
+		Ok(Deinterlacer { graph })
+	}
+
+	pub fn push(&mut self, frame: &Video) -> Result<(), ffmpeg::Error> {
+		self.graph.get("in").expect("Filter graph is missing its source.").source().add(frame)
+	}
+
+	/// Signal the end of the stream so the filter gives up the frames it's holding onto.
+	pub fn flush(&mut self) -> Result<(), ffmpeg::Error> {
+		self.graph.get("in").expect("Filter graph is missing its source.").source().flush()
+	}
+
+	/// Returns true if a filtered frame was written into `frame`.
+	pub fn pull(&mut self, frame: &mut Video) -> bool {
+		self.graph.get("out").expect("Filter graph is missing its sink.").sink().frame(frame).is_ok()
+	}
+}
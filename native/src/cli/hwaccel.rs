@@ -0,0 +1,112 @@
+// Hardware decoding: the GPU's video engine decodes and each frame is copied back to memory for the scaler. H.265
+// and AV1 are slow to decode in software, enough to be most of a run, and the decode engine sits idle otherwise.
+// ffmpeg-the-third doesn't wrap any of this, so it goes through ffi the way ffmpeg's doc/examples/hw_decode.c does.
+
+use ffmpeg_the_third as ffmpeg;
+
+use crate::ffmpeg::ffi;
+use crate::ffmpeg::util::frame::video::Video;
+use clap::ValueEnum;
+use std::ffi::CStr;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HwAccel {
+	/// Whichever of the others this machine can decode the stream with, or software if none.
+	Auto,
+	/// NVIDIA GPUs, through CUDA.
+	Nvdec,
+	/// Intel and AMD GPUs on Linux.
+	Vaapi,
+	/// macOS.
+	Videotoolbox,
+}
+
+impl HwAccel {
+	fn device_types(&self) -> &'static [ffi::AVHWDeviceType] {
+		use ffi::AVHWDeviceType::*;
+		match self {
+			HwAccel::Auto if cfg!(target_os = "macos") => &[AV_HWDEVICE_TYPE_VIDEOTOOLBOX],
+			HwAccel::Auto => &[AV_HWDEVICE_TYPE_CUDA, AV_HWDEVICE_TYPE_VAAPI],
+			HwAccel::Nvdec => &[AV_HWDEVICE_TYPE_CUDA],
+			HwAccel::Vaapi => &[AV_HWDEVICE_TYPE_VAAPI],
+			HwAccel::Videotoolbox => &[AV_HWDEVICE_TYPE_VIDEOTOOLBOX],
+		}
+	}
+}
+
+fn device_name(device_type: ffi::AVHWDeviceType) -> String {
+	let name = unsafe { ffi::av_hwdevice_get_type_name(device_type) };
+	if name.is_null() { "unknown".to_string() } else { unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned() }
+}
+
+/// Whether the codec can decode on this kind of device given just the device, which is all we set up.
+fn supports(codec: *const ffi::AVCodec, device_type: ffi::AVHWDeviceType) -> bool {
+	(0..).map_while(|idx| unsafe { ffi::avcodec_get_hw_config(codec, idx).as_ref() }).any(|config| {
+		config.device_type == device_type && config.methods & ffi::AV_CODEC_HW_CONFIG_METHOD_HW_DEVICE_CTX as i32 != 0
+	})
+}
+
+/// Downloads frames from the device a decoder was attached to.
+pub struct HwDecoder {
+	/// Reused from frame to frame, unless something downstream is still holding the last one.
+	downloaded: Video,
+}
+
+impl HwDecoder {
+	/// Give the decoder's context a device to decode on, before the decoder is opened. None means decode in software,
+	/// because nothing asked for is here or none of it handles this codec. Only `auto` falls back quietly.
+	pub fn attach(accel: HwAccel, context: &mut ffmpeg::codec::context::Context, verbose: bool) -> Option<Self> {
+		let codec = ffmpeg::decoder::find(context.id())?;
+		for &device_type in accel.device_types() {
+			let name = device_name(device_type);
+			if !supports(unsafe { codec.as_ptr() }, device_type) {
+				if accel != HwAccel::Auto {
+					eprintln!("{} can't be decoded with {name}.", codec.name());
+				}
+				continue;
+			}
+			let mut device = std::ptr::null_mut();
+			let err = unsafe { ffi::av_hwdevice_ctx_create(&mut device, device_type, std::ptr::null(), std::ptr::null_mut(), 0) };
+			if err < 0 {
+				if accel != HwAccel::Auto {
+					eprintln!("Couldn't open a {name} device: {}", ffmpeg::Error::from(err));
+				}
+				continue;
+			}
+			// The context takes our reference and frees the device with itself.
+			unsafe { (*context.as_mut_ptr()).hw_device_ctx = device };
+			if verbose {
+				eprintln!("Decoding {} with {name}.", codec.name());
+			}
+			return Some(HwDecoder { downloaded: Video::empty() });
+		}
+		if accel != HwAccel::Auto || verbose {
+			eprintln!("Decoding in software.");
+		}
+		None
+	}
+
+	/// The frame in memory: downloaded if it's on the device, or as it was if the decoder fell back to software,
+	/// which it can do on its own for a stream the hardware turns down partway.
+	pub fn download<'a>(&'a mut self, frame: &'a Video) -> Result<&'a Video, ffmpeg::Error> {
+		unsafe {
+			if (*frame.as_ptr()).hw_frames_ctx.is_null() {
+				return Ok(frame);
+			}
+			let downloaded = self.downloaded.as_mut_ptr();
+			if ffi::av_frame_is_writable(downloaded) == 0 {
+				ffi::av_frame_unref(downloaded);
+			}
+			let err = ffi::av_hwframe_transfer_data(downloaded, frame.as_ptr(), 0);
+			if err < 0 {
+				return Err(ffmpeg::Error::from(err));
+			}
+			// Timestamps and side data (the display matrix, for rotation) don't come across with the pixels.
+			let err = ffi::av_frame_copy_props(downloaded, frame.as_ptr());
+			if err < 0 {
+				return Err(ffmpeg::Error::from(err));
+			}
+		}
+		Ok(&self.downloaded)
+	}
+}
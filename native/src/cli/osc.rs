@@ -0,0 +1,119 @@
+// Open Sound Control output for live performance tools (TouchDesigner, VDMX, Blender's OSC addons).
+// OSC is simple enough that we encode it by hand: one bundle per frame, a few messages per marker.
+//
+// Addresses, with an optional /<camera_id> after /fiducial in multi-camera runs:
+//   /fiducial/frame                   i frame_id, f timestamp
+//   /fiducial/<marker_id>/translation f x, f y, f z
+//   /fiducial/<marker_id>/quaternion  f w, f x, f y, f z
+//   /fiducial/<marker_id>/error       f error
+
+use crate::geometry::mat3_to_quat;
+use crate::output::Sink;
+use crate::record::FrameRecord;
+use std::io;
+use std::net::UdpSocket;
+
+enum OscArg {
+	Int(i32),
+	Float(f32),
+}
+
+fn push_padded_str(out: &mut Vec<u8>, s: &str) {
+	out.extend_from_slice(s.as_bytes());
+	// Strings are null terminated and padded to a multiple of four bytes.
+	let padding = 4 - (s.len() % 4);
+	out.extend(std::iter::repeat_n(0u8, padding));
+}
+
+fn encode_message(address: &str, args: &[OscArg]) -> Vec<u8> {
+	let mut out = Vec::with_capacity(64);
+	push_padded_str(&mut out, address);
+	let tags: String = std::iter::once(',').chain(args.iter().map(|a| match a {
+		OscArg::Int(_) => 'i',
+		OscArg::Float(_) => 'f',
+	})).collect();
+	push_padded_str(&mut out, &tags);
+	for a in args {
+		match a {
+			OscArg::Int(v) => out.extend_from_slice(&v.to_be_bytes()),
+			OscArg::Float(v) => out.extend_from_slice(&v.to_be_bytes()),
+		}
+	}
+	out
+}
+
+fn encode_bundle(messages: &[Vec<u8>]) -> Vec<u8> {
+	let mut out = Vec::with_capacity(16 + messages.iter().map(|m| m.len() + 4).sum::<usize>());
+	push_padded_str(&mut out, "#bundle");
+	// A time tag of 1 means "immediately".
+	out.extend_from_slice(&1u64.to_be_bytes());
+	for m in messages {
+		out.extend_from_slice(&(m.len() as i32).to_be_bytes());
+		out.extend_from_slice(m);
+	}
+	out
+}
+
+pub fn encode_frame(record: &FrameRecord) -> Vec<u8> {
+	let prefix = match &record.camera_id {
+		Some(camera_id) => format!("/fiducial/{camera_id}"),
+		None => "/fiducial".to_string(),
+	};
+	let mut messages = vec![encode_message(&format!("{prefix}/frame"), &[OscArg::Int(record.frame_id as i32), OscArg::Float(record.timestamp as f32)])];
+	for m in &record.markers {
+		let Some(pose) = m.best_pose() else {
+			continue;
+		};
+		let t = pose.translation;
+		let q = mat3_to_quat(&pose.rotation);
+		let address = format!("{prefix}/{}", m.marker_id);
+		messages.push(encode_message(&format!("{address}/translation"), &[OscArg::Float(t[0]), OscArg::Float(t[1]), OscArg::Float(t[2])]));
+		messages.push(encode_message(&format!("{address}/quaternion"), &[OscArg::Float(q[0]), OscArg::Float(q[1]), OscArg::Float(q[2]), OscArg::Float(q[3])]));
+		messages.push(encode_message(&format!("{address}/error"), &[OscArg::Float(pose.error)]));
+	}
+	encode_bundle(&messages)
+}
+
+pub struct OscSink {
+	socket: UdpSocket,
+}
+
+impl OscSink {
+	pub fn connect(address: &str) -> io::Result<Self> {
+		let socket = UdpSocket::bind("0.0.0.0:0")?;
+		socket.connect(address)?;
+		Ok(OscSink { socket })
+	}
+}
+
+impl Sink for OscSink {
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		match self.socket.send(&encode_frame(record)) {
+			// Nobody listening yet is normal for live tools. Keep sending.
+			Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(()),
+			other => other.map(|_| ()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_encode_message_padding() {
+		let message = encode_message("/a", &[OscArg::Float(1.0)]);
+		// "/a\0\0" + ",f\0\0" + 4 bytes of float.
+		assert_eq!(message.len(), 12);
+		assert_eq!(&message[0..4], b"/a\0\0");
+		assert_eq!(&message[4..8], b",f\0\0");
+		assert_eq!(&message[8..12], &1.0f32.to_be_bytes());
+	}
+
+	#[test]
+	fn test_encode_bundle_header() {
+		let bundle = encode_frame(&FrameRecord::default());
+		assert_eq!(&bundle[0..8], b"#bundle\0");
+		assert_eq!(&bundle[8..16], &1u64.to_be_bytes());
+	}
+}
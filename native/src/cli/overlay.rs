@@ -0,0 +1,237 @@
+// A preview video with the detections drawn on top, for checking a track by eye before importing it.
+// Outlines are green with the first corner marked, ids are drawn in the middle, and the best pose's axes are
+// projected back in red (X), green (Y), and blue (Z, out of the marker).
+
+use crate::ffmpeg::{self, codec, encoder, format, Packet, Rational};
+use crate::ffmpeg::format::Pixel;
+use crate::ffmpeg::software::scaling::{context::Context, flag::Flags};
+use crate::ffmpeg::util::frame::video::Video;
+use crate::geometry::{self, Pinhole};
+use crate::record::FrameRecord;
+use image::{Rgb, RgbImage};
+use std::path::{Path, PathBuf};
+
+const OUTLINE: Rgb<u8> = Rgb([0, 255, 0]);
+const FIRST_CORNER: Rgb<u8> = Rgb([255, 0, 255]);
+const TEXT: Rgb<u8> = Rgb([255, 255, 0]);
+const AXES: [Rgb<u8>; 3] = [Rgb([255, 0, 0]), Rgb([0, 255, 0]), Rgb([0, 0, 255])];
+
+// 3x5 pixel digits, one row per byte with the low three bits used.
+const DIGITS: [[u8; 5]; 10] = [
+	[0b111, 0b101, 0b101, 0b101, 0b111],
+	[0b010, 0b110, 0b010, 0b010, 0b111],
+	[0b111, 0b001, 0b111, 0b100, 0b111],
+	[0b111, 0b001, 0b111, 0b001, 0b111],
+	[0b101, 0b101, 0b111, 0b001, 0b001],
+	[0b111, 0b100, 0b111, 0b001, 0b111],
+	[0b111, 0b100, 0b111, 0b101, 0b111],
+	[0b111, 0b001, 0b010, 0b010, 0b010],
+	[0b111, 0b101, 0b111, 0b101, 0b111],
+	[0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+fn put(img: &mut RgbImage, x: i64, y: i64, color: Rgb<u8>) {
+	if x >= 0 && y >= 0 && (x as u32) < img.width() && (y as u32) < img.height() {
+		img.put_pixel(x as u32, y as u32, color);
+	}
+}
+
+fn draw_dot(img: &mut RgbImage, (x, y): (f32, f32), radius: i64, color: Rgb<u8>) {
+	let (cx, cy) = (x.round() as i64, y.round() as i64);
+	for dy in -radius..=radius {
+		for dx in -radius..=radius {
+			put(img, cx + dx, cy + dy, color);
+		}
+	}
+}
+
+/// A line a few pixels thick, so it survives the encoder at preview bitrates.
+pub fn draw_line(img: &mut RgbImage, from: (f32, f32), to: (f32, f32), thickness: i64, color: Rgb<u8>) {
+	let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil().max(1.0);
+	// Don't spend forever on a line projected from a point just in front of the camera.
+	if !steps.is_finite() || steps > 20_000.0 {
+		return;
+	}
+	for i in 0..=steps as usize {
+		let t = i as f32 / steps;
+		draw_dot(img, (geometry::lerp(from.0, to.0, t), geometry::lerp(from.1, to.1, t)), thickness / 2, color);
+	}
+}
+
+fn draw_number(img: &mut RgbImage, value: usize, (x, y): (f32, f32), scale: i64, color: Rgb<u8>) {
+	let text = value.to_string();
+	let width = text.len() as i64 * 4 * scale - scale;
+	let (left, top) = (x.round() as i64 - width / 2, y.round() as i64 - 5 * scale / 2);
+	for (idx, digit) in text.bytes().enumerate() {
+		let glyph = DIGITS[(digit - b'0') as usize];
+		for (row, bits) in glyph.iter().enumerate() {
+			for col in 0..3 {
+				if bits & (0b100 >> col) == 0 {
+					continue;
+				}
+				for sy in 0..scale {
+					for sx in 0..scale {
+						put(img, left + (idx as i64 * 4 + col) * scale + sx, top + row as i64 * scale + sy, color);
+					}
+				}
+			}
+		}
+	}
+}
+
+/// Draw one frame's detections. Corners need to be in full-frame pixel coordinates.
+pub fn draw_record(img: &mut RgbImage, record: &FrameRecord, marker_size_mm: f32) {
+	let thickness = (img.width().max(img.height()) / 500).max(2) as i64;
+	for m in &record.markers {
+		for idx in 0..4 {
+			draw_line(img, m.corners[idx], m.corners[(idx + 1) % 4], thickness, OUTLINE);
+		}
+		draw_dot(img, m.corners[0], thickness * 2, FIRST_CORNER);
+
+		if let (Some(pinhole), Some(pose)) = (&record.intrinsics, m.best_pose()) {
+			draw_axes(img, pinhole, &pose.rotation, &pose.translation, marker_size_mm, thickness);
+		}
+
+		let center = m.corners.iter().fold((0.0, 0.0), |acc, c| (acc.0 + c.0 / 4.0, acc.1 + c.1 / 4.0));
+		draw_number(img, m.marker_id, center, thickness * 2, TEXT);
+	}
+}
+
+fn draw_axes(img: &mut RgbImage, pinhole: &Pinhole, rotation: &geometry::Mat3, translation: &geometry::Vec3, marker_size_mm: f32, thickness: i64) {
+	if translation[2] <= 0.0 {
+		return;
+	}
+	let origin = pinhole.project(translation);
+	let length = marker_size_mm / 2.0;
+	for (axis, color) in AXES.iter().enumerate() {
+		let mut tip = [0.0; 3];
+		tip[axis] = length;
+		let tip = geometry::add(translation, &geometry::mat_mul_vec(rotation, &tip));
+		if tip[2] > 0.0 {
+			draw_line(img, origin, pinhole.project(&tip), thickness, *color);
+		}
+	}
+}
+
+/// In multi-camera runs each camera gets its own preview: out.mp4 becomes out_left.mp4.
+pub fn path_for_camera(path: &Path, camera_id: Option<&str>) -> PathBuf {
+	let Some(camera_id) = camera_id else {
+		return path.to_path_buf();
+	};
+	let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+	let name = match path.extension() {
+		Some(extension) => format!("{stem}_{camera_id}.{}", extension.to_string_lossy()),
+		None => format!("{stem}_{camera_id}"),
+	};
+	path.with_file_name(name)
+}
+
+/// Encodes annotated frames to a video file. The container comes from the file extension.
+pub struct OverlayEncoder {
+	output: format::context::Output,
+	encoder: encoder::Video,
+	scaler: Context,
+	time_base: Rational,
+	stream_time_base: Rational,
+	rgb: Video,
+	yuv: Video,
+	last_pts: Option<i64>,
+}
+
+impl OverlayEncoder {
+	pub fn new(path: &Path, width: u32, height: u32, time_base: Rational, frame_rate: Option<Rational>) -> Result<Self, ffmpeg::Error> {
+		let mut output = format::output(path)?;
+		let codec = encoder::find(codec::Id::H264).or_else(|| encoder::find(codec::Id::MPEG4)).ok_or(ffmpeg::Error::EncoderNotFound)?;
+		let global_header = output.format().flags().contains(format::Flags::GLOBAL_HEADER);
+		// 4:2:0 needs even dimensions.
+		let (encoded_width, encoded_height) = (width & !1, height & !1);
+
+		let mut stream = output.add_stream(codec)?;
+		let mut video = codec::context::Context::new_with_codec(codec).encoder().video()?;
+		video.set_width(encoded_width);
+		video.set_height(encoded_height);
+		video.set_format(Pixel::YUV420P);
+		video.set_time_base(time_base);
+		video.set_frame_rate(frame_rate);
+		if global_header {
+			video.set_flags(codec::Flags::GLOBAL_HEADER);
+		}
+		let encoder = video.open_as(codec)?;
+		stream.set_parameters(&encoder);
+		stream.set_time_base(time_base);
+		output.write_header()?;
+		let stream_time_base = output.stream(0).ok_or(ffmpeg::Error::StreamNotFound)?.time_base();
+
+		let scaler = Context::get(Pixel::RGB24, width, height, Pixel::YUV420P, encoded_width, encoded_height, Flags::BILINEAR)?;
+		Ok(OverlayEncoder {
+			output,
+			encoder,
+			scaler,
+			time_base,
+			stream_time_base,
+			rgb: Video::new(Pixel::RGB24, width, height),
+			yuv: Video::empty(),
+			last_pts: None,
+		})
+	}
+
+	pub fn write(&mut self, img: &RgbImage, pts: Option<i64>) -> Result<(), ffmpeg::Error> {
+		let stride = self.rgb.stride(0);
+		let row_bytes = img.width() as usize * 3;
+		let data = self.rgb.data_mut(0);
+		for (y, row) in img.as_raw().chunks_exact(row_bytes).enumerate() {
+			data[y * stride..y * stride + row_bytes].copy_from_slice(row);
+		}
+		self.scaler.run(&self.rgb, &mut self.yuv)?;
+		// Muxers reject repeated timestamps, which duplicated frames in the source would otherwise give us.
+		let pts = match (pts, self.last_pts) {
+			(Some(pts), Some(last)) if pts <= last => Some(last + 1),
+			(None, Some(last)) => Some(last + 1),
+			(pts, _) => pts,
+		};
+		self.last_pts = pts;
+		self.yuv.set_pts(pts);
+		self.encoder.send_frame(&self.yuv)?;
+		self.write_packets()
+	}
+
+	fn write_packets(&mut self) -> Result<(), ffmpeg::Error> {
+		let mut packet = Packet::empty();
+		while self.encoder.receive_packet(&mut packet).is_ok() {
+			packet.set_stream(0);
+			packet.rescale_ts(self.time_base, self.stream_time_base);
+			packet.write_interleaved(&mut self.output)?;
+		}
+		Ok(())
+	}
+
+	pub fn finish(&mut self) -> Result<(), ffmpeg::Error> {
+		self.encoder.send_eof()?;
+		self.write_packets()?;
+		self.output.write_trailer()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::record::MarkerRecord;
+
+	#[test]
+	fn test_draw_record_stays_in_bounds() {
+		let mut img = RgbImage::new(64, 48);
+		let record = FrameRecord {
+			markers: vec![MarkerRecord { marker_id: 42, corners: [(-10.0, 5.0), (30.0, 5.0), (30.0, 40.0), (-10.0, 40.0)], ..Default::default() }],
+			..Default::default()
+		};
+		draw_record(&mut img, &record, 50.0);
+		assert_eq!(*img.get_pixel(30, 20), OUTLINE);
+		assert_eq!(*img.get_pixel(63, 0), Rgb([0, 0, 0]));
+	}
+
+	#[test]
+	fn test_path_for_camera() {
+		assert_eq!(path_for_camera(Path::new("out/preview.mp4"), Some("left")), PathBuf::from("out/preview_left.mp4"));
+		assert_eq!(path_for_camera(Path::new("preview.mp4"), None), PathBuf::from("preview.mp4"));
+	}
+}
@@ -0,0 +1,803 @@
+// The per-video tracking loop: decode, convert, detect, and hand frame records back to the caller.
+// This is lifting heavily from https://github.com/shssoichiro/ffmpeg-the-third/blob/master/examples/dump-frames.rs
+use ffmpeg_the_third as ffmpeg;
+
+use aruco3::{Detection, CameraIntrinsics};
+use crate::cli::{Args, ToneMap};
+use crate::cli::debug_dump::DebugDumper;
+use crate::cli::deinterlace::{DeinterlaceMode, Deinterlacer};
+use crate::cli::hwaccel::HwDecoder;
+use crate::ffmpeg::format::{input, Pixel};
+use crate::ffmpeg::media::Type;
+use crate::ffmpeg::util::color::TransferCharacteristic;
+use crate::ffmpeg::software::scaling::{context::Context, flag::Flags};
+use crate::ffmpeg::util::frame::side_data::Type as SideDataType;
+use crate::ffmpeg::util::frame::video::Video;
+use crate::detect::{self, FrameDetector};
+use crate::focal::{self, FocalFit};
+use crate::frame::{FrameBuffer, FrameView};
+use crate::geometry::Pinhole;
+use crate::intrinsics_track::IntrinsicsTrack;
+use crate::mask::MaskSource;
+use crate::occlusion::{self, OcclusionBridge};
+use crate::preprocess::Preprocess;
+use crate::cli::overlay::{OverlayEncoder, draw_record, path_for_camera};
+use crate::record::{FrameRecord, MarkerRecord, PoseRecord};
+use crate::rolling_shutter::RollingShutter;
+use crate::stats::StageTimings;
+use crate::stop::StopCondition;
+use crate::timecode::{Timecode, TimecodeStart, parse_timecode};
+use crate::timing::{FrameClock, Resampler};
+use crate::tonemap::{Gray16Image, ToneMapper, Transfer};
+use image::{self, DynamicImage, GrayImage};
+use std::cell::Cell;
+use std::sync::mpsc;
+use std::time::Instant;
+
+/// Everything that can differ between the cameras of a multi-camera run.
+#[derive(Clone, Debug)]
+pub struct Camera {
+	/// Only set when there's more than one camera, in which case every record is tagged with it.
+	pub id: Option<String>,
+	pub filename: String,
+	pub focal_length_mm: f32,
+	pub sensor_size_mm: Option<f32>,
+	pub fov_h_radians: Option<f32>,
+	/// Intrinsics by frame for a zoom lens, in place of the fixed lens settings above.
+	pub intrinsics_track: Option<IntrinsicsTrack>,
+	/// Overrides the timecode embedded in the file.
+	pub timecode_start: Option<TimecodeStart>,
+	/// Added to every frame index so cameras that started recording at different times line up. With --target-fps
+	/// it's taken in seconds instead, see Resampler::new.
+	pub frame_offset: i64,
+	/// A lens fit to the footage itself by --refine-focal, in place of the fixed lens settings.
+	pub refined: Option<Pinhole>,
+	/// Where not to look for markers. See mask.rs.
+	pub mask: Option<MaskSource>,
+}
+
+/// One frame from sample_frames.
+pub struct Sample {
+	pub gray: GrayImage,
+	/// Where `gray` starts in the upright frame, when it's cropped.
+	pub offset: (f32, f32),
+	/// The upright frame's size.
+	pub width: u32,
+	pub height: u32,
+}
+
+/// Clockwise quarter-turns needed to display a frame upright.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Rotation {
+	None,
+	Clockwise90,
+	Clockwise180,
+	Clockwise270,
+}
+
+impl Rotation {
+	/// Build from a counter-clockwise angle in degrees, as stored in the display matrix, snapping to the nearest quarter turn.
+	fn from_ccw_degrees(ccw: f64) -> Self {
+		// The display matrix says how the stored frame is rotated, so we undo it by turning the other way.
+		match ((-ccw / 90.0).round() as i32).rem_euclid(4) {
+			1 => Rotation::Clockwise90,
+			2 => Rotation::Clockwise180,
+			3 => Rotation::Clockwise270,
+			_ => Rotation::None,
+		}
+	}
+
+	/// Read the rotation for a decoded frame, falling back to the stream's legacy 'rotate' tag.
+	pub(super) fn from_frame(frame: &Video, rotate_tag: Option<&str>) -> Self {
+		if let Some(side_data) = frame.side_data(SideDataType::DisplayMatrix)
+			&& let Some(ccw) = display_matrix_rotation(side_data.data()) {
+			return Rotation::from_ccw_degrees(ccw);
+		}
+		// The older 'rotate' tag is clockwise, so flip it into the display matrix convention.
+		match rotate_tag.and_then(|t| t.trim().parse::<f64>().ok()) {
+			Some(cw) => Rotation::from_ccw_degrees(-cw),
+			None => Rotation::None,
+		}
+	}
+
+	pub(super) fn quarter_turns(&self) -> u32 {
+		match self {
+			Rotation::None => 0,
+			Rotation::Clockwise90 => 1,
+			Rotation::Clockwise180 => 2,
+			Rotation::Clockwise270 => 3,
+		}
+	}
+
+	fn apply(&self, img: DynamicImage) -> DynamicImage {
+		match self {
+			Rotation::None => img,
+			Rotation::Clockwise90 => img.rotate90(),
+			Rotation::Clockwise180 => img.rotate180(),
+			Rotation::Clockwise270 => img.rotate270(),
+		}
+	}
+}
+
+/// Decode the counter-clockwise rotation in degrees from a raw 3x3 display matrix.
+/// This mirrors av_display_rotation_get: the first two columns are 16.16 fixed point.
+fn display_matrix_rotation(data: &[u8]) -> Option<f64> {
+	if data.len() < 9 * 4 {
+		return None;
+	}
+	let m: Vec<f64> = data.chunks_exact(4).take(9).map(|b| i32::from_ne_bytes([b[0], b[1], b[2], b[3]]) as f64 / 65536.0).collect();
+	let scale_0 = m[0].hypot(m[3]);
+	let scale_1 = m[1].hypot(m[4]);
+	if scale_0 == 0.0 || scale_1 == 0.0 {
+		return None;
+	}
+	Some(-(m[1] / scale_1).atan2(m[0] / scale_0).to_degrees())
+}
+
+/// The detector the arguments ask for, with the --backend in place of aruco3 if it isn't the built-in one.
+fn frame_detector(args: &Args) -> FrameDetector {
+	let detector = FrameDetector::new(args.dictionaries().clone(), args.marker_size(), args.qr_size(), args.max_hamming);
+	// run() already made sure the backend can be set up.
+	match &args.dictionaries().aruco {
+		Some(dictionary) => match args.backend.create(dictionary) {
+			Ok(Some(backend)) => detector.with_backend(backend),
+			_ => detector,
+		},
+		None => detector,
+	}
+}
+
+/// Track a single video, handing each frame record to `emit` in order.
+pub fn track_video(args: &Args, camera: &Camera, emit: &mut dyn FnMut(FrameRecord)) -> Result<(), ffmpeg::Error> {
+	let mut detector = frame_detector(args);
+	let mut occlusion = args.partial_markers.then(|| OcclusionBridge::new(occlusion::MAX_FRAMES));
+
+	let mut ictx = input(&camera.filename)?;
+	let input = ictx
+		.streams()
+		.best(Type::Video)
+		.ok_or(ffmpeg::Error::StreamNotFound)?;
+	let video_stream_index = input.index();
+	let rotate_tag = input.metadata().get("rotate").map(|t| t.to_string());
+	let time_base = input.time_base();
+	let native_rate = frame_rate(input.avg_frame_rate()).or(frame_rate(input.rate()));
+	let mut clock = FrameClock::new(f64::from(time_base), native_rate);
+	let mut stop = StopCondition::new(args.stop_after_detections.map(|n| n as usize), args.stop_after_gap);
+	// Set once there's nothing more to track, past --end-frame or a stop condition, so decoding can end early.
+	let finished = Cell::new(false);
+	// Cameras usually tag the video stream, but a MOV can keep it on its tmcd track instead.
+	let embedded_timecode = input.metadata().get("timecode").map(|t| t.to_string())
+		.or_else(|| ictx.metadata().get("timecode").map(|t| t.to_string()))
+		.or_else(|| ictx.streams().find_map(|stream| stream.metadata().get("timecode").map(|t| t.to_string())));
+	let timecode_start = camera.timecode_start.or_else(|| match parse_timecode(embedded_timecode.as_deref()?) {
+		Ok(start) => Some(start),
+		Err(e) => {
+			eprintln!("Ignoring the timecode in {}: {e}", camera.filename);
+			None
+		},
+	});
+	let start_time = Some(input.start_time()).filter(|t| *t != ffmpeg::ffi::AV_NOPTS_VALUE).map(|t| t as f64 * f64::from(time_base));
+	let timecode = timecode_start.and_then(|start| Timecode::new(start, native_rate?, start_time.unwrap_or(0.0)));
+	if camera.timecode_start.is_some() && timecode.is_none() {
+		eprintln!("{} has no frame rate to count timecode at.", camera.filename);
+	}
+
+	// The frame_offset is in this camera's own frames, and the resampler counts in the target rate's, so it takes the
+	// offset in seconds and its samples come out already numbered on the synced timeline.
+	let offset_seconds = match native_rate {
+		Some(rate) => camera.frame_offset as f64 / rate,
+		None if camera.frame_offset != 0 && args.target_fps.is_some() => {
+			eprintln!("{} has no frame rate to turn its frame_offset into seconds, so it's resampled without it.", camera.filename);
+			0.0
+		},
+		None => 0.0,
+	};
+	let mut resampler = args.target_fps.map(|fps| Resampler::new(fps, start_time, offset_seconds));
+	let frame_offset = if resampler.is_some() { 0 } else { camera.frame_offset };
+
+	let mut emit_record = |mut record: FrameRecord| {
+		let synced_frame = record.frame_id as i64 + frame_offset;
+		if synced_frame >= 0 {
+			record.frame_id = synced_frame as usize;
+			record.camera_id = camera.id.clone();
+			record.timecode = timecode.as_ref().map(|timecode| timecode.at(record.timestamp));
+			emit(record);
+		}
+	};
+	let overlay_path = args.render_overlay.as_ref().map(|path| path_for_camera(path, camera.id.as_deref()));
+	let overlay_rate = Some(input.avg_frame_rate()).filter(|rate| rate.numerator() > 0 && rate.denominator() > 0);
+	// Created on the first frame we keep, once we know the upright frame size.
+	let mut overlay: Option<OverlayEncoder> = None;
+	let mut dumper = match &args.debug_dump {
+		Some(directory) => match DebugDumper::new(directory.clone()) {
+			Ok(dumper) => Some(dumper),
+			Err(e) => {
+				eprintln!("Not writing debug images to {}: {e}", directory.display());
+				None
+			},
+		},
+		None => None,
+	};
+
+	let mut context_decoder =
+		ffmpeg::codec::context::Context::from_parameters(input.parameters())?;
+
+	let mut hw = args.hwaccel.and_then(|accel| HwDecoder::attach(accel, &mut context_decoder, args.verbose));
+	// Frame threads only help a software decoder.
+	if let Some(parallelism) = std::thread::available_parallelism().ok().filter(|_| hw.is_none()) {
+		context_decoder.set_threading(ffmpeg::threading::Config {
+			kind: ffmpeg::threading::Type::Frame,
+			count: parallelism.get().min(16), // FFMPEG does not recommend more than 16 threads.
+		});
+	}
+
+	let mut decoder = context_decoder.decoder().video()?;
+
+	// Anything deeper than 8 bits (or with an HDR curve) gets pulled out as 16-bit luma so we can tone-map it ourselves.
+	let transfer = match decoder.color_transfer_characteristic() {
+		TransferCharacteristic::SMPTE2084 => Transfer::Pq,
+		TransferCharacteristic::ARIB_STD_B67 => Transfer::Hlg,
+		_ => Transfer::Sdr,
+	};
+	let high_bit_depth = args.tonemap != ToneMap::Off && (pixel_bit_depth(decoder.format()) > 8 || transfer != Transfer::Sdr);
+	let tone_mapper = (high_bit_depth && args.tonemap == ToneMap::Auto).then(|| ToneMapper::new(transfer));
+	if args.verbose && high_bit_depth {
+		eprintln!("Source is {:?} with {:?} transfer. Converting through 16-bit luma.", decoder.format(), transfer);
+	}
+
+	// The detector only looks at luminance, so unless asked otherwise we skip RGB entirely.
+	let scaled_format = if high_bit_depth {
+		Pixel::GRAY16LE
+	} else if args.rgb {
+		Pixel::RGB24
+	} else {
+		Pixel::GRAY8
+	};
+
+	// Made for the first frame, since a hardware decoder's frames come back in whatever format the device downloads to.
+	let mut scaler: Option<Context> = None;
+
+	// Both depend on the first decoded frame, since that's where the display matrix shows up. The intrinsics are
+	// worked out again every frame when they follow a zoom.
+	let mut rotation: Option<Rotation> = None;
+	let mut intrinsics: Option<(CameraIntrinsics, Pinhole)> = None;
+	let mut rolling_shutter: Option<RollingShutter> = None;
+	let mut masker = camera.mask.as_ref().map(MaskSource::masker);
+
+	// Also made for the first frame, for the same reason.
+	let deinterlace_filter = args.deinterlace.filter_name(decoder.field_order());
+	if let (true, Some(filter_name)) = (args.verbose, deinterlace_filter) {
+		eprintln!("Deinterlacing with {filter_name}.");
+	}
+	let mut deinterlacer: Option<Deinterlacer> = None;
+
+	// Allocated once at the output size, so the scaler writes into the same buffer every frame.
+	let mut scaled_frame = Video::new(scaled_format, decoder.width(), decoder.height());
+	// And its luma is written over the last frame's, handed back at the end of each one.
+	let mut frame_buffer = FrameBuffer::default();
+	let mut frame_index = 0;
+	// Anything between one frame finishing and the next arriving is decoding.
+	let mut last_frame_done = Instant::now();
+
+	// A frame that couldn't be decoded comes through as an empty one with the reason it was skipped, so it still gets
+	// a record and the frame numbers after it still line up.
+	let mut process_frame = |frame: &Video, skipped: Option<String>| -> Result<(), ffmpeg::Error> {
+		if finished.get() {
+			return Ok(());
+		}
+		if args.end_frame != 0 && frame_index >= args.end_frame as usize {
+			finished.set(true);
+			return Ok(());
+		}
+		// Tick on every frame, even skipped ones, so the gaps between frames stay meaningful.
+		let timing = clock.tick(frame.timestamp().or(frame.pts()));
+		if let Some(reason) = skipped {
+			if frame_index >= args.start_frame as usize {
+				eprintln!("Skipping frame {frame_index} of {}: {reason}", camera.filename);
+				// The resampler would only smear the frames either side into it, so it gets bridged instead.
+				if resampler.is_none() {
+					emit_record(FrameRecord {
+						frame_id: frame_index,
+						timestamp: timing.timestamp,
+						dropped_frames: timing.dropped_frames,
+						duplicate: timing.duplicate,
+						skipped: Some(reason),
+						..Default::default()
+					});
+				}
+			}
+		} else if frame_index >= args.start_frame as usize {
+			let convert_start = Instant::now();
+			let mut timings = StageTimings { decode: convert_start - last_frame_done, ..Default::default() };
+			let scaler = match scaler.as_mut() {
+				Some(scaler) => scaler,
+				None => scaler.insert(Context::get(frame.format(), frame.width(), frame.height(), scaled_format, frame.width(), frame.height(), Flags::BILINEAR)?),
+			};
+			scaler.run(frame, &mut scaled_frame)?;
+			let img: DynamicImage = if high_bit_depth {
+				let luma = luma16_from_frame(&scaled_frame);
+				match &tone_mapper {
+					Some(tone_mapper) => DynamicImage::ImageLuma8(tone_mapper.map(&luma)),
+					None => DynamicImage::ImageLuma16(luma),
+				}
+			} else {
+				// Either RGB24 or GRAY8, read in place out of the scaler's buffer.
+				let channels = if args.rgb { 3 } else { 1 };
+				let view = FrameView::new(scaled_frame.data(0), scaled_frame.width(), scaled_frame.height(), scaled_frame.stride(0), channels)
+					.expect("The scaler's frame holds its own rows.");
+				frame_image(&mut frame_buffer, &view)
+			};
+			let rotation = *rotation.get_or_insert_with(|| {
+				if args.no_autorotate { Rotation::None } else { Rotation::from_frame(frame, rotate_tag.as_deref()) }
+			});
+			let img = rotation.apply(img);
+			let (intrinsics, pinhole) = match &camera.intrinsics_track {
+				Some(track) => {
+					let pinhole = track.pinhole_at(frame_index, img.width(), img.height(), camera.sensor_size_mm);
+					intrinsics.insert((detect::intrinsics_for(&pinhole), pinhole))
+				},
+				None => intrinsics.get_or_insert_with(|| build_intrinsics(camera, img.width(), img.height())),
+			};
+			if args.verbose && frame_index == args.start_frame as usize && rotation != Rotation::None {
+				eprintln!("Applying {:?} rotation from stream metadata.", rotation);
+			}
+			let mut preview = overlay_path.is_some().then(|| img.to_rgb8());
+			// Masked after the preview is taken, so the overlay still shows what's under it.
+			let mut img = img;
+			if let Some(masker) = masker.as_mut()
+				&& let Err(e) = masker.apply(frame_index, &mut img) {
+				eprintln!("{e}");
+			}
+			// Crop after rotating so the region is in the same space the user sees, but keep the full frame intrinsics.
+			let (img, crop_offset) = match args.crop {
+				Some(crop) => {
+					let crop = crop.clamped(img.width(), img.height());
+					(img.crop_imm(crop.x, crop.y, crop.width, crop.height), (crop.x as f32, crop.y as f32))
+				},
+				None => (img, (0.0, 0.0)),
+			};
+			let (color, gray) = split_for_detection(&mut frame_buffer, img, args.preprocess.as_ref());
+			let detect_start = Instant::now();
+			timings.convert = detect_start - convert_start;
+			let mut found = detector.find(color, &gray, crop_offset, pinhole);
+			if args.verbose && found.recovered {
+				eprintln!("Frame {frame_index}: recovered {} markers on a second look.", found.aruco.as_ref().map_or(0, |d| d.markers.len()));
+			}
+			if let Some(readout_ms) = args.rolling_shutter_ms {
+				let shutter = rolling_shutter.get_or_insert_with(|| RollingShutter::new(readout_ms / 1000.0, pinhole.width, pinhole.height, rotation.quarter_turns()));
+				shutter.begin_frame(timing.timestamp);
+				if let Some(detections) = found.aruco.as_mut() {
+					correct_detection_rolling_shutter(shutter, detections);
+				}
+				// Our own detectors solve their poses up front, so solve again from the moved corners.
+				for (markers, size) in [(&mut found.backend, args.marker_size()), (&mut found.qr_codes, args.qr_size())] {
+					correct_rolling_shutter(shutter, markers, pinhole, size);
+				}
+			}
+			let pose_start = Instant::now();
+			timings.detect = pose_start - detect_start;
+			let mut record = detector.record(found, frame_index, timing.timestamp, &gray, crop_offset, intrinsics, pinhole, args.self_calibrate);
+			if let Some(occlusion) = occlusion.as_mut() {
+				occlusion.bridge(&mut record, &gray, crop_offset, pinhole, args.marker_size());
+			}
+			timings.pose = pose_start.elapsed();
+			record.timings = Some(timings);
+			record.dropped_frames = timing.dropped_frames;
+			record.duplicate = timing.duplicate;
+			if let (Some(path), Some(preview)) = (&overlay_path, preview.as_mut()) {
+				draw_record(preview, &record, args.marker_size());
+				let encoder = match overlay.as_mut() {
+					Some(encoder) => encoder,
+					None => overlay.insert(OverlayEncoder::new(path, preview.width(), preview.height(), time_base, overlay_rate)?),
+				};
+				encoder.write(preview, frame.timestamp().or(frame.pts()))?;
+			}
+			if let Some(dumper) = dumper.as_mut() {
+				// Draw in the coordinates of the image the detector actually saw.
+				let mut local = FrameRecord { camera_id: camera.id.clone(), ..record.clone() };
+				local.offset_corners((-crop_offset.0, -crop_offset.1));
+				if let Some(pinhole) = local.intrinsics.as_mut() {
+					pinhole.cx -= crop_offset.0;
+					pinhole.cy -= crop_offset.1;
+				}
+				if let Err(e) = dumper.dump_if_failed(&gray, &local, args.marker_size()) {
+					eprintln!("Couldn't write debug images for frame {frame_index}: {e}");
+				}
+			}
+			frame_buffer.recycle(gray);
+			if args.crop_local_coords {
+				record.offset_corners((-crop_offset.0, -crop_offset.1));
+			}
+			if let Some(reason) = stop.update(&record) {
+				eprintln!("Stopping {} at frame {frame_index}: {reason}.", camera.filename);
+				finished.set(true);
+			}
+			match resampler.as_mut() {
+				Some(resampler) => {
+					for sample in resampler.push(record) {
+						emit_record(sample);
+					}
+				},
+				None => emit_record(record),
+			}
+		}
+		frame_index += 1;
+		last_frame_done = Instant::now();
+		Ok(())
+	};
+
+	let mut decoded = Video::empty();
+	let mut filtered = Video::empty();
+	// `failed` is a packet that couldn't be read or that the decoder wouldn't take, and why, which stands for a frame
+	// that has to be skipped.
+	let mut receive_and_process_decoded_frames =
+		|decoder: &mut ffmpeg::decoder::Video, failed: Option<(Option<i64>, String)>| -> Result<(), ffmpeg::Error> {
+			if let Some((pts, reason)) = failed {
+				let mut placeholder = Video::empty();
+				placeholder.set_pts(pts);
+				process_frame(&placeholder, Some(reason))?;
+			}
+			let mut stuck = false;
+			loop {
+				match receive_frame(decoder, &mut decoded) {
+					Ok(true) => stuck = false,
+					Ok(false) => break,
+					Err(e) if args.strict => return Err(e),
+					// Twice running and the decoder is stuck, not past one bad frame. It gets another go with the next packet.
+					Err(_) if stuck => break,
+					Err(e) => {
+						stuck = true;
+						process_frame(&Video::empty(), Some(format!("Couldn't decode it: {e}")))?;
+						continue;
+					},
+				}
+				let frame = match hw.as_mut().map(|hw| hw.download(&decoded)) {
+					Some(Ok(frame)) => frame,
+					Some(Err(e)) if !args.strict => {
+						process_frame(&decoded, Some(format!("Couldn't copy it back from the GPU: {e}")))?;
+						continue;
+					},
+					Some(Err(e)) => return Err(e),
+					None => &decoded,
+				};
+				match deinterlace_filter {
+					Some(filter_name) => {
+						let deinterlacer = match deinterlacer.as_mut() {
+							Some(deinterlacer) => deinterlacer,
+							None => deinterlacer.insert(Deinterlacer::new(filter_name, decoder, frame.format(), time_base, args.deinterlace == DeinterlaceMode::Auto)?),
+						};
+						deinterlacer.push(frame)?;
+						while deinterlacer.pull(&mut filtered) {
+							process_frame(&filtered, None)?;
+						}
+					},
+					None => process_frame(frame, None)?,
+				}
+			}
+			Ok(())
+		};
+
+	let mut read_errors = 0;
+	for item in ictx.packets() {
+		if finished.get() {
+			break;
+		}
+		let (stream, packet) = match item {
+			Ok(item) => item,
+			Err(ffmpeg::Error::Eof) => break,
+			Err(e) if args.strict || read_errors == MAX_READ_ERRORS => return Err(e),
+			// There's no telling which stream it was from, but it's most likely the video, which has most of the bytes.
+			Err(e) => {
+				read_errors += 1;
+				receive_and_process_decoded_frames(&mut decoder, Some((None, format!("Couldn't read it from the file: {e}"))))?;
+				continue;
+			},
+		};
+		read_errors = 0;
+		if stream.index() == video_stream_index {
+			// One bad packet shouldn't cost the rest of the video, unless --strict says it should.
+			let failed = match decoder.send_packet(&packet) {
+				Ok(()) => None,
+				Err(e) if args.strict => return Err(e),
+				Err(e) => Some((packet.pts(), format!("Couldn't decode it: {e}"))),
+			};
+			receive_and_process_decoded_frames(&mut decoder, failed)?;
+		}
+	}
+	decoder.send_eof()?;
+	receive_and_process_decoded_frames(&mut decoder, None)?;
+	// The deinterlacer looks a frame ahead, so it's still holding the last one.
+	if let Some(deinterlacer) = deinterlacer.as_mut() {
+		deinterlacer.flush()?;
+		while deinterlacer.pull(&mut filtered) {
+			process_frame(&filtered, None)?;
+		}
+	}
+	if let Some(overlay) = overlay.as_mut() {
+		overlay.finish()?;
+	}
+
+	Ok(())
+}
+
+/// Up to `count` frames from --start-frame as the detector would see them, near enough: upright luma, cropped and
+/// cleaned up as asked, but decoded in software and without tone-mapping. For trying settings out before the real run.
+pub fn sample_frames(args: &Args, camera: &Camera, count: usize) -> Result<Vec<Sample>, ffmpeg::Error> {
+	let mut ictx = input(&camera.filename)?;
+	let input = ictx.streams().best(Type::Video).ok_or(ffmpeg::Error::StreamNotFound)?;
+	let video_stream_index = input.index();
+	let rotate_tag = input.metadata().get("rotate").map(|t| t.to_string());
+	let mut decoder = ffmpeg::codec::context::Context::from_parameters(input.parameters())?.decoder().video()?;
+	let mut scaler: Option<Context> = None;
+	let mut masker = camera.mask.as_ref().map(MaskSource::masker);
+	let mut decoded = Video::empty();
+	let mut frame_index = 0;
+	let mut frames = vec![];
+	let mut receive = |decoder: &mut ffmpeg::decoder::Video, frames: &mut Vec<Sample>| -> Result<(), ffmpeg::Error> {
+		let mut stuck = false;
+		while frames.len() < count {
+			match receive_frame(decoder, &mut decoded) {
+				Ok(true) => stuck = false,
+				Ok(false) => break,
+				Err(e) if args.strict => return Err(e),
+				Err(_) if stuck => break,
+				// Still a frame, for counting to --start-frame, but not one to sample.
+				Err(_) => {
+					stuck = true;
+					frame_index += 1;
+					continue;
+				},
+			}
+			frame_index += 1;
+			if frame_index <= args.start_frame as usize {
+				continue;
+			}
+			let (width, height) = (decoded.width(), decoded.height());
+			let scaler = match scaler.as_mut() {
+				Some(scaler) => scaler,
+				None => scaler.insert(Context::get(decoded.format(), width, height, Pixel::GRAY8, width, height, Flags::BILINEAR)?),
+			};
+			let mut scaled = Video::new(Pixel::GRAY8, width, height);
+			scaler.run(&decoded, &mut scaled)?;
+			let img = FrameView::new(scaled.data(0), width, height, scaled.stride(0), 1).expect("The scaler's frame holds its own rows.").to_image();
+			let rotation = if args.no_autorotate { Rotation::None } else { Rotation::from_frame(&decoded, rotate_tag.as_deref()) };
+			let mut img = rotation.apply(img);
+			if let Some(masker) = masker.as_mut()
+				&& let Err(e) = masker.apply(frame_index - 1, &mut img) {
+				eprintln!("{e}");
+			}
+			let (width, height) = (img.width(), img.height());
+			let (img, offset) = match args.crop {
+				Some(crop) => {
+					let crop = crop.clamped(width, height);
+					(img.crop_imm(crop.x, crop.y, crop.width, crop.height), (crop.x as f32, crop.y as f32))
+				},
+				None => (img, (0.0, 0.0)),
+			};
+			// The samples are kept, so there's nothing to recycle into the buffer.
+			let (_, gray) = split_for_detection(&mut FrameBuffer::default(), img, args.preprocess.as_ref());
+			frames.push(Sample { gray, offset, width, height });
+		}
+		Ok(())
+	};
+	let mut read_errors = 0;
+	for item in ictx.packets() {
+		if frames.len() >= count {
+			break;
+		}
+		let (stream, packet) = match item {
+			Ok(item) => item,
+			Err(ffmpeg::Error::Eof) => break,
+			Err(e) if args.strict || read_errors == MAX_READ_ERRORS => return Err(e),
+			Err(_) => {
+				read_errors += 1;
+				continue;
+			},
+		};
+		read_errors = 0;
+		if stream.index() == video_stream_index {
+			decoder.send_packet(&packet)?;
+			receive(&mut decoder, &mut frames)?;
+		}
+	}
+	if frames.len() < count {
+		decoder.send_eof()?;
+		receive(&mut decoder, &mut frames)?;
+	}
+	Ok(frames)
+}
+
+/// Fit the camera's focal length (and with --refine-principal-point, its principal point) to the markers in its first
+/// `count` frames, starting from its lens settings. None if there wasn't enough to go on.
+pub fn refine_focal(args: &Args, camera: &Camera, count: usize) -> Result<Option<FocalFit>, ffmpeg::Error> {
+	let samples = sample_frames(args, camera, count)?;
+	let Some(first) = samples.first() else {
+		return Ok(None);
+	};
+	let (intrinsics, pinhole) = build_intrinsics(camera, first.width, first.height);
+	let mut detector = frame_detector(args);
+	let mut quads = vec![];
+	for (frame_index, sample) in samples.into_iter().enumerate() {
+		let found = detector.find(None, &sample.gray, sample.offset, &pinhole);
+		let record = detector.record(found, frame_index, 0.0, &sample.gray, sample.offset, &intrinsics, &pinhole, false);
+		// QR codes are a different size, and their corners are rougher anyway.
+		quads.extend(record.markers.iter().filter(|m| m.payload.is_none()).map(|m| m.corners));
+	}
+	Ok(focal::refine(&quads, args.marker_size(), &pinhole, args.refine_principal_point))
+}
+
+/// Track several cameras at once, one thread each, and hand the records back interleaved in synchronized frame order.
+pub fn track_cameras(args: &Args, cameras: &[Camera], emit: &mut dyn FnMut(FrameRecord)) -> Result<(), ffmpeg::Error> {
+	std::thread::scope(|scope| {
+		let mut workers = vec![];
+		let mut receivers = vec![];
+		for camera in cameras {
+			// Bounded so a fast camera can't buffer the whole video while we wait on a slow one.
+			let (sender, receiver) = mpsc::sync_channel::<FrameRecord>(64);
+			receivers.push(receiver);
+			workers.push(scope.spawn(move || track_video(args, camera, &mut |record| {
+				let _ = sender.send(record);
+			})));
+		}
+
+		// Every camera produces frames in increasing order, so we only ever need to look at the head of each queue.
+		let mut heads: Vec<Option<FrameRecord>> = receivers.iter().map(|r| r.recv().ok()).collect();
+		while let Some(next) = heads.iter().enumerate().filter_map(|(idx, head)| head.as_ref().map(|h| (idx, h.frame_id))).min_by_key(|(_, frame_id)| *frame_id).map(|(idx, _)| idx) {
+			let record = heads[next].take().expect("Head was just checked.");
+			heads[next] = receivers[next].recv().ok();
+			emit(record);
+		}
+
+		for worker in workers {
+			worker.join().expect("Camera worker panicked.")?;
+		}
+		Ok(())
+	})
+}
+
+fn correct_rolling_shutter(shutter: &mut RollingShutter, markers: &mut [MarkerRecord], pinhole: &Pinhole, marker_size_mm: f32) {
+	for m in markers.iter_mut() {
+		let corners = shutter.correct(m.marker_id, m.corners);
+		if corners != m.corners {
+			m.corners = corners;
+			m.poses = PoseRecord::from_quad(&corners, marker_size_mm, pinhole).into_iter().collect();
+		}
+	}
+}
+
+// Through f32, since the detector picks the corner type.
+#[allow(clippy::unnecessary_cast)]
+fn correct_detection_rolling_shutter(shutter: &mut RollingShutter, detection: &mut Detection) {
+	for m in detection.markers.iter_mut() {
+		let corners = std::array::from_fn(|i| (m.corners[i].0 as f32, m.corners[i].1 as f32));
+		let moved = shutter.correct(m.id as usize, corners);
+		for (c, moved) in m.corners.iter_mut().zip(moved) {
+			c.0 = moved.0.round() as _;
+			c.1 = moved.1.round() as _;
+		}
+	}
+}
+
+// Frame rates come back as 0/0 when the container doesn't know.
+/// How many packets in a row can fail to read before the rest of the file is taken to be unreadable.
+const MAX_READ_ERRORS: u32 = 64;
+
+/// The decoder's next frame into `frame`: true if there was one, false if it needs more packets first or has given
+/// everything it had. Anything else is an error decoding a frame.
+fn receive_frame(decoder: &mut ffmpeg::decoder::Video, frame: &mut Video) -> Result<bool, ffmpeg::Error> {
+	match decoder.receive_frame(frame) {
+		Ok(()) => Ok(true),
+		Err(ffmpeg::Error::Eof) => Ok(false),
+		Err(ffmpeg::Error::Other { errno }) if errno == ffmpeg::util::error::EAGAIN => Ok(false),
+		Err(e) => Err(e),
+	}
+}
+
+pub fn frame_rate(rate: ffmpeg::Rational) -> Option<f64> {
+	if rate.numerator() > 0 && rate.denominator() > 0 {
+		Some(f64::from(rate))
+	} else {
+		None
+	}
+}
+
+pub(super) fn pixel_bit_depth(format: Pixel) -> u32 {
+	// The safe pixel format descriptor wrapper doesn't expose component depths.
+	unsafe {
+		let descriptor = ffmpeg::ffi::av_pix_fmt_desc_get(format.into());
+		if descriptor.is_null() {
+			8
+		} else {
+			(*descriptor).comp[0].depth as u32
+		}
+	}
+}
+
+// Copy a GRAY16LE frame out row by row, since each row may be padded past the image width.
+fn luma16_from_frame(frame: &Video) -> Gray16Image {
+	let (width, height) = (frame.width() as usize, frame.height() as usize);
+	let stride = frame.stride(0);
+	let data = frame.data(0);
+	let mut samples = Vec::with_capacity(width * height);
+	for row in 0..height {
+		let row_bytes = &data[row * stride..row * stride + width * 2];
+		samples.extend(row_bytes.chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]])));
+	}
+	Gray16Image::from_raw(width as u32, height as u32, samples).expect("Frame buffer should match its own dimensions.")
+}
+
+// Intrinsics have to be built for the upright frame, so the width and height here come after any rotation.
+// We also build our own pinhole model with the same numbers so the output side can project and triangulate.
+fn build_intrinsics(camera: &Camera, width: u32, height: u32) -> (CameraIntrinsics, Pinhole) {
+	if let Some(pinhole) = camera.refined.filter(|p| (p.width, p.height) == (width, height)) {
+		return (detect::intrinsics_for(&pinhole), pinhole);
+	}
+	let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+	if camera.fov_h_radians.is_some() && camera.sensor_size_mm.is_some() {
+		let hfov = camera.fov_h_radians.unwrap();
+		let sensor_width_mm = camera.sensor_size_mm.unwrap(); // TODO: This is not HW, necessarily. This might be diagonal width.
+		let f = cx / (hfov / 2.0).tan();
+		(CameraIntrinsics::new_from_fov_horizontal(hfov, sensor_width_mm, width, height), Pinhole { width, height, fx: f, fy: f, cx, cy })
+	} else {
+		let f = camera.focal_length_mm;
+		(CameraIntrinsics::new(width, height, f, f, None, None), Pinhole { width, height, fx: f, fy: f, cx, cy })
+	}
+}
+
+/// A frame as the scaler left it. GRAY8 is written over the last frame's luma, and RGB24 is packed into an image of
+/// its own, since that's for aruco3 to keep.
+fn frame_image(buffer: &mut FrameBuffer, view: &FrameView) -> DynamicImage {
+	match view.channels() {
+		1 => DynamicImage::ImageLuma8(buffer.luma(view)),
+		_ => view.to_image(),
+	}
+}
+
+/// What aruco3 sees, if it's not just the luma, and the luma everything else reads. A gray frame is its own luma, so
+/// it's moved rather than copied, and the luma of anything else is written over the last frame's.
+fn split_for_detection(buffer: &mut FrameBuffer, img: DynamicImage, preprocess: Option<&Preprocess>) -> (Option<DynamicImage>, GrayImage) {
+	let (color, gray) = match img {
+		DynamicImage::ImageLuma8(gray) => (None, gray),
+		img => {
+			let gray = buffer.luma_of(&img);
+			(Some(img), gray)
+		},
+	};
+	match preprocess {
+		// aruco3 looks at the cleaned up luma too.
+		Some(preprocess) => (None, preprocess.apply(gray)),
+		None => (color, gray),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_rotation_from_display_matrix() {
+		// A phone held in portrait typically stores a -90 (ccw) display matrix, which we undo with a clockwise turn.
+		assert_eq!(Rotation::from_ccw_degrees(-90.0), Rotation::Clockwise90);
+		assert_eq!(Rotation::from_ccw_degrees(90.0), Rotation::Clockwise270);
+		assert_eq!(Rotation::from_ccw_degrees(180.0), Rotation::Clockwise180);
+		assert_eq!(Rotation::from_ccw_degrees(-1.0), Rotation::None);
+	}
+
+	#[test]
+	fn test_frames_reuse_the_luma() {
+		// Rows padded from 60 pixels to 64, like the scaler's.
+		let data: Vec<u8> = (0..64 * 48).map(|i| i as u8).collect();
+		let mut buffer = FrameBuffer::default();
+		let mut first = None;
+		for _ in 0..2 {
+			let view = FrameView::new(&data, 60, 48, 64, 1).unwrap();
+			let img = frame_image(&mut buffer, &view);
+			let (color, gray) = split_for_detection(&mut buffer, img, None);
+			assert!(color.is_none());
+			assert_eq!(gray.as_raw(), view.to_luma8().as_raw());
+			let samples = gray.as_raw().as_ptr();
+			assert_eq!(*first.get_or_insert(samples), samples);
+			buffer.recycle(gray);
+		}
+	}
+}
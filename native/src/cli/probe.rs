@@ -0,0 +1,121 @@
+// The probe subcommand: what the tracker would make of a file, as one JSON object on stdout, without tracking it.
+// Handy for checking a clip before a long run, and for the Blender addon to fill in its settings. e.g.
+//   fiducial_track_video probe take.mov
+// The rotation is read from the first decoded frame, the same way the tracker reads it, so it's what will be applied.
+
+use crate::cli::Error;
+use crate::cli::pipeline::{Rotation, frame_rate, pixel_bit_depth};
+use crate::ffmpeg::{self, ffi, format::input, media::Type, util::frame::video::Video};
+use crate::value::Value;
+use std::ffi::{CStr, c_char};
+use std::path::Path;
+
+/// Metadata keys that say something about the lens or the camera, e.g. QuickTime's
+/// com.apple.quicktime.camera.focal_length.35mm_equivalent or lens_model from a phone.
+const LENS_KEYWORDS: [&str; 4] = ["lens", "focal", "make", "model"];
+
+fn lens_metadata<'a>(entries: impl Iterator<Item = (&'a str, &'a str)>) -> Vec<(String, Value)> {
+	entries.filter(|(key, _)| LENS_KEYWORDS.iter().any(|word| key.to_ascii_lowercase().contains(word)))
+		.map(|(key, value)| (key.to_string(), Value::Str(value.to_string())))
+		.collect()
+}
+
+fn name(name: *const c_char) -> Option<Value> {
+	(!name.is_null()).then(|| Value::Str(unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned()))
+}
+
+pub fn probe(path: &Path) -> Result<(), Error> {
+	ffmpeg::init()?;
+	let mut ictx = input(path)?;
+	let stream = ictx.streams().best(Type::Video).ok_or(ffmpeg::Error::StreamNotFound)?;
+	let index = stream.index();
+	let time_base = f64::from(stream.time_base());
+	let fps = frame_rate(stream.avg_frame_rate()).or(frame_rate(stream.rate()));
+	let duration = Some(stream.duration()).filter(|d| *d > 0).map(|d| d as f64 * time_base)
+		.or(Some(ictx.duration()).filter(|d| *d > 0).map(|d| d as f64 / ffi::AV_TIME_BASE as f64));
+	let frames = stream.frames();
+	let rotate_tag = stream.metadata().get("rotate").map(|t| t.to_string());
+	let mut lens = lens_metadata(ictx.metadata().iter());
+	lens.extend(lens_metadata(stream.metadata().iter()));
+	let timecode = stream.metadata().get("timecode").or_else(|| ictx.metadata().get("timecode")).map(|t| t.to_string());
+	let codec = stream.parameters().id().name().to_string();
+	let mut decoder = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?.decoder().video()?;
+
+	let mut first = Video::empty();
+	let mut decoded = false;
+	for (stream, packet) in ictx.packets().filter_map(Result::ok) {
+		if stream.index() == index {
+			decoder.send_packet(&packet)?;
+			if decoder.receive_frame(&mut first).is_ok() {
+				decoded = true;
+				break;
+			}
+		}
+	}
+	if !decoded {
+		decoder.send_eof()?;
+		decoded = decoder.receive_frame(&mut first).is_ok();
+	}
+	let rotation = if decoded { Rotation::from_frame(&first, rotate_tag.as_deref()) } else { Rotation::None };
+
+	let (width, height) = (decoder.width(), decoder.height());
+	let upright = if rotation.quarter_turns() % 2 == 1 { (height, width) } else { (width, height) };
+	let mut out = vec![
+		("file".to_string(), Value::Str(path.display().to_string())),
+		("container".to_string(), Value::Str(ictx.format().name().to_string())),
+		("codec".to_string(), Value::Str(codec)),
+		("width".to_string(), Value::Int(width as i64)),
+		("height".to_string(), Value::Int(height as i64)),
+		// What the detector sees, and so what corners are measured in.
+		("upright_width".to_string(), Value::Int(upright.0 as i64)),
+		("upright_height".to_string(), Value::Int(upright.1 as i64)),
+		("rotation".to_string(), Value::Int(rotation.quarter_turns() as i64 * 90)),
+	];
+	if let Some(pixel_format) = name(unsafe { ffi::av_get_pix_fmt_name(decoder.format().into()) }) {
+		out.push(("pixel_format".to_string(), pixel_format));
+	}
+	out.push(("bit_depth".to_string(), Value::Int(pixel_bit_depth(decoder.format()) as i64)));
+	if let Some(transfer) = name(unsafe { ffi::av_color_transfer_name(decoder.color_transfer_characteristic().into()) }) {
+		out.push(("transfer".to_string(), transfer));
+	}
+	if let Some(fps) = fps {
+		out.push(("frame_rate".to_string(), Value::F64(fps)));
+	}
+	if let Some(duration) = duration {
+		out.push(("duration".to_string(), Value::F64(duration)));
+	}
+	// Not every container counts its frames, so fall back on the duration and rate, and say so.
+	match (frames, duration.zip(fps)) {
+		(frames, _) if frames > 0 => out.push(("frames".to_string(), Value::Int(frames))),
+		(_, Some((duration, fps))) => {
+			out.push(("frames".to_string(), Value::Int((duration * fps).round() as i64)));
+			out.push(("frames_estimated".to_string(), Value::Bool(true)));
+		},
+		_ => {},
+	}
+	if let Some(timecode) = timecode {
+		out.push(("timecode".to_string(), Value::Str(timecode)));
+	}
+	if !lens.is_empty() {
+		out.push(("lens".to_string(), Value::Map(lens)));
+	}
+	println!("{}", Value::Map(out).to_json());
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_lens_metadata() {
+		let entries = [
+			("com.apple.quicktime.camera.focal_length.35mm_equivalent", "26"),
+			("com.apple.quicktime.make", "Apple"),
+			("encoder", "Lavf61.7.100"),
+			("creation_time", "2024-05-01T12:00:00.000000Z"),
+		];
+		let lens = lens_metadata(entries.into_iter());
+		assert_eq!(lens.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>(), vec![entries[0].0, entries[1].0]);
+	}
+}
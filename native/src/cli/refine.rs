@@ -0,0 +1,93 @@
+// The refine subcommand: clean up a track file that's already been written, without tracking the video again, so the
+// filtering can be tuned a few times over. e.g.
+//   fiducial_track_video refine take.jsonl --output take.refined.jsonl --smooth-window 3
+// See refinement.rs for what each step does. The file is read whole, since gaps are filled from both sides.
+
+use crate::cli::compress::Compression;
+use crate::cli::{Error, create_output, usage};
+use crate::output::Finish;
+use crate::refinement::Refinement;
+use crate::value::Value;
+use clap::error::ErrorKind;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+
+#[derive(clap::Args, Debug)]
+pub struct RefineArgs {
+	/// The JSON lines a run wrote. A .gz or .zst one is decompressed first.
+	input: PathBuf,
+
+	/// Where to write the refined JSON lines. Defaults to stdout. A .gz or .zst extension compresses them.
+	#[arg(long)]
+	output: Option<PathBuf>,
+
+	/// Frames either side to take the median of when looking for translation spikes. 0 leaves spikes alone.
+	#[arg(long, default_value_t = 3)]
+	spike_window: usize,
+
+	/// How far from that median a translation can be before it's a spike, as a fraction of the marker's distance.
+	#[arg(long, default_value_t = 0.05)]
+	spike_threshold: f32,
+
+	/// The longest gap, in frames, to fill in between two sightings of a marker. 0 leaves gaps alone.
+	#[arg(long, default_value_t = 5)]
+	max_gap: usize,
+
+	/// Frames either side to average poses over. 0 leaves the track unsmoothed.
+	#[arg(long, default_value_t = 2)]
+	smooth_window: usize,
+
+	/// If 'true', keep the pose candidates in the order they were written, even where the two solutions swap.
+	#[arg(long, default_value_t = false)]
+	keep_flips: bool,
+}
+
+pub fn refine(args: &RefineArgs) -> Result<(), Error> {
+	if !(args.spike_threshold > 0.0 && args.spike_threshold.is_finite()) {
+		return Err(usage(ErrorKind::InvalidValue, "--spike-threshold needs to be a positive number."));
+	}
+	let refinement = Refinement {
+		spike_window: args.spike_window,
+		spike_threshold: args.spike_threshold,
+		max_gap: args.max_gap,
+		smooth_window: args.smooth_window,
+		fix_flips: !args.keep_flips,
+	};
+	let input = &args.input;
+	let io_error = |e: std::io::Error| usage(ErrorKind::Io, format!("Couldn't read {}: {e}", input.display()));
+	let file = BufReader::new(File::open(input).map_err(io_error)?);
+	let reader: Box<dyn Read> = match Compression::from_path(input) {
+		Some(compression) => compression.reader(file).map_err(io_error)?,
+		None => Box::new(file),
+	};
+
+	let mut header = None;
+	let mut records = vec![];
+	for (idx, line) in BufReader::new(reader).lines().enumerate() {
+		let line = line.map_err(io_error)?;
+		if line.trim().is_empty() {
+			continue;
+		}
+		let value = Value::parse_json(&line).map_err(|e| usage(ErrorKind::InvalidValue, format!("Line {} of {} isn't a record: {e}", idx + 1, input.display())))?;
+		if value.get("type") == Some(&Value::Str("header".to_string())) {
+			header = Some(value);
+		} else {
+			records.push(value);
+		}
+	}
+
+	let summary = refinement.apply(&mut records);
+	eprintln!("Refined {} records: {} flips fixed, {} spikes removed, {} detections filled in.", records.len(), summary.flips, summary.spikes, summary.filled);
+
+	let mut writer = create_output(args.output.as_deref(), None).map_err(Error::Usage)?;
+	let write_error = |e: std::io::Error| usage(ErrorKind::Io, format!("Couldn't write the refined records: {e}"));
+	if let Some(mut header) = header {
+		header.set("refined", refinement.to_value());
+		writeln!(writer, "{}", header.to_json()).map_err(write_error)?;
+	}
+	for record in &records {
+		writeln!(writer, "{}", record.to_json()).map_err(write_error)?;
+	}
+	writer.finish().map_err(write_error)
+}
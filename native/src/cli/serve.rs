@@ -0,0 +1,272 @@
+// Push records to whoever is connected as they're produced, one JSON object per line (or per WebSocket message).
+// This is what lets the Blender addon puppet objects live from a webcam instead of polling a file.
+
+use crate::output::Sink;
+use crate::record::FrameRecord;
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServeAddress {
+	/// A Unix domain socket path, given as unix:/path/to.sock.
+	Unix(PathBuf),
+	/// A TCP address, given as tcp:host:port or just host:port.
+	Tcp(String),
+}
+
+pub fn parse_serve_address(s: &str) -> Result<ServeAddress, String> {
+	if let Some(path) = s.strip_prefix("unix:") {
+		if path.is_empty() {
+			return Err("Expected a socket path after 'unix:'.".to_string());
+		}
+		return Ok(ServeAddress::Unix(PathBuf::from(path)));
+	}
+	let address = s.strip_prefix("tcp:").unwrap_or(s);
+	// A bare port is the common case for local tooling.
+	if address.parse::<u16>().is_ok() {
+		return Ok(ServeAddress::Tcp(format!("127.0.0.1:{address}")));
+	}
+	if !address.contains(':') {
+		return Err(format!("Expected unix:/path, host:port, or a port number, got '{s}'."));
+	}
+	Ok(ServeAddress::Tcp(address.to_string()))
+}
+
+type Clients = Arc<Mutex<Vec<Box<dyn Write + Send>>>>;
+
+// A client this far behind is gone for our purposes.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct SocketServer {
+	clients: Clients,
+	/// The Unix socket we bound, and its device and inode, so we only clean up the file that's still ours.
+	socket_path: Option<(PathBuf, (u64, u64))>,
+}
+
+impl SocketServer {
+	pub fn bind(address: &ServeAddress) -> io::Result<Self> {
+		let clients: Clients = Arc::new(Mutex::new(vec![]));
+		let accepted = clients.clone();
+		let socket_path = match address {
+			ServeAddress::Tcp(address) => {
+				let listener = TcpListener::bind(address)?;
+				std::thread::spawn(move || {
+					for stream in listener.incoming().flatten() {
+						let _ = stream.set_nodelay(true);
+						let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+						accepted.lock().unwrap().push(Box::new(stream));
+					}
+				});
+				None
+			},
+			#[cfg(unix)]
+			ServeAddress::Unix(path) => {
+				remove_stale_socket(path)?;
+				let listener = std::os::unix::net::UnixListener::bind(path)?;
+				let created = socket_identity(path)?;
+				std::thread::spawn(move || {
+					for stream in listener.incoming().flatten() {
+						let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+						accepted.lock().unwrap().push(Box::new(stream));
+					}
+				});
+				Some((path.clone(), created))
+			},
+			#[cfg(not(unix))]
+			ServeAddress::Unix(_) => {
+				return Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets aren't available on this platform."));
+			},
+		};
+		Ok(SocketServer { clients, socket_path })
+	}
+}
+
+impl Sink for SocketServer {
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		let line = record.to_json() + "\n";
+		// Clients that hang up just get dropped. The run carries on whether or not anybody is listening.
+		self.clients.lock().unwrap().retain_mut(|client| client.write_all(line.as_bytes()).is_ok());
+		Ok(())
+	}
+}
+
+impl Drop for SocketServer {
+	fn drop(&mut self) {
+		#[cfg(unix)]
+		if let Some((path, created)) = &self.socket_path {
+			// Someone else may have replaced it since, with a socket of their own or something else entirely.
+			if socket_identity(path).is_ok_and(|identity| identity == *created) {
+				let _ = std::fs::remove_file(path);
+			}
+		}
+	}
+}
+
+/// A socket left over from a previous run would make bind fail, so it goes. Anything else at the path is somebody's
+/// file, and a typo in --serve shouldn't delete it.
+#[cfg(unix)]
+fn remove_stale_socket(path: &std::path::Path) -> io::Result<()> {
+	use std::os::unix::fs::FileTypeExt;
+	match std::fs::symlink_metadata(path) {
+		Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+		Ok(_) => Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and isn't a socket.", path.display()))),
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+		Err(e) => Err(e),
+	}
+}
+
+/// The device and inode of the socket at `path`, or an error if there isn't a socket there.
+#[cfg(unix)]
+fn socket_identity(path: &std::path::Path) -> io::Result<(u64, u64)> {
+	use std::os::unix::fs::{FileTypeExt, MetadataExt};
+	let metadata = std::fs::symlink_metadata(path)?;
+	if !metadata.file_type().is_socket() {
+		return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't a socket.", path.display())));
+	}
+	Ok((metadata.dev(), metadata.ino()))
+}
+
+/// The same records as text messages over WebSocket, for browser dashboards and remote clients.
+pub struct WebSocketServer {
+	clients: Arc<Mutex<Vec<WebSocket<TcpStream>>>>,
+}
+
+impl WebSocketServer {
+	pub fn bind(address: &str) -> io::Result<Self> {
+		let clients = Arc::new(Mutex::new(vec![]));
+		let accepted = clients.clone();
+		let listener = TcpListener::bind(address)?;
+		std::thread::spawn(move || {
+			for stream in listener.incoming().flatten() {
+				let accepted = accepted.clone();
+				// The handshake blocks on the client, so don't let one slow browser hold up the others.
+				std::thread::spawn(move || {
+					let _ = stream.set_nodelay(true);
+					let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+					match tungstenite::accept(stream) {
+						Ok(socket) => accepted.lock().unwrap().push(socket),
+						Err(e) => eprintln!("WebSocket handshake failed: {e}"),
+					}
+				});
+			}
+		});
+		Ok(WebSocketServer { clients })
+	}
+}
+
+/// Read whatever the client sent since the last record, without waiting for more. Nothing a client says matters to
+/// us, but tungstenite answers a Ping or a Close as it reads them. False once the client's gone.
+fn answer(client: &mut WebSocket<TcpStream>) -> bool {
+	if client.get_mut().set_nonblocking(true).is_err() {
+		return false;
+	}
+	let open = loop {
+		match client.read() {
+			Ok(_) => {},
+			Err(tungstenite::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => break true,
+			Err(_) => break false,
+		}
+	};
+	// Sends block with the write timeout, like the plain socket server's.
+	open && client.get_mut().set_nonblocking(false).is_ok()
+}
+
+impl Sink for WebSocketServer {
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		let json = record.to_json();
+		self.clients.lock().unwrap().retain_mut(|client| answer(client) && client.send(Message::text(json.clone())).is_ok());
+		Ok(())
+	}
+
+	fn finish(&mut self) -> io::Result<()> {
+		for client in self.clients.lock().unwrap().iter_mut() {
+			let _ = client.close(None);
+			let _ = client.flush();
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_serve_address() {
+		assert_eq!(parse_serve_address("unix:/tmp/fiducials.sock"), Ok(ServeAddress::Unix(PathBuf::from("/tmp/fiducials.sock"))));
+		assert_eq!(parse_serve_address("9000"), Ok(ServeAddress::Tcp("127.0.0.1:9000".to_string())));
+		assert_eq!(parse_serve_address("tcp:0.0.0.0:9000"), Ok(ServeAddress::Tcp("0.0.0.0:9000".to_string())));
+		assert!(parse_serve_address("unix:").is_err());
+		assert!(parse_serve_address("localhost").is_err());
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn test_unix_socket_leaves_other_files_alone() {
+		let dir = std::env::temp_dir().join(format!("fiducial_serve_{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let path = dir.join("records.sock");
+
+		std::fs::write(&path, "not a socket").unwrap();
+		assert_eq!(SocketServer::bind(&ServeAddress::Unix(path.clone())).err().map(|e| e.kind()), Some(io::ErrorKind::AlreadyExists));
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+
+		// A stale socket is replaced, and ours is cleaned up after, but not whatever took its place.
+		std::fs::remove_file(&path).unwrap();
+		drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+		let server = SocketServer::bind(&ServeAddress::Unix(path.clone())).unwrap();
+		drop(server);
+		assert!(!path.exists());
+		let server = SocketServer::bind(&ServeAddress::Unix(path.clone())).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		std::fs::write(&path, "someone else's").unwrap();
+		drop(server);
+		assert_eq!(std::fs::read_to_string(&path).unwrap(), "someone else's");
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_websocket_loopback() {
+		let address = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+		let mut server = WebSocketServer::bind(&address.to_string()).unwrap();
+		let (mut client, _) = tungstenite::connect(format!("ws://{address}")).unwrap();
+		// The handshake finishes on the server's own thread.
+		let deadline = std::time::Instant::now() + Duration::from_secs(5);
+		while server.clients.lock().unwrap().is_empty() {
+			assert!(std::time::Instant::now() < deadline, "the server never took the client");
+			std::thread::sleep(Duration::from_millis(10));
+		}
+
+		client.send(Message::Ping(vec![1, 2, 3].into())).unwrap();
+		let mut ponged = false;
+		for frame_id in 0..100 {
+			server.write(&FrameRecord { frame_id, ..Default::default() }).unwrap();
+			loop {
+				match client.read().unwrap() {
+					Message::Pong(payload) => ponged = payload[..] == [1, 2, 3],
+					Message::Text(text) => {
+						assert!(text.contains(&format!("\"frame_id\":{frame_id}")), "{text}");
+						break;
+					},
+					other => panic!("unexpected {other:?}"),
+				}
+			}
+			if ponged {
+				break;
+			}
+			std::thread::sleep(Duration::from_millis(10));
+		}
+		assert!(ponged);
+
+		client.close(None).unwrap();
+		while !server.clients.lock().unwrap().is_empty() {
+			assert!(std::time::Instant::now() < deadline, "the server never let the client go");
+			server.write(&FrameRecord::default()).unwrap();
+			std::thread::sleep(Duration::from_millis(10));
+		}
+	}
+}
@@ -0,0 +1,150 @@
+// SQLite output, for runs too long to load whole: one row per frame, per detection, and per pose candidate, indexed
+// by marker, so a question like "where does marker 7 drop out" is a query instead of a script. e.g.
+//   SELECT f.frame_id, f.timestamp FROM frames f
+//   WHERE NOT EXISTS (SELECT 1 FROM markers m WHERE m.frame = f.id AND m.marker_id = 7);
+//
+// Poses keep the pose's own columns in the run's units, but the rotation is always a quaternion (w, x, y, z) here,
+// whatever --rotation-format says, so it fits in columns. Rows go in inside a transaction that's committed every
+// few hundred frames, since committing each insert on its own is what makes SQLite slow.
+
+use crate::geometry::mat3_to_quat;
+use crate::output::Sink;
+use crate::record::FrameRecord;
+use crate::value::Value;
+use rusqlite::{Connection, params};
+use std::io;
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE header (json TEXT NOT NULL);
+CREATE TABLE frames (
+	id INTEGER PRIMARY KEY,
+	frame_id INTEGER NOT NULL,
+	camera_id TEXT,
+	timestamp REAL NOT NULL,
+	timecode TEXT,
+	dropped_frames INTEGER NOT NULL,
+	duplicate INTEGER NOT NULL,
+	source_frame INTEGER,
+	skipped TEXT
+);
+CREATE TABLE markers (
+	id INTEGER PRIMARY KEY,
+	frame INTEGER NOT NULL REFERENCES frames (id),
+	marker_id INTEGER NOT NULL,
+	instance INTEGER,
+	x0 REAL NOT NULL, y0 REAL NOT NULL, x1 REAL NOT NULL, y1 REAL NOT NULL,
+	x2 REAL NOT NULL, y2 REAL NOT NULL, x3 REAL NOT NULL, y3 REAL NOT NULL,
+	payload TEXT,
+	hamming_distance INTEGER,
+	confidence REAL,
+	partial INTEGER NOT NULL
+);
+CREATE TABLE poses (
+	marker INTEGER NOT NULL REFERENCES markers (id),
+	rank INTEGER NOT NULL,
+	tx REAL NOT NULL, ty REAL NOT NULL, tz REAL NOT NULL,
+	qw REAL NOT NULL, qx REAL NOT NULL, qy REAL NOT NULL, qz REAL NOT NULL,
+	error REAL NOT NULL,
+	reprojection_error REAL,
+	PRIMARY KEY (marker, rank)
+);
+CREATE INDEX frames_by_frame_id ON frames (camera_id, frame_id);
+CREATE INDEX markers_by_marker_id ON markers (marker_id, frame);
+CREATE INDEX markers_by_frame ON markers (frame);
+";
+
+/// Frames per transaction. Readers of a run in progress see it this far behind.
+const FRAMES_PER_COMMIT: u32 = 500;
+
+fn sql_error(e: rusqlite::Error) -> io::Error {
+	io::Error::other(e)
+}
+
+pub struct SqliteSink {
+	connection: Connection,
+	pending: u32,
+}
+
+impl SqliteSink {
+	/// A new database at `path`, replacing whatever was there like the other outputs do.
+	pub fn create(path: &Path) -> io::Result<Self> {
+		match std::fs::remove_file(path) {
+			Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+			_ => {},
+		}
+		Self::new(Connection::open(path).map_err(sql_error)?)
+	}
+
+	pub fn new(connection: Connection) -> io::Result<Self> {
+		connection.execute_batch(SCHEMA).map_err(sql_error)?;
+		connection.execute_batch("BEGIN").map_err(sql_error)?;
+		Ok(SqliteSink { connection, pending: 0 })
+	}
+
+	fn insert(&mut self, record: &FrameRecord) -> rusqlite::Result<()> {
+		let connection = &self.connection;
+		connection.prepare_cached("INSERT INTO frames (frame_id, camera_id, timestamp, timecode, dropped_frames, duplicate, source_frame, skipped) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)")?
+			.execute(params![record.frame_id as i64, record.camera_id, record.timestamp, record.timecode, record.dropped_frames, record.duplicate, record.source_frame.map(|f| f as i64), record.skipped])?;
+		let frame = connection.last_insert_rowid();
+		for marker in &record.markers {
+			let c = &marker.corners;
+			connection.prepare_cached("INSERT INTO markers (frame, marker_id, instance, x0, y0, x1, y1, x2, y2, x3, y3, payload, hamming_distance, confidence, partial) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)")?
+				.execute(params![frame, marker.marker_id as i64, marker.instance, c[0].0, c[0].1, c[1].0, c[1].1, c[2].0, c[2].1, c[3].0, c[3].1, marker.payload, marker.hamming_distance, marker.confidence.map(|c| c.score), marker.partial.is_some()])?;
+			let marker_row = connection.last_insert_rowid();
+			for (rank, pose) in marker.poses.iter().enumerate() {
+				let (t, q) = (pose.translation, mat3_to_quat(&pose.rotation));
+				connection.prepare_cached("INSERT INTO poses (marker, rank, tx, ty, tz, qw, qx, qy, qz, error, reprojection_error) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)")?
+					.execute(params![marker_row, rank as i64, t[0], t[1], t[2], q[0], q[1], q[2], q[3], pose.error, pose.reprojection_error])?;
+			}
+		}
+		Ok(())
+	}
+}
+
+impl Sink for SqliteSink {
+	fn header(&mut self, header: &Value) -> io::Result<()> {
+		self.connection.execute("INSERT INTO header (json) VALUES (?1)", [header.to_json()]).map_err(sql_error)?;
+		Ok(())
+	}
+
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		self.insert(record).map_err(sql_error)?;
+		self.pending += 1;
+		if self.pending >= FRAMES_PER_COMMIT {
+			self.connection.execute_batch("COMMIT; BEGIN").map_err(sql_error)?;
+			self.pending = 0;
+		}
+		Ok(())
+	}
+
+	fn finish(&mut self) -> io::Result<()> {
+		self.connection.execute_batch("COMMIT").map_err(sql_error)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::record::{MarkerRecord, PoseRecord};
+
+	#[test]
+	fn test_marker_gap_query() {
+		let mut sink = SqliteSink::new(Connection::open_in_memory().unwrap()).unwrap();
+		let pose = PoseRecord { translation: [0.0, 0.0, 500.0], rotation: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], ..Default::default() };
+		let marker = MarkerRecord { marker_id: 7, poses: vec![pose.clone(), pose], ..Default::default() };
+		for frame_id in 0..4 {
+			let markers = if frame_id == 2 { vec![] } else { vec![marker.clone()] };
+			sink.write(&FrameRecord { frame_id, timestamp: frame_id as f64 / 30.0, markers, ..Default::default() }).unwrap();
+		}
+		sink.finish().unwrap();
+
+		let missing: i64 = sink.connection.query_row(
+			"SELECT f.frame_id FROM frames f WHERE NOT EXISTS (SELECT 1 FROM markers m WHERE m.frame = f.id AND m.marker_id = 7)", [], |row| row.get(0),
+		).unwrap();
+		assert_eq!(missing, 2);
+		let (poses, qw): (i64, f64) = sink.connection.query_row("SELECT COUNT(*), MAX(qw) FROM poses", [], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+		assert_eq!(poses, 6);
+		assert!((qw - 1.0).abs() < 1e-6);
+	}
+}
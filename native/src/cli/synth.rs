@@ -0,0 +1,183 @@
+// The synth subcommand: render ARUCO markers moving along known paths into a video or an image sequence, with the
+// true poses alongside, so the whole pipeline's accuracy can be checked in CI without any footage. e.g.
+//   fiducial_track_video synth take.mp4 --noise 4 --motion-blur 0.5
+//   fiducial_track_video take.mp4 ARUCO 50 --focal-length-mm 1920 --output tracked.jsonl
+// then compare tracked.jsonl against take.truth.jsonl. With no --sensor-size-mm the tracker takes the focal length
+// as pixels, which is how it's given here.
+
+use crate::cli::overlay::OverlayEncoder;
+use crate::cli::{Error, bench, usage};
+use crate::ffmpeg::{self, Rational};
+use crate::filters::box_blur;
+use crate::geometry::Pinhole;
+use crate::output::{JsonLinesSink, Sink};
+use crate::synthetic::{self, Rng};
+use crate::value::Value;
+use clap::error::ErrorKind;
+use image::DynamicImage;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Renders averaged per frame when the shutter is open for part of it.
+const MOTION_SAMPLES: usize = 8;
+
+#[derive(clap::Args, Debug)]
+pub struct SynthArgs {
+	/// A video file to write, or a directory (a path with no extension) to fill with frame_000000.png and so on.
+	output: PathBuf,
+
+	/// Where to write the true pose of every marker in view, as the JSON lines the tracker writes. Defaults to the
+	/// output with a .truth.jsonl extension, or truth.jsonl in the directory.
+	#[arg(long)]
+	ground_truth: Option<PathBuf>,
+
+	/// How many frames to render.
+	#[arg(long, default_value_t = 90)]
+	frames: usize,
+
+	/// Frames per second.
+	#[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..=1000))]
+	fps: u32,
+
+	/// The frame size in pixels.
+	#[arg(long, default_value = "1920x1080", value_parser = bench::parse_size)]
+	size: (u32, u32),
+
+	/// The focal length in pixels. Defaults to the width, about a 53 degree horizontal field of view.
+	#[arg(long)]
+	focal_length_px: Option<f32>,
+
+	/// The length of each marker's black border, outside edge.
+	#[arg(long, default_value_t = 50.0)]
+	marker_size_mm: f32,
+
+	/// How many markers to draw, laid out in a grid.
+	#[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u64).range(1..=synthetic::MAX_SCENE_MARKERS as u64))]
+	markers: u64,
+
+	/// Changes the markers' paths and the noise, repeatably.
+	#[arg(long, default_value_t = 0)]
+	seed: u64,
+
+	/// The standard deviation of Gaussian sensor noise, in gray levels.
+	#[arg(long, default_value_t = 0.0)]
+	noise: f32,
+
+	/// Defocus: a box blur of this radius in pixels.
+	#[arg(long, default_value_t = 0)]
+	blur: u32,
+
+	/// The fraction of each frame's interval the shutter is open, from 0 to 1, smearing the markers along their paths.
+	/// The exposure is centered on the frame's time, which is when the true pose is taken.
+	#[arg(long, default_value_t = 0.0)]
+	motion_blur: f32,
+
+	/// The background gray level.
+	#[arg(long, default_value_t = 128)]
+	background: u8,
+
+	/// How much darker the right edge of the frame is than the left, from 0 to 1.
+	#[arg(long, default_value_t = 0.0)]
+	gradient: f32,
+
+	/// How much the exposure wavers from frame to frame, as a fraction of full brightness.
+	#[arg(long, default_value_t = 0.0)]
+	flicker: f32,
+}
+
+impl SynthArgs {
+	fn is_sequence(&self) -> bool {
+		self.output.extension().is_none()
+	}
+
+	fn ground_truth_path(&self) -> PathBuf {
+		match &self.ground_truth {
+			Some(path) => path.clone(),
+			None if self.is_sequence() => self.output.join("truth.jsonl"),
+			None => self.output.with_extension("truth.jsonl"),
+		}
+	}
+
+	fn header(&self, pinhole: &Pinhole) -> Value {
+		let settings = vec![
+			("dictionary".to_string(), Value::Str(synthetic::DICTIONARY.to_string())),
+			("marker_size_mm".to_string(), Value::F32(self.marker_size_mm)),
+			("focal_length_px".to_string(), Value::F32(pinhole.fx)),
+			("seed".to_string(), Value::Int(self.seed as i64)),
+			("noise".to_string(), Value::F32(self.noise)),
+			("blur".to_string(), Value::Int(self.blur as i64)),
+			("motion_blur".to_string(), Value::F32(self.motion_blur)),
+			("gradient".to_string(), Value::F32(self.gradient)),
+			("flicker".to_string(), Value::F32(self.flicker)),
+		];
+		Value::Map(vec![
+			("type".to_string(), Value::Str("header".to_string())),
+			("version".to_string(), Value::Str(env!("CARGO_PKG_VERSION").to_string())),
+			("convention".to_string(), Value::Str("opencv".to_string())),
+			("units".to_string(), Value::Str("mm".to_string())),
+			("scale".to_string(), Value::F32(1.0)),
+			("rotation_format".to_string(), Value::Str("rotation".to_string())),
+			("synthetic".to_string(), Value::Map(settings)),
+		])
+	}
+}
+
+fn io_error(path: &Path, e: impl std::fmt::Display) -> Error {
+	usage(ErrorKind::Io, format!("Couldn't write {}: {e}", path.display()))
+}
+
+pub fn synth(args: &SynthArgs) -> Result<(), Error> {
+	if !(0.0..=1.0).contains(&args.motion_blur) || !(0.0..=1.0).contains(&args.gradient) {
+		return Err(usage(ErrorKind::ValueValidation, "--motion-blur and --gradient go from 0 to 1."));
+	}
+	let (width, height) = args.size;
+	let f = args.focal_length_px.unwrap_or(width as f32);
+	let pinhole = Pinhole { width, height, fx: f, fy: f, cx: width as f32 / 2.0, cy: height as f32 / 2.0 };
+
+	let mut encoder = if args.is_sequence() {
+		std::fs::create_dir_all(&args.output).map_err(|e| io_error(&args.output, e))?;
+		None
+	} else {
+		ffmpeg::init()?;
+		Some(OverlayEncoder::new(&args.output, width, height, Rational::new(1, args.fps as i32), Some(Rational::new(args.fps as i32, 1)))?)
+	};
+	let truth_path = args.ground_truth_path();
+	let file = File::create(&truth_path).map_err(|e| io_error(&truth_path, e))?;
+	let mut truth = JsonLinesSink::new(BufWriter::new(file));
+	truth.header(&args.header(&pinhole)).map_err(|e| io_error(&truth_path, e))?;
+
+	let mut rng = Rng::new(args.seed);
+	let dt = 1.0 / args.fps as f32;
+	for frame in 0..args.frames {
+		let t = frame as f32 * dt;
+		let render = |t: f32| synthetic::render(&pinhole, &synthetic::scene(args.markers as usize, args.seed, t), args.marker_size_mm, args.background);
+		let mut img = if args.motion_blur > 0.0 {
+			let offsets = (0..MOTION_SAMPLES).map(|k| (k as f32 / (MOTION_SAMPLES - 1) as f32 - 0.5) * args.motion_blur * dt);
+			synthetic::average(&offsets.map(|offset| render(t + offset)).collect::<Vec<_>>())
+		} else {
+			render(t)
+		};
+		if args.blur > 0 {
+			img = box_blur(&img, args.blur);
+		}
+		synthetic::light(&mut img, 1.0 + args.flicker * rng.normal(), args.gradient);
+		if args.noise > 0.0 {
+			synthetic::add_noise(&mut img, args.noise, &mut rng);
+		}
+
+		match encoder.as_mut() {
+			Some(encoder) => encoder.write(&DynamicImage::ImageLuma8(img).to_rgb8(), Some(frame as i64))?,
+			None => {
+				let path = args.output.join(format!("frame_{frame:06}.png"));
+				img.save(&path).map_err(|e| io_error(&path, e))?;
+			},
+		}
+		let record = synthetic::truth_record(frame, frame as f64 / args.fps as f64, &pinhole, &synthetic::scene(args.markers as usize, args.seed, t), args.marker_size_mm);
+		truth.write(&record).map_err(|e| io_error(&truth_path, e))?;
+	}
+	if let Some(encoder) = encoder.as_mut() {
+		encoder.finish()?;
+	}
+	truth.finish().map_err(|e| io_error(&truth_path, e))
+}
@@ -0,0 +1,217 @@
+// A COLMAP text model (cameras.txt, images.txt, points3D.txt) built from the detections, so fiducials can seed or
+// constrain a reconstruction of the same footage. See https://colmap.github.io/format.html
+//
+// Image names are frame_000000.png and so on, for frames extracted with
+//   ffmpeg -i clip.mp4 -start_number 0 frame_%06d.png
+// and are put under <camera_id>/ in multi-camera runs.
+//
+// Every marker corner is a 2D point. With a marker map the mapped corners become known 3D points and each
+// frame that sees a mapped marker gets a real camera pose. Without one, poses are left as identity and there are no
+// 3D points, which is still enough to import the detections as matched features. Lengths are in the run's output
+// units, but the axes are always OpenCV's since that's what COLMAP expects.
+
+use crate::geometry::{self, Pinhole};
+use crate::marker_map::MarkerMap;
+use crate::output::Sink;
+use crate::record::FrameRecord;
+use crate::transform::{Convention, scale_record, transform_record};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+pub struct ColmapSink {
+	directory: PathBuf,
+	marker_size_mm: f32,
+	marker_map: Option<MarkerMap>,
+	/// Takes the records' poses back into OpenCV's camera convention, which is also COLMAP's.
+	to_opencv: geometry::Mat3,
+	/// Output units per mm, which the records' translations have already been scaled by.
+	length_scale: f32,
+	cameras: Vec<(Option<String>, Pinhole)>,
+	images: String,
+	image_count: usize,
+	/// (image_id, point2d_idx) for every observation of a 3D point, keyed by POINT3D_ID.
+	observations: BTreeMap<usize, Vec<(usize, usize)>>,
+}
+
+impl ColmapSink {
+	pub fn new(directory: PathBuf, marker_size_mm: f32, marker_map: Option<MarkerMap>, convention: Convention, length_scale: f32) -> Self {
+		let to_opencv = geometry::transpose(&convention.basis());
+		ColmapSink { directory, marker_size_mm, marker_map, to_opencv, length_scale, cameras: vec![], images: String::new(), image_count: 0, observations: BTreeMap::new() }
+	}
+
+	// Stable ids for marker corners, so the same corner is the same point across every image. COLMAP ids start at 1.
+	fn point_id(marker_id: usize, corner: usize) -> usize {
+		marker_id * 4 + corner + 1
+	}
+
+	/// For each mapped id in the frame, which of its markers the map's points belong to, by index in the record. An
+	/// id seen more than once at a time (see instances.rs) is only where the map says in one place, so that's the copy
+	/// that lines up best with the located camera, `rotation` and `translation` in mm. The others are unmapped.
+	fn mapped_copies(&self, record: &FrameRecord, rotation: &geometry::Mat3, translation: &geometry::Vec3) -> BTreeMap<usize, (f32, usize)> {
+		let mut out: BTreeMap<usize, (f32, usize)> = BTreeMap::new();
+		let (Some(map), Some(pinhole)) = (&self.marker_map, &record.intrinsics) else {
+			return out;
+		};
+		for (idx, m) in record.markers.iter().enumerate() {
+			let Some(corners) = map.corners(m.marker_id, self.marker_size_mm) else {
+				continue;
+			};
+			let error: f32 = corners.iter().zip(&m.corners).map(|(p, (u, v))| {
+				let (x, y) = pinhole.project(&geometry::add(&geometry::mat_mul_vec(rotation, p), translation));
+				(x - u).hypot(y - v)
+			}).sum();
+			if out.get(&m.marker_id).is_none_or(|(best, _)| error < *best) {
+				out.insert(m.marker_id, (error, idx));
+			}
+		}
+		out
+	}
+
+	fn camera_id(&mut self, record: &FrameRecord) -> Option<usize> {
+		let intrinsics = record.intrinsics?;
+		// A zoom (--intrinsics-track) gets a new camera whenever the lens changes.
+		if let Some(idx) = self.cameras.iter().position(|(id, c)| *id == record.camera_id && *c == intrinsics) {
+			return Some(idx + 1);
+		}
+		self.cameras.push((record.camera_id.clone(), intrinsics));
+		Some(self.cameras.len())
+	}
+}
+
+impl Sink for ColmapSink {
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		// Stereo records are triangulated from images we've already written.
+		if record.camera_id.as_deref() == Some("stereo") {
+			return Ok(());
+		}
+		let Some(camera_id) = self.camera_id(record) else {
+			return Ok(());
+		};
+		let pose = self.marker_map.as_ref().and_then(|map| {
+			let mut record = record.clone();
+			// The map is in mm and OpenCV axes, so put the record back the same way before comparing them.
+			transform_record(&mut record, &self.to_opencv, &[0.0; 3]);
+			scale_record(&mut record, 1.0 / self.length_scale);
+			map.locate_camera(&record, self.marker_size_mm)
+		});
+		// A mapped run only keeps the frames we could place.
+		if self.marker_map.is_some() && pose.is_none() {
+			return Ok(());
+		}
+		let (rotation, translation) = pose.unwrap_or((geometry::IDENTITY, [0.0; 3]));
+		let mapped = self.mapped_copies(record, &rotation, &translation);
+		let translation = geometry::scale(&translation, self.length_scale);
+		let q = geometry::mat3_to_quat(&rotation);
+
+		self.image_count += 1;
+		let image_id = self.image_count;
+		let name = match &record.camera_id {
+			Some(camera) => format!("{camera}/frame_{:06}.png", record.frame_id),
+			None => format!("frame_{:06}.png", record.frame_id),
+		};
+		let _ = writeln!(self.images, "{image_id} {} {} {} {} {} {} {} {camera_id} {name}", q[0], q[1], q[2], q[3], translation[0], translation[1], translation[2]);
+		let mut points = vec![];
+		for (idx, m) in record.markers.iter().enumerate() {
+			let mapped = mapped.get(&m.marker_id).is_some_and(|(_, copy)| *copy == idx);
+			for (corner, (x, y)) in m.corners.iter().enumerate() {
+				let point3d_id = if mapped {
+					let id = Self::point_id(m.marker_id, corner);
+					self.observations.entry(id).or_default().push((image_id, points.len()));
+					id.to_string()
+				} else {
+					"-1".to_string()
+				};
+				points.push(format!("{x} {y} {point3d_id}"));
+			}
+		}
+		let _ = writeln!(self.images, "{}", points.join(" "));
+		Ok(())
+	}
+
+	fn finish(&mut self) -> io::Result<()> {
+		std::fs::create_dir_all(&self.directory)?;
+
+		let mut cameras = BufWriter::new(File::create(self.directory.join("cameras.txt"))?);
+		writeln!(cameras, "# Camera list with one line of data per camera:")?;
+		writeln!(cameras, "#   CAMERA_ID, MODEL, WIDTH, HEIGHT, PARAMS[]")?;
+		for (idx, (_, c)) in self.cameras.iter().enumerate() {
+			writeln!(cameras, "{} PINHOLE {} {} {} {} {} {}", idx + 1, c.width, c.height, c.fx, c.fy, c.cx, c.cy)?;
+		}
+		cameras.flush()?;
+
+		let mut images = BufWriter::new(File::create(self.directory.join("images.txt"))?);
+		writeln!(images, "# Image list with two lines of data per image:")?;
+		writeln!(images, "#   IMAGE_ID, QW, QX, QY, QZ, TX, TY, TZ, CAMERA_ID, NAME")?;
+		writeln!(images, "#   POINTS2D[] as (X, Y, POINT3D_ID)")?;
+		images.write_all(self.images.as_bytes())?;
+		images.flush()?;
+
+		let mut points = BufWriter::new(File::create(self.directory.join("points3D.txt"))?);
+		writeln!(points, "# 3D point list with one line of data per point:")?;
+		writeln!(points, "#   POINT3D_ID, X, Y, Z, R, G, B, ERROR, TRACK[] as (IMAGE_ID, POINT2D_IDX)")?;
+		if let Some(map) = &self.marker_map {
+			for (&marker_id, _) in map.markers.iter() {
+				let Some(corners) = map.corners(marker_id, self.marker_size_mm) else {
+					continue;
+				};
+				for (corner, p) in corners.iter().enumerate() {
+					let p = geometry::scale(p, self.length_scale);
+					let id = Self::point_id(marker_id, corner);
+					let Some(track) = self.observations.get(&id) else {
+						continue;
+					};
+					let track: Vec<String> = track.iter().map(|(image_id, idx)| format!("{image_id} {idx}")).collect();
+					writeln!(points, "{id} {} {} {} 255 255 255 0 {}", p[0], p[1], p[2], track.join(" "))?;
+				}
+			}
+		}
+		points.flush()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::record::{MarkerRecord, PoseRecord};
+
+	#[test]
+	fn test_mapped_observations() {
+		let map = MarkerMap::parse("3 0 0 0 0 0 0").unwrap();
+		let mut sink = ColmapSink::new(PathBuf::new(), 50.0, Some(map), Convention::Opencv, 1.0);
+		let pinhole = Pinhole { width: 640, height: 480, fx: 500.0, fy: 500.0, cx: 320.0, cy: 240.0 };
+		let marker = |marker_id| MarkerRecord {
+			marker_id,
+			corners: geometry::marker_corners(50.0).map(|c| pinhole.project(&geometry::add(&c, &[0.0, 0.0, 500.0]))),
+			poses: vec![PoseRecord { translation: [0.0, 0.0, 500.0], rotation: geometry::IDENTITY, ..Default::default() }],
+			..Default::default()
+		};
+		sink.write(&FrameRecord { frame_id: 4, intrinsics: Some(pinhole), markers: vec![marker(1), marker(3)], ..Default::default() }).unwrap();
+		// No mapped marker in view, so the frame can't be placed.
+		sink.write(&FrameRecord { frame_id: 5, intrinsics: Some(pinhole), markers: vec![marker(1)], ..Default::default() }).unwrap();
+		assert_eq!(sink.image_count, 1);
+		assert!(sink.images.starts_with("1 1 0 0 0 0 0 500 1 frame_000004.png\n295 265 -1"));
+		// Marker 3's first corner is the fifth 2D point in the image.
+		assert_eq!(sink.observations[&13], [(1, 4)]);
+	}
+
+	#[test]
+	fn test_one_copy_takes_the_mapped_points() {
+		let map = MarkerMap::parse("3 0 0 0 0 0 0").unwrap();
+		let mut sink = ColmapSink::new(PathBuf::new(), 50.0, Some(map), Convention::Opencv, 1.0);
+		let pinhole = Pinhole { width: 640, height: 480, fx: 500.0, fy: 500.0, cx: 320.0, cy: 240.0 };
+		let copy = |instance, x| MarkerRecord {
+			marker_id: 3,
+			instance: Some(instance),
+			corners: geometry::marker_corners(50.0).map(|c| pinhole.project(&geometry::add(&c, &[x, 0.0, 500.0]))),
+			poses: vec![PoseRecord { translation: [x, 0.0, 500.0], rotation: geometry::IDENTITY, error: x, ..Default::default() }],
+			..Default::default()
+		};
+		// The copy off to the side is listed first, but it's the one in the middle the map puts in front of the camera.
+		sink.write(&FrameRecord { frame_id: 0, intrinsics: Some(pinhole), markers: vec![copy(1, 200.0), copy(0, 0.0)], ..Default::default() }).unwrap();
+		assert_eq!(sink.observations[&13], [(1, 4)]);
+		assert!(sink.images.lines().nth(1).unwrap().starts_with("495 265 -1 545 265 -1"), "{}", sink.images);
+	}
+}
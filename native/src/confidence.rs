@@ -0,0 +1,188 @@
+// How much to trust a detection, measured from the image around it after the fact.
+//
+// aruco3 only tells us which markers it accepted, so we look at each one again:
+//   border_contrast   how much darker the black border is than the white quiet zone around it, 0 to 1
+//   corner_sharpness  how crisp the outer edge is near each corner, 0 (smeared over many pixels) to 1 (a clean step)
+//   decode_margin     how far the least certain bit cell is from the black/white threshold, 0 (a coin flip) to 1
+// The overall score is the geometric mean of whichever of these we could measure, so one bad term drags it down
+// without zeroing it out. The decode margin needs the marker's grid size, which we guess from the dictionary name.
+
+use crate::geometry::{Mat3, apply_homography as apply, square_to_quad};
+use crate::record::MarkerRecord;
+use crate::value::Value;
+use image::GrayImage;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Confidence {
+	pub score: f32,
+	pub border_contrast: f32,
+	pub corner_sharpness: f32,
+	pub decode_margin: Option<f32>,
+}
+
+impl Confidence {
+	pub fn to_value(&self) -> Value {
+		let mut out = vec![
+			("score".to_string(), Value::F32(self.score)),
+			("border_contrast".to_string(), Value::F32(self.border_contrast)),
+			("corner_sharpness".to_string(), Value::F32(self.corner_sharpness)),
+		];
+		if let Some(decode_margin) = self.decode_margin {
+			out.push(("decode_margin".to_string(), Value::F32(decode_margin)));
+		}
+		Value::Map(out)
+	}
+}
+
+/// Data bits along one side of a marker from the dictionary, not counting the black border.
+pub fn marker_grid_size(dictionary_name: &str) -> Option<u32> {
+	let name = dictionary_name.to_ascii_uppercase();
+	// Families named for their bit count, e.g. APRILTAG_36H11 or ARUCO_MIP_25H7.
+	if let Some(family) = name.rsplit('_').next()
+		&& let Some((bits, _)) = family.split_once('H')
+		&& let Ok(bits) = bits.parse::<u32>() {
+		let side = (bits as f32).sqrt().round() as u32;
+		return (side * side == bits).then_some(side);
+	}
+	match name.as_str() {
+		"ARUCO" | "ARUCO_DEFAULT" => Some(5),
+		"ARTAG" | "CHILITAGS" | "ARTOOLKITPLUS" | "ARTOOLKITPLUSBCH" => Some(6),
+		_ => None,
+	}
+}
+
+/// Bilinear sample, or None off the edge of the image.
+pub fn sample(img: &GrayImage, (x, y): (f32, f32)) -> Option<f32> {
+	// Pixel centers are at half coordinates.
+	let (x, y) = (x - 0.5, y - 0.5);
+	if !(x >= 0.0 && y >= 0.0 && x < (img.width() - 1) as f32 && y < (img.height() - 1) as f32) {
+		return None;
+	}
+	let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+	let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+	let p = |dx, dy| img.get_pixel(x0 + dx, y0 + dy)[0] as f32;
+	let top = p(0, 0) * (1.0 - fx) + p(1, 0) * fx;
+	let bottom = p(0, 1) * (1.0 - fx) + p(1, 1) * fx;
+	Some(top * (1.0 - fy) + bottom * fy)
+}
+
+fn mean(values: impl Iterator<Item = f32>) -> Option<f32> {
+	let (sum, count) = values.fold((0.0, 0), |(sum, count), v| (sum + v, count + 1));
+	(count > 0).then(|| sum / count as f32)
+}
+
+/// Mean intensity over the middle of a cell, in marker units where the whole marker (border included) is 0 to 1.
+fn cell_mean(img: &GrayImage, homography: &Mat3, (u, v): (f32, f32), cell: f32) -> Option<f32> {
+	let offsets = [-0.25, 0.0, 0.25];
+	mean(offsets.iter().flat_map(|du| offsets.iter().map(move |dv| (u + du * cell, v + dv * cell)))
+		.filter_map(|p| sample(img, apply(homography, p))))
+}
+
+/// Score one detection against the image it was found in. `grid_size` is the number of data cells per side, if known.
+pub fn measure(img: &GrayImage, corners: &[(f32, f32); 4], grid_size: Option<u32>) -> Option<Confidence> {
+	let homography = square_to_quad(corners)?;
+	// Without a known grid, assume the common 4x4 layout just for placing border and quiet zone samples.
+	let cells = grid_size.unwrap_or(4) + 2;
+	let cell = 1.0 / cells as f32;
+
+	let border_cells = (0..cells).flat_map(|i| [(i, 0), (i, cells - 1), (0, i), (cells - 1, i)]);
+	let black = mean(border_cells.filter_map(|(i, j)| cell_mean(img, &homography, ((i as f32 + 0.5) * cell, (j as f32 + 0.5) * cell), cell)))?;
+	// Half a cell outside each edge, where there should be nothing but white.
+	let along = (0..cells).map(|i| (i as f32 + 0.5) * cell);
+	let quiet_zone = along.flat_map(|t| [(t, -0.5 * cell), (t, 1.0 + 0.5 * cell), (-0.5 * cell, t), (1.0 + 0.5 * cell, t)]);
+	let white = mean(quiet_zone.filter_map(|p| sample(img, apply(&homography, p))))?;
+	let border_contrast = ((white - black) / 255.0).clamp(0.0, 1.0);
+
+	// Compare the step across the edge over a pixel and a half with the step over six pixels. A clean edge has
+	// nearly all of its contrast in the first; a blurry one spreads it out.
+	let mut sharpness = vec![];
+	for idx in 0..4 {
+		let (from, to) = (corners[idx], corners[(idx + 1) % 4]);
+		let (ex, ey) = (to.0 - from.0, to.1 - from.1);
+		let length = ex.hypot(ey);
+		if length < 1.0 {
+			continue;
+		}
+		// Corners go clockwise on screen, so this normal points out of the marker.
+		let normal = (ey / length, -ex / length);
+		for t in [0.15, 0.85] {
+			let p = (from.0 + ex * t, from.1 + ey * t);
+			let step = |d: f32| Some(sample(img, (p.0 + normal.0 * d, p.1 + normal.1 * d))? - sample(img, (p.0 - normal.0 * d, p.1 - normal.1 * d))?);
+			if let (Some(narrow), Some(wide)) = (step(0.75), step(3.0))
+				&& wide > 1.0 {
+				sharpness.push((narrow / wide).clamp(0.0, 1.0));
+			}
+		}
+	}
+	let corner_sharpness = mean(sharpness.into_iter()).unwrap_or(0.0);
+
+	let decode_margin = grid_size.and_then(|bits| {
+		let threshold = (white + black) / 2.0;
+		let half_range = (white - black) / 2.0;
+		if half_range <= 0.0 {
+			return Some(0.0);
+		}
+		let mut margin: Option<f32> = None;
+		for i in 1..=bits {
+			for j in 1..=bits {
+				let value = cell_mean(img, &homography, ((i as f32 + 0.5) * cell, (j as f32 + 0.5) * cell), cell)?;
+				let m = ((value - threshold).abs() / half_range).min(1.0);
+				margin = Some(margin.map_or(m, |margin| margin.min(m)));
+			}
+		}
+		margin
+	});
+
+	let terms = [Some(border_contrast), Some(corner_sharpness), decode_margin];
+	let (product, count) = terms.iter().flatten().fold((1.0f32, 0), |(p, n), t| (p * t, n + 1));
+	let score = product.powf(1.0 / count as f32);
+	Some(Confidence { score, border_contrast, corner_sharpness, decode_margin })
+}
+
+/// Fill in the confidence of every marker. `offset` takes the markers' corners into the image's pixel coordinates.
+pub fn measure_markers(img: &GrayImage, markers: &mut [MarkerRecord], grid_size: Option<u32>, (dx, dy): (f32, f32)) {
+	for m in markers.iter_mut() {
+		m.confidence = measure(img, &m.corners.map(|(x, y)| (x + dx, y + dy)), grid_size);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::Luma;
+
+	// A 4x4 marker, 60 pixels across at (20, 20), alternating bits, on a white page.
+	fn synthetic_marker() -> GrayImage {
+		GrayImage::from_fn(100, 100, |x, y| {
+			let (cx, cy) = (x as i64 - 20, y as i64 - 20);
+			if !(0..60).contains(&cx) || !(0..60).contains(&cy) {
+				return Luma([230]);
+			}
+			let (i, j) = (cx / 10, cy / 10);
+			let border = i == 0 || j == 0 || i == 5 || j == 5;
+			if border || (i + j) % 2 == 0 { Luma([20]) } else { Luma([230]) }
+		})
+	}
+
+	#[test]
+	fn test_grid_size() {
+		assert_eq!(marker_grid_size("APRILTAG_36H11"), Some(6));
+		assert_eq!(marker_grid_size("ARUCO_MIP_16H3"), Some(4));
+		assert_eq!(marker_grid_size("ARUCO"), Some(5));
+		assert_eq!(marker_grid_size("SOMETHING_ELSE"), None);
+	}
+
+	#[test]
+	fn test_clean_marker_is_confident() {
+		let img = synthetic_marker();
+		let corners = [(20.0, 20.0), (80.0, 20.0), (80.0, 80.0), (20.0, 80.0)];
+		let c = measure(&img, &corners, Some(4)).unwrap();
+		assert!(c.border_contrast > 0.8, "{c:?}");
+		assert!(c.corner_sharpness > 0.9, "{c:?}");
+		assert!(c.decode_margin.unwrap() > 0.9, "{c:?}");
+		assert!(c.score > 0.8, "{c:?}");
+		// A quad that's half a cell off sees its bits split down the middle.
+		let shifted = corners.map(|(x, y)| (x + 5.0, y));
+		assert!(measure(&img, &shifted, Some(4)).unwrap().decode_margin.unwrap() < 0.5);
+	}
+}
@@ -0,0 +1,160 @@
+// The detection core: everything between a decoded image and a FrameRecord that doesn't care where the image came
+// from. The video pipeline feeds it frames from ffmpeg, and the WASM build (see wasm/) feeds it canvas pixels, so a
+// live preview in a browser finds exactly what the offline solve does.
+
+use aruco3::{ARDictionary, CameraIntrinsics, Detection, Detector, DetectorConfig};
+use crate::backend::FiducialDetector;
+use crate::confidence;
+use crate::dictionary::Dictionaries;
+use crate::distortion;
+use crate::filters;
+use crate::geometry::Pinhole;
+use crate::qr;
+use crate::record::{FrameRecord, MarkerRecord, PoseRecord};
+use image::{DynamicImage, GrayImage};
+
+pub struct FrameDetector {
+	aruco: Option<Detector>,
+	/// Another detector standing in for aruco3, see backend.rs.
+	backend: Option<Box<dyn FiducialDetector>>,
+	grid_size: Option<u32>,
+	dictionaries: Dictionaries,
+	marker_size_mm: f32,
+	qr_size_mm: f32,
+	max_hamming: Option<u32>,
+	/// Whether the detector found anything last frame, so a sudden dropout gets a second look.
+	detected_last_frame: bool,
+}
+
+/// What one frame turned up, before the ArUco poses are solved.
+pub struct Detections {
+	pub aruco: Option<Detection>,
+	/// What a backend standing in for aruco3 found, with poses already solved.
+	pub backend: Vec<MarkerRecord>,
+	pub qr_codes: Vec<MarkerRecord>,
+	/// The ArUco markers only turned up on a second look.
+	pub recovered: bool,
+}
+
+impl FrameDetector {
+	pub fn new(dictionaries: Dictionaries, marker_size_mm: f32, qr_size_mm: f32, max_hamming: Option<u32>) -> Self {
+		let aruco = dictionaries.aruco.as_deref().map(|name| Detector {
+			config: DetectorConfig::default(),
+			dictionary: ARDictionary::new_from_named_dict(name),
+		});
+		let grid_size = dictionaries.aruco.as_deref().and_then(confidence::marker_grid_size);
+		FrameDetector { aruco, backend: None, grid_size, dictionaries, marker_size_mm, qr_size_mm, max_hamming, detected_last_frame: false }
+	}
+
+	/// Look for the ArUco-style markers with `backend` instead of aruco3.
+	pub fn with_backend(mut self, backend: Box<dyn FiducialDetector>) -> Self {
+		self.aruco = None;
+		self.backend = Some(backend);
+		self
+	}
+
+	/// Find every marker in one frame's luma, `gray`, possibly cropped out of the full frame at `offset`. The ArUco
+	/// detector sees `color` instead if there is one, as with --rgb. Otherwise it's handed a copy of `gray`, since it
+	/// takes its image by value. Corners come back in full frame pixels.
+	pub fn find(&mut self, color: Option<DynamicImage>, gray: &GrayImage, offset: (f32, f32), pinhole: &Pinhole) -> Detections {
+		let mut recovered = false;
+		let aruco = self.aruco.as_ref().map(|detector| {
+			let mut detections = detector.detect(color.unwrap_or_else(|| DynamicImage::ImageLuma8(gray.clone())));
+			if detections.markers.is_empty() && self.detected_last_frame {
+				detections = retry_detection(detector, gray).unwrap_or(detections);
+				recovered = !detections.markers.is_empty();
+			}
+			if let Some(max_hamming) = self.max_hamming {
+				drop_distant_matches(&mut detections, max_hamming);
+			}
+			// The pose solve needs full-frame corners to line up with the principal point.
+			offset_detection_corners(&mut detections, offset);
+			detections
+		});
+		self.detected_last_frame = aruco.as_ref().is_some_and(|d| !d.markers.is_empty());
+		let backend = match self.backend.as_mut() {
+			Some(backend) => backend.detect(gray).into_iter()
+				.filter(|m| self.max_hamming.is_none_or(|max| m.hamming_distance.is_none_or(|d| d <= max)))
+				.map(|m| {
+					let corners = m.corners.map(|(x, y)| (x + offset.0, y + offset.1));
+					MarkerRecord {
+						marker_id: m.id,
+						corners,
+						poses: PoseRecord::from_quad(&corners, self.marker_size_mm, pinhole).into_iter().collect(),
+						hamming_distance: m.hamming_distance,
+						..Default::default()
+					}
+				})
+				.collect(),
+			None => vec![],
+		};
+		let qr_codes = if self.dictionaries.qr { qr::detect(gray, offset, pinhole, self.qr_size_mm) } else { vec![] };
+		Detections { aruco, backend, qr_codes, recovered }
+	}
+
+	/// Solve and score what `find` turned up. With `sample_edges`, markers also keep points along their edges for
+	/// --self-calibrate.
+	#[allow(clippy::too_many_arguments)]
+	pub fn record(&self, found: Detections, frame_id: usize, timestamp: f64, gray: &GrayImage, offset: (f32, f32), intrinsics: &CameraIntrinsics, pinhole: &Pinhole, sample_edges: bool) -> FrameRecord {
+		let Detections { aruco, backend, qr_codes, .. } = found;
+		let mut record = match &aruco {
+			Some(detections) => FrameRecord::from_detection(frame_id, timestamp, detections, self.marker_size_mm, intrinsics, pinhole),
+			None => FrameRecord { frame_id, timestamp, intrinsics: Some(*pinhole), markers: backend, ..Default::default() },
+		};
+		let local = (-offset.0, -offset.1);
+		// The terms all assume a black border, so QR codes go in without a score.
+		confidence::measure_markers(gray, &mut record.markers, self.grid_size, local);
+		if sample_edges {
+			distortion::sample_edges(gray, &mut record.markers, local);
+		}
+		record.markers.extend(qr_codes);
+		record
+	}
+}
+
+/// The detector's intrinsics for our own pinhole model, in pixels.
+pub fn intrinsics_for(pinhole: &Pinhole) -> CameraIntrinsics {
+	CameraIntrinsics::new(pinhole.width, pinhole.height, pinhole.fx, pinhole.fy, Some(pinhole.cx), Some(pinhole.cy))
+}
+
+/// When markers vanish from one frame to the next it's usually a fast move blurring the border. Try the frame at half
+/// size, which widens the detector's threshold window relative to the markers and halves the blur, then a sharpened
+/// copy, and keep the first that finds anything.
+fn retry_detection(detector: &Detector, gray: &GrayImage) -> Option<Detection> {
+	let mut half = detector.detect(DynamicImage::ImageLuma8(filters::half_size(gray)));
+	if !half.markers.is_empty() {
+		scale_detection_corners(&mut half, 2.0);
+		return Some(half);
+	}
+	Some(detector.detect(DynamicImage::ImageLuma8(filters::unsharp_mask(gray, 2, 1.5)))).filter(|d| !d.markers.is_empty())
+}
+
+// Keep only markers that matched their dictionary code within the given number of bit errors.
+#[allow(clippy::unnecessary_cast)]
+fn drop_distant_matches(detection: &mut Detection, max_hamming: u32) {
+	detection.markers.retain(|m| m.hamming_distance as u32 <= max_hamming);
+}
+
+// Shift detected corners in place. The detector picks the corner type, so we round-trip through f32.
+#[allow(clippy::unnecessary_cast)]
+fn offset_detection_corners(detection: &mut Detection, (dx, dy): (f32, f32)) {
+	if dx == 0.0 && dy == 0.0 {
+		return;
+	}
+	for m in detection.markers.iter_mut() {
+		for c in m.corners.iter_mut() {
+			c.0 = (c.0 as f32 + dx) as _;
+			c.1 = (c.1 as f32 + dy) as _;
+		}
+	}
+}
+
+#[allow(clippy::unnecessary_cast)]
+fn scale_detection_corners(detection: &mut Detection, factor: f32) {
+	for m in detection.markers.iter_mut() {
+		for c in m.corners.iter_mut() {
+			c.0 = (c.0 as f32 * factor) as _;
+			c.1 = (c.1 as f32 * factor) as _;
+		}
+	}
+}
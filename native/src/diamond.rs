@@ -0,0 +1,157 @@
+// ChArUco diamonds, for --diamond-square-mm: a 3x3 chessboard with an ArUco marker in each of its four white squares,
+// so four ids around the one black square in the middle. Four ids give far more combinations than one marker of the
+// same size, which is why they're used to tag small props, and the pose comes from all sixteen marker corners at once,
+// which is much steadier than any one marker's.
+//
+// The markers are found by the usual detector and grouped here afterwards. Each diamond goes out as one more marker:
+// its corners are the middle square's, its pose is solved at the middle square's center, and its ids are in the order
+// OpenCV prints them in, top, left, right, bottom. Like a QR code's, its marker_id is a hash (of the ids), so the same
+// diamond gets the same id in every run.
+
+use crate::geometry::{self, Pinhole, Vec3};
+use crate::record::{FrameRecord, MarkerRecord, PoseRecord};
+
+/// How far from where the top marker says a neighbour should be it still counts, as a fraction of the squares' pitch.
+const TOLERANCE: f32 = 0.25;
+
+/// Where each marker's square is, in the diamond's own space: centered on the middle square with +Y up, in squares.
+const CELLS: [(f32, f32); 4] = [(0.0, 1.0), (-1.0, 0.0), (1.0, 0.0), (0.0, -1.0)];
+
+/// A stable id for the four ids. 32-bit FNV-1a over them, as for QR payloads.
+pub fn diamond_id(ids: &[usize; 4]) -> usize {
+	let mut hash: u32 = 0x811c9dc5;
+	for id in ids {
+		for byte in (*id as u32).to_le_bytes() {
+			hash ^= byte as u32;
+			hash = hash.wrapping_mul(0x01000193);
+		}
+	}
+	hash as usize
+}
+
+pub struct DiamondFinder {
+	marker_size_mm: f32,
+	square_size_mm: f32,
+}
+
+impl DiamondFinder {
+	pub fn new(marker_size_mm: f32, square_size_mm: f32) -> Self {
+		DiamondFinder { marker_size_mm, square_size_mm }
+	}
+
+	/// A marker's corners in the diamond's space.
+	fn model(&self, cell: usize) -> [Vec3; 4] {
+		let (x, y) = CELLS[cell];
+		geometry::marker_corners(self.marker_size_mm).map(|p| [p[0] + x * self.square_size_mm, p[1] + y * self.square_size_mm, 0.0])
+	}
+
+	/// Add a marker for every diamond among the record's ArUco markers.
+	pub fn apply(&self, record: &mut FrameRecord) {
+		let Some(pinhole) = record.intrinsics else {
+			return;
+		};
+		let candidates: Vec<&MarkerRecord> = record.markers.iter().filter(|m| m.payload.is_none() && m.diamond.is_none()).collect();
+		let mut used = vec![false; candidates.len()];
+		let mut diamonds = vec![];
+		for top in 0..candidates.len() {
+			if used[top] {
+				continue;
+			}
+			let Some(members) = self.group(&candidates, top, &used) else {
+				continue;
+			};
+			if let Some(diamond) = self.solve(&members.map(|idx| candidates[idx]), &pinhole) {
+				for idx in members {
+					used[idx] = true;
+				}
+				diamonds.push(diamond);
+			}
+		}
+		record.markers.extend(diamonds);
+	}
+
+	/// With `top` as the top marker, the markers in each of the four squares, going by where its corners put them.
+	fn group(&self, candidates: &[&MarkerRecord], top: usize, used: &[bool]) -> Option<[usize; 4]> {
+		let h = geometry::square_to_quad(&candidates[top].corners)?;
+		// The top marker's unit square spans its own marker, so the others are out in multiples of the square's pitch.
+		let to_image = |p: &Vec3| {
+			let (x, y) = (p[0] - CELLS[0].0 * self.square_size_mm, p[1] - CELLS[0].1 * self.square_size_mm);
+			geometry::apply_homography(&h, (x / self.marker_size_mm + 0.5, 0.5 - y / self.marker_size_mm))
+		};
+		let center = |corners: &[(f32, f32); 4]| {
+			let sum = corners.iter().fold((0.0, 0.0), |(x, y), c| (x + c.0, y + c.1));
+			(sum.0 / 4.0, sum.1 / 4.0)
+		};
+		let distance = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0).hypot(a.1 - b.1);
+		let middle = to_image(&[0.0; 3]);
+		let mut members = [top; 4];
+		for (cell, member) in members.iter_mut().enumerate().skip(1) {
+			let model = self.model(cell);
+			let expected = center(&model.map(|p| to_image(&p)));
+			let tolerance = TOLERANCE * distance(expected, middle);
+			let (idx, found) = candidates.iter().enumerate()
+				.filter(|(idx, _)| *idx != top && !used[*idx])
+				.min_by(|(_, a), (_, b)| distance(center(&a.corners), expected).total_cmp(&distance(center(&b.corners), expected)))?;
+			// It has to be the right way up too, or it's some other marker that happens to be nearby.
+			if distance(center(&found.corners), expected) > tolerance || distance(found.corners[0], to_image(&model[0])) > tolerance {
+				return None;
+			}
+			*member = idx;
+		}
+		Some(members)
+	}
+
+	/// The diamond's marker, posed from every corner of its four markers.
+	fn solve(&self, members: &[&MarkerRecord; 4], pinhole: &Pinhole) -> Option<MarkerRecord> {
+		let points: Vec<(Vec3, (f32, f32))> = members.iter().enumerate()
+			.flat_map(|(cell, m)| self.model(cell).into_iter().zip(m.corners))
+			.collect();
+		// Start from each of the top marker's candidate poses, moved down a square to the middle.
+		let (rotation, translation, cost) = members[0].poses.iter().map(|pose| {
+			let offset = geometry::mat_mul_vec(&pose.rotation, &[0.0, -CELLS[0].1 * self.square_size_mm, 0.0]);
+			let (rotation, translation) = geometry::refine_pose(&pose.rotation, &geometry::add(&pose.translation, &offset), &points, pinhole);
+			(rotation, translation, geometry::reprojection_cost(&rotation, &translation, &points, pinhole))
+		}).min_by(|a, b| a.2.total_cmp(&b.2))?;
+		let corners = geometry::marker_corners(self.square_size_mm).map(|p| pinhole.project(&geometry::add(&geometry::mat_mul_vec(&rotation, &p), &translation)));
+		let rms = (cost / points.len() as f32).sqrt();
+		let ids = members.map(|m| m.marker_id);
+		Some(MarkerRecord {
+			marker_id: diamond_id(&ids),
+			corners,
+			poses: vec![PoseRecord { rotation, translation, error: rms, reprojection_error: Some(rms) }],
+			hamming_distance: members.iter().filter_map(|m| m.hamming_distance).max(),
+			diamond: Some(ids),
+			..Default::default()
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_find_diamond() {
+		let pinhole = Pinhole { width: 1280, height: 720, fx: 900.0, fy: 900.0, cx: 640.0, cy: 360.0 };
+		let finder = DiamondFinder::new(30.0, 40.0);
+		let rotation = geometry::rodrigues(&[0.3, -0.2, 0.1]);
+		let translation = [40.0, -20.0, 500.0];
+		let project = |p: &Vec3| pinhole.project(&geometry::add(&geometry::mat_mul_vec(&rotation, p), &translation));
+		let ids = [17, 3, 250, 42];
+		let mut markers: Vec<MarkerRecord> = (0..4).map(|cell| {
+			let corners = finder.model(cell).map(|p| project(&p));
+			MarkerRecord { marker_id: ids[cell], corners, poses: PoseRecord::from_quad(&corners, 30.0, &pinhole).into_iter().collect(), ..Default::default() }
+		}).collect();
+		// Somebody else's marker off to the side.
+		let stray = markers[2].corners.map(|(x, y)| (x + 300.0, y));
+		markers.push(MarkerRecord { marker_id: 5, corners: stray, poses: PoseRecord::from_quad(&stray, 30.0, &pinhole).into_iter().collect(), ..Default::default() });
+		let mut record = FrameRecord { intrinsics: Some(pinhole), markers, ..Default::default() };
+		finder.apply(&mut record);
+		assert_eq!(record.markers.len(), 6);
+		let diamond = &record.markers[5];
+		assert_eq!((diamond.marker_id, diamond.diamond), (diamond_id(&ids), Some(ids)));
+		let pose = &diamond.poses[0];
+		assert!(geometry::length(&geometry::sub(&pose.translation, &translation)) < 1.0, "{:?}", pose.translation);
+		assert!(pose.reprojection_error.unwrap() < 0.1);
+	}
+}
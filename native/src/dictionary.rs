@@ -0,0 +1,101 @@
+// The fiducial_dictionary argument, which can name more than one family of markers joined with '+':
+//   ARUCO                              one of aruco3's dictionaries (see --print-supported-dictionaries)
+//   QR                                 QR codes, see qr.rs
+//   AUTO                               whichever of aruco3's dictionaries finds the most markers in the first frames
+// so "APRILTAG_36H11+QR" tracks both kinds at once.
+//
+// AUTO is for when nobody knows which ArUco variant was printed. The command line tool tries every dictionary on a
+// few frames (see identify) and tracks with the best. Markers from a small dictionary often read as some code of a
+// bigger one with a few bits corrected, so clean matches count for more than corrected ones.
+
+use aruco3::{ARDictionary, Detector, DetectorConfig};
+use crate::qr;
+use image::{DynamicImage, GrayImage};
+
+pub const AUTO: &str = "AUTO";
+
+#[derive(Clone, Debug, Default)]
+pub struct Dictionaries {
+	/// The dictionary for aruco3's square marker detector, if any.
+	pub aruco: Option<String>,
+	/// Pick `aruco` from the footage. Until it's picked, no ArUco markers are looked for.
+	pub auto: bool,
+	pub qr: bool,
+}
+
+pub fn parse_dictionaries(text: &str) -> Result<Dictionaries, String> {
+	let mut out = Dictionaries::default();
+	for name in text.split('+') {
+		if name.eq_ignore_ascii_case(qr::QR) {
+			out.qr = true;
+		} else if name.eq_ignore_ascii_case(AUTO) && out.aruco.is_none() {
+			out.auto = true;
+		} else if let Some(previous) = out.aruco.as_deref().or(out.auto.then_some(AUTO)) {
+			return Err(format!("Only one ArUco-style dictionary can be used at a time, got {previous} and {name}."));
+		} else {
+			out.aruco = Some(name.to_string());
+		}
+	}
+	Ok(out)
+}
+
+/// How one dictionary did on the sample frames.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DictionaryScore {
+	pub name: String,
+	pub detections: usize,
+	/// Detections that matched a code without any bits corrected.
+	pub exact: usize,
+	/// Frames with at least one detection.
+	pub frames: usize,
+}
+
+/// Try each of aruco3's dictionaries on the frames, best first.
+#[allow(clippy::unnecessary_cast)]
+pub fn identify(frames: &[GrayImage], names: &[String]) -> Vec<DictionaryScore> {
+	let mut scores: Vec<DictionaryScore> = std::thread::scope(|scope| {
+		let workers: Vec<_> = names.iter().map(|name| scope.spawn(move || {
+			let detector = Detector { config: DetectorConfig::default(), dictionary: ARDictionary::new_from_named_dict(name) };
+			let mut score = DictionaryScore { name: name.clone(), detections: 0, exact: 0, frames: 0 };
+			for frame in frames {
+				let markers = detector.detect(DynamicImage::ImageLuma8(frame.clone())).markers;
+				score.detections += markers.len();
+				score.exact += markers.iter().filter(|m| m.hamming_distance as u32 == 0).count();
+				score.frames += usize::from(!markers.is_empty());
+			}
+			score
+		})).collect();
+		workers.into_iter().map(|worker| worker.join().expect("Detection doesn't panic.")).collect()
+	});
+	rank(&mut scores);
+	scores
+}
+
+fn rank(scores: &mut [DictionaryScore]) {
+	scores.sort_by(|a, b| b.exact.cmp(&a.exact).then(b.detections.cmp(&a.detections)).then(a.name.cmp(&b.name)));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_dictionaries() {
+		let d = parse_dictionaries("APRILTAG_36H11+qr").unwrap();
+		assert_eq!(d.aruco.as_deref(), Some("APRILTAG_36H11"));
+		assert!(d.qr);
+		assert!(parse_dictionaries("QR").unwrap().aruco.is_none());
+		assert!(parse_dictionaries("ARUCO+APRILTAG_36H11").is_err());
+		let auto = parse_dictionaries("auto+QR").unwrap();
+		assert!(auto.auto && auto.aruco.is_none());
+		assert!(parse_dictionaries("AUTO+ARUCO").is_err());
+	}
+
+	#[test]
+	fn test_rank_prefers_clean_matches() {
+		let score = |name: &str, detections, exact| DictionaryScore { name: name.to_string(), detections, exact, frames: 10 };
+		let mut scores = vec![score("ARUCO_MIP_36H12", 40, 2), score("ARUCO", 30, 30), score("APRILTAG_16H5", 30, 30)];
+		rank(&mut scores);
+		assert_eq!(scores.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), vec!["APRILTAG_16H5", "ARUCO", "ARUCO_MIP_36H12"]);
+	}
+}
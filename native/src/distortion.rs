@@ -0,0 +1,326 @@
+// Lens distortion self-calibration, for --self-calibrate, when there's no calibration for the lens. Everything else
+// assumes an ideal pinhole, so a wide lens's barrel distortion bends the markers near the edge of frame and skews their
+// poses.
+//
+// While tracking, each marker's border gets measured at a few points along every side (sample_edges). Once the video
+// is done we fit Brown's radial model, k1 and k2 about a principal point, to everything we saw from each camera:
+//   - through the right lens, the points along each side of a marker fall on a straight line, and
+//   - each marker's undistorted corners look like a square of the known size from some pose.
+// The first term does most of the work, and only needs markers that are big in frame and off center, where the bend
+// is actually visible. The second leans on the focal length being right, so it's weighted down. Then every record's
+// corners are undistorted and its poses solved again from them (Distortion::apply).
+
+use crate::confidence;
+use crate::geometry::{self, Pinhole};
+use crate::record::{FrameRecord, MarkerRecord, PoseRecord};
+use crate::value::Value;
+use image::GrayImage;
+use std::collections::BTreeMap;
+
+/// Points measured along each side, leaving the ends alone since the corners are already measured there.
+const EDGE_SAMPLES: usize = 8;
+/// How far either side of the corners' straight line to look for the border's edge, in pixels.
+const SEARCH_PX: f32 = 3.0;
+/// The smallest step in brightness across a pixel that counts as the edge.
+const MIN_CONTRAST: f32 = 20.0;
+/// Sides shorter than this in pixels are too short to show any bend.
+const MIN_EDGE_PX: f32 = 20.0;
+/// We won't guess at a lens from fewer sides than this.
+const MIN_LINES: usize = 40;
+/// Past this many markers the fit is plenty constrained, so longer videos get thinned out evenly.
+const MAX_OBSERVATIONS: usize = 2000;
+/// How much a square that won't fit counts next to a bent side.
+const SQUARE_WEIGHT: f64 = 0.25;
+/// The principal point is only weakly pinned down by bent sides, so it's held softly to the middle of the frame. A
+/// pixel away costs about as much as every sample being this many pixels off its line.
+const CENTER_WEIGHT: f64 = 0.02;
+
+/// Radial distortion to the fourth order: a point at normalized radius r from (cx, cy) is imaged at
+/// r * (1 + k1 r^2 + k2 r^4). Negative k1 is barrel distortion.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Distortion {
+	pub k1: f32,
+	pub k2: f32,
+	pub cx: f32,
+	pub cy: f32,
+}
+
+impl Distortion {
+	fn params(&self) -> [f64; 4] {
+		[self.k1 as f64, self.k2 as f64, self.cx as f64, self.cy as f64]
+	}
+
+	/// Where a detected pixel would have been imaged through an ideal lens with the same focal length.
+	pub fn undistort(&self, pinhole: &Pinhole, p: (f32, f32)) -> (f32, f32) {
+		let [u, v] = undistort(&self.params(), pinhole, p);
+		(u as f32, v as f32)
+	}
+
+	/// Undistort a record's corners, move its principal point, and solve its poses again through the ideal lens.
+	pub fn apply(&self, record: &mut FrameRecord, marker_size_mm: f32, qr_size_mm: f32) {
+		let Some(pinhole) = record.intrinsics.as_mut() else {
+			return;
+		};
+		(pinhole.cx, pinhole.cy) = (self.cx, self.cy);
+		let pinhole = *pinhole;
+		for m in record.markers.iter_mut() {
+			m.corners = m.corners.map(|c| self.undistort(&pinhole, c));
+			m.edges.clear();
+			let size = if m.payload.is_some() { qr_size_mm } else { marker_size_mm };
+			m.poses = solve(&m.corners, size, &pinhole).into_iter().collect();
+		}
+	}
+
+	pub fn to_value(&self, camera_id: Option<&str>) -> Value {
+		let mut out = vec![];
+		if let Some(camera_id) = camera_id {
+			out.push(("camera_id".to_string(), Value::Str(camera_id.to_string())));
+		}
+		out.extend([
+			("k1".to_string(), Value::F32(self.k1)),
+			("k2".to_string(), Value::F32(self.k2)),
+			("cx".to_string(), Value::F32(self.cx)),
+			("cy".to_string(), Value::F32(self.cy)),
+		]);
+		Value::Map(out)
+	}
+}
+
+fn undistort(params: &[f64; 4], pinhole: &Pinhole, (u, v): (f32, f32)) -> [f64; 2] {
+	let [k1, k2, cx, cy] = *params;
+	let (fx, fy) = (pinhole.fx as f64, pinhole.fy as f64);
+	let (xd, yd) = ((u as f64 - cx) / fx, (v as f64 - cy) / fy);
+	// No closed form this way round, but fixed point iteration gets there for any lens we could track through.
+	let (mut x, mut y) = (xd, yd);
+	for _ in 0..10 {
+		let r2 = x * x + y * y;
+		let factor = (1.0 + k1 * r2 + k2 * r2 * r2).max(0.1);
+		(x, y) = (xd / factor, yd / factor);
+	}
+	[fx * x + cx, fy * y + cy]
+}
+
+/// Our homography pose for the square, polished against its corners.
+fn solve(corners: &[(f32, f32); 4], marker_size_mm: f32, pinhole: &Pinhole) -> Option<PoseRecord> {
+	let mut pose = PoseRecord::from_quad(corners, marker_size_mm, pinhole)?;
+	let points: Vec<_> = geometry::marker_corners(marker_size_mm).into_iter().zip(corners.iter().copied()).collect();
+	(pose.rotation, pose.translation) = geometry::refine_pose(&pose.rotation, &pose.translation, &points, pinhole);
+	let rms = pose.reprojection_rms(corners, pinhole, marker_size_mm);
+	(pose.error, pose.reprojection_error) = (rms, Some(rms));
+	Some(pose)
+}
+
+/// Measure where each side of each marker's border really is, for the fit. Corners are full-frame and `offset` takes
+/// them into the (maybe cropped) image.
+pub fn sample_edges(img: &GrayImage, markers: &mut [MarkerRecord], (dx, dy): (f32, f32)) {
+	for m in markers.iter_mut() {
+		let center = m.corners.iter().fold((0.0, 0.0), |acc, c| (acc.0 + c.0 / 4.0, acc.1 + c.1 / 4.0));
+		m.edges = (0..4).filter_map(|i| {
+			let (a, b) = (m.corners[i], m.corners[(i + 1) % 4]);
+			let (ex, ey) = (b.0 - a.0, b.1 - a.1);
+			let length = (ex * ex + ey * ey).sqrt();
+			if length < MIN_EDGE_PX {
+				return None;
+			}
+			// Point the normal out of the marker, so the edge is a step up from the dark border to the quiet zone.
+			let (mut nx, mut ny) = (-ey / length, ex / length);
+			if nx * ((a.0 + b.0) / 2.0 - center.0) + ny * ((a.1 + b.1) / 2.0 - center.1) < 0.0 {
+				(nx, ny) = (-nx, -ny);
+			}
+			let points: Vec<_> = (0..EDGE_SAMPLES).filter_map(|s| {
+				let t = 0.15 + 0.7 * s as f32 / (EDGE_SAMPLES - 1) as f32;
+				let (x, y) = (a.0 + ex * t, a.1 + ey * t);
+				let offset = find_edge(img, (x + dx, y + dy), (nx, ny))?;
+				Some((x + nx * offset, y + ny * offset))
+			}).collect();
+			(points.len() > EDGE_SAMPLES / 2).then_some(points)
+		}).collect();
+	}
+}
+
+/// Distance along the normal to the strongest dark to light step near a point, to a fraction of a pixel.
+fn find_edge(img: &GrayImage, (x, y): (f32, f32), (nx, ny): (f32, f32)) -> Option<f32> {
+	const STEP: f32 = 0.5;
+	let n = (SEARCH_PX / STEP) as i32;
+	let profile: Vec<f32> = (-n - 1..=n + 1).map(|k| confidence::sample(img, (x + nx * k as f32 * STEP, y + ny * k as f32 * STEP))).collect::<Option<_>>()?;
+	// Central differences, so gradient[i] is at i - n steps along the normal.
+	let gradient: Vec<f32> = profile.windows(3).map(|w| w[2] - w[0]).collect();
+	let (best, &peak) = gradient.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1))?;
+	if peak < MIN_CONTRAST || best == 0 || best == gradient.len() - 1 {
+		return None;
+	}
+	// The top of a parabola through the peak and its neighbours.
+	let (left, right) = (gradient[best - 1], gradient[best + 1]);
+	let curvature = left - 2.0 * peak + right;
+	let shift = if curvature < 0.0 { 0.5 * (left - right) / curvature } else { 0.0 };
+	Some((best as f32 - n as f32 + shift) * STEP)
+}
+
+struct Observation {
+	pinhole: Pinhole,
+	corners: [(f32, f32); 4],
+	edges: Vec<Vec<(f32, f32)>>,
+}
+
+/// Fit each camera's lens to the markers in its records. Cameras without enough to go on are left out.
+pub fn estimate(records: &[FrameRecord], marker_size_mm: f32) -> BTreeMap<Option<String>, Distortion> {
+	let mut cameras: BTreeMap<Option<String>, Vec<Observation>> = BTreeMap::new();
+	for record in records {
+		let Some(pinhole) = record.intrinsics else {
+			continue;
+		};
+		cameras.entry(record.camera_id.clone()).or_default().extend(record.markers.iter()
+			.filter(|m| !m.edges.is_empty())
+			.map(|m| Observation { pinhole, corners: m.corners, edges: m.edges.clone() }));
+	}
+	cameras.into_iter().filter_map(|(camera_id, observations)| Some((camera_id, fit(&observations, marker_size_mm)?))).collect()
+}
+
+fn fit(observations: &[Observation], marker_size_mm: f32) -> Option<Distortion> {
+	if observations.iter().map(|o| o.edges.len()).sum::<usize>() < MIN_LINES {
+		return None;
+	}
+	let observations: Vec<_> = observations.iter().step_by(observations.len().div_ceil(MAX_OBSERVATIONS)).collect();
+	let pinhole = observations[0].pinhole;
+	let center = [pinhole.cx as f64, pinhole.cy as f64];
+	let samples: usize = observations.iter().map(|o| o.edges.iter().map(Vec::len).sum::<usize>()).sum();
+	let center_weight = CENTER_WEIGHT * (samples as f64).sqrt();
+	let residuals = |params: &[f64; 4]| -> Vec<f64> {
+		let mut out = Vec::with_capacity(samples + 8 * observations.len() + 2);
+		for o in &observations {
+			for edge in &o.edges {
+				let points: Vec<[f64; 2]> = edge.iter().map(|&p| undistort(params, &o.pinhole, p)).collect();
+				out.extend(line_distances(&points));
+			}
+			let pinhole = Pinhole { cx: params[2] as f32, cy: params[3] as f32, ..o.pinhole };
+			let corners = o.corners.map(|c| {
+				let [u, v] = undistort(params, &o.pinhole, c);
+				(u as f32, v as f32)
+			});
+			// Always the same number of residuals, or the differences below don't line up.
+			let mut square = [0.0; 8];
+			if let Some((rotation, translation)) = geometry::pose_from_quad(&corners, marker_size_mm, &pinhole) {
+				for (i, (model, (u, v))) in geometry::marker_corners(marker_size_mm).iter().zip(corners).enumerate() {
+					let (pu, pv) = pinhole.project(&geometry::add(&geometry::mat_mul_vec(&rotation, model), &translation));
+					square[2 * i] = SQUARE_WEIGHT * (pu - u) as f64;
+					square[2 * i + 1] = SQUARE_WEIGHT * (pv - v) as f64;
+				}
+			}
+			out.extend(square);
+		}
+		out.push(center_weight * (params[2] - center[0]));
+		out.push(center_weight * (params[3] - center[1]));
+		out
+	};
+	let [k1, k2, cx, cy] = levenberg_marquardt([0.0, 0.0, center[0], center[1]], [1e-3, 1e-3, 0.5, 0.5], residuals);
+	Some(Distortion { k1: k1 as f32, k2: k2 as f32, cx: cx as f32, cy: cy as f32 })
+}
+
+/// Signed distances from each point to the points' total least squares line.
+fn line_distances(points: &[[f64; 2]]) -> impl Iterator<Item = f64> + '_ {
+	let n = points.len() as f64;
+	let (mx, my) = points.iter().fold((0.0, 0.0), |acc, p| (acc.0 + p[0] / n, acc.1 + p[1] / n));
+	let (sxx, sxy, syy) = points.iter().fold((0.0, 0.0, 0.0), |acc, p| {
+		let (x, y) = (p[0] - mx, p[1] - my);
+		(acc.0 + x * x, acc.1 + x * y, acc.2 + y * y)
+	});
+	// The line runs along the spread's major axis.
+	let angle = 0.5 * (2.0 * sxy).atan2(sxx - syy);
+	let (nx, ny) = (-angle.sin(), angle.cos());
+	points.iter().map(move |p| (p[0] - mx) * nx + (p[1] - my) * ny)
+}
+
+/// Minimize the sum of squared residuals, with the Jacobian taken by central differences of the given step sizes.
+fn levenberg_marquardt(mut params: [f64; 4], steps: [f64; 4], residuals: impl Fn(&[f64; 4]) -> Vec<f64>) -> [f64; 4] {
+	let cost = |r: &[f64]| r.iter().map(|v| v * v).sum::<f64>();
+	let mut current = residuals(&params);
+	let mut damping = 1e-3;
+	for _ in 0..30 {
+		let jacobian: Vec<Vec<f64>> = (0..4).map(|i| {
+			let (mut plus, mut minus) = (params, params);
+			plus[i] += steps[i];
+			minus[i] -= steps[i];
+			residuals(&plus).iter().zip(residuals(&minus)).map(|(p, m)| (p - m) / (2.0 * steps[i])).collect()
+		}).collect();
+		let mut jtj = [[0.0; 4]; 4];
+		let mut jtr = [0.0; 4];
+		for (i, (row, rhs)) in jtj.iter_mut().zip(jtr.iter_mut()).enumerate() {
+			for (j, v) in row.iter_mut().enumerate() {
+				*v = jacobian[i].iter().zip(&jacobian[j]).map(|(a, b)| a * b).sum();
+			}
+			*rhs = -jacobian[i].iter().zip(&current).map(|(a, r)| a * r).sum::<f64>();
+		}
+		let mut improved = false;
+		while damping < 1e6 {
+			let mut damped = jtj;
+			for (i, row) in damped.iter_mut().enumerate() {
+				row[i] += damping * jtj[i][i].max(1e-12);
+			}
+			let Some(step) = geometry::solve_linear(damped, jtr) else {
+				damping *= 10.0;
+				continue;
+			};
+			let candidate: [f64; 4] = std::array::from_fn(|i| params[i] + step[i]);
+			let candidate_residuals = residuals(&candidate);
+			let (before, after) = (cost(&current), cost(&candidate_residuals));
+			if after < before {
+				(params, current) = (candidate, candidate_residuals);
+				damping = (damping * 0.1).max(1e-9);
+				improved = before - after > 1e-9 * before;
+				break;
+			}
+			damping *= 10.0;
+		}
+		if !improved {
+			break;
+		}
+	}
+	params
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::geometry::Vec3;
+
+	#[test]
+	fn test_recovers_barrel_distortion() {
+		let pinhole = Pinhole { width: 1000, height: 1000, fx: 800.0, fy: 800.0, cx: 500.0, cy: 500.0 };
+		let lens = Distortion { k1: -0.2, k2: 0.05, cx: 500.0, cy: 500.0 };
+		let distort = |p: Vec3| {
+			let (x, y) = (p[0] / p[2], p[1] / p[2]);
+			let r2 = x * x + y * y;
+			let factor = 1.0 + lens.k1 * r2 + lens.k2 * r2 * r2;
+			(pinhole.fx * x * factor + lens.cx, pinhole.fy * y * factor + lens.cy)
+		};
+		// A 5x5 grid of 80mm markers at 600mm, each tipped a little differently, out to the corners of the frame.
+		let size = 80.0;
+		let mut truth = vec![];
+		let markers = (0..25).map(|i| {
+			let (gx, gy) = ((i % 5) as f32 - 2.0, (i / 5) as f32 - 2.0);
+			let rotation = geometry::rodrigues(&[0.1 * gy, -0.1 * gx, 0.05 * i as f32]);
+			let translation = [150.0 * gx, 150.0 * gy, 600.0];
+			truth.push(translation);
+			let model = geometry::marker_corners(size);
+			let to_camera = |p: &Vec3| geometry::add(&geometry::mat_mul_vec(&rotation, p), &translation);
+			let edges = (0..4).map(|e| (1..EDGE_SAMPLES - 1).map(|s| {
+				let t = s as f32 / (EDGE_SAMPLES - 1) as f32;
+				distort(to_camera(&geometry::lerp_vec3(&model[e], &model[(e + 1) % 4], t)))
+			}).collect()).collect();
+			MarkerRecord { marker_id: i, corners: model.map(|c| distort(to_camera(&c))), edges, ..Default::default() }
+		}).collect();
+		let mut record = FrameRecord { intrinsics: Some(pinhole), markers, ..Default::default() };
+
+		let estimate = estimate(std::slice::from_ref(&record), size);
+		let fitted = estimate[&None];
+		assert!((fitted.k1 - lens.k1).abs() < 0.02, "{fitted:?}");
+		assert!((fitted.cx - lens.cx).abs() < 2.0 && (fitted.cy - lens.cy).abs() < 2.0, "{fitted:?}");
+
+		fitted.apply(&mut record, size, size);
+		for (m, expected) in record.markers.iter().zip(&truth) {
+			let found = m.poses[0].translation;
+			assert!(geometry::length(&geometry::sub(&found, expected)) < 3.0, "{found:?} vs {expected:?}");
+			assert!(m.edges.is_empty());
+		}
+	}
+}
@@ -0,0 +1,66 @@
+// Small grayscale image operations for giving the detector a second look at a frame.
+
+use image::{GrayImage, Luma};
+
+/// Mean over a (2 * radius + 1) square window, clamped at the edges. Two separable passes of running sums.
+pub fn box_blur(img: &GrayImage, radius: u32) -> GrayImage {
+	let (w, h) = (img.width() as usize, img.height() as usize);
+	let r = radius as usize;
+	let pass = |src: &[f32], w: usize, h: usize| -> Vec<f32> {
+		// Blur along rows, writing the result transposed so the second pass also runs along rows.
+		let mut out = vec![0.0; w * h];
+		for y in 0..h {
+			let row = &src[y * w..(y + 1) * w];
+			let mut prefix = vec![0.0f32; w + 1];
+			for x in 0..w {
+				prefix[x + 1] = prefix[x] + row[x];
+			}
+			for x in 0..w {
+				let (x0, x1) = (x.saturating_sub(r), (x + r + 1).min(w));
+				out[x * h + y] = (prefix[x1] - prefix[x0]) / (x1 - x0) as f32;
+			}
+		}
+		out
+	};
+	let values: Vec<f32> = img.as_raw().iter().map(|v| *v as f32).collect();
+	let blurred = pass(&pass(&values, w, h), h, w);
+	GrayImage::from_fn(img.width(), img.height(), |x, y| Luma([blurred[y as usize * w + x as usize].round() as u8]))
+}
+
+/// Sharpen by adding back `amount` times the difference from a blurred copy. Helps with mild motion blur, where the
+/// border's edges are smeared over a few pixels.
+pub fn unsharp_mask(img: &GrayImage, radius: u32, amount: f32) -> GrayImage {
+	let blurred = box_blur(img, radius);
+	GrayImage::from_fn(img.width(), img.height(), |x, y| {
+		let (v, b) = (img.get_pixel(x, y)[0] as f32, blurred.get_pixel(x, y)[0] as f32);
+		Luma([(v + amount * (v - b)).round().clamp(0.0, 255.0) as u8])
+	})
+}
+
+/// Half the width and height, averaging each 2x2 block. A detector with a fixed-size threshold window then looks at
+/// twice the area around each pixel, and blur is half as many pixels wide.
+pub fn half_size(img: &GrayImage) -> GrayImage {
+	GrayImage::from_fn((img.width() / 2).max(1), (img.height() / 2).max(1), |x, y| {
+		let (x0, y0) = ((2 * x).min(img.width() - 1), (2 * y).min(img.height() - 1));
+		let (x1, y1) = ((x0 + 1).min(img.width() - 1), (y0 + 1).min(img.height() - 1));
+		let sum = [(x0, y0), (x1, y0), (x0, y1), (x1, y1)].iter().map(|&(x, y)| img.get_pixel(x, y)[0] as u32).sum::<u32>();
+		Luma([((sum + 2) / 4) as u8])
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_unsharp_mask_steepens_edges() {
+		// A soft ramp from dark to light across the middle.
+		let img = GrayImage::from_fn(32, 8, |x, _| Luma([(x.saturating_sub(12) * 32).min(255) as u8]));
+		assert_eq!(box_blur(&img, 2).get_pixel(0, 0)[0], 0);
+		let sharp = unsharp_mask(&img, 2, 1.0);
+		// The foot of the ramp gets pulled darker, so the edge is steeper.
+		assert!(sharp.get_pixel(13, 4)[0] < img.get_pixel(13, 4)[0]);
+		assert_eq!(sharp.get_pixel(0, 4)[0], 0);
+		assert_eq!(half_size(&img).width(), 16);
+	}
+}
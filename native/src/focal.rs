@@ -0,0 +1,129 @@
+// Focal length from the markers themselves, for --refine-focal, when the lens settings are only roughly known (a phone,
+// or somewhere along a zoom). A square seen at an angle only looks like a square of its size through the right focal
+// length: through any other, even the best pose leaves its corners a little off. So we try focal lengths, solve every
+// marker's pose through each, and keep whichever leaves the least reprojection error over all of them together.
+//
+// Markers square on to the camera look the same through any focal length, so it takes a few seen at an angle. The
+// principal point can be fit the same way afterwards, but that needs markers spread over the frame to pin it down.
+
+use crate::geometry::{self, Pinhole};
+
+/// We won't fit a lens to fewer markers than this.
+const MIN_MARKERS: usize = 8;
+/// Past this many markers the fit is plenty constrained, so longer samples get thinned out evenly.
+const MAX_MARKERS: usize = 400;
+/// How far either way from the first guess to look, as a factor.
+const SEARCH_RANGE: f32 = 4.0;
+/// Focal lengths tried across that range before narrowing in on the best.
+const COARSE_STEPS: usize = 48;
+/// How far the principal point can move from the first guess, as a fraction of the frame's size.
+const CENTER_RANGE: f32 = 0.1;
+/// Across the search range the error has to change by at least this much (RMS, in pixels), or the markers don't say
+/// anything about the focal length.
+const MIN_CONTRAST_PX: f32 = 0.5;
+
+pub struct FocalFit {
+	pub pinhole: Pinhole,
+	/// RMS reprojection error in pixels over the markers used, through the first guess.
+	pub rms_before: f32,
+	/// And through the fit.
+	pub rms_after: f32,
+	pub markers: usize,
+}
+
+/// Fit the focal length (and with `principal_point`, the principal point) to markers' corners in full frame pixels,
+/// starting from `initial`. None when there aren't enough markers or they're too square on to tell.
+pub fn refine(quads: &[[(f32, f32); 4]], marker_size_mm: f32, initial: &Pinhole, principal_point: bool) -> Option<FocalFit> {
+	if quads.len() < MIN_MARKERS || initial.fx <= 0.0 || !initial.fx.is_finite() {
+		return None;
+	}
+	let quads: Vec<_> = quads.iter().step_by(quads.len().div_ceil(MAX_MARKERS)).collect();
+	let model = geometry::marker_corners(marker_size_mm);
+	let samples = 4 * quads.len();
+	let rms = |pinhole: &Pinhole| (cost(&quads, &model, marker_size_mm, pinhole) / samples as f32).sqrt();
+	let with_focal = |pinhole: &Pinhole, f: f32| Pinhole { fx: f, fy: f, ..*pinhole };
+
+	// Square pixels, so one focal length for both axes. Searched in log space since it's a ratio we're unsure of.
+	let focal_rms = |pinhole: &Pinhole, log_f: f32| rms(&with_focal(pinhole, log_f.exp()));
+	let (lo, hi) = ((initial.fx / SEARCH_RANGE).ln(), (initial.fx * SEARCH_RANGE).ln());
+	let step = (hi - lo) / COARSE_STEPS as f32;
+	let coarse: Vec<f32> = (0..=COARSE_STEPS).map(|i| focal_rms(initial, lo + step * i as f32)).collect();
+	let best = (0..coarse.len()).min_by(|&a, &b| coarse[a].total_cmp(&coarse[b]))?;
+	// Best at the end of the range means the answer is past it, or there isn't one.
+	if best == 0 || best == COARSE_STEPS || coarse[0].min(coarse[COARSE_STEPS]) - coarse[best] < MIN_CONTRAST_PX {
+		return None;
+	}
+	let around = lo + step * best as f32;
+	let mut pinhole = with_focal(initial, golden_section(around - step, around + step, |log_f| focal_rms(initial, log_f)).exp());
+
+	if principal_point {
+		let (dx, dy) = (CENTER_RANGE * initial.width as f32, CENTER_RANGE * initial.height as f32);
+		// The focal length and principal point trade off a little, so go round a few times.
+		for _ in 0..3 {
+			let cx = golden_section(initial.cx - dx, initial.cx + dx, |cx| rms(&Pinhole { cx, ..pinhole }));
+			pinhole.cx = cx;
+			let cy = golden_section(initial.cy - dy, initial.cy + dy, |cy| rms(&Pinhole { cy, ..pinhole }));
+			pinhole.cy = cy;
+			let log_f = pinhole.fx.ln();
+			pinhole = with_focal(&pinhole, golden_section(log_f - step, log_f + step, |log_f| focal_rms(&pinhole, log_f)).exp());
+		}
+	}
+	Some(FocalFit { pinhole, rms_before: rms(initial), rms_after: rms(&pinhole), markers: quads.len() })
+}
+
+/// Squared reprojection error summed over every marker, each through its best pose for this lens.
+fn cost(quads: &[&[(f32, f32); 4]], model: &[geometry::Vec3; 4], marker_size_mm: f32, pinhole: &Pinhole) -> f32 {
+	quads.iter().map(|corners| {
+		let points: Vec<_> = model.iter().copied().zip(corners.iter().copied()).collect();
+		let Some((rotation, translation)) = geometry::pose_from_quad(corners, marker_size_mm, pinhole) else {
+			return 0.0;
+		};
+		let (rotation, translation) = geometry::refine_pose(&rotation, &translation, &points, pinhole);
+		geometry::reprojection_cost(&rotation, &translation, &points, pinhole)
+	}).sum()
+}
+
+/// The minimum of `f` between `lo` and `hi`, assuming there's only the one.
+fn golden_section(mut lo: f32, mut hi: f32, f: impl Fn(f32) -> f32) -> f32 {
+	let ratio = (5f32.sqrt() - 1.0) / 2.0;
+	let (mut a, mut b) = (hi - ratio * (hi - lo), lo + ratio * (hi - lo));
+	let (mut fa, mut fb) = (f(a), f(b));
+	for _ in 0..30 {
+		if fa < fb {
+			(hi, b, fb) = (b, a, fa);
+			a = hi - ratio * (hi - lo);
+			fa = f(a);
+		} else {
+			(lo, a, fa) = (a, b, fb);
+			b = lo + ratio * (hi - lo);
+			fb = f(b);
+		}
+	}
+	(lo + hi) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_refine_focal_length() {
+		let truth = Pinhole { width: 1280, height: 720, fx: 900.0, fy: 900.0, cx: 640.0, cy: 360.0 };
+		let model = geometry::marker_corners(100.0);
+		let view = |axis: geometry::Vec3, position: geometry::Vec3| {
+			let rotation = geometry::rodrigues(&axis);
+			model.map(|p| truth.project(&geometry::add(&geometry::mat_mul_vec(&rotation, &p), &position)))
+		};
+		let tilted: Vec<_> = (0..12).map(|i| {
+			let angle = i as f32 * 0.5;
+			view([0.6 * angle.cos(), 0.6 * angle.sin(), 0.1], [150.0 * angle.sin(), 80.0 * angle.cos(), 700.0 + 20.0 * i as f32])
+		}).collect();
+		let guess = Pinhole { fx: 600.0, fy: 600.0, ..truth };
+		let fit = refine(&tilted, 100.0, &guess, false).unwrap();
+		assert!((fit.pinhole.fx - 900.0).abs() < 9.0, "{}", fit.pinhole.fx);
+		assert!(fit.rms_after < fit.rms_before);
+
+		let square_on: Vec<_> = (0..12).map(|i| view([0.0; 3], [20.0 * i as f32, 0.0, 800.0])).collect();
+		assert!(refine(&square_on, 100.0, &guess, false).is_none());
+	}
+}
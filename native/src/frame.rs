@@ -0,0 +1,154 @@
+// A borrowed view of 8-bit pixels as they come from a decoder or a host: rows of packed samples, each `stride` bytes
+// from the start of the last, where the stride can run past the row for alignment. Building the detector's images
+// straight from the view means one pass over the frame instead of copying it out and then converting the copy.
+//
+// Everything but aruco3 reads the frame's luma, which a FrameBuffer writes over the last frame's rather than into a
+// new buffer each time. aruco3 takes its image by value, so it gets a copy of its own: the luma, or with --rgb the
+// colour frame.
+
+use crate::simd;
+use image::{DynamicImage, GrayImage, RgbImage, RgbaImage};
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameView<'a> {
+	data: &'a [u8],
+	width: u32,
+	height: u32,
+	stride: usize,
+	channels: u32,
+}
+
+impl<'a> FrameView<'a> {
+	/// Gray (1 channel), RGB (3), or RGBA (4). None if `data` is too short for `height` rows of `stride` bytes, or
+	/// a stride shorter than a row. The last row doesn't need its padding.
+	pub fn new(data: &'a [u8], width: u32, height: u32, stride: usize, channels: u32) -> Option<Self> {
+		let row_bytes = width as usize * channels as usize;
+		let needed = if height == 0 { 0 } else { stride * (height as usize - 1) + row_bytes };
+		(matches!(channels, 1 | 3 | 4) && stride >= row_bytes && data.len() >= needed).then_some(FrameView { data, width, height, stride, channels })
+	}
+
+	pub fn width(&self) -> u32 {
+		self.width
+	}
+
+	pub fn height(&self) -> u32 {
+		self.height
+	}
+
+	pub fn channels(&self) -> u32 {
+		self.channels
+	}
+
+	/// Each row's pixels, without the padding.
+	pub fn rows(&self) -> impl Iterator<Item = &'a [u8]> + use<'a> {
+		let (data, stride, row_bytes) = (self.data, self.stride, self.width as usize * self.channels as usize);
+		(0..self.height as usize).map(move |y| &data[y * stride..y * stride + row_bytes])
+	}
+
+	fn packed(&self) -> Vec<u8> {
+		let mut samples = Vec::with_capacity(self.width as usize * self.height as usize * self.channels as usize);
+		for row in self.rows() {
+			samples.extend_from_slice(row);
+		}
+		samples
+	}
+
+	/// Write the view's luma over `samples`, which only grows if there's more of it than last time.
+	fn luma_into(&self, samples: &mut Vec<u8>) {
+		samples.clear();
+		if self.channels == 1 {
+			for row in self.rows() {
+				samples.extend_from_slice(row);
+			}
+			return;
+		}
+		let width = self.width as usize;
+		samples.resize(width * self.height as usize, 0);
+		for (row, out) in self.rows().zip(samples.chunks_exact_mut(width.max(1))) {
+			simd::luma_row(row, self.channels as usize, out);
+		}
+	}
+
+	/// Rec. 709 luma, converted on the way out of the view. Alpha is ignored.
+	pub fn to_luma8(&self) -> GrayImage {
+		let mut samples = vec![];
+		self.luma_into(&mut samples);
+		GrayImage::from_raw(self.width, self.height, samples).expect("One sample per pixel.")
+	}
+
+	/// The pixels as they are, packed into an image of the matching kind.
+	pub fn to_image(&self) -> DynamicImage {
+		let (width, height, samples) = (self.width, self.height, self.packed());
+		match self.channels {
+			1 => GrayImage::from_raw(width, height, samples).map(DynamicImage::ImageLuma8),
+			3 => RgbImage::from_raw(width, height, samples).map(DynamicImage::ImageRgb8),
+			_ => RgbaImage::from_raw(width, height, samples).map(DynamicImage::ImageRgba8),
+		}.expect("Packed to the view's size.")
+	}
+}
+
+/// Luma kept from one frame to the next. What luma() hands out is written over the samples last given back to
+/// recycle(), so once a video's frames settle on a size nothing more is allocated for them.
+#[derive(Debug, Default)]
+pub struct FrameBuffer {
+	samples: Vec<u8>,
+}
+
+impl FrameBuffer {
+	/// Like FrameView::to_luma8, but written over the recycled samples.
+	pub fn luma(&mut self, view: &FrameView) -> GrayImage {
+		let mut samples = std::mem::take(&mut self.samples);
+		view.luma_into(&mut samples);
+		GrayImage::from_raw(view.width, view.height, samples).expect("One sample per pixel.")
+	}
+
+	/// The luma of what the frame became on the way to the detector, rotated or cropped, say. 16-bit frames are
+	/// converted afresh.
+	pub fn luma_of(&mut self, image: &DynamicImage) -> GrayImage {
+		let channels = match image {
+			DynamicImage::ImageLuma8(_) => 1,
+			DynamicImage::ImageRgb8(_) => 3,
+			DynamicImage::ImageRgba8(_) => 4,
+			other => return other.to_luma8(),
+		};
+		let (width, height) = (image.width(), image.height());
+		let view = FrameView::new(image.as_bytes(), width, height, width as usize * channels as usize, channels).expect("An image's rows are packed.");
+		self.luma(&view)
+	}
+
+	/// Give back what luma() handed out, once the frame's done with it, for the next frame to be written over.
+	pub fn recycle(&mut self, gray: GrayImage) {
+		self.samples = gray.into_raw();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_skips_padding() {
+		// Two RGB pixels per row, padded to 8 bytes, with the last row's padding missing.
+		let data = [255, 255, 255, 0, 0, 0, 9, 9, 255, 0, 0, 0, 0, 255];
+		let view = FrameView::new(&data, 2, 2, 8, 3).unwrap();
+		assert_eq!(view.to_luma8().as_raw(), &vec![255, 0, 54, 18]);
+		assert!(FrameView::new(&data[..13], 2, 2, 8, 3).is_none());
+		assert!(FrameView::new(&data, 2, 2, 5, 3).is_none());
+	}
+
+	#[test]
+	fn test_buffer_reused() {
+		let data: Vec<u8> = (0..64 * 48 * 3).map(|i| i as u8).collect();
+		let view = FrameView::new(&data, 60, 48, 64 * 3, 3).unwrap();
+		let mut buffer = FrameBuffer::default();
+		let first = buffer.luma(&view);
+		let samples = first.as_raw().as_ptr();
+		buffer.recycle(first);
+		for _ in 0..3 {
+			let gray = buffer.luma(&view);
+			assert_eq!(gray.as_raw().as_ptr(), samples);
+			assert_eq!(gray.as_raw(), view.to_luma8().as_raw());
+			buffer.recycle(gray);
+		}
+	}
+}
@@ -0,0 +1,479 @@
+// Small linear algebra helpers for working with solved poses.
+// Rotations are row-major 3x3 matrices and quaternions are stored as [w, x, y, z].
+// The pose solver has its own matrix types, but we keep plain arrays here so the output side doesn't care which.
+
+pub type Vec3 = [f32; 3];
+pub type Mat3 = [[f32; 3]; 3];
+pub type Quat = [f32; 4];
+
+pub const IDENTITY: Mat3 = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+/// Marker-space corner positions for a square marker, in the same order the detector reports image corners.
+/// This is the SOLVEPNP_IPPE_SQUARE layout: top-left, top-right, bottom-right, bottom-left with +Y up.
+pub fn marker_corners(marker_size: f32) -> [Vec3; 4] {
+	let hw = marker_size / 2.0;
+	[[-hw, hw, 0.0], [hw, hw, 0.0], [hw, -hw, 0.0], [-hw, -hw, 0.0]]
+}
+
+/// A plain pinhole camera in pixels. This mirrors the intrinsics we hand to the pose solver so we can do our own projections.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Pinhole {
+	pub width: u32,
+	pub height: u32,
+	pub fx: f32,
+	pub fy: f32,
+	pub cx: f32,
+	pub cy: f32,
+}
+
+impl Pinhole {
+	/// Direction of the ray through a pixel, in camera space with +Z forward. Not normalized.
+	pub fn ray(&self, (u, v): (f32, f32)) -> Vec3 {
+		[(u - self.cx) / self.fx, (v - self.cy) / self.fy, 1.0]
+	}
+
+	/// Pixel position of a camera-space point.
+	pub fn project(&self, p: &Vec3) -> (f32, f32) {
+		(self.fx * p[0] / p[2] + self.cx, self.fy * p[1] / p[2] + self.cy)
+	}
+}
+
+pub fn add(a: &Vec3, b: &Vec3) -> Vec3 {
+	[a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn sub(a: &Vec3, b: &Vec3) -> Vec3 {
+	[a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn scale(a: &Vec3, s: f32) -> Vec3 {
+	[a[0] * s, a[1] * s, a[2] * s]
+}
+
+pub fn dot(a: &Vec3, b: &Vec3) -> f32 {
+	a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: &Vec3, b: &Vec3) -> Vec3 {
+	[a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+pub fn length(a: &Vec3) -> f32 {
+	dot(a, a).sqrt()
+}
+
+pub fn normalize(a: &Vec3) -> Vec3 {
+	let l = length(a);
+	if l == 0.0 { *a } else { scale(a, 1.0 / l) }
+}
+
+pub fn transpose(m: &Mat3) -> Mat3 {
+	[
+		[m[0][0], m[1][0], m[2][0]],
+		[m[0][1], m[1][1], m[2][1]],
+		[m[0][2], m[1][2], m[2][2]],
+	]
+}
+
+pub fn mat_mul(a: &Mat3, b: &Mat3) -> Mat3 {
+	let mut out = [[0.0; 3]; 3];
+	for (r, row) in out.iter_mut().enumerate() {
+		for (c, value) in row.iter_mut().enumerate() {
+			*value = a[r][0] * b[0][c] + a[r][1] * b[1][c] + a[r][2] * b[2][c];
+		}
+	}
+	out
+}
+
+pub fn mat_mul_vec(m: &Mat3, v: &Vec3) -> Vec3 {
+	[dot(&m[0], v), dot(&m[1], v), dot(&m[2], v)]
+}
+
+/// The rotation matrix whose columns are the given axes.
+pub fn from_columns(x: &Vec3, y: &Vec3, z: &Vec3) -> Mat3 {
+	[
+		[x[0], y[0], z[0]],
+		[x[1], y[1], z[1]],
+		[x[2], y[2], z[2]],
+	]
+}
+
+/// Rotation matrix from a Rodrigues (axis * angle) vector, as used by OpenCV.
+pub fn rodrigues(r: &Vec3) -> Mat3 {
+	let theta = length(r);
+	if theta < 1e-9 {
+		return IDENTITY;
+	}
+	let [x, y, z] = scale(r, 1.0 / theta);
+	let (s, c) = theta.sin_cos();
+	let k = 1.0 - c;
+	[
+		[c + x * x * k, x * y * k - z * s, x * z * k + y * s],
+		[y * x * k + z * s, c + y * y * k, y * z * k - x * s],
+		[z * x * k - y * s, z * y * k + x * s, c + z * z * k],
+	]
+}
+
+/// The inverse of rodrigues: axis * angle, with the angle in [0, pi].
+pub fn rotation_vector(m: &Mat3) -> Vec3 {
+	let q = mat3_to_quat(m);
+	// q and -q are the same rotation. Pick the one with the shorter angle.
+	let q = if q[0] < 0.0 { [-q[0], -q[1], -q[2], -q[3]] } else { q };
+	let axis = [q[1], q[2], q[3]];
+	let sin_half = length(&axis);
+	if sin_half < 1e-9 {
+		return [0.0; 3];
+	}
+	scale(&axis, 2.0 * sin_half.atan2(q[0]) / sin_half)
+}
+
+/// Euler angles in radians for R = Rz * Ry * Rx, which is Blender's XYZ mode. At gimbal lock X is taken as zero.
+pub fn mat3_to_euler_xyz(m: &Mat3) -> Vec3 {
+	let y = (-m[2][0]).clamp(-1.0, 1.0).asin();
+	if m[2][0].abs() > 0.99999 {
+		return [0.0, y, (-m[0][1]).atan2(m[1][1])];
+	}
+	[m[2][1].atan2(m[2][2]), y, m[1][0].atan2(m[0][0])]
+}
+
+/// The unit normal of the plane best fitting the points in the least squares sense, with an arbitrary sign. None if
+/// there are fewer than three points or they're all on a line.
+pub fn plane_normal(points: &[Vec3]) -> Option<Vec3> {
+	if points.len() < 3 {
+		return None;
+	}
+	let center = scale(&points.iter().fold([0.0; 3], |acc, p| add(&acc, p)), 1.0 / points.len() as f32);
+	let mut covariance = [[0.0; 3]; 3];
+	for p in points {
+		let d = sub(p, &center);
+		for r in 0..3 {
+			for c in 0..3 {
+				covariance[r][c] += d[r] * d[c];
+			}
+		}
+	}
+	// The normal is the eigenvector with the smallest eigenvalue. Closed form for symmetric 3x3 matrices, after
+	// Smith, "Eigenvalues of a symmetric 3x3 matrix" (1961).
+	let q = (covariance[0][0] + covariance[1][1] + covariance[2][2]) / 3.0;
+	let off_diagonal = covariance[0][1].powi(2) + covariance[0][2].powi(2) + covariance[1][2].powi(2);
+	let p = (((0..3).map(|i| (covariance[i][i] - q).powi(2)).sum::<f32>() + 2.0 * off_diagonal) / 6.0).sqrt();
+	if p < 1e-9 {
+		return None;
+	}
+	let b: Mat3 = std::array::from_fn(|r| std::array::from_fn(|c| (covariance[r][c] - if r == c { q } else { 0.0 }) / p));
+	let det = dot(&b[0], &cross(&b[1], &b[2]));
+	let phi = (det / 2.0).clamp(-1.0, 1.0).acos() / 3.0;
+	let smallest = q + 2.0 * p * (phi + 2.0 * std::f32::consts::FRAC_PI_3).cos();
+	// Any two rows of (C - smallest * I) span the plane the normal is perpendicular to. Take the best conditioned pair.
+	let rows: [Vec3; 3] = std::array::from_fn(|r| std::array::from_fn(|c| covariance[r][c] - if r == c { smallest } else { 0.0 }));
+	let normal = [cross(&rows[0], &rows[1]), cross(&rows[0], &rows[2]), cross(&rows[1], &rows[2])].into_iter()
+		.max_by(|a, b| length(a).total_cmp(&length(b)))?;
+	// Collinear points have two small eigenvalues, so every pair of rows is nearly parallel.
+	if length(&normal) < 1e-6 * (q * 3.0).powi(2) {
+		return None;
+	}
+	Some(normalize(&normal))
+}
+
+pub fn lerp(a: f32, b: f32, t: f32) -> f32 {
+	a + (b - a) * t
+}
+
+pub fn lerp_vec3(a: &Vec3, b: &Vec3, t: f32) -> Vec3 {
+	[lerp(a[0], b[0], t), lerp(a[1], b[1], t), lerp(a[2], b[2], t)]
+}
+
+pub fn mat3_to_quat(m: &Mat3) -> Quat {
+	let trace = m[0][0] + m[1][1] + m[2][2];
+	let q = if trace > 0.0 {
+		let s = (trace + 1.0).sqrt() * 2.0;
+		[0.25 * s, (m[2][1] - m[1][2]) / s, (m[0][2] - m[2][0]) / s, (m[1][0] - m[0][1]) / s]
+	} else if m[0][0] > m[1][1] && m[0][0] > m[2][2] {
+		let s = (1.0 + m[0][0] - m[1][1] - m[2][2]).sqrt() * 2.0;
+		[(m[2][1] - m[1][2]) / s, 0.25 * s, (m[0][1] + m[1][0]) / s, (m[0][2] + m[2][0]) / s]
+	} else if m[1][1] > m[2][2] {
+		let s = (1.0 + m[1][1] - m[0][0] - m[2][2]).sqrt() * 2.0;
+		[(m[0][2] - m[2][0]) / s, (m[0][1] + m[1][0]) / s, 0.25 * s, (m[1][2] + m[2][1]) / s]
+	} else {
+		let s = (1.0 + m[2][2] - m[0][0] - m[1][1]).sqrt() * 2.0;
+		[(m[1][0] - m[0][1]) / s, (m[0][2] + m[2][0]) / s, (m[1][2] + m[2][1]) / s, 0.25 * s]
+	};
+	quat_normalize(&q)
+}
+
+pub fn quat_to_mat3(q: &Quat) -> Mat3 {
+	let [w, x, y, z] = *q;
+	[
+		[1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - z * w), 2.0 * (x * z + y * w)],
+		[2.0 * (x * y + z * w), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - x * w)],
+		[2.0 * (x * z - y * w), 2.0 * (y * z + x * w), 1.0 - 2.0 * (x * x + y * y)],
+	]
+}
+
+pub fn quat_normalize(q: &Quat) -> Quat {
+	let norm = (q[0] * q[0] + q[1] * q[1] + q[2] * q[2] + q[3] * q[3]).sqrt();
+	if norm == 0.0 {
+		return [1.0, 0.0, 0.0, 0.0];
+	}
+	[q[0] / norm, q[1] / norm, q[2] / norm, q[3] / norm]
+}
+
+pub fn slerp(a: &Quat, b: &Quat, t: f32) -> Quat {
+	let mut dot = a[0] * b[0] + a[1] * b[1] + a[2] * b[2] + a[3] * b[3];
+	// Take the short way around.
+	let b = if dot < 0.0 {
+		dot = -dot;
+		[-b[0], -b[1], -b[2], -b[3]]
+	} else {
+		*b
+	};
+	if dot > 0.9995 {
+		// Close enough that a normalized lerp is indistinguishable and avoids dividing by ~0.
+		return quat_normalize(&[lerp(a[0], b[0], t), lerp(a[1], b[1], t), lerp(a[2], b[2], t), lerp(a[3], b[3], t)]);
+	}
+	let theta = dot.acos();
+	let sin_theta = theta.sin();
+	let wa = ((1.0 - t) * theta).sin() / sin_theta;
+	let wb = (t * theta).sin() / sin_theta;
+	[wa * a[0] + wb * b[0], wa * a[1] + wb * b[1], wa * a[2] + wb * b[2], wa * a[3] + wb * b[3]]
+}
+
+/// Maps the unit square onto a quad: (0,0), (1,0), (1,1), (0,1) go to the four corners in order.
+/// From Heckbert, "Fundamentals of Texture Mapping and Image Warping", section 2.2.3.
+pub fn square_to_quad(corners: &[(f32, f32); 4]) -> Option<Mat3> {
+	let [(x0, y0), (x1, y1), (x2, y2), (x3, y3)] = *corners;
+	let (dx1, dx2, dx3) = (x1 - x2, x3 - x2, x0 - x1 + x2 - x3);
+	let (dy1, dy2, dy3) = (y1 - y2, y3 - y2, y0 - y1 + y2 - y3);
+	let det = dx1 * dy2 - dx2 * dy1;
+	if det.abs() < 1e-12 {
+		return None;
+	}
+	let g = (dx3 * dy2 - dx2 * dy3) / det;
+	let h = (dx1 * dy3 - dx3 * dy1) / det;
+	Some([
+		[x1 - x0 + g * x1, x3 - x0 + h * x3, x0],
+		[y1 - y0 + g * y1, y3 - y0 + h * y3, y0],
+		[g, h, 1.0],
+	])
+}
+
+pub fn apply_homography(m: &Mat3, (u, v): (f32, f32)) -> (f32, f32) {
+	let w = m[2][0] * u + m[2][1] * v + m[2][2];
+	((m[0][0] * u + m[0][1] * v + m[0][2]) / w, (m[1][0] * u + m[1][1] * v + m[1][2]) / w)
+}
+
+/// Pose of a square of the given size from its four image corners (in marker_corners order), by decomposing the
+/// plane-to-image homography. Less robust than the detector's solver on small or oblique markers, but it works for
+/// anything with four corners. Returns (rotation, translation) taking marker space to camera space.
+pub fn pose_from_quad(corners: &[(f32, f32); 4], marker_size: f32, pinhole: &Pinhole) -> Option<(Mat3, Vec3)> {
+	let normalized = corners.map(|c| {
+		let r = pinhole.ray(c);
+		(r[0], r[1])
+	});
+	let h = square_to_quad(&normalized)?;
+	// Marker (x, y) to unit square (s, t): s = x / size + 0.5 and t = 0.5 - y / size, since +Y is up in marker space.
+	let to_square = [[1.0 / marker_size, 0.0, 0.5], [0.0, -1.0 / marker_size, 0.5], [0.0, 0.0, 1.0]];
+	let m = mat_mul(&h, &to_square);
+	let column = |c: usize| [m[0][c], m[1][c], m[2][c]];
+	let (c1, c2, c3) = (column(0), column(1), column(2));
+	let mut lambda = 2.0 / (length(&c1) + length(&c2));
+	// The homography is only defined up to sign. Pick the one that puts the marker in front of the camera.
+	if c3[2] < 0.0 {
+		lambda = -lambda;
+	}
+	if !lambda.is_finite() {
+		return None;
+	}
+	let x = normalize(&scale(&c1, lambda));
+	let y = scale(&c2, lambda);
+	let y = normalize(&sub(&y, &scale(&x, dot(&x, &y))));
+	Some((from_columns(&x, &y, &cross(&x, &y)), scale(&c3, lambda)))
+}
+
+/// Solve a small dense linear system by Gaussian elimination with partial pivoting. None if it's singular.
+pub fn solve_linear<const N: usize>(mut a: [[f64; N]; N], mut b: [f64; N]) -> Option<[f64; N]> {
+	for col in 0..N {
+		let pivot = (col..N).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+		if a[pivot][col].abs() < 1e-12 {
+			return None;
+		}
+		a.swap(col, pivot);
+		b.swap(col, pivot);
+		let pivot_row = a[col];
+		for row in col + 1..N {
+			let f = a[row][col] / pivot_row[col];
+			for (value, pivot) in a[row].iter_mut().zip(pivot_row.iter()).skip(col) {
+				*value -= f * pivot;
+			}
+			b[row] -= f * b[col];
+		}
+	}
+	let mut x = [0.0; N];
+	for row in (0..N).rev() {
+		let sum: f64 = (row + 1..N).map(|k| a[row][k] * x[k]).sum();
+		x[row] = (b[row] - sum) / a[row][row];
+	}
+	Some(x)
+}
+
+/// Squared reprojection error of (world point, pixel) pairs through a world-to-camera pose.
+pub fn reprojection_cost(rotation: &Mat3, translation: &Vec3, points: &[(Vec3, (f32, f32))], pinhole: &Pinhole) -> f32 {
+	points.iter().map(|(p, (u, v))| {
+		let (pu, pv) = pinhole.project(&add(&mat_mul_vec(rotation, p), translation));
+		(pu - u).powi(2) + (pv - v).powi(2)
+	}).sum()
+}
+
+/// Polish a world-to-camera pose against any number of (world point, pixel) pairs by Levenberg-Marquardt on the
+/// reprojection error. Only meant to take a good first guess the last little way, e.g. from one marker's pose to a
+/// fit over every marker in view.
+pub fn refine_pose(rotation: &Mat3, translation: &Vec3, points: &[(Vec3, (f32, f32))], pinhole: &Pinhole) -> (Mat3, Vec3) {
+	let (mut rotation, mut translation) = (*rotation, *translation);
+	let mut cost = reprojection_cost(&rotation, &translation, points, pinhole);
+	let mut damping = 1e-3;
+	for _ in 0..20 {
+		// Parameters are a small rotation applied on the left, then a change in translation.
+		let mut jtj = [[0.0f64; 6]; 6];
+		let mut jtr = [0.0f64; 6];
+		for (p, (u, v)) in points {
+			let rotated = mat_mul_vec(&rotation, p);
+			let [x, y, z] = add(&rotated, &translation);
+			if z <= 0.0 {
+				continue;
+			}
+			let (pu, pv) = pinhole.project(&[x, y, z]);
+			// d(u, v) / d(camera point), then the camera point moves by -[rotated]x per unit rotation and 1:1 with translation.
+			let du = [pinhole.fx / z, 0.0, -pinhole.fx * x / (z * z)];
+			let dv = [0.0, pinhole.fy / z, -pinhole.fy * y / (z * z)];
+			for (d, residual) in [(du, pu - u), (dv, pv - v)] {
+				let row = [
+					dot(&d, &cross(&[1.0, 0.0, 0.0], &rotated)),
+					dot(&d, &cross(&[0.0, 1.0, 0.0], &rotated)),
+					dot(&d, &cross(&[0.0, 0.0, 1.0], &rotated)),
+					d[0], d[1], d[2],
+				];
+				for i in 0..6 {
+					for j in 0..6 {
+						jtj[i][j] += (row[i] * row[j]) as f64;
+					}
+					jtr[i] -= (row[i] * residual) as f64;
+				}
+			}
+		}
+		let mut damped = jtj;
+		for (i, row) in damped.iter_mut().enumerate() {
+			row[i] += damping * jtj[i][i].max(1e-9);
+		}
+		let Some(step) = solve_linear(damped, jtr) else {
+			break;
+		};
+		let step = step.map(|s| s as f32);
+		let candidate_rotation = mat_mul(&rodrigues(&[step[0], step[1], step[2]]), &rotation);
+		let candidate_translation = add(&translation, &[step[3], step[4], step[5]]);
+		let candidate_cost = reprojection_cost(&candidate_rotation, &candidate_translation, points, pinhole);
+		if candidate_cost < cost {
+			let converged = cost - candidate_cost < 1e-6 * cost;
+			(rotation, translation, cost) = (candidate_rotation, candidate_translation, candidate_cost);
+			damping = (damping * 0.1).max(1e-7);
+			if converged {
+				break;
+			}
+		} else {
+			damping *= 10.0;
+		}
+	}
+	(rotation, translation)
+}
+
+/// Interpolate between two rotation matrices along the shortest arc.
+pub fn slerp_mat3(a: &Mat3, b: &Mat3, t: f32) -> Mat3 {
+	quat_to_mat3(&slerp(&mat3_to_quat(a), &mat3_to_quat(b), t))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_mat_close(a: &Mat3, b: &Mat3) {
+		for r in 0..3 {
+			for c in 0..3 {
+				assert!((a[r][c] - b[r][c]).abs() < 1e-5, "{:?} != {:?}", a, b);
+			}
+		}
+	}
+
+	#[test]
+	fn test_quat_round_trip() {
+		// 90 degrees about Z and 180 degrees about X, which exercises both branches of the conversion.
+		let rz: Mat3 = [[0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]];
+		let rx: Mat3 = [[1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, -1.0]];
+		assert_mat_close(&quat_to_mat3(&mat3_to_quat(&rz)), &rz);
+		assert_mat_close(&quat_to_mat3(&mat3_to_quat(&rx)), &rx);
+	}
+
+	#[test]
+	fn test_rodrigues_matches_quarter_turn() {
+		let rz: Mat3 = [[0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]];
+		assert_mat_close(&rodrigues(&[0.0, 0.0, std::f32::consts::FRAC_PI_2]), &rz);
+	}
+
+	#[test]
+	fn test_rotation_vector_round_trip() {
+		let r = [0.4, -1.1, 0.7];
+		let back = rotation_vector(&rodrigues(&r));
+		assert!(length(&sub(&back, &r)) < 1e-5, "{back:?}");
+	}
+
+	#[test]
+	fn test_euler_xyz_round_trip() {
+		let (x, y, z) = (0.3, -0.8, 1.9);
+		let m = mat_mul(&rodrigues(&[0.0, 0.0, z]), &mat_mul(&rodrigues(&[0.0, y, 0.0]), &rodrigues(&[x, 0.0, 0.0])));
+		let back = mat3_to_euler_xyz(&m);
+		assert!(length(&sub(&back, &[x, y, z])) < 1e-5, "{back:?}");
+	}
+
+	#[test]
+	fn test_plane_normal() {
+		// A tilted plane, z = 0.5x + 10.
+		let points: Vec<Vec3> = [(0.0, 0.0), (100.0, 0.0), (0.0, 100.0), (100.0, 100.0), (30.0, 70.0)].iter().map(|&(x, y)| [x, y, 0.5 * x + 10.0]).collect();
+		let normal = plane_normal(&points).unwrap();
+		let expected = normalize(&[-0.5, 0.0, 1.0]);
+		assert!(dot(&normal, &expected).abs() > 1.0 - 1e-5, "{normal:?}");
+		assert!(plane_normal(&[[0.0; 3], [1.0, 1.0, 1.0], [2.0, 2.0, 2.0]]).is_none());
+	}
+
+	#[test]
+	fn test_refine_pose() {
+		let pinhole = Pinhole { width: 1000, height: 1000, fx: 800.0, fy: 800.0, cx: 500.0, cy: 500.0 };
+		let rotation = rodrigues(&[0.2, 0.4, -0.1]);
+		let translation = [30.0, -40.0, 900.0];
+		let points: Vec<(Vec3, (f32, f32))> = [[0.0, 0.0, 0.0], [200.0, 0.0, 0.0], [0.0, 200.0, 0.0], [200.0, 200.0, 50.0], [-100.0, 50.0, 0.0]].iter()
+			.map(|p| (*p, pinhole.project(&add(&mat_mul_vec(&rotation, p), &translation))))
+			.collect();
+		let (r, t) = refine_pose(&rodrigues(&[0.25, 0.35, -0.05]), &[0.0, 0.0, 1000.0], &points, &pinhole);
+		assert!(reprojection_cost(&r, &t, &points, &pinhole) < 1e-3);
+		assert!(length(&sub(&t, &translation)) < 0.5, "{t:?}");
+	}
+
+	#[test]
+	fn test_pose_from_quad() {
+		let pinhole = Pinhole { width: 1000, height: 1000, fx: 800.0, fy: 800.0, cx: 500.0, cy: 500.0 };
+		let rotation = rodrigues(&[0.3, -0.2, 0.1]);
+		let translation = [20.0, -10.0, 600.0];
+		let corners = marker_corners(80.0).map(|c| pinhole.project(&add(&mat_mul_vec(&rotation, &c), &translation)));
+		let (r, t) = pose_from_quad(&corners, 80.0, &pinhole).unwrap();
+		for error in r.iter().zip(rotation.iter()).flat_map(|(a, b)| a.iter().zip(b.iter())).map(|(a, b)| a - b) {
+			assert!(error.abs() < 1e-3, "{r:?}");
+		}
+		assert!(length(&sub(&t, &translation)) < 0.1, "{t:?}");
+	}
+
+	#[test]
+	fn test_slerp_halfway() {
+		let identity: Mat3 = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+		let rz: Mat3 = [[0.0, -1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]];
+		let half = slerp_mat3(&identity, &rz, 0.5);
+		let s = std::f32::consts::FRAC_1_SQRT_2;
+		assert_mat_close(&half, &[[s, -s, 0.0], [s, s, 0.0], [0.0, 0.0, 1.0]]);
+	}
+}
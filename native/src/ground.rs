@@ -0,0 +1,186 @@
+// Ground-plane alignment for --floor-markers: markers known to lie flat on the floor define a world frame with the
+// floor at Z = 0 and +Z up, so the output drops into a Z-up scene like Blender's standing the right way up.
+//
+// The floor is fit to the positions of the floor markers in view. With three or more that's a least squares plane,
+// which averages out the tilt error of any one pose; with one or two we fall back to the markers' own face normals.
+// Either way "up" is the side the markers face. The origin is the lowest numbered one, and +X follows its +X, laid
+// flat.
+//
+// The first time three or more are in view at once, that fit is kept for good: each floor marker's place on the
+// floor is remembered, and from then on the floor is found from whichever of them are in view (lined up in the
+// plane when there are two or more, from the one marker's pose otherwise), so markers coming and going don't move
+// it. Floor markers first seen later join in where they were seen. A camera that loses every floor marker keeps its
+// last fit (see transform::HeldTransform).
+
+use crate::geometry::{self, Mat3, Vec3};
+use crate::record::{FrameRecord, PoseRecord};
+use crate::transform::HeldTransform;
+use std::collections::BTreeMap;
+
+pub struct GroundPlane {
+	floor_markers: Vec<usize>,
+	/// Each floor marker's pose on the locked floor, marker to floor. Empty until it's locked.
+	layout: BTreeMap<usize, (Mat3, Vec3)>,
+	held: HeldTransform,
+}
+
+/// Which way is up in camera space, from the markers' positions or, for fewer than three, their faces.
+fn up(poses: &[&PoseRecord]) -> Vec3 {
+	let positions: Vec<Vec3> = poses.iter().map(|pose| pose.translation).collect();
+	let face_normals = poses.iter().fold([0.0; 3], |acc, pose| geometry::add(&acc, &geometry::mat_mul_vec(&pose.rotation, &[0.0, 0.0, 1.0])));
+	match geometry::plane_normal(&positions) {
+		Some(normal) if geometry::dot(&normal, &face_normals) < 0.0 => geometry::scale(&normal, -1.0),
+		Some(normal) => normal,
+		None => geometry::normalize(&face_normals),
+	}
+}
+
+/// A marker's +X in camera space, laid flat on the plane facing `up`.
+fn flat_x(pose: &PoseRecord, up: &Vec3) -> Vec3 {
+	let x = geometry::mat_mul_vec(&pose.rotation, &[1.0, 0.0, 0.0]);
+	geometry::normalize(&geometry::sub(&x, &geometry::scale(up, geometry::dot(&x, up))))
+}
+
+fn centroid(points: &[Vec3]) -> Vec3 {
+	geometry::scale(&points.iter().fold([0.0; 3], |acc, p| geometry::add(&acc, p)), 1.0 / points.len() as f32)
+}
+
+impl GroundPlane {
+	pub fn new(floor_markers: Vec<usize>) -> Self {
+		GroundPlane { floor_markers, layout: BTreeMap::new(), held: HeldTransform::default() }
+	}
+
+	/// The floor markers in this record with a pose, lowest id first.
+	fn floor<'a>(&self, record: &'a FrameRecord) -> Vec<(usize, &'a PoseRecord)> {
+		let mut floor: Vec<_> = record.markers.iter()
+			.filter(|m| self.floor_markers.contains(&m.marker_id))
+			.filter_map(|m| Some((m.marker_id, m.best_pose()?)))
+			.collect();
+		floor.sort_by_key(|(id, _)| *id);
+		floor
+	}
+
+	/// The camera to floor transform fit to just these floor markers.
+	fn fit(floor: &[(usize, &PoseRecord)]) -> Option<(Mat3, Vec3)> {
+		let (_, first) = floor.first()?;
+		let up = up(&floor.iter().map(|(_, pose)| *pose).collect::<Vec<_>>());
+		let x = flat_x(first, &up);
+		// The floor's axes in camera space are the columns of its camera-space rotation, so the rows of the inverse.
+		let rotation = [x, geometry::cross(&up, &x), up];
+		Some((rotation, geometry::scale(&geometry::mat_mul_vec(&rotation, &first.translation), -1.0)))
+	}
+
+	/// The camera to floor transform for the locked floor, from the floor markers in view that are part of it.
+	fn locate(&self, floor: &[(usize, &PoseRecord)]) -> Option<(Mat3, Vec3)> {
+		let known: Vec<(&PoseRecord, &(Mat3, Vec3))> = floor.iter().filter_map(|(id, pose)| Some((*pose, self.layout.get(id)?))).collect();
+		if let [(pose, (rotation, translation))] = known[..] {
+			// floor <- marker <- camera.
+			let rotation = geometry::mat_mul(rotation, &geometry::transpose(&pose.rotation));
+			return Some((rotation, geometry::sub(translation, &geometry::mat_mul_vec(&rotation, &pose.translation))));
+		}
+		let (first, _) = known.first()?;
+		// Up comes from the camera's view of them. Then it's turning the markers about it, and sliding them, until they
+		// best cover where they belong on the floor, which is the same in-plane fit whatever the camera's angle.
+		let up = up(&known.iter().map(|(pose, _)| *pose).collect::<Vec<_>>());
+		let (e1, e2) = (flat_x(first, &up), geometry::cross(&up, &flat_x(first, &up)));
+		let seen: Vec<Vec3> = known.iter().map(|(pose, _)| pose.translation).collect();
+		let placed: Vec<Vec3> = known.iter().map(|(_, (_, translation))| *translation).collect();
+		let (seen_center, placed_center) = (centroid(&seen), centroid(&placed));
+		let (mut sin, mut cos) = (0.0, 0.0);
+		for (p, q) in seen.iter().zip(&placed) {
+			let p = geometry::sub(p, &seen_center);
+			let (ax, ay) = (geometry::dot(&p, &e1), geometry::dot(&p, &e2));
+			let (bx, by) = (q[0] - placed_center[0], q[1] - placed_center[1]);
+			sin += ax * by - ay * bx;
+			cos += ax * bx + ay * by;
+		}
+		let angle = sin.atan2(cos);
+		let x = geometry::sub(&geometry::scale(&e1, angle.cos()), &geometry::scale(&e2, angle.sin()));
+		let rotation = [x, geometry::cross(&up, &x), up];
+		Some((rotation, geometry::sub(&placed_center, &geometry::mat_mul_vec(&rotation, &seen_center))))
+	}
+
+	pub fn apply(&mut self, record: &mut FrameRecord) {
+		let floor = self.floor(record);
+		let measured = if self.layout.is_empty() { Self::fit(&floor) } else { self.locate(&floor) };
+		// Lock the floor the first time there are enough markers for a plane, and place any newcomers on it after.
+		let placing = !self.layout.is_empty() || floor.len() >= 3;
+		if let Some((rotation, translation)) = measured && placing {
+			for (id, pose) in &floor {
+				self.layout.entry(*id).or_insert_with(|| {
+					(geometry::mat_mul(&rotation, &pose.rotation), geometry::add(&geometry::mat_mul_vec(&rotation, &pose.translation), &translation))
+				});
+			}
+		}
+		self.held.apply(record, measured);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::record::{MarkerRecord, PoseRecord};
+
+	#[test]
+	fn test_floor_becomes_z_zero() {
+		// A camera 1.5m up looking down at 45 degrees, so the floor is tilted in camera space.
+		let camera_from_floor = geometry::rodrigues(&[3.0 * std::f32::consts::FRAC_PI_4, 0.0, 0.0]);
+		let camera_position = [0.0, -1500.0, 1500.0];
+		let to_camera = |p: Vec3| geometry::mat_mul_vec(&camera_from_floor, &geometry::sub(&p, &camera_position));
+		let floor_marker = |marker_id: usize, p: Vec3| MarkerRecord {
+			marker_id,
+			poses: vec![PoseRecord { translation: to_camera(p), rotation: camera_from_floor, ..Default::default() }],
+			..Default::default()
+		};
+		let mut record = FrameRecord {
+			markers: vec![
+				floor_marker(1, [-200.0, 0.0, 0.0]),
+				floor_marker(2, [200.0, 0.0, 0.0]),
+				floor_marker(3, [0.0, 300.0, 0.0]),
+				// A prop standing on a box.
+				floor_marker(9, [50.0, 100.0, 400.0]),
+			],
+			..Default::default()
+		};
+		GroundPlane::new(vec![1, 2, 3]).apply(&mut record);
+		for m in &record.markers[..3] {
+			assert!(m.poses[0].translation[2].abs() < 1e-2, "{:?}", m.poses[0].translation);
+		}
+		// 400mm up, measured from marker 1 at (-200, 0).
+		let prop = record.markers[3].poses[0].translation;
+		assert!(geometry::length(&geometry::sub(&prop, &[250.0, 100.0, 400.0])) < 1e-2, "{prop:?}");
+	}
+
+	#[test]
+	fn test_floor_stays_put_when_a_marker_drops_out() {
+		// The camera from test_floor_becomes_z_zero, turned about the vertical by `turn` and moved to `position`.
+		let view = |turn: f32, position: Vec3| {
+			let camera_from_floor = geometry::mat_mul(&geometry::rodrigues(&[3.0 * std::f32::consts::FRAC_PI_4, 0.0, 0.0]), &geometry::rodrigues(&[0.0, 0.0, turn]));
+			move |marker_id: usize, p: Vec3| MarkerRecord {
+				marker_id,
+				poses: vec![PoseRecord { translation: geometry::mat_mul_vec(&camera_from_floor, &geometry::sub(&p, &position)), rotation: camera_from_floor, ..Default::default() }],
+				..Default::default()
+			}
+		};
+		let mut ground = GroundPlane::new(vec![1, 2, 3]);
+		let marker = view(0.0, [0.0, -1500.0, 1500.0]);
+		let mut first = FrameRecord {
+			markers: vec![marker(1, [-200.0, 0.0, 0.0]), marker(2, [200.0, 0.0, 0.0]), marker(3, [0.0, 300.0, 0.0]), marker(9, [50.0, 100.0, 400.0])],
+			..Default::default()
+		};
+		ground.apply(&mut first);
+
+		// Marker 1, which the origin is on, is gone, and the camera has moved. The prop hasn't.
+		let marker = view(0.3, [300.0, -1400.0, 1600.0]);
+		let mut second = FrameRecord { markers: vec![marker(2, [200.0, 0.0, 0.0]), marker(3, [0.0, 300.0, 0.0]), marker(9, [50.0, 100.0, 400.0])], ..Default::default() };
+		ground.apply(&mut second);
+		let (before, after) = (&first.markers[3].poses[0], &second.markers[2].poses[0]);
+		assert!(geometry::length(&geometry::sub(&before.translation, &after.translation)) < 0.1, "{:?} {:?}", before.translation, after.translation);
+		assert!(geometry::length(&geometry::rotation_vector(&geometry::mat_mul(&before.rotation, &geometry::transpose(&after.rotation)))) < 1e-3);
+
+		// Down to one known marker, it's still the same floor.
+		let mut third = FrameRecord { markers: vec![marker(3, [0.0, 300.0, 0.0]), marker(9, [50.0, 100.0, 400.0])], ..Default::default() };
+		ground.apply(&mut third);
+		assert!(geometry::length(&geometry::sub(&before.translation, &third.markers[1].poses[0].translation)) < 0.1);
+	}
+}
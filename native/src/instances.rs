@@ -0,0 +1,91 @@
+// Telling apart markers that share an id, as happens when the same sheet is printed twice. Each camera keeps every
+// copy it has seen of an id with where it was last, and each frame's copies are matched to those by distance in the
+// image, nearest pair first. A copy left over once they're all taken is a new one and gets the next index.
+//
+// Ids that have only ever been seen once at a time never get an "instance", so ordinary runs are unchanged. Once
+// there's a second copy, every sighting of that id gets one, and the sightings before it were instance 0.
+
+use crate::record::FrameRecord;
+use std::collections::BTreeMap;
+
+type Point = (f32, f32);
+
+#[derive(Default)]
+pub struct InstanceTracker {
+	/// Where each copy of each id was last seen in each camera, by instance index.
+	last_seen: BTreeMap<(Option<String>, usize), Vec<Point>>,
+}
+
+fn centroid(corners: &[Point; 4]) -> Point {
+	(corners.iter().map(|c| c.0).sum::<f32>() / 4.0, corners.iter().map(|c| c.1).sum::<f32>() / 4.0)
+}
+
+impl InstanceTracker {
+	pub fn apply(&mut self, record: &mut FrameRecord) {
+		let mut by_id: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+		for (idx, m) in record.markers.iter().enumerate() {
+			by_id.entry(m.marker_id).or_default().push(idx);
+		}
+		for (marker_id, indices) in by_id {
+			let tracks = self.last_seen.entry((record.camera_id.clone(), marker_id)).or_default();
+			let centers: Vec<Point> = indices.iter().map(|idx| centroid(&record.markers[*idx].corners)).collect();
+			let mut pairs: Vec<(f32, usize, usize)> = centers.iter().enumerate()
+				.flat_map(|(i, c)| tracks.iter().enumerate().map(move |(t, last)| ((c.0 - last.0).hypot(c.1 - last.1), i, t)))
+				.collect();
+			pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+			let mut assigned = vec![None; centers.len()];
+			let mut taken = vec![false; tracks.len()];
+			for (_, i, t) in pairs {
+				if assigned[i].is_none() && !taken[t] {
+					assigned[i] = Some(t);
+					taken[t] = true;
+				}
+			}
+			for (i, center) in centers.iter().enumerate() {
+				let t = *assigned[i].get_or_insert_with(|| {
+					tracks.push(*center);
+					tracks.len() - 1
+				});
+				tracks[t] = *center;
+			}
+			if tracks.len() > 1 {
+				for (idx, t) in indices.iter().zip(assigned) {
+					record.markers[*idx].instance = t.map(|t| t as u32);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::record::MarkerRecord;
+
+	fn frame(centers: &[(usize, f32)]) -> FrameRecord {
+		let markers = centers.iter().map(|&(marker_id, x)| MarkerRecord {
+			marker_id,
+			corners: [(x - 10.0, 90.0), (x + 10.0, 90.0), (x + 10.0, 110.0), (x - 10.0, 110.0)],
+			..Default::default()
+		}).collect();
+		FrameRecord { markers, ..Default::default() }
+	}
+
+	#[test]
+	fn test_instances_follow_their_markers() {
+		let mut tracker = InstanceTracker::default();
+		let mut alone = frame(&[(3, 100.0), (5, 400.0)]);
+		tracker.apply(&mut alone);
+		assert!(alone.markers.iter().all(|m| m.instance.is_none()));
+
+		// A second copy of 3 shows up, listed first.
+		let mut both = frame(&[(3, 300.0), (3, 110.0), (5, 400.0)]);
+		tracker.apply(&mut both);
+		assert_eq!(both.markers.iter().map(|m| m.instance).collect::<Vec<_>>(), vec![Some(1), Some(0), None]);
+
+		// They move toward each other, swapping the order they're listed in, and keep their indices.
+		let mut closer = frame(&[(3, 140.0), (3, 260.0)]);
+		tracker.apply(&mut closer);
+		assert_eq!(closer.markers.iter().map(|m| m.instance).collect::<Vec<_>>(), vec![Some(0), Some(1)]);
+	}
+}
@@ -0,0 +1,146 @@
+// Per-frame intrinsics for zoom lenses, for --intrinsics-track. A zoom changes the focal length during the shot, and
+// solving every frame with one focal length puts the markers at the wrong depth whenever it's off.
+//
+// The track is a CSV with a header row naming its columns: "frame" and one focal length column, any of
+//   fx               focal length in pixels, with an optional fy (defaults to fx)
+//   focal_length_mm  needs the camera's --sensor-size-mm (the diagonal) to turn it into pixels
+//   fov_h_radians    horizontal field of view
+// plus optional cx and cy in pixels for a principal point that moves with the zoom (default: the middle of the frame).
+// Frame numbers count decoded frames from 0, and pixels are in the upright frame. Frames between rows are
+// interpolated, and frames before the first row or after the last hold it. Cameras that record their lens settings
+// keep them in maker notes or a sidecar file that ffmpeg doesn't decode, so export those to this format first (e.g.
+// with exiftool). Blank lines and anything after a '#' are ignored.
+
+use crate::geometry::{Pinhole, lerp};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Focal {
+	Pixels,
+	Millimeters,
+	FovRadians,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Row {
+	frame: usize,
+	focal: f32,
+	fy: Option<f32>,
+	principal: Option<(f32, f32)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IntrinsicsTrack {
+	focal: Focal,
+	/// In frame order, and never empty.
+	rows: Vec<Row>,
+}
+
+pub fn parse_intrinsics_track_file(path: &str) -> Result<IntrinsicsTrack, String> {
+	let text = std::fs::read_to_string(path).map_err(|e| format!("Couldn't read intrinsics track {path}: {e}"))?;
+	IntrinsicsTrack::parse(&text)
+}
+
+impl IntrinsicsTrack {
+	pub fn parse(text: &str) -> Result<Self, String> {
+		let mut lines = text.lines().enumerate()
+			.map(|(idx, line)| (idx + 1, line.split('#').next().unwrap_or_default().trim()))
+			.filter(|(_, line)| !line.is_empty());
+		let (_, header) = lines.next().ok_or("The intrinsics track is empty.")?;
+		let columns: Vec<String> = header.split(',').map(|c| c.trim().to_ascii_lowercase()).collect();
+		let column = |name: &str| columns.iter().position(|c| c == name);
+		let frame_column = column("frame").ok_or("The intrinsics track needs a 'frame' column.")?;
+		let (focal, focal_column) = match [("fx", Focal::Pixels), ("focal_length_mm", Focal::Millimeters), ("fov_h_radians", Focal::FovRadians)]
+			.into_iter().filter_map(|(name, focal)| Some((focal, column(name)?))).collect::<Vec<_>>()[..] {
+			[found] => found,
+			[] => return Err("The intrinsics track needs one of fx, focal_length_mm, or fov_h_radians.".to_string()),
+			_ => return Err("The intrinsics track should have only one of fx, focal_length_mm, and fov_h_radians.".to_string()),
+		};
+		let fy_column = column("fy");
+		if fy_column.is_some() && focal != Focal::Pixels {
+			return Err("The intrinsics track's fy column only goes with fx.".to_string());
+		}
+		let principal_columns = match (column("cx"), column("cy")) {
+			(Some(cx), Some(cy)) => Some((cx, cy)),
+			(None, None) => None,
+			_ => return Err("The intrinsics track needs both cx and cy, or neither.".to_string()),
+		};
+
+		let mut rows = lines.map(|(line_number, line)| {
+			let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+			let number = |idx: usize| -> Result<f32, String> {
+				let field = fields.get(idx).ok_or(format!("Line {line_number} is missing its {} value.", columns[idx]))?;
+				field.parse().map_err(|e| format!("Line {line_number}, {}: {e}", columns[idx]))
+			};
+			let frame = fields.get(frame_column).ok_or(format!("Line {line_number} is missing its frame."))?
+				.parse().map_err(|e| format!("Line {line_number}, frame: {e}"))?;
+			let focal = number(focal_column)?;
+			if !focal.is_finite() || focal <= 0.0 {
+				return Err(format!("Line {line_number}: the focal length must be greater than zero."));
+			}
+			let fy = fy_column.map(number).transpose()?;
+			let principal = match principal_columns {
+				Some((cx, cy)) => Some((number(cx)?, number(cy)?)),
+				None => None,
+			};
+			Ok(Row { frame, focal, fy, principal })
+		}).collect::<Result<Vec<_>, String>>()?;
+		if rows.is_empty() {
+			return Err("The intrinsics track has no rows.".to_string());
+		}
+		rows.sort_by_key(|row| row.frame);
+		Ok(IntrinsicsTrack { focal, rows })
+	}
+
+	/// Whether the focal lengths are in mm, which takes a sensor size to turn into pixels.
+	pub fn needs_sensor_size(&self) -> bool {
+		self.focal == Focal::Millimeters
+	}
+
+	/// The camera for one frame of the given (upright) size.
+	pub fn pinhole_at(&self, frame: usize, width: u32, height: u32, sensor_diagonal_mm: Option<f32>) -> Pinhole {
+		let after = self.rows.partition_point(|row| row.frame <= frame);
+		let (a, b) = match (after.checked_sub(1), self.rows.get(after)) {
+			(Some(before), Some(b)) => (&self.rows[before], b),
+			(Some(before), None) => (&self.rows[before], &self.rows[before]),
+			(None, _) => (&self.rows[0], &self.rows[0]),
+		};
+		let t = if b.frame > a.frame { (frame - a.frame) as f32 / (b.frame - a.frame) as f32 } else { 0.0 };
+		let center = (width as f32 / 2.0, height as f32 / 2.0);
+		let pixels = |row: &Row| {
+			let fx = match self.focal {
+				Focal::Pixels => row.focal,
+				// Without a sensor size, millimeters are pixels, same as --focal-length-mm.
+				Focal::Millimeters => sensor_diagonal_mm.map_or(row.focal, |sensor| row.focal * (width as f32).hypot(height as f32) / sensor),
+				Focal::FovRadians => center.0 / (row.focal / 2.0).tan(),
+			};
+			let (cx, cy) = row.principal.unwrap_or(center);
+			(fx, row.fy.unwrap_or(fx), cx, cy)
+		};
+		let ((fx_a, fy_a, cx_a, cy_a), (fx_b, fy_b, cx_b, cy_b)) = (pixels(a), pixels(b));
+		Pinhole { width, height, fx: lerp(fx_a, fx_b, t), fy: lerp(fy_a, fy_b, t), cx: lerp(cx_a, cx_b, t), cy: lerp(cy_a, cy_b, t) }
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_interpolates_between_rows() {
+		let track = IntrinsicsTrack::parse("frame, focal_length_mm\n# zooming in\n100, 70\n0, 24\n").unwrap();
+		assert!(track.needs_sensor_size());
+		// A sensor with a 40mm diagonal, imaged at 1600x1200 so there are 2000 pixels corner to corner.
+		let at = |frame| track.pinhole_at(frame, 1600, 1200, Some(40.0));
+		assert_eq!(at(0).fx, 24.0 * 50.0);
+		assert!((at(50).fx - 47.0 * 50.0).abs() < 1e-2);
+		assert_eq!(at(500).fx, 70.0 * 50.0);
+		assert_eq!((at(50).cx, at(50).cy), (800.0, 600.0));
+
+		let track = IntrinsicsTrack::parse("frame,fx,fy,cx,cy\n10,1000,1010,950,530").unwrap();
+		assert_eq!(track.pinhole_at(0, 1920, 1080, None), Pinhole { width: 1920, height: 1080, fx: 1000.0, fy: 1010.0, cx: 950.0, cy: 530.0 });
+
+		assert!(IntrinsicsTrack::parse("frame,fx,focal_length_mm\n0,1,2").is_err());
+		assert!(IntrinsicsTrack::parse("frame,fov_h_radians,fy\n0,1,2").is_err());
+		assert!(IntrinsicsTrack::parse("frame,fx\n0,banana").is_err());
+	}
+}
@@ -0,0 +1,50 @@
+// The tracking core, without ffmpeg: marker detection, pose solving, and everything that works on frame records
+// after that. The command line tool (cli, behind the "cli" feature) decodes video and writes outputs on top of this,
+// and the bindings in wasm/ build the core on its own, so nothing outside cli can touch decoding or sockets.
+
+#[cfg(feature = "cli")]
+use ffmpeg_the_third as ffmpeg;
+
+pub mod anchor;
+pub mod backend;
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod colmap;
+pub mod confidence;
+pub mod detect;
+pub mod diamond;
+pub mod dictionary;
+pub mod distortion;
+pub mod filters;
+pub mod focal;
+pub mod frame;
+pub mod geometry;
+pub mod ground;
+pub mod instances;
+pub mod intrinsics_track;
+pub mod map_builder;
+pub mod marker_map;
+pub mod mask;
+pub mod motion;
+pub mod msgpack;
+pub mod occlusion;
+pub mod output;
+pub mod preprocess;
+pub mod qr;
+pub mod quads;
+pub mod record;
+pub mod refinement;
+pub mod report;
+pub mod rolling_shutter;
+pub mod schema;
+pub mod simd;
+pub mod stats;
+pub mod stereo;
+pub mod stop;
+pub mod synthetic;
+pub mod timecode;
+pub mod timing;
+pub mod tonemap;
+pub mod track2d;
+pub mod transform;
+pub mod value;
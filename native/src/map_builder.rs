@@ -0,0 +1,162 @@
+// Building a marker map from the footage itself, for --build-map, so a set doesn't have to be surveyed by hand.
+//
+// The first marker with a pose becomes the world origin (unless --marker-map gave us a start, whose markers then stay
+// put). From then on, each frame that sees a mapped marker places the camera with MarkerMap::camera_pose, and every
+// other marker in that frame gets a world pose estimate through it. A marker joins the map on its first estimate and
+// settles on the average of all of them, weighted towards the sharper sightings. Markers only ever seen in frames
+// with no mapped marker never join, so the footage needs chains of co-visible markers to cover the set.
+//
+// The file is written at the end in marker_map.rs's format, ready to pass back in with --marker-map.
+
+use crate::geometry::{self, Mat3, Quat, Vec3};
+use crate::marker_map::{MapMarker, MarkerMap};
+use crate::output::Sink;
+use crate::record::FrameRecord;
+use crate::transform::{Convention, scale_record, transform_record};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, Default)]
+struct Estimate {
+	weight: f32,
+	translation: Vec3,
+	/// Weighted sum of quaternions, all on the same side as the first one so they don't cancel.
+	rotation: Quat,
+}
+
+impl Estimate {
+	fn add(&mut self, rotation: &Mat3, translation: &Vec3, weight: f32) {
+		let mut q = geometry::mat3_to_quat(rotation);
+		if self.weight > 0.0 && (0..4).map(|i| q[i] * self.rotation[i]).sum::<f32>() < 0.0 {
+			q = q.map(|v| -v);
+		}
+		self.weight += weight;
+		self.translation = geometry::add(&self.translation, &geometry::scale(translation, weight));
+		for (sum, v) in self.rotation.iter_mut().zip(q) {
+			*sum += v * weight;
+		}
+	}
+
+	fn mean(&self) -> MapMarker {
+		MapMarker {
+			rotation: geometry::quat_to_mat3(&geometry::quat_normalize(&self.rotation)),
+			translation: geometry::scale(&self.translation, 1.0 / self.weight),
+			size: None,
+		}
+	}
+}
+
+pub struct MapBuilder {
+	marker_size_mm: f32,
+	map: MarkerMap,
+	/// Markers we're still estimating. Anything in the map but not here was given to us and doesn't move.
+	estimates: BTreeMap<usize, Estimate>,
+}
+
+impl MapBuilder {
+	pub fn new(marker_size_mm: f32, start: Option<MarkerMap>) -> Self {
+		MapBuilder { marker_size_mm, map: start.unwrap_or_default(), estimates: BTreeMap::new() }
+	}
+
+	/// Take in one record, with poses in OpenCV camera space and mm.
+	pub fn push(&mut self, record: &FrameRecord) {
+		if self.map.markers.is_empty() {
+			let Some((first, _)) = record.markers.iter().filter_map(|m| Some((m.marker_id, m.best_pose()?))).min_by_key(|(id, _)| *id) else {
+				return;
+			};
+			self.map.markers.insert(first, MapMarker { rotation: geometry::IDENTITY, translation: [0.0; 3], size: None });
+		}
+		let Some(camera) = self.map.camera_pose(record, self.marker_size_mm) else {
+			return;
+		};
+		for m in &record.markers {
+			let fixed = self.map.markers.contains_key(&m.marker_id) && !self.estimates.contains_key(&m.marker_id);
+			let Some(pose) = m.best_pose().filter(|_| !fixed) else {
+				continue;
+			};
+			let rotation = geometry::mat_mul(&camera.rotation, &pose.rotation);
+			let translation = geometry::add(&geometry::mat_mul_vec(&camera.rotation, &pose.translation), &camera.position);
+			// Close, square-on sightings reproject well, far and oblique ones don't.
+			let weight = 1.0 / (1.0 + pose.reprojection_error.unwrap_or(1.0)).powi(2);
+			let estimate = self.estimates.entry(m.marker_id).or_default();
+			estimate.add(&rotation, &translation, weight);
+			self.map.markers.insert(m.marker_id, estimate.mean());
+		}
+	}
+
+	pub fn map(&self) -> &MarkerMap {
+		&self.map
+	}
+}
+
+/// Feeds --build-map from the output records and writes the map when the run is done.
+pub struct MapBuilderSink {
+	path: PathBuf,
+	builder: MapBuilder,
+	/// Takes the records' poses back into OpenCV's camera convention, which is what the map is built in.
+	to_opencv: Mat3,
+	/// Output units per mm, which the records' translations have already been scaled by.
+	length_scale: f32,
+}
+
+impl MapBuilderSink {
+	pub fn new(path: PathBuf, marker_size_mm: f32, start: Option<MarkerMap>, convention: Convention, length_scale: f32) -> Self {
+		let to_opencv = geometry::transpose(&convention.basis());
+		MapBuilderSink { path, builder: MapBuilder::new(marker_size_mm, start), to_opencv, length_scale }
+	}
+}
+
+impl Sink for MapBuilderSink {
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		// Triangulated records repeat what the left camera already saw.
+		if record.camera_id.as_deref() == Some("stereo") {
+			return Ok(());
+		}
+		let mut record = record.clone();
+		transform_record(&mut record, &self.to_opencv, &[0.0; 3]);
+		scale_record(&mut record, 1.0 / self.length_scale);
+		self.builder.push(&record);
+		Ok(())
+	}
+
+	fn finish(&mut self) -> io::Result<()> {
+		std::fs::write(&self.path, self.builder.map().to_text())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::geometry::Pinhole;
+	use crate::record::{MarkerRecord, PoseRecord};
+
+	#[test]
+	fn test_chains_markers_from_the_first() {
+		let pinhole = Pinhole { width: 1000, height: 1000, fx: 800.0, fy: 800.0, cx: 500.0, cy: 500.0 };
+		// Three markers in a row on a wall, 200mm apart, filmed by a camera panning along it, so 1 and 3 are never in
+		// the same frame.
+		let world = |id: usize| [200.0 * id as f32, 0.0, 0.0];
+		let frame = |camera_x: f32, ids: &[usize]| FrameRecord {
+			intrinsics: Some(pinhole),
+			markers: ids.iter().map(|&id| {
+				let translation = [world(id)[0] - camera_x, 0.0, 600.0];
+				MarkerRecord {
+					marker_id: id,
+					corners: geometry::marker_corners(50.0).map(|c| pinhole.project(&geometry::add(&c, &translation))),
+					poses: vec![PoseRecord { translation, rotation: geometry::IDENTITY, reprojection_error: Some(0.1), ..Default::default() }],
+					..Default::default()
+				}
+			}).collect(),
+			..Default::default()
+		};
+		let mut builder = MapBuilder::new(50.0, None);
+		builder.push(&frame(300.0, &[1, 2]));
+		builder.push(&frame(500.0, &[2, 3]));
+		let map = builder.map();
+		assert_eq!(map.markers.len(), 3);
+		assert_eq!(map.markers[&1].translation, [0.0; 3]);
+		let t = map.markers[&3].translation;
+		assert!(geometry::length(&geometry::sub(&t, &[400.0, 0.0, 0.0])) < 0.5, "{t:?}");
+	}
+}
@@ -0,0 +1,191 @@
+// Known positions of fiducials in a shared world frame, e.g. from surveying a set or from an earlier mapping run.
+//
+// The file is plain text, one marker per line:
+//   <marker_id> <tx> <ty> <tz> <rx> <ry> <rz> [size]
+// with the translation in mm and the rotation as a Rodrigues vector, taking marker space (see geometry::marker_corners)
+// to world space. The optional size is the marker's edge length in mm, for maps mixing marker sizes; without it the
+// marker is assumed to be the size given on the command line. Blank lines and anything after a '#' are ignored.
+//
+// Given a map, each frame's camera is placed in the world from every mapped marker in view: a first guess from the
+// single best marker pose, then refined against all of the mapped corners at once.
+
+use crate::geometry::{self, Mat3, Vec3};
+use crate::record::{CameraPose, FrameRecord};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MapMarker {
+	pub rotation: Mat3,
+	pub translation: Vec3,
+	/// The edge length in mm, if it's not the one on the command line.
+	pub size: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MarkerMap {
+	pub markers: BTreeMap<usize, MapMarker>,
+}
+
+pub fn parse_marker_map_file(path: &str) -> Result<MarkerMap, String> {
+	let text = std::fs::read_to_string(path).map_err(|e| format!("Couldn't read marker map {path}: {e}"))?;
+	MarkerMap::parse(&text)
+}
+
+impl MarkerMap {
+	pub fn parse(text: &str) -> Result<Self, String> {
+		let mut map = MarkerMap::default();
+		for (line_number, line) in text.lines().enumerate() {
+			let line = line.split('#').next().unwrap_or_default().trim();
+			if line.is_empty() {
+				continue;
+			}
+			let fields: Vec<&str> = line.split_whitespace().collect();
+			let [id, values @ ..] = &fields[..] else {
+				continue;
+			};
+			let id = id.parse::<usize>().map_err(|e| format!("Line {}: bad marker id: {e}", line_number + 1))?;
+			let values = values.iter().map(|v| v.parse::<f32>()).collect::<Result<Vec<f32>, _>>().map_err(|e| format!("Line {}: {e}", line_number + 1))?;
+			let (tx, ty, tz, rx, ry, rz, size) = match values[..] {
+				[tx, ty, tz, rx, ry, rz] => (tx, ty, tz, rx, ry, rz, None),
+				[tx, ty, tz, rx, ry, rz, size] if size > 0.0 => (tx, ty, tz, rx, ry, rz, Some(size)),
+				_ => return Err(format!("Line {}: expected a marker id followed by tx ty tz rx ry rz and an optional positive size.", line_number + 1)),
+			};
+			map.markers.insert(id, MapMarker { rotation: geometry::rodrigues(&[rx, ry, rz]), translation: [tx, ty, tz], size });
+		}
+		Ok(map)
+	}
+
+	/// The map in the file format parse reads.
+	pub fn to_text(&self) -> String {
+		let mut out = String::from("# id tx ty tz rx ry rz [size]\n");
+		for (id, m) in &self.markers {
+			let [rx, ry, rz] = geometry::rotation_vector(&m.rotation);
+			let [tx, ty, tz] = m.translation;
+			out.push_str(&format!("{id} {tx} {ty} {tz} {rx} {ry} {rz}"));
+			if let Some(size) = m.size {
+				out.push_str(&format!(" {size}"));
+			}
+			out.push('\n');
+		}
+		out
+	}
+
+	/// World positions of a mapped marker's corners, in the detector's corner order. `marker_size_mm` is used unless
+	/// the map gives the marker its own size.
+	pub fn corners(&self, marker_id: usize, marker_size_mm: f32) -> Option<[Vec3; 4]> {
+		let m = self.markers.get(&marker_id)?;
+		Some(geometry::marker_corners(m.size.unwrap_or(marker_size_mm)).map(|c| geometry::add(&geometry::mat_mul_vec(&m.rotation, &c), &m.translation)))
+	}
+
+	/// The marker the camera is placed from first: the most confident mapped one.
+	fn seed(&self, record: &FrameRecord) -> Option<usize> {
+		record.markers.iter().enumerate()
+			.filter_map(|(idx, m)| Some((idx, self.markers.get(&m.marker_id)?, m.best_pose()?)))
+			.min_by(|a, b| a.2.error.total_cmp(&b.2.error))
+			.map(|(idx, _, _)| idx)
+	}
+
+	/// Every mapped corner in the frame as (world position, pixel). An id seen more than once at a time (see
+	/// instances.rs) is only where the map says in one place, so its copies are left out unless one is the `seed`.
+	fn correspondences(&self, record: &FrameRecord, marker_size_mm: f32, seed: usize) -> Vec<(Vec3, (f32, f32))> {
+		record.markers.iter().enumerate()
+			.filter(|(idx, m)| *idx == seed || record.markers.iter().filter(|other| other.marker_id == m.marker_id).count() == 1)
+			.filter_map(|(_, m)| Some(self.corners(m.marker_id, marker_size_mm)?.into_iter().zip(m.corners)))
+			.flatten()
+			.collect()
+	}
+
+	/// The world-to-camera transform (x_camera = rotation * x_world + translation) for a frame. None if no mapped
+	/// marker is in view. Without intrinsics this is only the single most confident marker's answer.
+	pub fn locate_camera(&self, record: &FrameRecord, marker_size_mm: f32) -> Option<(Mat3, Vec3)> {
+		let seed = self.seed(record)?;
+		let m = &record.markers[seed];
+		let (mapped, pose) = (&self.markers[&m.marker_id], m.best_pose()?);
+		// The detector solved every marker at the command line size. Distance scales with the real size.
+		let marker_translation = geometry::scale(&pose.translation, mapped.size.unwrap_or(marker_size_mm) / marker_size_mm);
+		// x_camera = R_cm * x_marker + t_cm and x_world = R_wm * x_marker + t_wm.
+		let rotation = geometry::mat_mul(&pose.rotation, &geometry::transpose(&mapped.rotation));
+		let translation = geometry::sub(&marker_translation, &geometry::mat_mul_vec(&rotation, &mapped.translation));
+		let Some(pinhole) = &record.intrinsics else {
+			return Some((rotation, translation));
+		};
+		Some(geometry::refine_pose(&rotation, &translation, &self.correspondences(record, marker_size_mm, seed), pinhole))
+	}
+
+	/// Where the camera is in the world for this frame, for the output.
+	pub fn camera_pose(&self, record: &FrameRecord, marker_size_mm: f32) -> Option<CameraPose> {
+		let (rotation, translation) = self.locate_camera(record, marker_size_mm)?;
+		let points = self.correspondences(record, marker_size_mm, self.seed(record)?);
+		let reprojection_error = record.intrinsics.map(|pinhole| (geometry::reprojection_cost(&rotation, &translation, &points, &pinhole) / points.len() as f32).sqrt());
+		let camera_rotation = geometry::transpose(&rotation);
+		Some(CameraPose {
+			rotation: camera_rotation,
+			position: geometry::scale(&geometry::mat_mul_vec(&camera_rotation, &translation), -1.0),
+			reprojection_error,
+			marker_count: points.len() / 4,
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::geometry::Pinhole;
+	use crate::record::{MarkerRecord, PoseRecord};
+
+	#[test]
+	fn test_parse() {
+		let map = MarkerMap::parse("# id tx ty tz rx ry rz\n3 100 0 0 0 0 0\n\n7 0 0 0 0 0 1.5708 # rotated\n").unwrap();
+		assert_eq!(map.markers.len(), 2);
+		assert_eq!(map.markers[&3].translation, [100.0, 0.0, 0.0]);
+		assert!(MarkerMap::parse("3 1 2 3").is_err());
+		assert_eq!(MarkerMap::parse("3 0 0 0 0 0 0 120").unwrap().markers[&3].size, Some(120.0));
+		assert_eq!(MarkerMap::parse(&map.to_text()).unwrap().markers[&3], map.markers[&3]);
+	}
+
+	#[test]
+	fn test_locate_camera() {
+		let map = MarkerMap::parse("3 100 0 0 0 0 0").unwrap();
+		// A camera 500mm in front of a marker 100mm along world X sees it at (0, 0, 500).
+		let record = FrameRecord {
+			markers: vec![MarkerRecord {
+				marker_id: 3,
+				poses: vec![PoseRecord { translation: [0.0, 0.0, 500.0], rotation: geometry::IDENTITY, error: 0.1, ..Default::default() }],
+				..Default::default()
+			}],
+			..Default::default()
+		};
+		let (rotation, translation) = map.locate_camera(&record, 50.0).unwrap();
+		assert_eq!(rotation, geometry::IDENTITY);
+		assert_eq!(translation, [-100.0, 0.0, 500.0]);
+		// The world origin is at x = -100 in camera space, so the mapped corner should land on the marker.
+		let corner = map.corners(3, 50.0).unwrap()[0];
+		assert_eq!(geometry::add(&geometry::mat_mul_vec(&rotation, &corner), &translation), [-25.0, 25.0, 500.0]);
+	}
+
+	#[test]
+	fn test_camera_pose_uses_every_marker() {
+		let map = MarkerMap::parse("1 0 0 0 0 0 0
+2 300 0 0 0 0 0 100").unwrap();
+		let pinhole = Pinhole { width: 1000, height: 1000, fx: 800.0, fy: 800.0, cx: 500.0, cy: 500.0 };
+		// World to camera is a plain shift: the camera sits at (150, 0, -800) looking along world +Z.
+		let translation = [-150.0, 0.0, 800.0];
+		let marker = |marker_id: usize, size: f32, center: Vec3, nudge: f32| MarkerRecord {
+			marker_id,
+			corners: geometry::marker_corners(size).map(|c| pinhole.project(&geometry::add(&geometry::add(&c, &center), &translation))),
+			// Marker 1's own pose is a little off, as if from a noisy solve.
+			poses: vec![PoseRecord { translation: geometry::add(&geometry::add(&center, &translation), &[nudge, 0.0, nudge]), rotation: geometry::IDENTITY, error: 1.0 - nudge / 10.0, ..Default::default() }],
+			..Default::default()
+		};
+		let record = FrameRecord {
+			intrinsics: Some(pinhole),
+			// Marker 2 is 100mm across, but the detector solved it as if it were the 50mm on the command line.
+			markers: vec![marker(1, 50.0, [0.0; 3], 8.0), marker(2, 100.0, [300.0, 0.0, 0.0], 0.0)],
+			..Default::default()
+		};
+		let camera = map.camera_pose(&record, 50.0).unwrap();
+		assert_eq!(camera.marker_count, 2);
+		assert!(geometry::length(&geometry::sub(&camera.position, &[150.0, 0.0, -800.0])) < 0.5, "{:?}", camera.position);
+		assert!(camera.reprojection_error.unwrap() < 0.05);
+	}
+}
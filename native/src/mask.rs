@@ -0,0 +1,134 @@
+// Regions of the frame to leave out of detection, for --mask: a black and white image the size of the upright frame
+// (stretched to fit if it isn't), white where markers are looked for and black where they aren't. For monitors showing
+// markers, reflections in glass, or crew walking through with a slate, which are easier to paint out once than to
+// filter out of the track afterwards.
+//
+// The black parts are painted white before the detector sees the frame, so nothing there has a border to be found by.
+// A path with a printf-style frame number in it, like masks/shot.%04d.png, is a sequence read by frame number (counting
+// decoded frames from 0). A frame without its own file keeps the last one before it, so a sequence only needs a file
+// where the mask changes.
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GrayImage, ImageBuffer, Pixel, Primitive};
+use std::path::Path;
+
+/// Mask pixels darker than this are left out.
+const THRESHOLD: u8 = 128;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaskSource {
+	path: String,
+}
+
+pub fn parse_mask(path: &str) -> Result<MaskSource, String> {
+	let source = MaskSource { path: path.to_string() };
+	// A sequence's first file might not be there yet, as when the masks are still being painted.
+	if source.number_format().is_none() {
+		image::open(path).map_err(|e| format!("Couldn't read the mask {path}: {e}"))?;
+	}
+	Ok(source)
+}
+
+impl MaskSource {
+	pub fn path(&self) -> &str {
+		&self.path
+	}
+
+	/// Where the frame number goes in a sequence's path, and how many digits it's padded to.
+	fn number_format(&self) -> Option<(usize, usize, usize)> {
+		let start = self.path.find('%')?;
+		let rest = &self.path[start + 1..];
+		let digits = rest.find('d')?;
+		let width = if digits == 0 { 0 } else { rest[..digits].parse().ok()? };
+		Some((start, start + digits + 2, width))
+	}
+
+	fn path_for(&self, frame_index: usize) -> String {
+		match self.number_format() {
+			Some((start, end, width)) => format!("{}{frame_index:0width$}{}", &self.path[..start], &self.path[end..]),
+			None => self.path.clone(),
+		}
+	}
+
+	pub fn masker(&self) -> Masker {
+		Masker { source: self.clone(), loaded: None, scaled: None }
+	}
+}
+
+/// A mask as it's applied over a run, keeping hold of the current image.
+pub struct Masker {
+	source: MaskSource,
+	/// The path of the mask in use, and the mask as read.
+	loaded: Option<(String, GrayImage)>,
+	/// That mask at the frame's size, once it's been needed.
+	scaled: Option<GrayImage>,
+}
+
+impl Masker {
+	/// Paint over the parts of upright frame `frame_index` the mask leaves out. An error means a mask file was there but
+	/// couldn't be read, in which case the last mask is kept.
+	pub fn apply(&mut self, frame_index: usize, img: &mut DynamicImage) -> Result<(), String> {
+		let path = self.source.path_for(frame_index);
+		let mut result = Ok(());
+		if self.loaded.as_ref().is_none_or(|(loaded, _)| *loaded != path) && Path::new(&path).is_file() {
+			match image::open(&path) {
+				Ok(mask) => {
+					self.loaded = Some((path, mask.to_luma8()));
+					self.scaled = None;
+				},
+				Err(e) => result = Err(format!("Couldn't read the mask {path}: {e}")),
+			}
+		}
+		let Some((_, mask)) = &self.loaded else {
+			return result;
+		};
+		let (width, height) = (img.width(), img.height());
+		let scaled = match &mut self.scaled {
+			Some(scaled) if scaled.dimensions() == (width, height) => scaled,
+			scaled if mask.dimensions() == (width, height) => scaled.insert(mask.clone()),
+			scaled => scaled.insert(imageops::resize(mask, width, height, FilterType::Nearest)),
+		};
+		match img {
+			DynamicImage::ImageLuma8(buffer) => paint_out(buffer, scaled),
+			DynamicImage::ImageLuma16(buffer) => paint_out(buffer, scaled),
+			DynamicImage::ImageRgb8(buffer) => paint_out(buffer, scaled),
+			other => {
+				let mut buffer = other.to_rgb8();
+				paint_out(&mut buffer, scaled);
+				*other = DynamicImage::ImageRgb8(buffer);
+			},
+		}
+		result
+	}
+}
+
+fn paint_out<P: Pixel>(img: &mut ImageBuffer<P, Vec<P::Subpixel>>, mask: &GrayImage) {
+	let white = <P::Subpixel as Primitive>::DEFAULT_MAX_VALUE;
+	for (pixel, m) in img.pixels_mut().zip(mask.pixels()) {
+		if m[0] < THRESHOLD {
+			pixel.apply(|_| white);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::Luma;
+
+	#[test]
+	fn test_mask_paints_out_black() {
+		let sequence = MaskSource { path: "masks/shot.%04d.png".to_string() };
+		assert_eq!(sequence.path_for(12), "masks/shot.0012.png");
+		assert_eq!(MaskSource { path: "m_%d.png".to_string() }.path_for(7), "m_7.png");
+
+		// Half the size of the frame, so it gets stretched to fit.
+		let mask = GrayImage::from_fn(4, 2, |x, _| Luma([if x < 2 { 0 } else { 255 }]));
+		let mut masker = MaskSource { path: "mask.png".to_string() }.masker();
+		masker.loaded = Some(("mask.png".to_string(), mask));
+		let mut img = DynamicImage::ImageLuma8(GrayImage::new(8, 4));
+		masker.apply(0, &mut img).unwrap();
+		let gray = img.to_luma8();
+		assert_eq!((gray.get_pixel(0, 3)[0], gray.get_pixel(3, 0)[0], gray.get_pixel(4, 0)[0], gray.get_pixel(7, 3)[0]), (255, 255, 0, 0));
+	}
+}
@@ -0,0 +1,86 @@
+// Per-marker velocities from consecutive frames, so downstream tools can do motion blur or prediction without
+// differentiating the track themselves.
+//
+// Both are backward differences of the best pose, in camera space: velocity in mm/s and angular velocity as an
+// axis * rad/s vector. They're only filled in when the marker was also seen in the camera's previous record, so
+// a gap in the track never turns into one long average. A pose that flips to the other IPPE solution between two
+// frames shows up as a spike, which is itself a useful thing for a filter to see.
+
+use crate::geometry::{self, Mat3, Vec3};
+use crate::record::FrameRecord;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug)]
+struct Sighting {
+	timestamp: f64,
+	translation: Vec3,
+	rotation: Mat3,
+}
+
+#[derive(Default)]
+pub struct VelocityEstimator {
+	/// The previous record's frame id for each camera, to tell a consecutive sighting from one across a gap.
+	last_frame: BTreeMap<Option<String>, usize>,
+	previous: BTreeMap<(Option<String>, usize, u32), (usize, Sighting)>,
+}
+
+impl VelocityEstimator {
+	pub fn apply(&mut self, record: &mut FrameRecord) {
+		let last_frame = self.last_frame.insert(record.camera_id.clone(), record.frame_id);
+		for m in record.markers.iter_mut() {
+			let Some(pose) = m.best_pose() else {
+				continue;
+			};
+			let sighting = Sighting { timestamp: record.timestamp, translation: pose.translation, rotation: pose.rotation };
+			let key = (record.camera_id.clone(), m.marker_id, m.instance.unwrap_or_default());
+			if let Some((frame_id, before)) = self.previous.insert(key, (record.frame_id, sighting.clone()))
+				&& Some(frame_id) == last_frame {
+				let dt = (sighting.timestamp - before.timestamp) as f32;
+				if dt > 0.0 {
+					m.velocity = Some(geometry::scale(&geometry::sub(&sighting.translation, &before.translation), 1.0 / dt));
+					let delta = geometry::mat_mul(&sighting.rotation, &geometry::transpose(&before.rotation));
+					m.angular_velocity = Some(geometry::scale(&geometry::rotation_vector(&delta), 1.0 / dt));
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::record::{MarkerRecord, PoseRecord};
+
+	fn frame(frame_id: usize, timestamp: f64, x: f32, angle: f32) -> FrameRecord {
+		FrameRecord {
+			frame_id,
+			timestamp,
+			markers: vec![MarkerRecord {
+				marker_id: 1,
+				poses: vec![PoseRecord { translation: [x, 0.0, 500.0], rotation: geometry::rodrigues(&[0.0, 0.0, angle]), ..Default::default() }],
+				..Default::default()
+			}],
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn test_backward_difference() {
+		let mut estimator = VelocityEstimator::default();
+		let mut first = frame(0, 0.0, 0.0, 0.0);
+		estimator.apply(&mut first);
+		assert!(first.markers[0].velocity.is_none());
+
+		let mut second = frame(1, 0.5, 10.0, 0.25);
+		estimator.apply(&mut second);
+		assert_eq!(second.markers[0].velocity, Some([20.0, 0.0, 0.0]));
+		let w = second.markers[0].angular_velocity.unwrap();
+		assert!((w[2] - 0.5).abs() < 1e-5, "{w:?}");
+
+		// Missing from frame 2, so frame 3 has nothing to difference against.
+		estimator.apply(&mut FrameRecord { frame_id: 2, timestamp: 1.0, ..Default::default() });
+		let mut fourth = frame(3, 1.5, 30.0, 0.0);
+		estimator.apply(&mut fourth);
+		assert!(fourth.markers[0].velocity.is_none());
+	}
+}
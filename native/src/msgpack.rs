@@ -0,0 +1,275 @@
+// MessagePack, for runs where JSONL gets too big or too slow to parse. Each record is one top-level map with exactly
+// the same keys as the JSON, so a stream of them can be converted back line for line. Hand-rolled like the JSON:
+// we only need the handful of types our records use.
+
+use crate::output::{Finish, Sink};
+use crate::record::FrameRecord;
+use crate::value::Value;
+use std::io::{self, Read, Write};
+
+pub fn encode(value: &Value, out: &mut Vec<u8>) {
+	match value {
+		Value::Null => out.push(0xc0),
+		Value::Bool(false) => out.push(0xc2),
+		Value::Bool(true) => out.push(0xc3),
+		Value::Int(i) => encode_int(*i, out),
+		Value::F32(f) => {
+			out.push(0xca);
+			out.extend_from_slice(&f.to_be_bytes());
+		},
+		Value::F64(f) => {
+			out.push(0xcb);
+			out.extend_from_slice(&f.to_be_bytes());
+		},
+		Value::Str(s) => encode_str(s, out),
+		Value::Array(items) => {
+			encode_len(items.len(), 0x90, 0xdc, out);
+			for item in items {
+				encode(item, out);
+			}
+		},
+		Value::Map(entries) => {
+			encode_len(entries.len(), 0x80, 0xde, out);
+			for (key, value) in entries {
+				encode_str(key, out);
+				encode(value, out);
+			}
+		},
+	}
+}
+
+fn encode_int(i: i64, out: &mut Vec<u8>) {
+	// Always use the smallest encoding. Frame ids and marker ids are usually tiny.
+	if (0..128).contains(&i) {
+		out.push(i as u8);
+	} else if (-32..0).contains(&i) {
+		out.push(i as i8 as u8);
+	} else if i >= 0 {
+		if i <= u8::MAX as i64 {
+			out.extend_from_slice(&[0xcc, i as u8]);
+		} else if i <= u16::MAX as i64 {
+			out.push(0xcd);
+			out.extend_from_slice(&(i as u16).to_be_bytes());
+		} else if i <= u32::MAX as i64 {
+			out.push(0xce);
+			out.extend_from_slice(&(i as u32).to_be_bytes());
+		} else {
+			out.push(0xcf);
+			out.extend_from_slice(&(i as u64).to_be_bytes());
+		}
+	} else if i >= i8::MIN as i64 {
+		out.extend_from_slice(&[0xd0, i as i8 as u8]);
+	} else if i >= i16::MIN as i64 {
+		out.push(0xd1);
+		out.extend_from_slice(&(i as i16).to_be_bytes());
+	} else if i >= i32::MIN as i64 {
+		out.push(0xd2);
+		out.extend_from_slice(&(i as i32).to_be_bytes());
+	} else {
+		out.push(0xd3);
+		out.extend_from_slice(&i.to_be_bytes());
+	}
+}
+
+fn encode_str(s: &str, out: &mut Vec<u8>) {
+	let len = s.len();
+	if len < 32 {
+		out.push(0xa0 | len as u8);
+	} else if len <= u8::MAX as usize {
+		out.extend_from_slice(&[0xd9, len as u8]);
+	} else if len <= u16::MAX as usize {
+		out.push(0xda);
+		out.extend_from_slice(&(len as u16).to_be_bytes());
+	} else {
+		out.push(0xdb);
+		out.extend_from_slice(&(len as u32).to_be_bytes());
+	}
+	out.extend_from_slice(s.as_bytes());
+}
+
+// Arrays and maps share a layout: a fix form for up to 15 entries, then 16 and 32 bit lengths.
+fn encode_len(len: usize, fix: u8, wide: u8, out: &mut Vec<u8>) {
+	if len < 16 {
+		out.push(fix | len as u8);
+	} else if len <= u16::MAX as usize {
+		out.push(wide);
+		out.extend_from_slice(&(len as u16).to_be_bytes());
+	} else {
+		out.push(wide + 1);
+		out.extend_from_slice(&(len as u32).to_be_bytes());
+	}
+}
+
+fn invalid(message: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+	let mut buf = [0u8; N];
+	reader.read_exact(&mut buf)?;
+	Ok(buf)
+}
+
+/// Read the next value from a stream. Returns None at a clean end of stream, and an error if it ends mid-value.
+pub fn decode(reader: &mut impl Read) -> io::Result<Option<Value>> {
+	let mut marker = [0u8; 1];
+	loop {
+		match reader.read(&mut marker) {
+			Ok(0) => return Ok(None),
+			Ok(_) => break,
+			Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(e),
+		}
+	}
+	decode_with_marker(marker[0], reader).map(Some)
+}
+
+fn decode_value(reader: &mut impl Read) -> io::Result<Value> {
+	let [marker] = read_array::<1>(reader)?;
+	decode_with_marker(marker, reader)
+}
+
+fn decode_with_marker(marker: u8, reader: &mut impl Read) -> io::Result<Value> {
+	Ok(match marker {
+		0x00..=0x7f => Value::Int(marker as i64),
+		0x80..=0x8f => decode_map((marker & 0x0f) as usize, reader)?,
+		0x90..=0x9f => decode_array((marker & 0x0f) as usize, reader)?,
+		0xa0..=0xbf => decode_str((marker & 0x1f) as usize, reader)?,
+		0xc0 => Value::Null,
+		0xc2 => Value::Bool(false),
+		0xc3 => Value::Bool(true),
+		0xca => Value::F32(f32::from_be_bytes(read_array(reader)?)),
+		0xcb => Value::F64(f64::from_be_bytes(read_array(reader)?)),
+		0xcc => Value::Int(u8::from_be_bytes(read_array(reader)?) as i64),
+		0xcd => Value::Int(u16::from_be_bytes(read_array(reader)?) as i64),
+		0xce => Value::Int(u32::from_be_bytes(read_array(reader)?) as i64),
+		0xcf => {
+			let v = u64::from_be_bytes(read_array(reader)?);
+			Value::Int(i64::try_from(v).map_err(|_| invalid(format!("Integer {v} is too large.")))?)
+		},
+		0xd0 => Value::Int(i8::from_be_bytes(read_array(reader)?) as i64),
+		0xd1 => Value::Int(i16::from_be_bytes(read_array(reader)?) as i64),
+		0xd2 => Value::Int(i32::from_be_bytes(read_array(reader)?) as i64),
+		0xd3 => Value::Int(i64::from_be_bytes(read_array(reader)?)),
+		0xd9 => decode_str(u8::from_be_bytes(read_array(reader)?) as usize, reader)?,
+		0xda => decode_str(u16::from_be_bytes(read_array(reader)?) as usize, reader)?,
+		0xdb => decode_str(u32::from_be_bytes(read_array(reader)?) as usize, reader)?,
+		0xdc => decode_array(u16::from_be_bytes(read_array(reader)?) as usize, reader)?,
+		0xdd => decode_array(u32::from_be_bytes(read_array(reader)?) as usize, reader)?,
+		0xde => decode_map(u16::from_be_bytes(read_array(reader)?) as usize, reader)?,
+		0xdf => decode_map(u32::from_be_bytes(read_array(reader)?) as usize, reader)?,
+		0xe0..=0xff => Value::Int(marker as i8 as i64),
+		other => return Err(invalid(format!("Unsupported MessagePack type 0x{other:02x}."))),
+	})
+}
+
+fn decode_str(len: usize, reader: &mut impl Read) -> io::Result<Value> {
+	let mut buf = vec![0u8; len];
+	reader.read_exact(&mut buf)?;
+	String::from_utf8(buf).map(Value::Str).map_err(|e| invalid(format!("String isn't UTF-8: {e}")))
+}
+
+fn decode_array(len: usize, reader: &mut impl Read) -> io::Result<Value> {
+	// Don't trust the length for the allocation. A corrupt file could claim billions of entries.
+	let mut items = Vec::with_capacity(len.min(1024));
+	for _ in 0..len {
+		items.push(decode_value(reader)?);
+	}
+	Ok(Value::Array(items))
+}
+
+fn decode_map(len: usize, reader: &mut impl Read) -> io::Result<Value> {
+	let mut entries = Vec::with_capacity(len.min(1024));
+	for _ in 0..len {
+		let Value::Str(key) = decode_value(reader)? else {
+			return Err(invalid("Map keys must be strings.".to_string()));
+		};
+		entries.push((key, decode_value(reader)?));
+	}
+	Ok(Value::Map(entries))
+}
+
+/// Turn a stream of MessagePack records back into JSON lines. Returns how many records were converted.
+pub fn convert_to_json_lines(reader: &mut impl Read, writer: &mut impl Write) -> io::Result<usize> {
+	let mut count = 0;
+	while let Some(value) = decode(reader)? {
+		writeln!(writer, "{}", value.to_json())?;
+		count += 1;
+	}
+	writer.flush()?;
+	Ok(count)
+}
+
+/// Records back to back with no framing. MessagePack values are self-delimiting.
+pub struct MsgPackSink<W: Finish> {
+	writer: W,
+	buffer: Vec<u8>,
+}
+
+impl<W: Finish> MsgPackSink<W> {
+	pub fn new(writer: W) -> Self {
+		MsgPackSink { writer, buffer: Vec::with_capacity(1024) }
+	}
+}
+
+impl<W: Finish> Sink for MsgPackSink<W> {
+	fn header(&mut self, header: &Value) -> io::Result<()> {
+		self.buffer.clear();
+		encode(header, &mut self.buffer);
+		self.writer.write_all(&self.buffer)
+	}
+
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		self.buffer.clear();
+		encode(&record.to_value(), &mut self.buffer);
+		self.writer.write_all(&self.buffer)
+	}
+
+	fn finish(&mut self) -> io::Result<()> {
+		self.writer.finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn round_trip(value: &Value) -> Value {
+		let mut bytes = vec![];
+		encode(value, &mut bytes);
+		decode(&mut bytes.as_slice()).unwrap().unwrap()
+	}
+
+	#[test]
+	fn test_int_encodings() {
+		for i in [0, 127, 128, 255, 256, 65536, -1, -32, -33, -129, -40000, i64::MIN, i64::MAX] {
+			assert_eq!(round_trip(&Value::Int(i)), Value::Int(i));
+		}
+		let mut bytes = vec![];
+		encode(&Value::Int(5), &mut bytes);
+		encode(&Value::Int(-1), &mut bytes);
+		assert_eq!(bytes, [0x05, 0xff]);
+	}
+
+	#[test]
+	fn test_record_round_trip() {
+		let mut record = FrameRecord { frame_id: 300, camera_id: Some("left".to_string()), timestamp: 10.0, ..Default::default() };
+		record.markers.push(Default::default());
+		let mut bytes = vec![];
+		let mut sink = MsgPackSink::new(&mut bytes);
+		sink.write(&record).unwrap();
+		sink.write(&record).unwrap();
+		let mut json = vec![];
+		assert_eq!(convert_to_json_lines(&mut bytes.as_slice(), &mut json).unwrap(), 2);
+		let line = record.to_json() + "\n";
+		assert_eq!(String::from_utf8(json).unwrap(), line.repeat(2));
+	}
+
+	#[test]
+	fn test_truncated_stream_is_an_error() {
+		let mut bytes = vec![];
+		encode(&FrameRecord::default().to_value(), &mut bytes);
+		bytes.pop();
+		assert!(decode(&mut bytes.as_slice()).is_err());
+	}
+}
@@ -0,0 +1,237 @@
+// Bridging short occlusions. A hand over a corner or across the bits stops a marker decoding even though most of it is
+// still in plain view, and a track that ends there is worse for a filter downstream than one with a few rough frames.
+// With --partial-markers, a marker that was in the last frame but not this one is looked for where it was, and written
+// with "partial": true, the id it had, and which of its corners could be seen.
+//
+// First we look for its border as a dark quad of about the same size in about the same place, from the same
+// threshold and flood fill as the debug dump. Covering the bits leaves that intact. Then each corner is checked for a dark border
+// corner against a light quiet zone; corners that fail (or every corner, if there was no quad) are carried over
+// from the last frame, shifted along with the ones that passed. Two visible corners are enough to keep going, for at
+// most so many frames in a row.
+
+use crate::confidence::sample;
+use crate::geometry::{Pinhole, apply_homography, square_to_quad};
+use crate::quads::{Scratch, adaptive_threshold_in, candidate_quads_in};
+use crate::record::{FrameRecord, MarkerRecord, PoseRecord};
+use image::{GrayImage, Luma};
+use std::collections::{BTreeMap, BTreeSet};
+
+type Quad = [(f32, f32); 4];
+
+/// How long a marker is bridged for before it's let go: half a second at 30 fps.
+pub const MAX_FRAMES: u32 = 15;
+
+/// How much lighter the quiet zone just outside a corner has to be than the border just inside, in gray levels.
+const MIN_CORNER_CONTRAST: f32 = 40.0;
+
+pub struct OcclusionBridge {
+	/// Where each copy of each marker id was last frame, full frame pixels, and how many frames in a row it's been
+	/// bridged for. Instances (see instances.rs) are only given out once the cameras' records come together, so the
+	/// copies are numbered here by matching them to where they were, the same way.
+	last: BTreeMap<(usize, u32), (Quad, u32)>,
+	max_frames: u32,
+	scratch: Scratch,
+}
+
+impl OcclusionBridge {
+	pub fn new(max_frames: u32) -> Self {
+		OcclusionBridge { last: BTreeMap::new(), max_frames, scratch: Scratch::default() }
+	}
+
+	/// Add a partial marker to `record` for each one that went missing since the last frame and can still be made
+	/// out. `gray` is the image the detector saw, at `offset` in the full frame.
+	pub fn bridge(&mut self, record: &mut FrameRecord, gray: &GrayImage, offset: (f32, f32), pinhole: &Pinhole, marker_size_mm: f32) {
+		// QR codes are a different size and find themselves again from their payload, so they're left alone.
+		let found: Vec<Quad> = record.markers.iter().filter(|m| m.payload.is_none()).map(|m| m.corners).collect();
+		let ids: Vec<usize> = record.markers.iter().filter(|m| m.payload.is_none()).map(|m| m.marker_id).collect();
+		// Each marker found is whichever copy of its id was nearest last frame, nearest pair first.
+		let mut pairs: Vec<(f32, usize, (usize, u32))> = found.iter().zip(&ids).enumerate()
+			.flat_map(|(i, (corners, id))| self.last.range((*id, 0)..=(*id, u32::MAX)).map(move |(key, (last, _))| (distance(centroid(corners), centroid(last)), i, *key)))
+			.collect();
+		pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+		let mut keys = vec![None; found.len()];
+		let mut seen = BTreeSet::new();
+		for (_, i, key) in pairs {
+			if keys[i].is_none() && seen.insert(key) {
+				keys[i] = Some(key);
+			}
+		}
+		let mut next: BTreeMap<(usize, u32), (Quad, u32)> = BTreeMap::new();
+		for ((corners, id), key) in found.iter().zip(&ids).zip(keys) {
+			// A copy we haven't seen before takes the first number nobody else has.
+			let key = key.unwrap_or_else(|| (*id, (0..).find(|n| !self.last.contains_key(&(*id, *n)) && !next.contains_key(&(*id, *n))).expect("Fewer than u32::MAX copies.")));
+			next.insert(key, (*corners, 0));
+		}
+		for ((marker_id, copy), (last, bridged)) in std::mem::take(&mut self.last) {
+			if seen.contains(&(marker_id, copy)) || bridged >= self.max_frames {
+				continue;
+			}
+			let local = last.map(|(x, y)| (x - offset.0, y - offset.1));
+			let Some((corners, visible)) = self.locate(gray, &local) else {
+				continue;
+			};
+			let corners = corners.map(|(x, y)| (x + offset.0, y + offset.1));
+			record.markers.push(MarkerRecord {
+				marker_id,
+				corners,
+				poses: PoseRecord::from_quad(&corners, marker_size_mm, pinhole).into_iter().collect(),
+				partial: Some(visible),
+				..Default::default()
+			});
+			next.insert((marker_id, copy), (corners, bridged + 1));
+		}
+		self.last = next;
+	}
+
+	/// The marker's corners this frame and which of them were seen, given where they were last frame.
+	fn locate(&mut self, gray: &GrayImage, last: &Quad) -> Option<(Quad, [bool; 4])> {
+		let side = (0..4).map(|i| distance(last[i], last[(i + 1) % 4])).sum::<f32>() / 4.0;
+		let (cx, cy) = centroid(last);
+		let area = quad_area(last);
+		if side < 4.0 {
+			return None;
+		}
+		// Search the last frame's box, grown by half a side each way for however it moved.
+		let grow = side / 2.0;
+		let clamp = |v: f32, max: u32| (v.max(0.0) as u32).min(max);
+		let x0 = clamp(last.iter().map(|c| c.0).fold(f32::MAX, f32::min) - grow, gray.width());
+		let y0 = clamp(last.iter().map(|c| c.1).fold(f32::MAX, f32::min) - grow, gray.height());
+		let x1 = clamp(last.iter().map(|c| c.0).fold(f32::MIN, f32::max) + grow, gray.width());
+		let y1 = clamp(last.iter().map(|c| c.1).fold(f32::MIN, f32::max) + grow, gray.height());
+		if x1 < x0 + 4 || y1 < y0 + 4 {
+			return None;
+		}
+		let window = GrayImage::from_fn(x1 - x0, y1 - y0, |x, y| Luma([gray.get_pixel(x0 + x, y0 + y)[0]]));
+		let threshold = adaptive_threshold_in(&mut self.scratch, &window, ((side / 4.0) as u32).max(3), 7.0);
+		let quad = candidate_quads_in(&mut self.scratch, &threshold, (side / 2.0) as u32).into_iter()
+			.map(|quad| align(quad.map(|(x, y)| (x + x0 as f32, y + y0 as f32)), last))
+			.filter(|quad| (0.6..1.6).contains(&(quad_area(quad) / area)))
+			.map(|quad| (distance(centroid(&quad), (cx, cy)), quad))
+			.filter(|(d, _)| *d < side / 3.0)
+			.min_by(|a, b| a.0.total_cmp(&b.0))
+			.map(|(_, quad)| quad);
+
+		let mut corners = quad.unwrap_or(*last);
+		let visible = visible_corners(gray, &corners);
+		let count = visible.iter().filter(|v| **v).count();
+		if count < 2 {
+			return None;
+		}
+		// Hidden corners move with the ones we could see.
+		let moved = (0..4).filter(|i| visible[*i]).map(|i| (corners[i].0 - last[i].0, corners[i].1 - last[i].1));
+		let (dx, dy) = moved.fold((0.0, 0.0), |(x, y), (dx, dy)| (x + dx / count as f32, y + dy / count as f32));
+		for i in (0..4).filter(|i| !visible[*i]) {
+			corners[i] = (last[i].0 + dx, last[i].1 + dy);
+		}
+		Some((corners, visible))
+	}
+}
+
+/// Whether each corner still looks like the corner of a marker: about as dark just inside as the darkest of them, and
+/// as light just outside as the lightest. Something laid over a corner is rarely as black as the border or as white
+/// as the paper.
+fn visible_corners(gray: &GrayImage, corners: &Quad) -> [bool; 4] {
+	let Some(homography) = square_to_quad(corners) else {
+		return [false; 4];
+	};
+	// Along the diagonal, about half a cell either side of the corner for the usual 6 or 7 cells across.
+	let unit = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+	let samples = unit.map(|(u, v): (f32, f32)| {
+		let (du, dv) = ((0.5 - u) * 0.15, (0.5 - v) * 0.15);
+		Some((sample(gray, apply_homography(&homography, (u + du, v + dv)))?, sample(gray, apply_homography(&homography, (u - du, v - dv)))?))
+	});
+	let black = samples.iter().flatten().map(|s| s.0).fold(f32::MAX, f32::min);
+	let white = samples.iter().flatten().map(|s| s.1).fold(f32::MIN, f32::max);
+	let margin = (white - black) / 3.0;
+	samples.map(|s| s.is_some_and(|(inside, outside)| outside - inside > MIN_CORNER_CONTRAST && inside < black + margin && outside > white - margin))
+}
+
+/// The quad's corners in the order that best matches `last`, since the extreme points always start at the top left.
+fn align(quad: Quad, last: &Quad) -> Quad {
+	let rotated = |k: usize| std::array::from_fn(|i| quad[(i + k) % 4]);
+	let cost = |q: &Quad| (0..4).map(|i| distance(q[i], last[i])).sum::<f32>();
+	(0..4).map(rotated).min_by(|a, b| cost(a).total_cmp(&cost(b))).expect("Four rotations.")
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+	(a.0 - b.0).hypot(a.1 - b.1)
+}
+
+fn centroid(quad: &Quad) -> (f32, f32) {
+	(quad.iter().map(|c| c.0).sum::<f32>() / 4.0, quad.iter().map(|c| c.1).sum::<f32>() / 4.0)
+}
+
+fn quad_area(quad: &Quad) -> f32 {
+	// Shoelace formula.
+	(0..4).map(|i| quad[i].0 * quad[(i + 1) % 4].1 - quad[(i + 1) % 4].0 * quad[i].1).sum::<f32>().abs() / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// A 4x4 marker, 60 pixels across at (40, 40), with everything inside `hidden` painted over in mid gray.
+	fn marker_image(hidden: (u32, u32, u32, u32)) -> GrayImage {
+		GrayImage::from_fn(140, 140, |x, y| {
+			if (hidden.0..hidden.2).contains(&x) && (hidden.1..hidden.3).contains(&y) {
+				return Luma([150]);
+			}
+			let (cx, cy) = (x as i64 - 40, y as i64 - 40);
+			if !(0..60).contains(&cx) || !(0..60).contains(&cy) {
+				return Luma([230]);
+			}
+			let (i, j) = (cx / 10, cy / 10);
+			let border = i == 0 || j == 0 || i == 5 || j == 5;
+			if border || (i + j) % 2 == 0 { Luma([20]) } else { Luma([230]) }
+		})
+	}
+
+	#[test]
+	fn test_bridges_occlusion() {
+		let pinhole = Pinhole { width: 140, height: 140, fx: 140.0, fy: 140.0, cx: 70.0, cy: 70.0 };
+		let corners = [(40.0, 40.0), (100.0, 40.0), (100.0, 100.0), (40.0, 100.0)];
+		let mut bridge = OcclusionBridge::new(10);
+		let mut first = FrameRecord { markers: vec![MarkerRecord { marker_id: 7, corners, ..Default::default() }], ..Default::default() };
+		bridge.bridge(&mut first, &marker_image((0, 0, 0, 0)), (0.0, 0.0), &pinhole, 50.0);
+		assert_eq!(first.markers.len(), 1);
+
+		// The bits covered: the border is still there, all four corners with it.
+		let mut covered = FrameRecord { frame_id: 1, ..Default::default() };
+		bridge.bridge(&mut covered, &marker_image((50, 50, 90, 90)), (0.0, 0.0), &pinhole, 50.0);
+		let m = &covered.markers[0];
+		assert_eq!((m.marker_id, m.partial), (7, Some([true; 4])));
+		assert!(m.corners.iter().zip(&corners).all(|(a, b)| distance(*a, *b) < 2.0), "{:?}", m.corners);
+		assert_eq!(m.poses.len(), 1);
+
+		// A corner covered.
+		let mut corner = FrameRecord { frame_id: 2, ..Default::default() };
+		bridge.bridge(&mut corner, &marker_image((30, 30, 60, 60)), (0.0, 0.0), &pinhole, 50.0);
+		assert_eq!(corner.markers[0].partial, Some([false, true, true, true]));
+		assert!(distance(corner.markers[0].corners[0], corners[0]) < 2.0, "{:?}", corner.markers[0].corners);
+
+		// Gone for good.
+		let mut gone = FrameRecord { frame_id: 3, ..Default::default() };
+		bridge.bridge(&mut gone, &GrayImage::from_pixel(140, 140, Luma([230])), (0.0, 0.0), &pinhole, 50.0);
+		assert!(gone.markers.is_empty());
+	}
+
+	#[test]
+	fn test_bridges_one_of_two_copies() {
+		let pinhole = Pinhole { width: 140, height: 140, fx: 140.0, fy: 140.0, cx: 70.0, cy: 70.0 };
+		let corners = [(40.0, 40.0), (100.0, 40.0), (100.0, 100.0), (40.0, 100.0)];
+		// The other copy of 7 is off to the side, outside the image, so only ours is there to find.
+		let elsewhere = corners.map(|(x, y)| (x + 400.0, y));
+		let copies = |corners: &[Quad]| corners.iter().map(|c| MarkerRecord { marker_id: 7, corners: *c, ..Default::default() }).collect();
+		let mut bridge = OcclusionBridge::new(10);
+		let mut first = FrameRecord { markers: copies(&[corners, elsewhere]), ..Default::default() };
+		bridge.bridge(&mut first, &marker_image((0, 0, 0, 0)), (0.0, 0.0), &pinhole, 50.0);
+
+		// Ours has its bits covered while the other is still decoding, so ours is bridged rather than taken as seen.
+		let mut covered = FrameRecord { frame_id: 1, markers: copies(&[elsewhere]), ..Default::default() };
+		bridge.bridge(&mut covered, &marker_image((50, 50, 90, 90)), (0.0, 0.0), &pinhole, 50.0);
+		assert_eq!(covered.markers.len(), 2);
+		let m = &covered.markers[1];
+		assert_eq!((m.marker_id, m.partial), (7, Some([true; 4])));
+		assert!(m.corners.iter().zip(&corners).all(|(a, b)| distance(*a, *b) < 2.0), "{:?}", m.corners);
+	}
+}
@@ -0,0 +1,173 @@
+// Where frame records end up. Every record goes to every sink, in order.
+
+use crate::record::FrameRecord;
+use crate::value::Value;
+use std::io::{self, BufWriter, Write};
+
+pub trait Sink {
+	/// Called once before the first record with a description of the run. Only file outputs bother with it.
+	fn header(&mut self, _header: &Value) -> io::Result<()> {
+		Ok(())
+	}
+
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()>;
+
+	/// Called once after the last record. Exporters that need the whole track write their files here.
+	fn finish(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
+
+/// A writer with something left to write after the last byte, like a compressed stream's trailer. Plain writers only
+/// need flushing. Sinks call this from their own finish, so a trailer that fails to write is an error rather than a
+/// quietly truncated file.
+pub trait Finish: Write {
+	fn finish(&mut self) -> io::Result<()> {
+		self.flush()
+	}
+}
+
+impl Finish for Vec<u8> {}
+impl Finish for std::fs::File {}
+impl Finish for io::Stdout {}
+
+impl<W: Finish> Finish for BufWriter<W> {
+	fn finish(&mut self) -> io::Result<()> {
+		self.flush()?;
+		self.get_mut().finish()
+	}
+}
+
+impl<W: Finish + ?Sized> Finish for &mut W {
+	fn finish(&mut self) -> io::Result<()> {
+		(**self).finish()
+	}
+}
+
+impl<W: Finish + ?Sized> Finish for Box<W> {
+	fn finish(&mut self) -> io::Result<()> {
+		(**self).finish()
+	}
+}
+
+/// One JSON object per line. This is the format the Blender addon reads.
+pub struct JsonLinesSink<W: Finish> {
+	writer: W,
+}
+
+impl<W: Finish> JsonLinesSink<W> {
+	pub fn new(writer: W) -> Self {
+		JsonLinesSink { writer }
+	}
+}
+
+impl<W: Finish> Sink for JsonLinesSink<W> {
+	fn header(&mut self, header: &Value) -> io::Result<()> {
+		writeln!(self.writer, "{}", header.to_json())
+	}
+
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		writeln!(self.writer, "{}", record.to_json())
+	}
+
+	fn finish(&mut self) -> io::Result<()> {
+		self.writer.finish()
+	}
+}
+
+/// A sink, whose name is for messages, and whether it's primary: the run's own output rather than a side channel.
+struct Output {
+	name: String,
+	primary: bool,
+	sink: Box<dyn Sink>,
+}
+
+#[derive(Default)]
+pub struct Outputs {
+	sinks: Vec<Output>,
+	/// The first error from a primary sink, held for finish so the run fails rather than ending quietly short.
+	failed: Option<io::Error>,
+}
+
+impl Outputs {
+	pub fn add(&mut self, name: &str, sink: Box<dyn Sink>) {
+		self.sinks.push(Output { name: name.to_string(), primary: false, sink });
+	}
+
+	/// Add the records' own destination, like --output. A side output going away is printed and forgotten, but this one
+	/// failing fails the run.
+	pub fn add_primary(&mut self, name: &str, sink: Box<dyn Sink>) {
+		self.sinks.push(Output { name: name.to_string(), primary: true, sink });
+	}
+
+	pub fn header(&mut self, header: &Value) {
+		self.each(|sink| sink.header(header));
+	}
+
+	/// Write to all sinks. One broken sink (a closed pipe, a full disk) shouldn't take down the rest of the run, so it
+	/// gets dropped. A primary one's error comes back from finish.
+	pub fn write(&mut self, record: &FrameRecord) {
+		self.each(|sink| sink.write(record));
+	}
+
+	fn each(&mut self, mut call: impl FnMut(&mut dyn Sink) -> io::Result<()>) {
+		self.sinks.retain_mut(|output| match call(output.sink.as_mut()) {
+			Ok(()) => true,
+			Err(e) if output.primary => {
+				let name = &output.name;
+				self.failed.get_or_insert_with(|| io::Error::new(e.kind(), format!("Failed to write to {name}: {e}")));
+				false
+			},
+			Err(e) => {
+				eprintln!("Stopped writing to {}: {e}", output.name);
+				false
+			},
+		});
+	}
+
+	/// Finish every sink, even after one fails. The first failure, counting a primary sink that failed before now, is
+	/// returned and any others are printed.
+	pub fn finish(&mut self) -> io::Result<()> {
+		let mut result = self.failed.take().map_or(Ok(()), Err);
+		for Output { name, sink, .. } in self.sinks.iter_mut() {
+			if let Err(e) = sink.finish() {
+				let e = io::Error::new(e.kind(), format!("Failed to finish writing to {name}: {e}"));
+				match result {
+					Ok(()) => result = Err(e),
+					Err(_) => eprintln!("{e}"),
+				}
+			}
+		}
+		result
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	struct Broken;
+
+	impl Sink for Broken {
+		fn write(&mut self, _record: &FrameRecord) -> io::Result<()> {
+			Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+		}
+	}
+
+	#[test]
+	fn test_primary_failure_fails_finish() {
+		let record = FrameRecord::default();
+		let mut outputs = Outputs::default();
+		outputs.add("side", Box::new(Broken));
+		outputs.write(&record);
+		assert!(outputs.finish().is_ok());
+
+		let mut outputs = Outputs::default();
+		outputs.add_primary("stdout", Box::new(Broken));
+		outputs.write(&record);
+		outputs.write(&record);
+		let e = outputs.finish().unwrap_err();
+		assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+		assert!(e.to_string().contains("stdout"));
+	}
+}
@@ -0,0 +1,168 @@
+// Optional clean-up of each frame before detection, for --preprocess. Stages run in the order given, on 8-bit luma:
+//   clahe[=clip]     contrast limited adaptive histogram equalization over an 8x8 grid of tiles, for flat or unevenly
+//                    lit footage. The clip limit (default 2) caps each tile's histogram at that many times its mean.
+//   denoise[=radius] a median filter, default radius 1 (3x3), for sensor noise. It keeps the marker edges sharp where a
+//                    blur would soften them.
+//   gamma=<g>        out = in^(1/g), so values above 1 lift the shadows of underexposed footage.
+// e.g. "--preprocess denoise,clahe,gamma=1.8". The debug dump and confidence scores see the processed frame too.
+
+use image::{GrayImage, Luma};
+
+const CLAHE_TILES: u32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+	Clahe { clip: f32 },
+	Denoise { radius: u32 },
+	Gamma(f32),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Preprocess {
+	pub stages: Vec<Stage>,
+}
+
+pub fn parse_preprocess(text: &str) -> Result<Preprocess, String> {
+	let stages = text.split(',').map(|stage| {
+		let (name, value) = match stage.split_once('=') {
+			Some((name, value)) => (name.trim(), Some(value.trim())),
+			None => (stage.trim(), None),
+		};
+		let number = |default: Option<f32>| -> Result<f32, String> {
+			let v = match value {
+				Some(v) => v.parse::<f32>().map_err(|e| format!("{name}: {e}"))?,
+				None => default.ok_or(format!("{name} needs a value, e.g. {name}=1.8"))?,
+			};
+			if v > 0.0 { Ok(v) } else { Err(format!("{name} must be greater than zero.")) }
+		};
+		match name.to_ascii_lowercase().as_str() {
+			"clahe" => Ok(Stage::Clahe { clip: number(Some(2.0))? }),
+			"denoise" => Ok(Stage::Denoise { radius: number(Some(1.0))?.round().max(1.0) as u32 }),
+			"gamma" => Ok(Stage::Gamma(number(None)?)),
+			_ => Err(format!("Unknown preprocessing stage '{name}'. Expected clahe, denoise, or gamma.")),
+		}
+	}).collect::<Result<_, _>>()?;
+	Ok(Preprocess { stages })
+}
+
+/// The stages as --preprocess takes them, for the header.
+impl std::fmt::Display for Preprocess {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let stages: Vec<String> = self.stages.iter().map(|stage| match stage {
+			Stage::Clahe { clip } => format!("clahe={clip}"),
+			Stage::Denoise { radius } => format!("denoise={radius}"),
+			Stage::Gamma(gamma) => format!("gamma={gamma}"),
+		}).collect();
+		write!(f, "{}", stages.join(","))
+	}
+}
+
+impl Preprocess {
+	pub fn apply(&self, mut img: GrayImage) -> GrayImage {
+		for stage in &self.stages {
+			img = match *stage {
+				Stage::Clahe { clip } => clahe(&img, clip),
+				Stage::Denoise { radius } => median(&img, radius),
+				Stage::Gamma(gamma) => {
+					let table: Vec<u8> = (0..256).map(|v| (255.0 * (v as f32 / 255.0).powf(1.0 / gamma)).round() as u8).collect();
+					GrayImage::from_fn(img.width(), img.height(), |x, y| Luma([table[img.get_pixel(x, y)[0] as usize]]))
+				},
+			};
+		}
+		img
+	}
+}
+
+fn median(img: &GrayImage, radius: u32) -> GrayImage {
+	let r = radius as i64;
+	let (w, h) = (img.width() as i64, img.height() as i64);
+	let mut window = Vec::with_capacity(((2 * r + 1) * (2 * r + 1)) as usize);
+	GrayImage::from_fn(img.width(), img.height(), |x, y| {
+		window.clear();
+		for dy in -r..=r {
+			for dx in -r..=r {
+				let (sx, sy) = ((x as i64 + dx).clamp(0, w - 1), (y as i64 + dy).clamp(0, h - 1));
+				window.push(img.get_pixel(sx as u32, sy as u32)[0]);
+			}
+		}
+		let middle = window.len() / 2;
+		Luma([*window.select_nth_unstable(middle).1])
+	})
+}
+
+fn clahe(img: &GrayImage, clip: f32) -> GrayImage {
+	let (w, h) = (img.width(), img.height());
+	let tile_w = w.div_ceil(CLAHE_TILES).max(1);
+	let tile_h = h.div_ceil(CLAHE_TILES).max(1);
+	let (tiles_x, tiles_y) = (w.div_ceil(tile_w), h.div_ceil(tile_h));
+	// A lookup table per tile: the clipped histogram's CDF.
+	let mut tables = vec![[0u8; 256]; (tiles_x * tiles_y) as usize];
+	for ty in 0..tiles_y {
+		for tx in 0..tiles_x {
+			let mut histogram = [0u32; 256];
+			let (x0, y0) = (tx * tile_w, ty * tile_h);
+			let (x1, y1) = ((x0 + tile_w).min(w), (y0 + tile_h).min(h));
+			for y in y0..y1 {
+				for x in x0..x1 {
+					histogram[img.get_pixel(x, y)[0] as usize] += 1;
+				}
+			}
+			let count = (x1 - x0) * (y1 - y0);
+			// Clip every bin and share what was cut off evenly, which limits how steep the mapping (and so the boost to
+			// noise) can get to about `clip` times the plain one.
+			let limit = ((clip * count as f32 / 256.0) as u32).max(1);
+			let excess: u32 = histogram.iter().map(|&n| n.saturating_sub(limit)).sum();
+			let mut cdf = 0.0;
+			let table = &mut tables[(ty * tiles_x + tx) as usize];
+			for (v, &n) in histogram.iter().enumerate() {
+				cdf += n.min(limit) as f32 + excess as f32 / 256.0;
+				table[v] = (255.0 * cdf / count as f32).round().min(255.0) as u8;
+			}
+		}
+	}
+	// Blend the four nearest tiles' tables by distance to their centers, so there are no seams at tile edges.
+	GrayImage::from_fn(w, h, |x, y| {
+		let v = img.get_pixel(x, y)[0] as usize;
+		let fx = ((x as f32 + 0.5) / tile_w as f32 - 0.5).clamp(0.0, (tiles_x - 1) as f32);
+		let fy = ((y as f32 + 0.5) / tile_h as f32 - 0.5).clamp(0.0, (tiles_y - 1) as f32);
+		let (tx0, ty0) = (fx.floor() as u32, fy.floor() as u32);
+		let (tx1, ty1) = ((tx0 + 1).min(tiles_x - 1), (ty0 + 1).min(tiles_y - 1));
+		let (ax, ay) = (fx - tx0 as f32, fy - ty0 as f32);
+		let at = |tx: u32, ty: u32| tables[(ty * tiles_x + tx) as usize][v] as f32;
+		let top = at(tx0, ty0) * (1.0 - ax) + at(tx1, ty0) * ax;
+		let bottom = at(tx0, ty1) * (1.0 - ax) + at(tx1, ty1) * ax;
+		Luma([(top * (1.0 - ay) + bottom * ay).round() as u8])
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_parse_preprocess() {
+		assert_eq!(parse_preprocess("clahe,denoise,gamma=1.8").unwrap().stages, vec![Stage::Clahe { clip: 2.0 }, Stage::Denoise { radius: 1 }, Stage::Gamma(1.8)]);
+		assert_eq!(parse_preprocess("denoise=2").unwrap().stages, vec![Stage::Denoise { radius: 2 }]);
+		assert!(parse_preprocess("gamma").is_err());
+		assert!(parse_preprocess("sharpen").is_err());
+		assert_eq!(parse_preprocess("clahe,gamma=1.8").unwrap().to_string(), "clahe=2,gamma=1.8");
+	}
+
+	fn apply(stages: &[Stage], img: GrayImage) -> GrayImage {
+		Preprocess { stages: stages.to_vec() }.apply(img)
+	}
+
+	#[test]
+	fn test_stages() {
+		// A dim, low contrast checkerboard with one hot pixel.
+		let img = GrayImage::from_fn(64, 64, |x, y| Luma([if (x / 4 + y / 4) % 2 == 0 { 40 } else { 60 }]));
+		let mut noisy = img.clone();
+		noisy.put_pixel(1, 1, Luma([255]));
+		assert_eq!(apply(&[Stage::Denoise { radius: 1 }], noisy).get_pixel(1, 1)[0], 40);
+		// The clip limit caps the stretch, so it takes a loose one to pull two close levels far apart.
+		let stretched = apply(&[Stage::Clahe { clip: 40.0 }], img.clone());
+		let contrast = |img: &GrayImage| img.get_pixel(36, 33)[0] as i32 - img.get_pixel(33, 33)[0] as i32;
+		assert!(contrast(&stretched) > 2 * contrast(&img), "{}", contrast(&stretched));
+		assert!(apply(&[Stage::Gamma(2.0)], img.clone()).get_pixel(0, 0)[0] > 40);
+	}
+}
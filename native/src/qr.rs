@@ -0,0 +1,53 @@
+// QR codes as fiducials, for pipelines that tag props with codes carrying asset ids.
+//
+// Decoding is done by rqrr. A QR code has no small integer id, so its marker_id is a hash of the payload: the same
+// code gets the same id in every run and from every camera, which is what the stereo pairing and track outputs key
+// on. The decoded text itself goes out as "payload". The pose comes from the symbol's outer corners (the outsides of
+// the three finder patterns, plus the fourth corner the decoder places from the grid) treated as a square.
+
+use crate::geometry::Pinhole;
+use crate::record::{MarkerRecord, PoseRecord};
+use image::GrayImage;
+
+/// The name that turns on QR decoding in the dictionary string, alone or as in "ARUCO+QR".
+pub const QR: &str = "QR";
+
+/// A stable id for a payload. 32-bit FNV-1a, so it stays well clear of usize limits everywhere we run.
+pub fn payload_id(payload: &str) -> usize {
+	let mut hash: u32 = 0x811c9dc5;
+	for byte in payload.bytes() {
+		hash ^= byte as u32;
+		hash = hash.wrapping_mul(0x01000193);
+	}
+	hash as usize
+}
+
+/// Find and decode every QR code in the image. `offset` moves image coordinates into the full frame the intrinsics
+/// describe, as when the image is a crop.
+pub fn detect(gray: &GrayImage, offset: (f32, f32), pinhole: &Pinhole, size_mm: f32) -> Vec<MarkerRecord> {
+	let mut prepared = rqrr::PreparedImage::prepare_from_greyscale(gray.width() as usize, gray.height() as usize, |x, y| gray.get_pixel(x as u32, y as u32)[0]);
+	prepared.detect_grids().into_iter().filter_map(|grid| {
+		let (_meta, payload) = grid.decode().ok()?;
+		// rqrr gives the corners in the symbol's own top-left, top-right, bottom-right, bottom-left order, like ours.
+		let corners = grid.bounds.map(|p| (p.x as f32 + offset.0, p.y as f32 + offset.1));
+		Some(MarkerRecord {
+			marker_id: payload_id(&payload),
+			corners,
+			poses: PoseRecord::from_quad(&corners, size_mm, pinhole).into_iter().collect(),
+			payload: Some(payload),
+			..Default::default()
+		})
+	}).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_payload_id_is_stable() {
+		// Reference value for FNV-1a over "a".
+		assert_eq!(payload_id("a"), 0xe40c292c);
+		assert_ne!(payload_id("prop_001"), payload_id("prop_002"));
+	}
+}
@@ -0,0 +1,124 @@
+// Binarization and rough quad finding, for looking at what a square-marker detector would see. The debug dump draws
+// these, and the occlusion bridge uses them to find a covered marker's border.
+
+use crate::simd;
+use image::GrayImage;
+
+/// Buffers that would otherwise be allocated on every call. Keep one around to reuse them from frame to frame.
+#[derive(Debug, Default)]
+pub struct Scratch {
+	columns: Vec<u32>,
+	sums: Vec<u32>,
+	widths: Vec<u32>,
+	seen: Vec<bool>,
+	stack: Vec<u32>,
+}
+
+/// Foreground (255) wherever a pixel is noticeably darker than its neighborhood, like the detector's binarization.
+pub fn adaptive_threshold(img: &GrayImage, radius: u32, offset: f32) -> GrayImage {
+	adaptive_threshold_in(&mut Scratch::default(), img, radius, offset)
+}
+
+pub fn adaptive_threshold_in(scratch: &mut Scratch, img: &GrayImage, radius: u32, offset: f32) -> GrayImage {
+	let (w, h, r) = (img.width() as usize, img.height() as usize, radius as usize);
+	let row = |y: usize| &img.as_raw()[y * w..(y + 1) * w];
+	// Each column's sum over the window's rows, slid down a row at a time, then summed across the window's columns.
+	let Scratch { columns, sums, widths, .. } = scratch;
+	columns.clear();
+	columns.resize(w, 0);
+	sums.resize(w, 0);
+	widths.clear();
+	widths.extend((0..w).map(|x| ((x + r + 1).min(w) - x.saturating_sub(r)) as u32));
+	for y in 0..r.min(h) {
+		simd::add_row(columns, row(y));
+	}
+	let mut out = vec![0; w * h];
+	for (y, out) in out.chunks_exact_mut(w.max(1)).enumerate() {
+		if y + r < h {
+			simd::add_row(columns, row(y + r));
+		}
+		if y > r {
+			simd::subtract_row(columns, row(y - r - 1));
+		}
+		let mut sum: u32 = columns[..r.min(w)].iter().sum();
+		for x in 0..w {
+			if x + r < w {
+				sum += columns[x + r];
+			}
+			if x > r {
+				sum -= columns[x - r - 1];
+			}
+			sums[x] = sum;
+		}
+		let rows = ((y + r + 1).min(h) - y.saturating_sub(r)) as u32;
+		simd::threshold_row(row(y), sums, widths, rows, offset, out);
+	}
+	GrayImage::from_raw(img.width(), img.height(), out).expect("One byte per pixel.")
+}
+
+/// Connected foreground regions of a plausible size, each approximated by its four extreme points.
+pub fn candidate_quads(threshold: &GrayImage, min_size: u32) -> Vec<[(f32, f32); 4]> {
+	candidate_quads_in(&mut Scratch::default(), threshold, min_size)
+}
+
+pub fn candidate_quads_in(scratch: &mut Scratch, threshold: &GrayImage, min_size: u32) -> Vec<[(f32, f32); 4]> {
+	let (w, h) = (threshold.width(), threshold.height());
+	let (seen, stack) = (&mut scratch.seen, &mut scratch.stack);
+	seen.clear();
+	seen.resize((w * h) as usize, false);
+	stack.clear();
+	let mut quads = vec![];
+	for start in 0..(w * h) {
+		if seen[start as usize] || threshold.as_raw()[start as usize] == 0 {
+			continue;
+		}
+		// Flood fill, tracking the point furthest toward each corner: top-left, top-right, bottom-right, bottom-left.
+		let mut extremes = [(i64::MIN, (0, 0)); 4];
+		let (mut min_x, mut max_x, mut min_y, mut max_y) = (w, 0, h, 0);
+		seen[start as usize] = true;
+		stack.push(start);
+		while let Some(idx) = stack.pop() {
+			let (x, y) = (idx % w, idx / w);
+			let (sx, sy) = (x as i64, y as i64);
+			for (extreme, key) in extremes.iter_mut().zip([-(sx + sy), sx - sy, sx + sy, sy - sx]) {
+				if key > extreme.0 {
+					*extreme = (key, (x, y));
+				}
+			}
+			(min_x, max_x, min_y, max_y) = (min_x.min(x), max_x.max(x), min_y.min(y), max_y.max(y));
+			let neighbors = [(x > 0).then(|| idx - 1), (x + 1 < w).then(|| idx + 1), (y > 0).then(|| idx - w), (y + 1 < h).then(|| idx + w)];
+			for n in neighbors.into_iter().flatten() {
+				if !seen[n as usize] && threshold.as_raw()[n as usize] != 0 {
+					seen[n as usize] = true;
+					stack.push(n);
+				}
+			}
+		}
+		let (bw, bh) = (max_x - min_x + 1, max_y - min_y + 1);
+		// Specks and the whole-frame background aren't interesting.
+		if bw.min(bh) < min_size || bw > w * 9 / 10 || bh > h * 9 / 10 {
+			continue;
+		}
+		quads.push(extremes.map(|(_, (x, y))| (x as f32, y as f32)));
+	}
+	quads
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use image::Luma;
+
+	#[test]
+	fn test_threshold_and_candidates() {
+		// A dark square on a light background.
+		let img = GrayImage::from_fn(64, 64, |x, y| if (20..40).contains(&x) && (10..30).contains(&y) { Luma([20]) } else { Luma([220]) });
+		let threshold = adaptive_threshold(&img, 8, 7.0);
+		assert_eq!(threshold.get_pixel(21, 11)[0], 255);
+		assert_eq!(threshold.get_pixel(5, 50)[0], 0);
+		let quads = candidate_quads(&threshold, 4);
+		assert_eq!(quads.len(), 1);
+		assert_eq!(quads[0][0], (20.0, 10.0));
+		assert_eq!(quads[0][2], (39.0, 29.0));
+	}
+}
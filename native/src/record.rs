@@ -0,0 +1,447 @@
+// The per-frame records we emit, decoupled from the detector's types so they can be retimed or filtered before output.
+
+use aruco3::{CameraIntrinsics, Detection, pose};
+use crate::confidence::Confidence;
+use crate::geometry::{self, Mat3, Pinhole, Vec3};
+use crate::stats::StageTimings;
+use crate::value::Value;
+use clap::ValueEnum;
+
+/// How pose rotations are written out. Each format gets its own key so a reader can't mistake one for another.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RotationFormat {
+	/// "rotation": a row-major 3x3 matrix.
+	#[default]
+	Matrix,
+	/// "quaternion": [w, x, y, z].
+	Quaternion,
+	/// "euler_xyz": radians about X, then Y, then Z, like Blender's XYZ rotation mode.
+	EulerXyz,
+	/// "axis_angle": the axis scaled by the angle in radians, like OpenCV's rvec.
+	AxisAngle,
+}
+
+impl RotationFormat {
+	/// The pose key the rotation is written under, which is also what the header calls the format.
+	pub fn key(&self) -> &'static str {
+		match self {
+			RotationFormat::Matrix => "rotation",
+			RotationFormat::Quaternion => "quaternion",
+			RotationFormat::EulerXyz => "euler_xyz",
+			RotationFormat::AxisAngle => "axis_angle",
+		}
+	}
+}
+
+/// Resolution-independent corner positions, for applying a track to a proxy and the full-res plate alike.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalizedCoords {
+	/// 0 to 1 across and down from the top-left corner of the frame, the same way up as the pixels.
+	Unit,
+	/// -1 to 1 from the center of the frame with y up, like OpenGL's normalized device coordinates.
+	Ndc,
+}
+
+impl NormalizedCoords {
+	pub fn name(&self) -> &'static str {
+		match self {
+			NormalizedCoords::Unit => "unit",
+			NormalizedCoords::Ndc => "ndc",
+		}
+	}
+
+	pub fn apply(&self, (x, y): (f32, f32), width: u32, height: u32) -> (f32, f32) {
+		let (u, v) = (x / width as f32, y / height as f32);
+		match self {
+			NormalizedCoords::Unit => (u, v),
+			NormalizedCoords::Ndc => (u * 2.0 - 1.0, 1.0 - v * 2.0),
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PoseRecord {
+	pub translation: Vec3,
+	pub rotation: Mat3,
+	pub error: f32,
+	/// RMS corner reprojection error in pixels, when we know the camera the pose was solved with.
+	pub reprojection_error: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MarkerRecord {
+	pub marker_id: usize,
+	/// Which copy of the id this is, when more than one has been seen at once (see instances.rs).
+	pub instance: Option<u32>,
+	pub corners: [(f32, f32); 4],
+	/// The corners again as fractions of the frame, with --normalized-coords.
+	pub corners_normalized: Option<[(f32, f32); 4]>,
+	/// Metric positions of the corners in camera space, when we have more than one view to triangulate from.
+	pub corners_3d: Option<[Vec3; 4]>,
+	pub poses: Vec<PoseRecord>,
+	/// The decoded contents, for markers that carry data (QR codes).
+	pub payload: Option<String>,
+	/// For a ChArUco diamond, the ids of its four markers: top, left, right, bottom. See diamond.rs.
+	pub diamond: Option<[usize; 4]>,
+	/// How many bits the detector had to correct to match the marker to its dictionary code.
+	pub hamming_distance: Option<u32>,
+	/// How clean the detection looked in the image, when we had the image to look at.
+	pub confidence: Option<Confidence>,
+	/// Camera-space velocity of the best pose in mm/s, when the marker was also in the previous frame. See motion.rs.
+	pub velocity: Option<Vec3>,
+	/// Camera-space angular velocity of the best pose as axis * rad/s, alongside velocity.
+	pub angular_velocity: Option<Vec3>,
+	/// Set when the marker didn't decode this frame and was found again where it last was (see occlusion.rs), with
+	/// which of its corners could be seen. The others are guesses.
+	pub partial: Option<[bool; 4]>,
+	/// Points along each side of the border, for --self-calibrate to straighten. Never written out.
+	pub edges: Vec<Vec<(f32, f32)>>,
+	/// For drawing the best pose, with --pose-axes. Worked out on the way out, so it's in the output's space and units.
+	pub axes: Option<MarkerAxes>,
+}
+
+/// A pose's orientation, ready to draw: the marker's plane normal, and where the tips of its X, Y, and Z axes are when
+/// they start at its center and are as long as its edge.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MarkerAxes {
+	pub normal: Vec3,
+	pub x: Vec3,
+	pub y: Vec3,
+	pub z: Vec3,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameRecord {
+	pub frame_id: usize,
+	/// Which camera this came from, if there's more than one.
+	pub camera_id: Option<String>,
+	/// Presentation time of the frame in seconds.
+	pub timestamp: f64,
+	/// How many frames appear to be missing between the previous frame and this one.
+	pub dropped_frames: u32,
+	/// True if this frame arrived at (nearly) the same time as the previous one.
+	pub duplicate: bool,
+	/// When retiming, the decoded frame closest to this sample.
+	pub source_frame: Option<usize>,
+	/// Why the frame couldn't be decoded, when it couldn't. Nothing was looked for in it.
+	pub skipped: Option<String>,
+	/// SMPTE timecode, when the file has one or --timecode-start was given.
+	pub timecode: Option<String>,
+	/// The camera model the poses were solved with.
+	pub intrinsics: Option<Pinhole>,
+	/// How long each stage took for this frame. Only used for the run statistics, never written out.
+	pub timings: Option<StageTimings>,
+	/// How to write the poses' rotations. Set on the way out, so every output agrees.
+	pub rotation_format: RotationFormat,
+	/// Where the camera was in the --marker-map's world, if any mapped markers were in view.
+	pub camera_pose: Option<CameraPose>,
+	pub markers: Vec<MarkerRecord>,
+}
+
+/// A camera's placement in a world frame, the way you'd set a camera object: x_world = rotation * x_camera + position.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CameraPose {
+	pub rotation: Mat3,
+	pub position: Vec3,
+	/// RMS distance in pixels between the mapped markers' detected corners and where this pose puts them.
+	pub reprojection_error: Option<f32>,
+	/// How many mapped markers the pose was solved from.
+	pub marker_count: usize,
+}
+
+impl FrameRecord {
+	// These casts are no-ops for some of the detector's numeric types, but we don't want to depend on which.
+	#[allow(clippy::unnecessary_cast)]
+	pub fn from_detection(frame_id: usize, timestamp: f64, detection: &Detection, marker_size_mm: f32, camera_intrinsics: &CameraIntrinsics, pinhole: &Pinhole) -> Self {
+		let markers = detection.markers.iter().map(|m| {
+			let (mp1, mp2) = pose::solve_with_intrinsics(&m.corners, marker_size_mm, camera_intrinsics);
+			let corners = [
+				(m.corners[0].0 as f32, m.corners[0].1 as f32),
+				(m.corners[1].0 as f32, m.corners[1].1 as f32),
+				(m.corners[2].0 as f32, m.corners[2].1 as f32),
+				(m.corners[3].0 as f32, m.corners[3].1 as f32),
+			];
+			let poses = [mp1, mp2].iter().map(|mp| {
+				let mut pose = PoseRecord {
+					translation: [mp.translation.x as f32, mp.translation.y as f32, mp.translation.z as f32],
+					rotation: [
+						[mp.rotation.m11 as f32, mp.rotation.m12 as f32, mp.rotation.m13 as f32],
+						[mp.rotation.m21 as f32, mp.rotation.m22 as f32, mp.rotation.m23 as f32],
+						[mp.rotation.m31 as f32, mp.rotation.m32 as f32, mp.rotation.m33 as f32],
+					],
+					error: mp.error as f32,
+					reprojection_error: None,
+				};
+				pose.reprojection_error = Some(pose.reprojection_rms(&corners, pinhole, marker_size_mm));
+				pose
+			}).collect();
+			MarkerRecord {
+				marker_id: m.id as usize,
+				corners,
+				corners_3d: None,
+				poses,
+				payload: None,
+				diamond: None,
+				hamming_distance: Some(m.hamming_distance as u32),
+				confidence: None,
+				velocity: None,
+				angular_velocity: None,
+				partial: None,
+				instance: None,
+				corners_normalized: None,
+				edges: vec![],
+				axes: None,
+			}
+		}).collect();
+		FrameRecord {
+			frame_id,
+			timestamp,
+			intrinsics: Some(*pinhole),
+			markers,
+			..Default::default()
+		}
+	}
+
+	/// Fill in every marker's normalized corners from its pixel corners, relative to the frame the intrinsics are for.
+	pub fn normalize_corners(&mut self, coords: NormalizedCoords) {
+		let Some(pinhole) = self.intrinsics else {
+			return;
+		};
+		for m in self.markers.iter_mut() {
+			m.corners_normalized = Some(m.corners.map(|c| coords.apply(c, pinhole.width, pinhole.height)));
+		}
+	}
+
+	/// Fill in every posed marker's axes from its best pose, each `length(marker)` long.
+	pub fn add_axes(&mut self, length: impl Fn(&MarkerRecord) -> f32) {
+		for m in self.markers.iter_mut() {
+			let length = length(m);
+			m.axes = m.best_pose().map(|pose| {
+				let r = &pose.rotation;
+				let tip = |axis: usize| geometry::add(&pose.translation, &geometry::scale(&[r[0][axis], r[1][axis], r[2][axis]], length));
+				// Z comes out of the marker's face, so it's the normal (see geometry::marker_corners).
+				MarkerAxes { normal: [r[0][2], r[1][2], r[2][2]], x: tip(0), y: tip(1), z: tip(2) }
+			});
+		}
+	}
+
+	/// Move every marker's corners by the given amount, e.g. to switch between crop-local and full-frame coordinates.
+	pub fn offset_corners(&mut self, (dx, dy): (f32, f32)) {
+		for m in self.markers.iter_mut() {
+			for c in m.corners.iter_mut() {
+				c.0 += dx;
+				c.1 += dy;
+			}
+		}
+	}
+
+	pub fn to_value(&self) -> Value {
+		let mut out = Vec::with_capacity(8);
+		out.push(("frame_id".to_string(), Value::Int(self.frame_id as i64)));
+		if let Some(camera_id) = &self.camera_id {
+			out.push(("camera_id".to_string(), Value::Str(camera_id.clone())));
+		}
+		out.push(("timestamp".to_string(), Value::F64(self.timestamp)));
+		if let Some(timecode) = &self.timecode {
+			out.push(("timecode".to_string(), Value::Str(timecode.clone())));
+		}
+		if self.dropped_frames > 0 {
+			out.push(("dropped_frames".to_string(), Value::Int(self.dropped_frames as i64)));
+		}
+		if self.duplicate {
+			out.push(("duplicate".to_string(), Value::Bool(true)));
+		}
+		if let Some(source_frame) = self.source_frame {
+			out.push(("source_frame".to_string(), Value::Int(source_frame as i64)));
+		}
+		if let Some(reason) = &self.skipped {
+			out.push(("skipped".to_string(), Value::Str(reason.clone())));
+		}
+		// Written with every frame, since a zoom (see --intrinsics-track) can change them from one to the next.
+		if let Some(pinhole) = &self.intrinsics {
+			out.push(("intrinsics".to_string(), Value::Map(vec![
+				("fx".to_string(), Value::F32(pinhole.fx)),
+				("fy".to_string(), Value::F32(pinhole.fy)),
+				("cx".to_string(), Value::F32(pinhole.cx)),
+				("cy".to_string(), Value::F32(pinhole.cy)),
+			])));
+		}
+		if let Some(camera_pose) = &self.camera_pose {
+			out.push(("camera_pose".to_string(), camera_pose.to_value(self.rotation_format)));
+		}
+		out.push(("detections".to_string(), Value::Array(self.markers.iter().map(|m| m.to_value(self.rotation_format)).collect())));
+		Value::Map(out)
+	}
+
+	// Convert a frame into a single-line JSON output.
+	pub fn to_json(&self) -> String {
+		self.to_value().to_json()
+	}
+}
+
+impl MarkerRecord {
+	/// The pose candidate with the lowest solver error.
+	pub fn best_pose(&self) -> Option<&PoseRecord> {
+		self.poses.iter().min_by(|a, b| a.error.total_cmp(&b.error))
+	}
+
+	pub fn to_value(&self, rotation_format: RotationFormat) -> Value {
+		let c = &self.corners;
+		let mut out = Vec::with_capacity(4);
+		out.push(("marker_id".to_string(), Value::Int(self.marker_id as i64)));
+		if let Some(instance) = self.instance {
+			out.push(("instance".to_string(), Value::Int(instance as i64)));
+		}
+		out.push(("corners".to_string(), Value::floats(&[c[0].0, c[0].1, c[1].0, c[1].1, c[2].0, c[2].1, c[3].0, c[3].1])));
+		if let Some(n) = &self.corners_normalized {
+			out.push(("corners_normalized".to_string(), Value::floats(&[n[0].0, n[0].1, n[1].0, n[1].1, n[2].0, n[2].1, n[3].0, n[3].1])));
+		}
+		if let Some(points) = &self.corners_3d {
+			out.push(("corners_3d".to_string(), Value::floats(points.as_flattened())));
+		}
+		out.push(("poses".to_string(), Value::Array(self.poses.iter().map(|p| p.to_value(rotation_format)).collect())));
+		if let Some(payload) = &self.payload {
+			out.push(("payload".to_string(), Value::Str(payload.clone())));
+		}
+		if let Some(ids) = &self.diamond {
+			out.push(("diamond_ids".to_string(), Value::Array(ids.iter().map(|id| Value::Int(*id as i64)).collect())));
+		}
+		if let Some(hamming_distance) = self.hamming_distance {
+			out.push(("hamming_distance".to_string(), Value::Int(hamming_distance as i64)));
+		}
+		if let Some(confidence) = &self.confidence {
+			out.push(("confidence".to_string(), confidence.to_value()));
+		}
+		if let Some(velocity) = &self.velocity {
+			out.push(("velocity".to_string(), Value::floats(velocity)));
+		}
+		if let Some(angular_velocity) = &self.angular_velocity {
+			out.push(("angular_velocity".to_string(), Value::floats(angular_velocity)));
+		}
+		if let Some(visible) = &self.partial {
+			out.push(("partial".to_string(), Value::Bool(true)));
+			out.push(("visible_corners".to_string(), Value::Array(visible.iter().map(|v| Value::Bool(*v)).collect())));
+		}
+		if let Some(axes) = &self.axes {
+			out.push(("normal".to_string(), Value::floats(&axes.normal)));
+			out.push(("axis_x".to_string(), Value::floats(&axes.x)));
+			out.push(("axis_y".to_string(), Value::floats(&axes.y)));
+			out.push(("axis_z".to_string(), Value::floats(&axes.z)));
+		}
+		Value::Map(out)
+	}
+}
+
+impl CameraPose {
+	pub fn to_value(&self, rotation_format: RotationFormat) -> Value {
+		let mut out = vec![
+			("position".to_string(), Value::floats(&self.position)),
+			(rotation_format.key().to_string(), rotation_value(&self.rotation, rotation_format)),
+			("marker_count".to_string(), Value::Int(self.marker_count as i64)),
+		];
+		if let Some(reprojection_error) = self.reprojection_error {
+			out.push(("reprojection_error".to_string(), Value::F32(reprojection_error)));
+		}
+		Value::Map(out)
+	}
+}
+
+/// A rotation the way a pose holds it in the given format.
+pub fn rotation_value(rotation: &Mat3, rotation_format: RotationFormat) -> Value {
+	match rotation_format {
+		RotationFormat::Matrix => Value::floats(rotation.as_flattened()),
+		RotationFormat::Quaternion => Value::floats(&geometry::mat3_to_quat(rotation)),
+		RotationFormat::EulerXyz => Value::floats(&geometry::mat3_to_euler_xyz(rotation)),
+		RotationFormat::AxisAngle => Value::floats(&geometry::rotation_vector(rotation)),
+	}
+}
+
+impl PoseRecord {
+	/// Our own single-solution pose for a square from its corners, for markers the detector's solver never sees.
+	/// The error is the reprojection error, since there's no solver residual to report.
+	pub fn from_quad(corners: &[(f32, f32); 4], marker_size_mm: f32, pinhole: &Pinhole) -> Option<Self> {
+		let (rotation, translation) = geometry::pose_from_quad(corners, marker_size_mm, pinhole)?;
+		let mut pose = PoseRecord { translation, rotation, ..Default::default() };
+		let rms = pose.reprojection_rms(corners, pinhole, marker_size_mm);
+		pose.error = rms;
+		pose.reprojection_error = Some(rms);
+		Some(pose)
+	}
+
+	/// RMS distance in pixels between the detected corners and the marker's corners projected through this pose.
+	pub fn reprojection_rms(&self, corners: &[(f32, f32); 4], pinhole: &Pinhole, marker_size_mm: f32) -> f32 {
+		let squared_error: f32 = geometry::marker_corners(marker_size_mm).iter().zip(corners.iter()).map(|(model, (u, v))| {
+			let p = geometry::add(&geometry::mat_mul_vec(&self.rotation, model), &self.translation);
+			let (pu, pv) = pinhole.project(&p);
+			(pu - u).powi(2) + (pv - v).powi(2)
+		}).sum();
+		(squared_error / 4.0).sqrt()
+	}
+
+	pub fn to_value(&self, rotation_format: RotationFormat) -> Value {
+		let mut out = vec![
+			("translation".to_string(), Value::floats(&self.translation)),
+			(rotation_format.key().to_string(), rotation_value(&self.rotation, rotation_format)),
+			("error".to_string(), Value::F32(self.error)),
+		];
+		if let Some(reprojection_error) = self.reprojection_error {
+			out.push(("reprojection_error".to_string(), Value::F32(reprojection_error)));
+		}
+		Value::Map(out)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_reprojection_error() {
+		let pinhole = Pinhole { width: 1000, height: 1000, fx: 800.0, fy: 800.0, cx: 500.0, cy: 500.0 };
+		let pose = PoseRecord { translation: [0.0, 0.0, 400.0], rotation: geometry::IDENTITY, ..Default::default() };
+		let mut corners = geometry::marker_corners(50.0).map(|c| pinhole.project(&geometry::add(&c, &pose.translation)));
+		assert!(pose.reprojection_rms(&corners, &pinhole, 50.0) < 1e-4);
+		// Nudging every corner by 3 pixels gives an RMS of 3.
+		for c in corners.iter_mut() {
+			c.0 += 3.0;
+		}
+		assert!((pose.reprojection_rms(&corners, &pinhole, 50.0) - 3.0).abs() < 1e-4);
+	}
+
+	#[test]
+	fn test_rotation_format_key() {
+		let pose = PoseRecord { rotation: geometry::rodrigues(&[0.0, 0.5, 0.0]), ..Default::default() };
+		let Value::Map(fields) = pose.to_value(RotationFormat::AxisAngle) else {
+			panic!("a pose should be a map");
+		};
+		assert!(fields.iter().all(|(k, _)| k != "rotation"));
+		let (_, axis_angle) = fields.iter().find(|(k, _)| k == "axis_angle").unwrap();
+		assert_eq!(axis_angle.to_json(), Value::floats(&geometry::rotation_vector(&pose.rotation)).to_json());
+	}
+
+	#[test]
+	fn test_normalized_coords() {
+		let pinhole = Pinhole { width: 1920, height: 1080, ..Default::default() };
+		let mut record = FrameRecord { intrinsics: Some(pinhole), markers: vec![MarkerRecord { corners: [(0.0, 0.0), (1920.0, 0.0), (960.0, 540.0), (480.0, 810.0)], ..Default::default() }], ..Default::default() };
+		record.normalize_corners(NormalizedCoords::Unit);
+		assert_eq!(record.markers[0].corners_normalized, Some([(0.0, 0.0), (1.0, 0.0), (0.5, 0.5), (0.25, 0.75)]));
+		record.normalize_corners(NormalizedCoords::Ndc);
+		assert_eq!(record.markers[0].corners_normalized, Some([(-1.0, 1.0), (1.0, 1.0), (0.0, 0.0), (-0.5, -0.5)]));
+	}
+
+	#[test]
+	fn test_add_axes() {
+		// Turned a quarter turn about Y, so the marker faces +X.
+		let pose = PoseRecord { translation: [10.0, 20.0, 500.0], rotation: geometry::rodrigues(&[0.0, std::f32::consts::FRAC_PI_2, 0.0]), ..Default::default() };
+		let mut record = FrameRecord { markers: vec![MarkerRecord { poses: vec![pose], ..Default::default() }, MarkerRecord::default()], ..Default::default() };
+		record.add_axes(|_| 50.0);
+		let axes = record.markers[0].axes.unwrap();
+		let close = |a: Vec3, b: Vec3| geometry::length(&geometry::sub(&a, &b)) < 1e-3;
+		assert!(close(axes.normal, [1.0, 0.0, 0.0]), "{:?}", axes.normal);
+		assert!(close(axes.x, [10.0, 20.0, 450.0]), "{:?}", axes.x);
+		assert!(close(axes.y, [10.0, 70.0, 500.0]), "{:?}", axes.y);
+		assert!(close(axes.z, [60.0, 20.0, 500.0]), "{:?}", axes.z);
+		// Nothing to draw without a pose.
+		assert!(record.markers[1].axes.is_none());
+	}
+}
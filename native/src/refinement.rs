@@ -0,0 +1,412 @@
+// Cleaning up a finished track, for the refine subcommand: so filtering can be tuned and tried again without tracking
+// the video again. It works on the records as they were written, whatever --rotation-format, --units, or --convention
+// they were written with, and only ever changes each detection's first pose, the one the addon reads. Each marker
+// (per camera, and per instance) is a track of its own, and these run over each track in order:
+//   flips      with two candidate poses, put first the one that turns least from the last frame's, since the planar
+//              solver's two solutions trade places whenever a marker is nearly face on
+//   spikes     a translation further from the median of its neighbours than some fraction of its distance from the
+//              camera is a bad solve, and is replaced with the median
+//   gaps       a marker missing for a few frames between two sightings is put back in those frames, blended between
+//              the two, with "interpolated": true
+//   smoothing  a moving average of translation and rotation, shrinking at the ends of a run so they stay put
+// Velocities worked out from the old poses no longer match, so they're dropped from any detection that changed.
+
+use crate::geometry::{self, Mat3, Quat, Vec3, lerp, lerp_vec3, mat3_to_quat, mat_mul, quat_normalize, quat_to_mat3, rodrigues, slerp};
+use crate::record::{RotationFormat, rotation_value};
+use crate::value::Value;
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Refinement {
+	/// Frames either side to take the median of when looking for spikes. 0 leaves spikes alone.
+	pub spike_window: usize,
+	/// How far from the median a translation can be, as a fraction of the median's distance from the camera.
+	pub spike_threshold: f32,
+	/// The longest gap, in frames, to fill in. 0 leaves gaps alone.
+	pub max_gap: usize,
+	/// Frames either side to average over. 0 leaves the track unsmoothed.
+	pub smooth_window: usize,
+	pub fix_flips: bool,
+}
+
+/// What refine changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RefineSummary {
+	pub flips: usize,
+	pub spikes: usize,
+	pub filled: usize,
+}
+
+/// (camera_id, marker_id, instance)
+type TrackKey = (Option<String>, i64, Option<i64>);
+
+struct Pose {
+	translation: Vec3,
+	rotation: Quat,
+	format: RotationFormat,
+	error: f32,
+}
+
+struct Sample {
+	record: usize,
+	detection: usize,
+	/// The record's place among its camera's records, which is what gaps are counted in.
+	step: usize,
+	pose: Pose,
+	corners: Vec<f32>,
+	changed: bool,
+}
+
+impl Refinement {
+	pub fn to_value(&self) -> Value {
+		Value::Map(vec![
+			("spike_window".to_string(), Value::Int(self.spike_window as i64)),
+			("spike_threshold".to_string(), Value::F32(self.spike_threshold)),
+			("max_gap".to_string(), Value::Int(self.max_gap as i64)),
+			("smooth_window".to_string(), Value::Int(self.smooth_window as i64)),
+			("fix_flips".to_string(), Value::Bool(self.fix_flips)),
+		])
+	}
+
+	/// Refine frame records read back from a track file, in the order they were written.
+	pub fn apply(&self, records: &mut [Value]) -> RefineSummary {
+		let mut summary = RefineSummary::default();
+		let mut cameras: BTreeMap<Option<String>, Vec<usize>> = BTreeMap::new();
+		let mut tracks: BTreeMap<TrackKey, Vec<Sample>> = BTreeMap::new();
+		for (record_idx, record) in records.iter().enumerate() {
+			let camera_id = record.get("camera_id").and_then(|c| match c {
+				Value::Str(s) => Some(s.clone()),
+				_ => None,
+			});
+			let Some(Value::Array(detections)) = record.get("detections") else {
+				continue;
+			};
+			let steps = cameras.entry(camera_id.clone()).or_default();
+			steps.push(record_idx);
+			for (detection_idx, detection) in detections.iter().enumerate() {
+				let (Some(Value::Int(marker_id)), Some(pose)) = (detection.get("marker_id"), first_pose(detection)) else {
+					continue;
+				};
+				let instance = match detection.get("instance") {
+					Some(Value::Int(instance)) => Some(*instance),
+					_ => None,
+				};
+				tracks.entry((camera_id.clone(), *marker_id, instance)).or_default().push(Sample {
+					record: record_idx,
+					detection: detection_idx,
+					step: steps.len() - 1,
+					pose,
+					corners: detection.get("corners").and_then(Value::as_floats).unwrap_or_default(),
+					changed: false,
+				});
+			}
+		}
+
+		for ((camera_id, marker_id, instance), mut samples) in tracks {
+			if self.fix_flips {
+				summary.flips += fix_flips(records, &mut samples);
+			}
+			if self.spike_window > 0 {
+				summary.spikes += self.remove_spikes(&mut samples);
+			}
+			if self.max_gap > 0 {
+				let before = samples.len();
+				samples = self.fill_gaps(records, &cameras[&camera_id], marker_id, instance, samples);
+				summary.filled += samples.len() - before;
+			}
+			if self.smooth_window > 0 {
+				self.smooth(&mut samples);
+			}
+			for sample in samples.iter().filter(|s| s.changed) {
+				write_pose(records, sample);
+			}
+		}
+		summary
+	}
+
+	fn remove_spikes(&self, samples: &mut [Sample]) -> usize {
+		let translations: Vec<(usize, Vec3)> = samples.iter().map(|s| (s.step, s.pose.translation)).collect();
+		let mut spikes = 0;
+		for (i, sample) in samples.iter_mut().enumerate() {
+			let window: Vec<&Vec3> = translations.iter().filter(|(step, _)| step.abs_diff(sample.step) <= self.spike_window).map(|(_, t)| t).collect();
+			// The median of two is no better a judge than either.
+			if window.len() < 3 {
+				continue;
+			}
+			let median: Vec3 = std::array::from_fn(|axis| {
+				let mut values: Vec<f32> = window.iter().map(|t| t[axis]).collect();
+				values.sort_by(f32::total_cmp);
+				values[values.len() / 2]
+			});
+			let t = translations[i].1;
+			let off = ((t[0] - median[0]).powi(2) + (t[1] - median[1]).powi(2) + (t[2] - median[2]).powi(2)).sqrt();
+			let distance = (median[0].powi(2) + median[1].powi(2) + median[2].powi(2)).sqrt();
+			if off > self.spike_threshold * distance {
+				sample.pose.translation = median;
+				sample.changed = true;
+				spikes += 1;
+			}
+		}
+		spikes
+	}
+
+	/// Put the marker back in the frames of each short gap, and return the track with them in order.
+	fn fill_gaps(&self, records: &mut [Value], steps: &[usize], marker_id: i64, instance: Option<i64>, samples: Vec<Sample>) -> Vec<Sample> {
+		let mut out: Vec<Sample> = Vec::with_capacity(samples.len());
+		for sample in samples {
+			if let Some(last) = out.last() {
+				// Two copies of an id in one frame without instances to tell them apart are both kept, with no gap between.
+				let missing = sample.step.saturating_sub(last.step + 1);
+				if (1..=self.max_gap).contains(&missing) {
+					let filled: Vec<Sample> = (1..=missing).map(|k| {
+						let alpha = k as f32 / (missing + 1) as f32;
+						let step = last.step + k;
+						let record = steps[step];
+						let corners: Vec<f32> = last.corners.iter().zip(&sample.corners).map(|(a, b)| lerp(*a, *b, alpha)).collect();
+						let mut detection = vec![("marker_id".to_string(), Value::Int(marker_id))];
+						if let Some(instance) = instance {
+							detection.push(("instance".to_string(), Value::Int(instance)));
+						}
+						detection.push(("corners".to_string(), Value::floats(&corners)));
+						detection.push(("poses".to_string(), Value::Array(vec![Value::Map(vec![])])));
+						detection.push(("interpolated".to_string(), Value::Bool(true)));
+						let Some(Value::Array(detections)) = records[record].get_mut("detections") else {
+							unreachable!("Only records with detections are counted as steps.");
+						};
+						detections.push(Value::Map(detection));
+						Sample {
+							record,
+							detection: detections.len() - 1,
+							step,
+							pose: Pose {
+								translation: lerp_vec3(&last.pose.translation, &sample.pose.translation, alpha),
+								rotation: slerp(&last.pose.rotation, &sample.pose.rotation, alpha),
+								format: last.pose.format,
+								error: lerp(last.pose.error, sample.pose.error, alpha),
+							},
+							corners,
+							changed: true,
+						}
+					}).collect();
+					out.extend(filled);
+				}
+			}
+			out.push(sample);
+		}
+		out
+	}
+
+	fn smooth(&self, samples: &mut [Sample]) {
+		// Runs of consecutive frames, so nothing is averaged across a gap that wasn't filled.
+		let mut runs = vec![];
+		let mut start = 0;
+		for i in 1..=samples.len() {
+			if i == samples.len() || samples[i].step != samples[i - 1].step + 1 {
+				runs.push(start..i);
+				start = i;
+			}
+		}
+		let poses: Vec<(Vec3, Quat)> = samples.iter().map(|s| (s.pose.translation, s.pose.rotation)).collect();
+		for run in runs {
+			for i in run.clone() {
+				let radius = self.smooth_window.min(i - run.start).min(run.end - 1 - i);
+				if radius == 0 {
+					continue;
+				}
+				let window = &poses[i - radius..=i + radius];
+				let n = window.len() as f32;
+				let center = poses[i].1;
+				let mut translation = [0.0; 3];
+				let mut rotation = [0.0; 4];
+				for (t, q) in window {
+					// q and -q are the same rotation, so line them all up with the middle one before adding.
+					let sign = if (0..4).map(|k| q[k] * center[k]).sum::<f32>() < 0.0 { -1.0 } else { 1.0 };
+					for k in 0..3 {
+						translation[k] += t[k] / n;
+					}
+					for k in 0..4 {
+						rotation[k] += sign * q[k];
+					}
+				}
+				samples[i].pose.translation = translation;
+				samples[i].pose.rotation = quat_normalize(&rotation);
+				samples[i].changed = true;
+			}
+		}
+	}
+}
+
+fn read_pose(pose: &Value) -> Option<Pose> {
+	let translation = pose.get("translation")?.as_floats()?;
+	let (format, rotation) = RotationFormat::value_variants().iter().find_map(|format| {
+		let v = pose.get(format.key())?.as_floats()?;
+		let rotation: Mat3 = match (format, v.len()) {
+			(RotationFormat::Matrix, 9) => [[v[0], v[1], v[2]], [v[3], v[4], v[5]], [v[6], v[7], v[8]]],
+			(RotationFormat::Quaternion, 4) => quat_to_mat3(&quat_normalize(&[v[0], v[1], v[2], v[3]])),
+			// R = Rz * Ry * Rx, as mat3_to_euler_xyz takes apart.
+			(RotationFormat::EulerXyz, 3) => mat_mul(&rodrigues(&[0.0, 0.0, v[2]]), &mat_mul(&rodrigues(&[0.0, v[1], 0.0]), &rodrigues(&[v[0], 0.0, 0.0]))),
+			(RotationFormat::AxisAngle, 3) => rodrigues(&[v[0], v[1], v[2]]),
+			_ => return None,
+		};
+		Some((*format, rotation))
+	})?;
+	(translation.len() == 3).then(|| Pose {
+		translation: [translation[0], translation[1], translation[2]],
+		rotation: mat3_to_quat(&rotation),
+		format,
+		error: pose.get("error").and_then(Value::as_f64).unwrap_or_default() as f32,
+	})
+}
+
+fn first_pose(detection: &Value) -> Option<Pose> {
+	match detection.get("poses")? {
+		Value::Array(poses) => read_pose(poses.first()?),
+		_ => None,
+	}
+}
+
+/// Move to the front whichever candidate turns least from the one in front the frame before. Returns how many moved.
+fn fix_flips(records: &mut [Value], samples: &mut [Sample]) -> usize {
+	let mut flips = 0;
+	for i in 1..samples.len() {
+		let previous = samples[i - 1].pose.rotation;
+		let sample = &mut samples[i];
+		let Some(Value::Array(poses)) = records[sample.record].get_mut("detections").and_then(|d| match d {
+			Value::Array(detections) => detections[sample.detection].get_mut("poses"),
+			_ => None,
+		}) else {
+			continue;
+		};
+		let closest = poses.iter().enumerate()
+			.filter_map(|(idx, pose)| Some((idx, read_pose(pose)?)))
+			.max_by(|a, b| alignment(&a.1.rotation, &previous).total_cmp(&alignment(&b.1.rotation, &previous)));
+		if let Some((idx, pose)) = closest.filter(|(idx, _)| *idx != 0) {
+			let moved = poses.remove(idx);
+			poses.insert(0, moved);
+			sample.pose = pose;
+			sample.changed = true;
+			flips += 1;
+		}
+	}
+	flips
+}
+
+/// |cos| of half the angle between two rotations: 1 for the same one.
+fn alignment(a: &Quat, b: &Quat) -> f32 {
+	(0..4).map(|k| a[k] * b[k]).sum::<f32>().abs()
+}
+
+fn write_pose(records: &mut [Value], sample: &Sample) {
+	let Some(Value::Array(detections)) = records[sample.record].get_mut("detections") else {
+		return;
+	};
+	let detection = &mut detections[sample.detection];
+	if let Value::Map(fields) = detection {
+		fields.retain(|(key, _)| key != "velocity" && key != "angular_velocity");
+	}
+	let rotation = quat_to_mat3(&sample.pose.rotation);
+	write_axes(detection, &sample.pose.translation, &rotation);
+	let Some(Value::Array(poses)) = detection.get_mut("poses") else {
+		return;
+	};
+	let Some(pose) = poses.first_mut() else {
+		return;
+	};
+	pose.set("translation", Value::floats(&sample.pose.translation));
+	pose.set(sample.pose.format.key(), rotation_value(&rotation, sample.pose.format));
+	if pose.get("error").is_none() {
+		pose.set("error", Value::F32(sample.pose.error));
+	}
+}
+
+/// Move --pose-axes' normal and axis tips onto the new pose, keeping their length. The X and Y tips are a length
+/// apart times the square root of two, whichever pose they came from.
+fn write_axes(detection: &mut Value, translation: &Vec3, rotation: &Mat3) {
+	let tip = |key: &str| detection.get(key).and_then(Value::as_floats).filter(|v| v.len() == 3).map(|v| [v[0], v[1], v[2]]);
+	let (Some(x), Some(y)) = (tip("axis_x"), tip("axis_y")) else {
+		return;
+	};
+	let length = geometry::length(&geometry::sub(&x, &y)) / std::f32::consts::SQRT_2;
+	let column = |axis: usize| [rotation[0][axis], rotation[1][axis], rotation[2][axis]];
+	detection.set("normal", Value::floats(&column(2)));
+	for (axis, key) in ["axis_x", "axis_y", "axis_z"].iter().enumerate() {
+		detection.set(key, Value::floats(&geometry::add(translation, &geometry::scale(&column(axis), length))));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::geometry::rotation_vector;
+	use crate::record::{FrameRecord, MarkerRecord, PoseRecord};
+
+	fn pose(z: f32, angle: f32) -> PoseRecord {
+		PoseRecord { translation: [0.0, 0.0, z], rotation: rodrigues(&[angle, 0.0, 0.0]), error: 0.1, ..Default::default() }
+	}
+
+	fn first(records: &[Value], frame: usize) -> Option<Pose> {
+		let Some(Value::Array(detections)) = records[frame].get("detections") else {
+			return None;
+		};
+		first_pose(detections.first()?)
+	}
+
+	#[test]
+	fn test_refine_track() {
+		// Marker 3 moving away at 10 mm a frame, tilted 0.3 radians about X, with the other solution (-0.3) first
+		// on frame 3, a spike on frame 5, and missing on frames 7 and 8.
+		let mut records: Vec<Value> = (0..12).map(|frame_id| {
+			let z = 500.0 + 10.0 * frame_id as f32;
+			let mut poses = vec![pose(z, 0.3), pose(z, -0.3)];
+			if frame_id == 3 {
+				poses.reverse();
+			}
+			if frame_id == 5 {
+				poses[0].translation[2] = 900.0;
+			}
+			let markers = if frame_id == 7 || frame_id == 8 { vec![] } else { vec![MarkerRecord { marker_id: 3, poses, ..Default::default() }] };
+			FrameRecord { frame_id, rotation_format: RotationFormat::AxisAngle, markers, ..Default::default() }.to_value()
+		}).collect();
+		let refinement = Refinement { spike_window: 2, spike_threshold: 0.05, max_gap: 3, smooth_window: 1, fix_flips: true };
+		let summary = refinement.apply(&mut records);
+		assert_eq!(summary, RefineSummary { flips: 1, spikes: 1, filled: 2 });
+
+		let flipped = first(&records, 3).unwrap();
+		assert!((rotation_vector(&quat_to_mat3(&flipped.rotation))[0] - 0.3).abs() < 1e-4);
+		assert_eq!(flipped.format, RotationFormat::AxisAngle);
+		assert!((first(&records, 5).unwrap().translation[2] - 550.0).abs() < 10.0);
+		let filled = first(&records, 8).unwrap();
+		assert!((filled.translation[2] - 580.0).abs() < 1.0, "{:?}", filled.translation);
+		let Some(Value::Array(detections)) = records[8].get("detections") else {
+			panic!("frame 8 has no detections");
+		};
+		assert_eq!(detections[0].get("interpolated"), Some(&Value::Bool(true)));
+		// The ends of the track stay put.
+		assert_eq!(first(&records, 0).unwrap().translation, [0.0, 0.0, 500.0]);
+	}
+
+	#[test]
+	fn test_refine_moves_axes() {
+		// The spike on frame 2 is smoothed away, and its axes have to follow.
+		let mut records: Vec<Value> = (0..5).map(|frame_id| {
+			let mut poses = vec![pose(500.0 + 10.0 * frame_id as f32, 0.3)];
+			if frame_id == 2 {
+				poses[0].translation[2] = 900.0;
+			}
+			let mut record = FrameRecord { frame_id, markers: vec![MarkerRecord { marker_id: 3, poses, ..Default::default() }], ..Default::default() };
+			record.add_axes(|_| 50.0);
+			record.to_value()
+		}).collect();
+		let refinement = Refinement { spike_window: 2, spike_threshold: 0.05, max_gap: 0, smooth_window: 1, fix_flips: false };
+		assert_eq!(refinement.apply(&mut records).spikes, 1);
+		let Some(Value::Array(detections)) = records[2].get("detections") else {
+			panic!("frame 2 has no detections");
+		};
+		let refined = first(&records, 2).unwrap();
+		let rotation = quat_to_mat3(&refined.rotation);
+		let z = detections[0].get("axis_z").and_then(Value::as_floats).unwrap();
+		let expected = geometry::add(&refined.translation, &geometry::scale(&[rotation[0][2], rotation[1][2], rotation[2][2]], 50.0));
+		assert!(z.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-3), "{z:?} vs {expected:?}");
+	}
+}
@@ -0,0 +1,195 @@
+// A dope sheet of the run for --report: for each marker, the frame ranges it was seen in, the gaps between them, and
+// how well it solved, to plan a re-shoot or where to start cleaning up by hand. A path ending in .csv gets one row per
+// range (visible or gap) for a spreadsheet; anything else gets a standalone HTML page with a timeline bar per marker.
+
+use crate::output::Sink;
+use crate::record::FrameRecord;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::path::PathBuf;
+
+/// How wide each marker's timeline is drawn in the HTML report, in pixels.
+const TIMELINE_WIDTH: f64 = 800.0;
+
+#[derive(Clone, Debug, PartialEq)]
+struct Range {
+	first_frame: usize,
+	last_frame: usize,
+	start_time: f64,
+	end_time: f64,
+	detections: usize,
+	reprojection_error_sum: f64,
+	reprojection_error_count: usize,
+}
+
+impl Range {
+	fn mean_reprojection_error(&self) -> Option<f64> {
+		(self.reprojection_error_count > 0).then(|| self.reprojection_error_sum / self.reprojection_error_count as f64)
+	}
+}
+
+pub struct ReportSink {
+	path: PathBuf,
+	marker_size_mm: f32,
+	/// The first and last frame of each camera, which the timelines span.
+	cameras: BTreeMap<Option<String>, (usize, usize)>,
+	/// By camera, marker id, and instance, so two printed copies of an id get a timeline each.
+	markers: BTreeMap<(Option<String>, usize, u32), Vec<Range>>,
+}
+
+impl ReportSink {
+	pub fn new(path: PathBuf, marker_size_mm: f32) -> Self {
+		ReportSink { path, marker_size_mm, cameras: BTreeMap::new(), markers: BTreeMap::new() }
+	}
+
+	fn push(&mut self, record: &FrameRecord) {
+		// Triangulated records describe frames we've already counted.
+		if record.camera_id.as_deref() == Some("stereo") {
+			return;
+		}
+		let span = self.cameras.entry(record.camera_id.clone()).or_insert((record.frame_id, record.frame_id));
+		span.1 = span.1.max(record.frame_id);
+		for m in &record.markers {
+			let error = m.best_pose().and_then(|pose| {
+				let pinhole = record.intrinsics.as_ref()?;
+				Some(pose.reprojection_error.unwrap_or_else(|| pose.reprojection_rms(&m.corners, pinhole, self.marker_size_mm)) as f64)
+			});
+			let ranges = self.markers.entry((record.camera_id.clone(), m.marker_id, m.instance.unwrap_or_default())).or_default();
+			let range = match ranges.last_mut() {
+				Some(range) if range.last_frame + 1 == record.frame_id || range.last_frame == record.frame_id => range,
+				_ => {
+					ranges.push(Range {
+						first_frame: record.frame_id,
+						last_frame: record.frame_id,
+						start_time: record.timestamp,
+						end_time: record.timestamp,
+						detections: 0,
+						reprojection_error_sum: 0.0,
+						reprojection_error_count: 0,
+					});
+					ranges.last_mut().expect("Just pushed.")
+				},
+			};
+			range.last_frame = record.frame_id;
+			range.end_time = record.timestamp;
+			range.detections += 1;
+			if let Some(error) = error {
+				range.reprojection_error_sum += error;
+				range.reprojection_error_count += 1;
+			}
+		}
+	}
+
+	fn csv(&self) -> String {
+		let mut out = "camera_id,marker_id,instance,kind,first_frame,last_frame,frames,start_time,end_time,mean_reprojection_error\n".to_string();
+		for ((camera_id, marker_id, instance), ranges) in &self.markers {
+			let camera_id = camera_id.as_deref().map(csv_field).unwrap_or_default();
+			for (idx, range) in ranges.iter().enumerate() {
+				if idx > 0 {
+					let previous = &ranges[idx - 1];
+					let _ = writeln!(out, "{camera_id},{marker_id},{instance},gap,{},{},{},{},{},", previous.last_frame + 1, range.first_frame - 1,
+						range.first_frame - previous.last_frame - 1, previous.end_time, range.start_time);
+				}
+				let error = range.mean_reprojection_error().map_or(String::new(), |e| format!("{e:.3}"));
+				let _ = writeln!(out, "{camera_id},{marker_id},{instance},visible,{},{},{},{},{},{error}", range.first_frame, range.last_frame,
+					range.last_frame - range.first_frame + 1, range.start_time, range.end_time);
+			}
+		}
+		out
+	}
+
+	fn html(&self) -> String {
+		let mut out = String::from("<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>Marker visibility</title><style>\n\
+			body { font-family: sans-serif; font-size: 13px; }\n\
+			table { border-collapse: collapse; }\n\
+			td, th { padding: 2px 8px; text-align: right; border-bottom: 1px solid #ddd; vertical-align: middle; }\n\
+			td.ranges { text-align: left; max-width: 24em; }\n\
+			svg rect.track { fill: #eee; }\n\
+			svg rect.seen { fill: #3a7; }\n\
+			</style></head><body>\n<h1>Marker visibility</h1>\n");
+		for (camera, (first, last)) in &self.cameras {
+			if let Some(camera_id) = camera {
+				let _ = writeln!(out, "<h2>Camera {}</h2>", html_escape(camera_id));
+			}
+			let _ = writeln!(out, "<p>Frames {first} to {last}.</p>");
+			out.push_str("<table>\n<tr><th>marker</th><th>timeline</th><th>frames seen</th><th>gaps</th><th>longest gap</th><th>reproj (px)</th><th>ranges</th></tr>\n");
+			let span = (last - first + 1) as f64;
+			let x = |frame: usize| (frame - first) as f64 / span * TIMELINE_WIDTH;
+			for ((_, marker_id, instance), ranges) in self.markers.iter().filter(|((c, _, _), _)| c == camera) {
+				let marker = if *instance > 0 { format!("{marker_id}.{instance}") } else { marker_id.to_string() };
+				let seen: usize = ranges.iter().map(|r| r.last_frame - r.first_frame + 1).sum();
+				let longest_gap = ranges.windows(2).map(|pair| pair[1].first_frame - pair[0].last_frame - 1).max().unwrap_or(0);
+				let (error_sum, error_count) = ranges.iter().fold((0.0, 0), |(sum, count), r| (sum + r.reprojection_error_sum, count + r.reprojection_error_count));
+				let error = if error_count > 0 { format!("{:.3}", error_sum / error_count as f64) } else { "-".to_string() };
+				let _ = write!(out, "<tr><td>{marker}</td><td><svg width=\"{TIMELINE_WIDTH}\" height=\"14\"><rect class=\"track\" width=\"{TIMELINE_WIDTH}\" height=\"14\"/>");
+				for range in ranges {
+					let error = range.mean_reprojection_error().map_or(String::new(), |e| format!(", {e:.3} px"));
+					let _ = write!(out, "<rect class=\"seen\" x=\"{:.1}\" width=\"{:.1}\" height=\"14\"><title>frames {} to {}{error}</title></rect>",
+						x(range.first_frame), (x(range.last_frame + 1) - x(range.first_frame)).max(1.0), range.first_frame, range.last_frame);
+				}
+				let list = ranges.iter().map(|r| format!("{}-{}", r.first_frame, r.last_frame)).collect::<Vec<_>>().join(", ");
+				let _ = writeln!(out, "</svg></td><td>{seen} ({:.0}%)</td><td>{}</td><td>{longest_gap}</td><td>{error}</td><td class=\"ranges\">{list}</td></tr>",
+					seen as f64 / span * 100.0, ranges.len() - 1);
+			}
+			out.push_str("</table>\n");
+		}
+		out.push_str("</body></html>\n");
+		out
+	}
+}
+
+fn csv_field(s: &str) -> String {
+	if s.contains([',', '"', '\n']) { format!("\"{}\"", s.replace('"', "\"\"")) } else { s.to_string() }
+}
+
+fn html_escape(s: &str) -> String {
+	s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+impl Sink for ReportSink {
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		self.push(record);
+		Ok(())
+	}
+
+	fn finish(&mut self) -> io::Result<()> {
+		let csv = self.path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+		std::fs::write(&self.path, if csv { self.csv() } else { self.html() })
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::record::MarkerRecord;
+
+	#[test]
+	fn test_visibility_ranges() {
+		let mut report = ReportSink::new(PathBuf::from("report.csv"), 50.0);
+		for frame_id in 0..10 {
+			let markers = if (3..6).contains(&frame_id) { vec![] } else { vec![MarkerRecord { marker_id: 2, ..Default::default() }] };
+			report.push(&FrameRecord { frame_id, timestamp: frame_id as f64 / 10.0, markers, ..Default::default() });
+		}
+		let ranges = &report.markers[&(None, 2, 0)];
+		assert_eq!(ranges.iter().map(|r| (r.first_frame, r.last_frame)).collect::<Vec<_>>(), vec![(0, 2), (6, 9)]);
+		let csv = report.csv();
+		let lines: Vec<&str> = csv.lines().collect();
+		assert_eq!(lines[1..], [",2,0,visible,0,2,3,0,0.2,", ",2,0,gap,3,5,3,0.2,0.6,", ",2,0,visible,6,9,4,0.6,0.9,"]);
+		assert!(report.html().contains("<td>2</td>"));
+	}
+
+	#[test]
+	fn test_instances_get_their_own_timelines() {
+		let mut report = ReportSink::new(PathBuf::from("report.csv"), 50.0);
+		let copy = |instance| MarkerRecord { marker_id: 2, instance: Some(instance), ..Default::default() };
+		for frame_id in 0..4 {
+			// The second copy is covered for frame 1, which is no gap in the first.
+			let markers = if frame_id == 1 { vec![copy(0)] } else { vec![copy(0), copy(1)] };
+			report.push(&FrameRecord { frame_id, markers, ..Default::default() });
+		}
+		assert_eq!(report.markers[&(None, 2, 0)].len(), 1);
+		assert_eq!(report.markers[&(None, 2, 1)].iter().map(|r| (r.first_frame, r.last_frame)).collect::<Vec<_>>(), vec![(0, 0), (2, 3)]);
+		assert!(report.html().contains("<td>2.1</td>"));
+	}
+}
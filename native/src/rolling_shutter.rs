@@ -0,0 +1,90 @@
+// Rolling shutter compensation, for --rolling-shutter-ms. A CMOS sensor reads its rows out one after another, so in a
+// fast pan the bottom of a marker is seen later than the top and the square comes out sheared, which the pose solver
+// reads as a tilt.
+//
+// We model row r of the stored frame as captured at (r / height - 0.5) * readout after the frame's timestamp, so the
+// middle row is on time. Each corner moves at the speed its marker's corner moved since the previous frame, and we
+// move it back to where it would have been at the timestamp before the pose is solved. That's a first order fix: it
+// assumes the motion is steady over one frame, and it does nothing for a marker's first frame in view.
+
+use std::collections::BTreeMap;
+
+pub struct RollingShutter {
+	readout: f32,
+	/// The upright frame's size, and how many clockwise quarter turns it is from the stored one.
+	width: f32,
+	height: f32,
+	quarter_turns: u32,
+	timestamp: f64,
+	previous: BTreeMap<usize, (f64, [(f32, f32); 4])>,
+	current: BTreeMap<usize, (f64, [(f32, f32); 4])>,
+}
+
+impl RollingShutter {
+	pub fn new(readout_seconds: f32, width: u32, height: u32, quarter_turns: u32) -> Self {
+		RollingShutter {
+			readout: readout_seconds,
+			width: width as f32,
+			height: height as f32,
+			quarter_turns: quarter_turns % 4,
+			timestamp: 0.0,
+			previous: BTreeMap::new(),
+			current: BTreeMap::new(),
+		}
+	}
+
+	/// How far through the readout a point in the upright frame was captured, from 0 (first row) to 1 (last).
+	fn readout_fraction(&self, (x, y): (f32, f32)) -> f32 {
+		// The stored frame's first row ends up on the right after a clockwise quarter turn, and so on.
+		match self.quarter_turns {
+			0 => y / self.height,
+			1 => 1.0 - x / self.width,
+			2 => 1.0 - y / self.height,
+			_ => x / self.width,
+		}
+	}
+
+	/// Start a new frame. Corners seen in the last one become the reference for this one's motion.
+	pub fn begin_frame(&mut self, timestamp: f64) {
+		self.previous = std::mem::take(&mut self.current);
+		self.timestamp = timestamp;
+	}
+
+	/// Where a marker's corners would have been if the whole frame were captured at its timestamp.
+	pub fn correct(&mut self, marker_id: usize, corners: [(f32, f32); 4]) -> [(f32, f32); 4] {
+		self.current.insert(marker_id, (self.timestamp, corners));
+		let Some((before, previous)) = self.previous.get(&marker_id) else {
+			return corners;
+		};
+		let dt = (self.timestamp - before) as f32;
+		if dt <= 0.0 {
+			return corners;
+		}
+		std::array::from_fn(|i| {
+			let (x, y) = corners[i];
+			let (vx, vy) = ((x - previous[i].0) / dt, (y - previous[i].1) / dt);
+			let delay = (self.readout_fraction((x, y)) - 0.5) * self.readout;
+			(x - vx * delay, y - vy * delay)
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_undoes_shear() {
+		// A 100 row frame read out over 10ms, panning right at 1000 px/s: a row at the bottom is seen 10ms after the top,
+		// so it's displaced 10 pixels further right.
+		let mut shutter = RollingShutter::new(0.01, 100, 100, 0);
+		let square = |shift: f32| [(40.0 + shift, 0.0), (60.0 + shift, 0.0), (60.0 + shift + 10.0, 100.0), (40.0 + shift + 10.0, 100.0)];
+		shutter.begin_frame(0.0);
+		shutter.correct(1, square(0.0));
+		shutter.begin_frame(0.1);
+		let corrected = shutter.correct(1, square(100.0));
+		// Top row was read 5ms early and the bottom 5ms late, so both land 5 pixels right of the top.
+		assert!((corrected[0].0 - 145.0).abs() < 1e-3, "{corrected:?}");
+		assert!((corrected[3].0 - 145.0).abs() < 1e-3, "{corrected:?}");
+	}
+}
@@ -0,0 +1,348 @@
+// A JSON Schema for what we write, printed by --emit-schema, so consumers can validate a run or generate a parser
+// instead of reading record.rs. Every line of the output (JSON lines, or a MessagePack map read back) is either the
+// header or a frame record, told apart by the header's "type".
+//
+// Keep this in step with the to_value functions in record.rs and confidence.rs. The test below walks a fully filled
+// in record to catch a field that was added there and forgotten here.
+
+use crate::record::{NormalizedCoords, RotationFormat};
+use crate::transform::{Convention, Units};
+use crate::value::Value;
+use clap::ValueEnum;
+
+fn entry(key: &str, value: Value) -> (String, Value) {
+	(key.to_string(), value)
+}
+
+fn typed(name: &str, description: &str) -> Value {
+	Value::Map(vec![entry("type", Value::Str(name.to_string())), entry("description", Value::Str(description.to_string()))])
+}
+
+fn numbers(count: usize, description: &str) -> Value {
+	Value::Map(vec![
+		entry("type", Value::Str("array".to_string())),
+		entry("description", Value::Str(description.to_string())),
+		entry("items", Value::Map(vec![entry("type", Value::Str("number".to_string()))])),
+		entry("minItems", Value::Int(count as i64)),
+		entry("maxItems", Value::Int(count as i64)),
+	])
+}
+
+fn reference(name: &str) -> Value {
+	Value::Map(vec![entry("$ref", Value::Str(format!("#/$defs/{name}")))])
+}
+
+fn strings(names: &[&str]) -> Value {
+	Value::Array(names.iter().map(|name| Value::Str(name.to_string())).collect())
+}
+
+/// A closed object with the given properties, of which `required` must be present.
+fn object(description: &str, properties: Vec<(String, Value)>, required: &[&str]) -> Value {
+	Value::Map(vec![
+		entry("type", Value::Str("object".to_string())),
+		entry("description", Value::Str(description.to_string())),
+		entry("properties", Value::Map(properties)),
+		entry("required", strings(required)),
+		entry("additionalProperties", Value::Bool(false)),
+	])
+}
+
+fn header() -> Value {
+	object("The first record, describing how to read the rest.", vec![
+		entry("type", Value::Map(vec![entry("const", Value::Str("header".to_string()))])),
+		entry("version", typed("string", "The version of the tracker that wrote the file.")),
+		entry("convention", Value::Map(vec![
+			entry("enum", Value::Array(Convention::value_variants().iter().map(|c| Value::Str(c.name().to_string())).collect())),
+			entry("description", Value::Str("Which way the camera's axes point in every 3D quantity.".to_string())),
+		])),
+		entry("units", Value::Map(vec![
+			entry("enum", Value::Array(Units::value_variants().iter().map(|u| Value::Str(u.name().to_string())).collect())),
+			entry("description", Value::Str("The length unit, before scale.".to_string())),
+		])),
+		entry("scale", typed("number", "An extra factor already applied to every length.")),
+		entry("rotation_format", Value::Map(vec![
+			entry("enum", Value::Array(RotationFormat::value_variants().iter().map(|f| Value::Str(f.key().to_string())).collect())),
+			entry("description", Value::Str("Which key holds each pose's rotation.".to_string())),
+		])),
+		entry("anchor_marker", typed("integer", "If set, every 3D quantity is in this marker's frame rather than the camera's.")),
+		entry("floor_markers", Value::Map(vec![
+			entry("type", Value::Str("array".to_string())),
+			entry("description", Value::Str("If set, every 3D quantity is in a Z-up frame with these markers' floor at Z = 0.".to_string())),
+			entry("items", Value::Map(vec![entry("type", Value::Str("integer".to_string()))])),
+		])),
+		entry("normalized_coords", Value::Map(vec![
+			entry("enum", Value::Array(NormalizedCoords::value_variants().iter().map(|c| Value::Str(c.name().to_string())).collect())),
+			entry("description", Value::Str("With --normalized-coords: unit is 0 to 1 from the top left, ndc is -1 to 1 from the center with y up.".to_string())),
+		])),
+		entry("target_fps", typed("number", "With --target-fps, the rate frames were resampled to. Frame n is at n / target_fps seconds.")),
+		entry("refined", typed("object", "Set by the refine subcommand, with the settings it cleaned up the track with.")),
+		entry("distortion", Value::Map(vec![
+			entry("type", Value::Str("array".to_string())),
+			entry("description", Value::Str("With --self-calibrate, each camera's estimated lens. Corners and poses are already corrected for it.".to_string())),
+			entry("items", reference("distortion")),
+		])),
+		entry("dictionary", object("The markers looked for, with AUTO already resolved.", vec![
+			entry("aruco", typed("string", "The ArUco-style dictionary, if any.")),
+			entry("qr", typed("boolean", "Whether QR codes were looked for.")),
+		], &["qr"])),
+		entry("marker_size_mm", typed("number", "The markers' edge length in mm.")),
+		entry("qr_size_mm", typed("number", "QR codes' edge length in mm, if it's different.")),
+		entry("diamond_square_mm", typed("number", "With --diamond-square-mm, the ChArUco diamonds' square size in mm.")),
+		entry("detector", object("The settings that change what the detector finds.", vec![
+			entry("backend", typed("string", "Which detector found the ArUco-style markers.")),
+			entry("max_hamming", typed("integer", "Detections correcting more bits than this were dropped.")),
+			entry("preprocess", typed("string", "The --preprocess stages, in order.")),
+			entry("crop", numbers(4, "The region searched, as x, y, width, height in upright pixels.")),
+			entry("mask", typed("string", "The --mask image, or sequence pattern.")),
+			entry("partial_markers", typed("boolean", "Whether partly hidden markers were written too.")),
+			entry("rolling_shutter_ms", typed("number", "The readout time corners were corrected for.")),
+		], &["backend", "partial_markers"])),
+		entry("cameras", Value::Map(vec![
+			entry("type", Value::Str("array".to_string())),
+			entry("description", Value::Str("Every video tracked, main camera first.".to_string())),
+			entry("items", reference("camera")),
+		])),
+	], &["type", "version", "convention", "units", "scale", "rotation_format"])
+}
+
+fn camera() -> Value {
+	object("A video, and the lens its first tracked frame was solved with. Frames carry their own intrinsics when they change.", vec![
+		entry("camera_id", typed("string", "Which camera, if there's more than one.")),
+		entry("file", typed("string", "The video's path, as given.")),
+		entry("input_fingerprint", typed("string", "64-bit FNV-1a over the file's length and its first and last MiB, in hex. Tells whether a track came from the file in hand, but an edit to the middle of the file goes unnoticed.")),
+		entry("width", typed("integer", "The upright frame's width in pixels.")),
+		entry("height", typed("integer", "The upright frame's height in pixels.")),
+		entry("fx", typed("number", "Horizontal focal length.")),
+		entry("fy", typed("number", "Vertical focal length.")),
+		entry("cx", typed("number", "The principal point's x.")),
+		entry("cy", typed("number", "The principal point's y.")),
+		entry("intrinsics_source", Value::Map(vec![
+			entry("enum", strings(&["lens", "refined", "intrinsics_track"])),
+			entry("description", Value::Str("Where the lens came from: the lens settings, --refine-focal, or --intrinsics-track.".to_string())),
+		])),
+	], &["file"])
+}
+
+fn intrinsics() -> Value {
+	object("The pinhole camera this frame's poses were solved with, in full-frame pixels.", vec![
+		entry("fx", typed("number", "Horizontal focal length.")),
+		entry("fy", typed("number", "Vertical focal length.")),
+		entry("cx", typed("number", "The principal point's x.")),
+		entry("cy", typed("number", "The principal point's y.")),
+	], &["fx", "fy", "cx", "cy"])
+}
+
+fn distortion() -> Value {
+	object("Radial distortion: a point at normalized radius r from (cx, cy) was imaged at r * (1 + k1 r^2 + k2 r^4).", vec![
+		entry("camera_id", typed("string", "Which camera, if there's more than one.")),
+		entry("k1", typed("number", "Second order coefficient. Negative is barrel distortion.")),
+		entry("k2", typed("number", "Fourth order coefficient.")),
+		entry("cx", typed("number", "The principal point's x, in pixels.")),
+		entry("cy", typed("number", "The principal point's y, in pixels.")),
+	], &["k1", "k2", "cx", "cy"])
+}
+
+/// Exactly one of the rotation keys is present, set by the header's rotation_format.
+fn rotation_keys() -> Value {
+	Value::Array(RotationFormat::value_variants().iter().map(|f| Value::Map(vec![entry("required", strings(&[f.key()]))])).collect())
+}
+
+fn pose() -> Value {
+	let properties = vec![
+		entry("translation", numbers(3, "The marker's center in camera space.")),
+		entry("rotation", numbers(9, "Marker to camera rotation, a row-major 3x3 matrix.")),
+		entry("quaternion", numbers(4, "Marker to camera rotation as [w, x, y, z].")),
+		entry("euler_xyz", numbers(3, "Marker to camera rotation as radians about X, then Y, then Z.")),
+		entry("axis_angle", numbers(3, "Marker to camera rotation as the axis scaled by the angle in radians.")),
+		entry("error", typed("number", "The pose solver's residual.")),
+		entry("reprojection_error", typed("number", "RMS distance in pixels between the detected and reprojected corners.")),
+	];
+	let mut out = object("One candidate pose for a marker.", properties, &["translation", "error"]);
+	if let Value::Map(fields) = &mut out {
+		fields.push(entry("oneOf", rotation_keys()));
+	}
+	out
+}
+
+fn camera_pose() -> Value {
+	let mut out = object("Where the camera was in the --marker-map's world: x_world = rotation * x_camera + position.", vec![
+		entry("position", numbers(3, "The camera's position in the world.")),
+		entry("rotation", numbers(9, "Camera to world rotation, a row-major 3x3 matrix.")),
+		entry("quaternion", numbers(4, "Camera to world rotation as [w, x, y, z].")),
+		entry("euler_xyz", numbers(3, "Camera to world rotation as radians about X, then Y, then Z.")),
+		entry("axis_angle", numbers(3, "Camera to world rotation as the axis scaled by the angle in radians.")),
+		entry("marker_count", typed("integer", "How many mapped markers the pose was solved from.")),
+		entry("reprojection_error", typed("number", "RMS distance in pixels between the mapped corners and where this pose puts them.")),
+	], &["position", "marker_count"]);
+	if let Value::Map(fields) = &mut out {
+		fields.push(entry("oneOf", rotation_keys()));
+	}
+	out
+}
+
+fn confidence() -> Value {
+	object("How much to trust the detection, each term from 0 to 1.", vec![
+		entry("score", typed("number", "The geometric mean of the other terms.")),
+		entry("border_contrast", typed("number", "How much darker the border is than the quiet zone around it.")),
+		entry("corner_sharpness", typed("number", "How crisp the outer edge is near each corner.")),
+		entry("decode_margin", typed("number", "How far the least certain bit cell is from the threshold.")),
+	], &["score", "border_contrast", "corner_sharpness"])
+}
+
+fn marker() -> Value {
+	object("One detected marker.", vec![
+		entry("marker_id", typed("integer", "The id within its dictionary. QR codes use a hash of the payload.")),
+		entry("instance", typed("integer", "Which copy of the id, once two have been seen at once. Counted from 0, which is what it was before.")),
+		entry("corners", numbers(8, "x0, y0, ... x3, y3 in pixels: top-left, top-right, bottom-right, bottom-left.")),
+		entry("corners_normalized", numbers(8, "With --normalized-coords, the corners again as fractions of the frame. See the header.")),
+		entry("corners_3d", numbers(12, "The corners triangulated by a stereo rig, x, y, z for each.")),
+		entry("poses", Value::Map(vec![
+			entry("type", Value::Str("array".to_string())),
+			entry("description", Value::Str("Candidate poses, usually both solutions of the planar solver.".to_string())),
+			entry("items", reference("pose")),
+		])),
+		entry("payload", typed("string", "Decoded text, for QR codes.")),
+		entry("diamond_ids", Value::Map(vec![
+			entry("type", Value::Str("array".to_string())),
+			entry("description", Value::Str("For a ChArUco diamond, its four markers' ids: top, left, right, bottom. Corners and pose are the middle square's.".to_string())),
+			entry("items", Value::Map(vec![entry("type", Value::Str("integer".to_string()))])),
+			entry("minItems", Value::Int(4)),
+			entry("maxItems", Value::Int(4)),
+		])),
+		entry("hamming_distance", typed("integer", "Bits that differ from the matched dictionary code.")),
+		entry("confidence", reference("confidence")),
+		entry("velocity", numbers(3, "Camera-space velocity of the best pose, length units per second.")),
+		entry("angular_velocity", numbers(3, "Camera-space angular velocity of the best pose as axis * rad/s.")),
+		entry("partial", Value::Map(vec![
+			entry("const", Value::Bool(true)),
+			entry("description", Value::Str("The marker didn't decode and was found again where it last was, with --partial-markers.".to_string())),
+		])),
+		entry("visible_corners", Value::Map(vec![
+			entry("type", Value::Str("array".to_string())),
+			entry("description", Value::Str("With partial, which corners could be seen. The others are carried over from the last frame.".to_string())),
+			entry("items", Value::Map(vec![entry("type", Value::Str("boolean".to_string()))])),
+			entry("minItems", Value::Int(4)),
+			entry("maxItems", Value::Int(4)),
+		])),
+		entry("interpolated", Value::Map(vec![
+			entry("const", Value::Bool(true)),
+			entry("description", Value::Str("Filled in by the refine subcommand between sightings on either side of a short gap.".to_string())),
+		])),
+		entry("normal", numbers(3, "With --pose-axes, the best pose's Z axis, out of the marker's face, as a unit vector.")),
+		entry("axis_x", numbers(3, "With --pose-axes, the tip of the best pose's X axis, one marker edge from its center.")),
+		entry("axis_y", numbers(3, "With --pose-axes, the tip of its Y axis.")),
+		entry("axis_z", numbers(3, "With --pose-axes, the tip of its Z axis.")),
+	], &["marker_id", "corners", "poses"])
+}
+
+fn frame() -> Value {
+	object("The markers found in one frame.", vec![
+		entry("frame_id", typed("integer", "The frame number, or the sample number when retiming.")),
+		entry("camera_id", typed("string", "Which camera this came from, in multi-camera runs. Triangulated records use 'stereo'.")),
+		entry("timestamp", typed("number", "Presentation time in seconds.")),
+		entry("timecode", Value::Map(vec![
+			entry("type", Value::Str("string".to_string())),
+			entry("description", Value::Str("SMPTE timecode as hh:mm:ss:ff, or hh:mm:ss;ff for drop frame.".to_string())),
+			entry("pattern", Value::Str("^[0-9]{2}:[0-9]{2}:[0-9]{2}[:;][0-9]{2,3}$".to_string())),
+		])),
+		entry("dropped_frames", typed("integer", "How many frames appear to be missing before this one.")),
+		entry("duplicate", typed("boolean", "Set if this frame arrived at the same time as the previous one.")),
+		entry("source_frame", typed("integer", "When retiming, the decoded frame closest to this sample.")),
+		entry("skipped", typed("string", "Set if the frame couldn't be decoded, to why. Nothing was looked for in it.")),
+		entry("intrinsics", reference("intrinsics")),
+		entry("camera_pose", reference("camera_pose")),
+		entry("detections", Value::Map(vec![
+			entry("type", Value::Str("array".to_string())),
+			entry("items", reference("marker")),
+		])),
+	], &["frame_id", "timestamp", "detections"])
+}
+
+pub fn schema() -> Value {
+	Value::Map(vec![
+		entry("$schema", Value::Str("https://json-schema.org/draft/2020-12/schema".to_string())),
+		entry("title", Value::Str(format!("{} output {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")))),
+		entry("version", Value::Str(env!("CARGO_PKG_VERSION").to_string())),
+		entry("oneOf", Value::Array(vec![reference("header"), reference("frame")])),
+		entry("$defs", Value::Map(vec![
+			entry("header", header()),
+			entry("frame", frame()),
+			entry("marker", marker()),
+			entry("pose", pose()),
+			entry("confidence", confidence()),
+			entry("camera_pose", camera_pose()),
+			entry("intrinsics", intrinsics()),
+			entry("distortion", distortion()),
+			entry("camera", camera()),
+		])),
+	])
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::confidence::Confidence;
+	use crate::distortion::Distortion;
+	use crate::record::{CameraPose, FrameRecord, MarkerRecord, PoseRecord};
+
+	fn get<'a>(value: &'a Value, key: &str) -> &'a Value {
+		let Value::Map(fields) = value else {
+			panic!("expected a map holding {key}");
+		};
+		&fields.iter().find(|(k, _)| k == key).unwrap_or_else(|| panic!("no {key}")).1
+	}
+
+	fn assert_documented(value: &Value, definition: &str) {
+		let Value::Map(fields) = value else {
+			panic!("{definition} should be a map");
+		};
+		let schema = schema();
+		let properties = get(get(get(&schema, "$defs"), definition), "properties");
+		for (key, _) in fields {
+			get(properties, key);
+		}
+	}
+
+	#[test]
+	fn test_schema_covers_records() {
+		let marker = MarkerRecord {
+			corners_3d: Some([[0.0; 3]; 4]),
+			poses: vec![PoseRecord { reprojection_error: Some(0.5), ..Default::default() }],
+			payload: Some("prop".to_string()),
+			diamond: Some([1, 2, 3, 4]),
+			hamming_distance: Some(1),
+			confidence: Some(Confidence { decode_margin: Some(0.5), ..Default::default() }),
+			velocity: Some([0.0; 3]),
+			angular_velocity: Some([0.0; 3]),
+			partial: Some([true, false, true, true]),
+			corners_normalized: Some([(0.0, 0.0); 4]),
+			instance: Some(1),
+			axes: Some(Default::default()),
+			..Default::default()
+		};
+		let camera = CameraPose { reprojection_error: Some(0.5), ..Default::default() };
+		let record = FrameRecord {
+			camera_id: Some("left".to_string()),
+			camera_pose: Some(camera),
+			dropped_frames: 1,
+			duplicate: true,
+			source_frame: Some(3),
+			skipped: Some("Couldn't decode it".to_string()),
+			timecode: Some("01:00:00:00".to_string()),
+			intrinsics: Some(Default::default()),
+			markers: vec![marker.clone()],
+			..Default::default()
+		};
+		assert_documented(&record.to_value(), "frame");
+		assert_documented(&marker.to_value(RotationFormat::Matrix), "marker");
+		for format in RotationFormat::value_variants() {
+			assert_documented(&marker.poses[0].to_value(*format), "pose");
+		}
+		assert_documented(&marker.confidence.unwrap().to_value(), "confidence");
+		for format in RotationFormat::value_variants() {
+			assert_documented(&camera.to_value(*format), "camera_pose");
+		}
+		assert_documented(&Distortion::default().to_value(Some("left")), "distortion");
+		assert_eq!(get(&schema(), "version"), &Value::Str(env!("CARGO_PKG_VERSION").to_string()));
+	}
+}
@@ -0,0 +1,288 @@
+// Vector versions of the per-pixel loops ahead of detection: RGB to luma (frame.rs) and the adaptive threshold's
+// running sums and comparisons (quads.rs). The scalar loops here define the results and the vector loops match them
+// bit for bit, so which one runs never changes a track. The choice is made at runtime on x86_64 (AVX2, or SSE4.1 for
+// luma), and aarch64 always has NEON. Each vector loop does what whole vectors it can and returns how far it got.
+
+/// Rec. 709 luma weights in 16-bit fixed point. They sum to 65536, so white stays 255.
+const WEIGHTS: [u32; 3] = [13933, 46871, 4732];
+
+/// Luma of a row of RGB (3 channels) or RGBA (4) pixels, into one byte per pixel of `dst`. Alpha is ignored.
+pub fn luma_row(src: &[u8], channels: usize, dst: &mut [u8]) {
+	let done = vector::luma_row(src, channels, dst);
+	scalar_luma_row(&src[done * channels..], channels, &mut dst[done..]);
+}
+
+fn scalar_luma_row(src: &[u8], channels: usize, dst: &mut [u8]) {
+	for (out, p) in dst.iter_mut().zip(src.chunks_exact(channels)) {
+		*out = ((WEIGHTS[0] * p[0] as u32 + WEIGHTS[1] * p[1] as u32 + WEIGHTS[2] * p[2] as u32 + (1 << 15)) >> 16) as u8;
+	}
+}
+
+/// Add a row of pixels into per-column sums.
+pub fn add_row(sums: &mut [u32], row: &[u8]) {
+	let done = vector::accumulate_row(sums, row, false);
+	scalar_accumulate_row(&mut sums[done..], &row[done..], false);
+}
+
+/// Take a row of pixels back out of per-column sums.
+pub fn subtract_row(sums: &mut [u32], row: &[u8]) {
+	let done = vector::accumulate_row(sums, row, true);
+	scalar_accumulate_row(&mut sums[done..], &row[done..], true);
+}
+
+fn scalar_accumulate_row(sums: &mut [u32], row: &[u8], subtract: bool) {
+	for (sum, v) in sums.iter_mut().zip(row) {
+		if subtract {
+			*sum -= *v as u32;
+		} else {
+			*sum += *v as u32;
+		}
+	}
+}
+
+/// 255 where a pixel is more than `offset` darker than the mean of its window, otherwise 0. The window around
+/// pixel x has `widths[x]` columns and `rows` rows, adding up to `sums[x]`. Sums and areas must stay below 2^31,
+/// since x86 only converts signed integers to floats.
+pub fn threshold_row(pixels: &[u8], sums: &[u32], widths: &[u32], rows: u32, offset: f32, out: &mut [u8]) {
+	let done = vector::threshold_row(pixels, sums, widths, rows, offset, out);
+	scalar_threshold_row(&pixels[done..], &sums[done..], &widths[done..], rows, offset, &mut out[done..]);
+}
+
+fn scalar_threshold_row(pixels: &[u8], sums: &[u32], widths: &[u32], rows: u32, offset: f32, out: &mut [u8]) {
+	for (x, out) in out.iter_mut().enumerate() {
+		let mean = sums[x] as f32 / (widths[x] * rows) as f32;
+		*out = if (pixels[x] as f32) < mean - offset { 255 } else { 0 };
+	}
+}
+
+#[cfg(target_arch = "x86_64")]
+mod vector {
+	use super::WEIGHTS;
+	use std::arch::x86_64::*;
+
+	pub fn luma_row(src: &[u8], channels: usize, dst: &mut [u8]) -> usize {
+		if is_x86_feature_detected!("sse4.1") {
+			// SAFETY: the CPU has SSE4.1.
+			unsafe { luma_row_sse41(src, channels, dst) }
+		} else {
+			0
+		}
+	}
+
+	pub fn accumulate_row(sums: &mut [u32], row: &[u8], subtract: bool) -> usize {
+		if is_x86_feature_detected!("avx2") {
+			// SAFETY: the CPU has AVX2.
+			unsafe { accumulate_row_avx2(sums, row, subtract) }
+		} else {
+			0
+		}
+	}
+
+	pub fn threshold_row(pixels: &[u8], sums: &[u32], widths: &[u32], rows: u32, offset: f32, out: &mut [u8]) -> usize {
+		if is_x86_feature_detected!("avx2") {
+			// SAFETY: the CPU has AVX2.
+			unsafe { threshold_row_avx2(pixels, sums, widths, rows, offset, out) }
+		} else {
+			0
+		}
+	}
+
+	/// Four pixels at a time, each channel shuffled out into its own 32-bit lanes.
+	#[target_feature(enable = "sse4.1")]
+	fn luma_row_sse41(src: &[u8], channels: usize, dst: &mut [u8]) -> usize {
+		let lanes = |c: i8| {
+			let at = |p: i8| p * channels as i8 + c;
+			_mm_setr_epi8(at(0), -1, -1, -1, at(1), -1, -1, -1, at(2), -1, -1, -1, at(3), -1, -1, -1)
+		};
+		let (r, g, b) = (lanes(0), lanes(1), lanes(2));
+		let [wr, wg, wb] = WEIGHTS.map(|w| _mm_set1_epi32(w as i32));
+		let round = _mm_set1_epi32(1 << 15);
+		let mut i = 0;
+		// A load is 16 bytes, past the four pixels for RGB, so stop while a whole load still fits.
+		while i + 4 <= dst.len() && i * channels + 16 <= src.len() {
+			// SAFETY: the loop condition keeps all 16 bytes inside `src`.
+			let v = unsafe { _mm_loadu_si128(src.as_ptr().add(i * channels) as *const __m128i) };
+			let weighted = _mm_add_epi32(
+				_mm_add_epi32(_mm_mullo_epi32(_mm_shuffle_epi8(v, r), wr), _mm_mullo_epi32(_mm_shuffle_epi8(v, g), wg)),
+				_mm_add_epi32(_mm_mullo_epi32(_mm_shuffle_epi8(v, b), wb), round),
+			);
+			let luma = _mm_srli_epi32::<16>(weighted);
+			let bytes = _mm_packus_epi16(_mm_packus_epi32(luma, luma), _mm_setzero_si128());
+			dst[i..i + 4].copy_from_slice(&_mm_cvtsi128_si32(bytes).to_le_bytes());
+			i += 4;
+		}
+		i
+	}
+
+	#[target_feature(enable = "avx2")]
+	fn accumulate_row_avx2(sums: &mut [u32], row: &[u8], subtract: bool) -> usize {
+		let n = sums.len().min(row.len());
+		let mut i = 0;
+		while i + 8 <= n {
+			// SAFETY: 8 bytes of `row` and 8 sums from i are in bounds.
+			unsafe {
+				let v = _mm256_cvtepu8_epi32(_mm_loadl_epi64(row.as_ptr().add(i) as *const __m128i));
+				let at = sums.as_mut_ptr().add(i) as *mut __m256i;
+				let s = _mm256_loadu_si256(at);
+				_mm256_storeu_si256(at, if subtract { _mm256_sub_epi32(s, v) } else { _mm256_add_epi32(s, v) });
+			}
+			i += 8;
+		}
+		i
+	}
+
+	#[target_feature(enable = "avx2")]
+	fn threshold_row_avx2(pixels: &[u8], sums: &[u32], widths: &[u32], rows: u32, offset: f32, out: &mut [u8]) -> usize {
+		let (rows, offset) = (_mm256_set1_epi32(rows as i32), _mm256_set1_ps(offset));
+		let mut i = 0;
+		while i + 8 <= out.len() {
+			// SAFETY: every slice holds a value per pixel of the row, and 8 from i are in bounds.
+			let (p, sum, width) = unsafe {
+				(
+					_mm256_cvtepu8_epi32(_mm_loadl_epi64(pixels.as_ptr().add(i) as *const __m128i)),
+					_mm256_loadu_si256(sums.as_ptr().add(i) as *const __m256i),
+					_mm256_loadu_si256(widths.as_ptr().add(i) as *const __m256i),
+				)
+			};
+			let mean = _mm256_div_ps(_mm256_cvtepi32_ps(sum), _mm256_cvtepi32_ps(_mm256_mullo_epi32(width, rows)));
+			let dark = _mm256_movemask_ps(_mm256_cmp_ps::<_CMP_LT_OQ>(_mm256_cvtepi32_ps(p), _mm256_sub_ps(mean, offset)));
+			for (k, out) in out[i..i + 8].iter_mut().enumerate() {
+				*out = if dark & (1 << k) != 0 { 255 } else { 0 };
+			}
+			i += 8;
+		}
+		i
+	}
+}
+
+#[cfg(target_arch = "aarch64")]
+mod vector {
+	use super::WEIGHTS;
+	use std::arch::aarch64::*;
+
+	/// Sixteen pixels at a time, with the channels split by an interleaved load.
+	pub fn luma_row(src: &[u8], channels: usize, dst: &mut [u8]) -> usize {
+		let weigh = |r: uint8x8_t, g: uint8x8_t, b: uint8x8_t| {
+			let (r, g, b) = (vmovl_u8(r), vmovl_u8(g), vmovl_u8(b));
+			let half = |r: uint16x4_t, g: uint16x4_t, b: uint16x4_t| {
+				let sum = vmlal_n_u16(vmlal_n_u16(vmull_n_u16(r, WEIGHTS[0] as u16), g, WEIGHTS[1] as u16), b, WEIGHTS[2] as u16);
+				vshrn_n_u32::<16>(vaddq_u32(sum, vdupq_n_u32(1 << 15)))
+			};
+			let low = half(vget_low_u16(r), vget_low_u16(g), vget_low_u16(b));
+			let high = half(vget_high_u16(r), vget_high_u16(g), vget_high_u16(b));
+			vmovn_u16(vcombine_u16(low, high))
+		};
+		let mut i = 0;
+		while i + 16 <= dst.len() && (i + 16) * channels <= src.len() {
+			// SAFETY: the loop condition keeps the 16 pixels inside `src` and `dst`.
+			unsafe {
+				let (r, g, b) = match channels {
+					3 => {
+						let v = vld3q_u8(src.as_ptr().add(i * 3));
+						(v.0, v.1, v.2)
+					},
+					_ => {
+						let v = vld4q_u8(src.as_ptr().add(i * 4));
+						(v.0, v.1, v.2)
+					},
+				};
+				let low = weigh(vget_low_u8(r), vget_low_u8(g), vget_low_u8(b));
+				let high = weigh(vget_high_u8(r), vget_high_u8(g), vget_high_u8(b));
+				vst1q_u8(dst.as_mut_ptr().add(i), vcombine_u8(low, high));
+			}
+			i += 16;
+		}
+		i
+	}
+
+	pub fn accumulate_row(sums: &mut [u32], row: &[u8], subtract: bool) -> usize {
+		let n = sums.len().min(row.len());
+		let mut i = 0;
+		while i + 8 <= n {
+			// SAFETY: 8 bytes of `row` and 8 sums from i are in bounds.
+			unsafe {
+				let v = vmovl_u8(vld1_u8(row.as_ptr().add(i)));
+				let at = sums.as_mut_ptr().add(i);
+				let (low, high) = (vld1q_u32(at), vld1q_u32(at.add(4)));
+				let (low, high) = if subtract {
+					(vsubw_u16(low, vget_low_u16(v)), vsubw_u16(high, vget_high_u16(v)))
+				} else {
+					(vaddw_u16(low, vget_low_u16(v)), vaddw_u16(high, vget_high_u16(v)))
+				};
+				vst1q_u32(at, low);
+				vst1q_u32(at.add(4), high);
+			}
+			i += 8;
+		}
+		i
+	}
+
+	pub fn threshold_row(pixels: &[u8], sums: &[u32], widths: &[u32], rows: u32, offset: f32, out: &mut [u8]) -> usize {
+		let offset = vdupq_n_f32(offset);
+		let dark = |p: uint16x4_t, sum: uint32x4_t, width: uint32x4_t| {
+			let mean = vdivq_f32(vcvtq_f32_u32(sum), vcvtq_f32_u32(vmulq_n_u32(width, rows)));
+			vmovn_u32(vcltq_f32(vcvtq_f32_u32(vmovl_u16(p)), vsubq_f32(mean, offset)))
+		};
+		let mut i = 0;
+		while i + 8 <= out.len() {
+			// SAFETY: every slice holds a value per pixel of the row, and 8 from i are in bounds.
+			unsafe {
+				let p = vmovl_u8(vld1_u8(pixels.as_ptr().add(i)));
+				let low = dark(vget_low_u16(p), vld1q_u32(sums.as_ptr().add(i)), vld1q_u32(widths.as_ptr().add(i)));
+				let high = dark(vget_high_u16(p), vld1q_u32(sums.as_ptr().add(i + 4)), vld1q_u32(widths.as_ptr().add(i + 4)));
+				// The comparison's all-ones lanes narrow to 255.
+				vst1_u8(out.as_mut_ptr().add(i), vmovn_u16(vcombine_u16(low, high)));
+			}
+			i += 8;
+		}
+		i
+	}
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod vector {
+	pub fn luma_row(_: &[u8], _: usize, _: &mut [u8]) -> usize {
+		0
+	}
+
+	pub fn accumulate_row(_: &mut [u32], _: &[u8], _: bool) -> usize {
+		0
+	}
+
+	pub fn threshold_row(_: &[u8], _: &[u32], _: &[u32], _: u32, _: f32, _: &mut [u8]) -> usize {
+		0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_vector_matches_scalar() {
+		// Lengths that leave a ragged tail after whole vectors.
+		let n = 37;
+		let bytes: Vec<u8> = (0..n * 4).map(|i| (i * 97 % 256) as u8).collect();
+		for channels in [3, 4] {
+			let (mut fast, mut slow) = (vec![0; n], vec![0; n]);
+			luma_row(&bytes[..n * channels], channels, &mut fast);
+			scalar_luma_row(&bytes[..n * channels], channels, &mut slow);
+			assert_eq!(fast, slow);
+		}
+
+		let (mut fast, mut slow) = (vec![1000u32; n], vec![1000u32; n]);
+		add_row(&mut fast, &bytes[..n]);
+		subtract_row(&mut fast, &bytes[n..2 * n]);
+		scalar_accumulate_row(&mut slow, &bytes[..n], false);
+		scalar_accumulate_row(&mut slow, &bytes[n..2 * n], true);
+		assert_eq!(fast, slow);
+
+		let widths: Vec<u32> = (0..n as u32).map(|x| 3 + x % 5).collect();
+		let sums: Vec<u32> = widths.iter().zip(&bytes).map(|(w, b)| w * 3 * (*b as u32 / 2 + 60)).collect();
+		let (mut fast, mut slow) = (vec![7; n], vec![7; n]);
+		threshold_row(&bytes[..n], &sums, &widths, 3, 7.0, &mut fast);
+		scalar_threshold_row(&bytes[..n], &sums, &widths, 3, 7.0, &mut slow);
+		assert_eq!(fast, slow);
+		assert!(fast.contains(&255) && fast.contains(&0));
+	}
+}
@@ -0,0 +1,223 @@
+// A summary of how a run went, to judge track quality without opening the output.
+
+use crate::output::Sink;
+use crate::record::FrameRecord;
+use crate::value::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Where the time went for one decoded frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StageTimings {
+	/// Demuxing, decoding, and deinterlacing, measured as the time since the previous frame was done.
+	pub decode: Duration,
+	/// Pixel format conversion, tone-mapping, rotation, and cropping.
+	pub convert: Duration,
+	pub detect: Duration,
+	pub pose: Duration,
+}
+
+impl StageTimings {
+	pub fn add(&mut self, other: &StageTimings) {
+		self.decode += other.decode;
+		self.convert += other.convert;
+		self.detect += other.detect;
+		self.pose += other.pose;
+	}
+}
+
+#[derive(Clone, Debug, Default)]
+struct MarkerStats {
+	detections: usize,
+	first_frame: usize,
+	last_frame: usize,
+	/// The most frames in a row the marker went missing between two sightings.
+	longest_gap: usize,
+	reprojection_error_sum: f64,
+	reprojection_error_count: usize,
+	pose_error_sum: f64,
+	pose_error_count: usize,
+	/// Whether the id has been seen more than once at a time, so its sightings carry an instance.
+	instanced: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RunStats {
+	marker_size_mm: f32,
+	frames: usize,
+	frames_with_detections: usize,
+	/// By camera, marker id, and instance, so two printed copies of an id are counted apart.
+	markers: BTreeMap<(Option<String>, usize, u32), MarkerStats>,
+	timings: StageTimings,
+	timed_frames: u32,
+}
+
+fn mean(sum: f64, count: usize) -> Option<f64> {
+	(count > 0).then(|| sum / count as f64)
+}
+
+impl RunStats {
+	pub fn new(marker_size_mm: f32) -> Self {
+		RunStats { marker_size_mm, ..Default::default() }
+	}
+
+	pub fn push(&mut self, record: &FrameRecord) {
+		// Triangulated records describe frames we've already counted.
+		if record.camera_id.as_deref() == Some("stereo") {
+			return;
+		}
+		self.frames += 1;
+		if !record.markers.is_empty() {
+			self.frames_with_detections += 1;
+		}
+		if let Some(timings) = &record.timings {
+			self.timings.add(timings);
+			self.timed_frames += 1;
+		}
+		for m in &record.markers {
+			let key = (record.camera_id.clone(), m.marker_id, m.instance.unwrap_or_default());
+			let stats = self.markers.entry(key).or_insert_with(|| MarkerStats { first_frame: record.frame_id, ..Default::default() });
+			stats.instanced |= m.instance.is_some();
+			if stats.detections > 0 {
+				stats.longest_gap = stats.longest_gap.max(record.frame_id.saturating_sub(stats.last_frame + 1));
+			}
+			stats.detections += 1;
+			stats.last_frame = record.frame_id;
+			let Some(pose) = m.best_pose() else {
+				continue;
+			};
+			stats.pose_error_sum += pose.error as f64;
+			stats.pose_error_count += 1;
+			if let Some(pinhole) = &record.intrinsics {
+				stats.reprojection_error_sum += pose.reprojection_error.unwrap_or_else(|| pose.reprojection_rms(&m.corners, pinhole, self.marker_size_mm)) as f64;
+				stats.reprojection_error_count += 1;
+			}
+		}
+	}
+
+	fn mean_timings(&self) -> Option<StageTimings> {
+		(self.timed_frames > 0).then(|| StageTimings {
+			decode: self.timings.decode / self.timed_frames,
+			convert: self.timings.convert / self.timed_frames,
+			detect: self.timings.detect / self.timed_frames,
+			pose: self.timings.pose / self.timed_frames,
+		})
+	}
+
+	/// A small table for the terminal.
+	pub fn summary(&self) -> String {
+		let mut out = String::new();
+		let _ = writeln!(out, "Processed {} frames, {} with at least one marker.", self.frames, self.frames_with_detections);
+		if let Some(t) = self.mean_timings() {
+			let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+			let _ = writeln!(out, "Mean time per frame: decode {:.2}ms, convert {:.2}ms, detect {:.2}ms, pose {:.2}ms.", ms(t.decode), ms(t.convert), ms(t.detect), ms(t.pose));
+		}
+		let _ = writeln!(out, "{:>8} {:>8} {:>10} {:>8} {:>8} {:>12} {:>12}", "camera", "marker", "detections", "first", "last", "longest gap", "reproj (px)");
+		for ((camera_id, marker_id, instance), s) in &self.markers {
+			let reprojection = mean(s.reprojection_error_sum, s.reprojection_error_count).map_or("-".to_string(), |e| format!("{e:.3}"));
+			let marker = if s.instanced { format!("{marker_id}.{instance}") } else { marker_id.to_string() };
+			let _ = writeln!(out, "{:>8} {:>8} {:>10} {:>8} {:>8} {:>12} {:>12}", camera_id.as_deref().unwrap_or("-"), marker, s.detections, s.first_frame, s.last_frame, s.longest_gap, reprojection);
+		}
+		out
+	}
+
+	pub fn to_value(&self) -> Value {
+		let optional = |v: Option<f64>| v.map_or(Value::Null, Value::F64);
+		let markers = self.markers.iter().map(|((camera_id, marker_id, instance), s)| {
+			let mut out = vec![];
+			if let Some(camera_id) = camera_id {
+				out.push(("camera_id".to_string(), Value::Str(camera_id.clone())));
+			}
+			out.push(("marker_id".to_string(), Value::Int(*marker_id as i64)));
+			if s.instanced {
+				out.push(("instance".to_string(), Value::Int(*instance as i64)));
+			}
+			out.extend([
+				("detections".to_string(), Value::Int(s.detections as i64)),
+				("first_frame".to_string(), Value::Int(s.first_frame as i64)),
+				("last_frame".to_string(), Value::Int(s.last_frame as i64)),
+				("longest_gap".to_string(), Value::Int(s.longest_gap as i64)),
+				("mean_reprojection_error".to_string(), optional(mean(s.reprojection_error_sum, s.reprojection_error_count))),
+				("mean_pose_error".to_string(), optional(mean(s.pose_error_sum, s.pose_error_count))),
+			]);
+			Value::Map(out)
+		}).collect();
+		let mut out = vec![
+			("frames".to_string(), Value::Int(self.frames as i64)),
+			("frames_with_detections".to_string(), Value::Int(self.frames_with_detections as i64)),
+			("markers".to_string(), Value::Array(markers)),
+		];
+		if let Some(t) = self.mean_timings() {
+			out.push(("mean_stage_seconds".to_string(), Value::Map(vec![
+				("decode".to_string(), Value::F64(t.decode.as_secs_f64())),
+				("convert".to_string(), Value::F64(t.convert.as_secs_f64())),
+				("detect".to_string(), Value::F64(t.detect.as_secs_f64())),
+				("pose".to_string(), Value::F64(t.pose.as_secs_f64())),
+			])));
+		}
+		Value::Map(out)
+	}
+}
+
+/// Prints the summary to stderr and/or writes it as JSON once the run is over.
+pub struct StatsSink {
+	stats: RunStats,
+	print: bool,
+	path: Option<PathBuf>,
+}
+
+impl StatsSink {
+	pub fn new(marker_size_mm: f32, print: bool, path: Option<PathBuf>) -> Self {
+		StatsSink { stats: RunStats::new(marker_size_mm), print, path }
+	}
+}
+
+impl Sink for StatsSink {
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		self.stats.push(record);
+		Ok(())
+	}
+
+	fn finish(&mut self) -> io::Result<()> {
+		if self.print {
+			eprint!("{}", self.stats.summary());
+		}
+		if let Some(path) = &self.path {
+			let mut file = std::fs::File::create(path)?;
+			writeln!(file, "{}", self.stats.to_value().to_json())?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::record::MarkerRecord;
+
+	#[test]
+	fn test_gaps_and_counts() {
+		let mut stats = RunStats::new(50.0);
+		for frame_id in [3, 4, 9, 10, 12] {
+			stats.push(&FrameRecord { frame_id, markers: vec![MarkerRecord { marker_id: 1, ..Default::default() }], ..Default::default() });
+		}
+		stats.push(&FrameRecord { frame_id: 13, ..Default::default() });
+		let s = &stats.markers[&(None, 1, 0)];
+		assert_eq!((s.detections, s.first_frame, s.last_frame, s.longest_gap), (5, 3, 12, 4));
+		assert_eq!((stats.frames, stats.frames_with_detections), (6, 5));
+	}
+
+	#[test]
+	fn test_instances_are_counted_apart() {
+		let mut stats = RunStats::new(50.0);
+		let copy = |instance| MarkerRecord { marker_id: 1, instance: Some(instance), ..Default::default() };
+		stats.push(&FrameRecord { frame_id: 0, markers: vec![copy(0), copy(1)], ..Default::default() });
+		stats.push(&FrameRecord { frame_id: 5, markers: vec![copy(1)], ..Default::default() });
+		assert_eq!((stats.markers[&(None, 1, 0)].detections, stats.markers[&(None, 1, 1)].detections), (1, 2));
+		assert_eq!(stats.markers[&(None, 1, 1)].longest_gap, 4);
+		assert!(stats.summary().contains("1.1"));
+	}
+}
@@ -0,0 +1,153 @@
+// Two-view triangulation of marker corners.
+// A single view gives two candidate poses (the IPPE ambiguity) and a depth that leans entirely on the marker size.
+// With a calibrated pair we can instead intersect rays from both cameras and get the corners directly.
+
+use crate::geometry::{self, Mat3, Pinhole, Vec3};
+use crate::record::{FrameRecord, MarkerRecord, PoseRecord};
+
+/// Pose of the second camera relative to the first, as from OpenCV's stereoCalibrate: x_right = rotation * x_left + translation.
+/// Translation is in mm, like the marker size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StereoRig {
+	pub rotation: Mat3,
+	pub translation: Vec3,
+}
+
+/// Either a Rodrigues vector and translation (6 values) or a row-major rotation matrix and translation (12 values).
+pub fn parse_extrinsics(s: &str) -> Result<StereoRig, String> {
+	let values = s.split(',').map(|v| v.trim().parse::<f32>()).collect::<Result<Vec<f32>, _>>().map_err(|e| format!("Extrinsics must be numbers: {e}"))?;
+	match values[..] {
+		[rx, ry, rz, tx, ty, tz] => Ok(StereoRig {
+			rotation: geometry::rodrigues(&[rx, ry, rz]),
+			translation: [tx, ty, tz],
+		}),
+		[r11, r12, r13, r21, r22, r23, r31, r32, r33, tx, ty, tz] => Ok(StereoRig {
+			rotation: [[r11, r12, r13], [r21, r22, r23], [r31, r32, r33]],
+			translation: [tx, ty, tz],
+		}),
+		_ => Err("Expected 6 (rvec, tvec) or 12 (rotation matrix, tvec) comma-separated values.".to_string()),
+	}
+}
+
+impl StereoRig {
+	/// Closest point between the rays through a pixel in each camera, in the left camera's frame.
+	pub fn triangulate(&self, left: &Pinhole, left_px: (f32, f32), right: &Pinhole, right_px: (f32, f32)) -> Vec3 {
+		let to_left = geometry::transpose(&self.rotation);
+		let right_center = geometry::scale(&geometry::mat_mul_vec(&to_left, &self.translation), -1.0);
+		let d1 = left.ray(left_px);
+		let d2 = geometry::mat_mul_vec(&to_left, &right.ray(right_px));
+
+		// Midpoint method: find s, t minimizing |s*d1 - (right_center + t*d2)|.
+		let w0 = geometry::scale(&right_center, -1.0);
+		let a = geometry::dot(&d1, &d1);
+		let b = geometry::dot(&d1, &d2);
+		let c = geometry::dot(&d2, &d2);
+		let d = geometry::dot(&d1, &w0);
+		let e = geometry::dot(&d2, &w0);
+		let denominator = a * c - b * b;
+		if denominator.abs() < 1e-12 {
+			// Parallel rays. There's no depth information, so this is as good as anything.
+			return right_center;
+		}
+		let s = (b * e - c * d) / denominator;
+		let t = (a * e - b * d) / denominator;
+		let p1 = geometry::scale(&d1, s);
+		let p2 = geometry::add(&right_center, &geometry::scale(&d2, t));
+		geometry::scale(&geometry::add(&p1, &p2), 0.5)
+	}
+
+	/// Build a combined record for every marker visible in both frames. Corners in the output are the left camera's.
+	pub fn triangulate_frames(&self, left: &FrameRecord, right: &FrameRecord, marker_size_mm: f32) -> Option<FrameRecord> {
+		let (left_camera, right_camera) = (left.intrinsics?, right.intrinsics?);
+		let markers = left.markers.iter().filter_map(|l| {
+			let r = right.markers.iter().find(|r| r.marker_id == l.marker_id)?;
+			let mut points = [[0.0; 3]; 4];
+			for (idx, p) in points.iter_mut().enumerate() {
+				*p = self.triangulate(&left_camera, l.corners[idx], &right_camera, r.corners[idx]);
+			}
+			let mut pose = pose_from_corners(&points, marker_size_mm);
+			// Points are in the left camera's frame, so that's the view we can check the fit against.
+			pose.reprojection_error = Some(pose.reprojection_rms(&l.corners, &left_camera, marker_size_mm));
+			Some(MarkerRecord {
+				marker_id: l.marker_id,
+				corners: l.corners,
+				corners_3d: Some(points),
+				poses: vec![pose],
+				payload: l.payload.clone(),
+				diamond: l.diamond,
+				hamming_distance: l.hamming_distance.max(r.hamming_distance),
+				// A triangulated marker is only as trustworthy as the worse of its two views.
+				confidence: [l.confidence, r.confidence].into_iter().flatten().min_by(|a, b| a.score.total_cmp(&b.score)),
+				velocity: None,
+				angular_velocity: None,
+				partial: None,
+				instance: None,
+				corners_normalized: None,
+				edges: vec![],
+				axes: None,
+			})
+		}).collect();
+		Some(FrameRecord {
+			markers,
+			// The left record has already been through the output transforms. This gets placed again from its own markers.
+			camera_pose: None,
+			..left.clone()
+		})
+	}
+}
+
+/// Fit a marker pose to four triangulated corners. The error is the RMS distance (mm) from the fitted model corners.
+pub fn pose_from_corners(points: &[Vec3; 4], marker_size_mm: f32) -> PoseRecord {
+	let center = geometry::scale(&points.iter().fold([0.0; 3], |acc, p| geometry::add(&acc, p)), 0.25);
+	// Average opposite edges for each axis, then square them up.
+	let x = geometry::normalize(&geometry::add(&geometry::sub(&points[1], &points[0]), &geometry::sub(&points[2], &points[3])));
+	let y = geometry::normalize(&geometry::add(&geometry::sub(&points[0], &points[3]), &geometry::sub(&points[1], &points[2])));
+	let z = geometry::normalize(&geometry::cross(&x, &y));
+	let y = geometry::cross(&z, &x);
+	let rotation = geometry::from_columns(&x, &y, &z);
+
+	let model = geometry::marker_corners(marker_size_mm);
+	let squared_error: f32 = model.iter().zip(points.iter()).map(|(m, p)| {
+		let fitted = geometry::add(&center, &geometry::mat_mul_vec(&rotation, m));
+		let delta = geometry::sub(&fitted, p);
+		geometry::dot(&delta, &delta)
+	}).sum();
+
+	PoseRecord {
+		translation: center,
+		rotation,
+		error: (squared_error / 4.0).sqrt(),
+		reprojection_error: None,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_triangulate_recovers_point() {
+		let camera = Pinhole { width: 1000, height: 1000, fx: 800.0, fy: 800.0, cx: 500.0, cy: 500.0 };
+		// Right camera 100mm to the right of the left one, looking the same way.
+		let rig = StereoRig { rotation: geometry::IDENTITY, translation: [-100.0, 0.0, 0.0] };
+		let point = [30.0, -20.0, 1000.0];
+		let right_point = geometry::add(&geometry::mat_mul_vec(&rig.rotation, &point), &rig.translation);
+		let found = rig.triangulate(&camera, camera.project(&point), &camera, camera.project(&right_point));
+		assert!(geometry::length(&geometry::sub(&found, &point)) < 1e-2, "{:?}", found);
+	}
+
+	#[test]
+	fn test_pose_from_corners_is_exact_for_a_square() {
+		let corners = geometry::marker_corners(50.0).map(|c| geometry::add(&c, &[0.0, 0.0, 500.0]));
+		let pose = pose_from_corners(&corners, 50.0);
+		assert!(pose.error < 1e-4);
+		assert!((pose.translation[2] - 500.0).abs() < 1e-4);
+	}
+
+	#[test]
+	fn test_parse_extrinsics() {
+		assert!(parse_extrinsics("0,0,0,-100,0,0").is_ok());
+		assert!(parse_extrinsics("1,0,0,0,1,0,0,0,1,-100,0,0").is_ok());
+		assert!(parse_extrinsics("1,2,3").is_err());
+	}
+}
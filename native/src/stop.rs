@@ -0,0 +1,63 @@
+// When to stop tracking before the end of the video, for --stop-after-detections and --stop-after-gap. For pulling a
+// calibration segment out of the front of a long recording without decoding the rest of it.
+
+use crate::record::FrameRecord;
+
+#[derive(Clone, Debug, Default)]
+pub struct StopCondition {
+	/// Stop once this many markers have been found, counting each marker in each frame.
+	pub after_detections: Option<usize>,
+	/// Stop once no markers have been found for this many seconds. Only counts from the first time any are found, so a
+	/// slow start doesn't end the run.
+	pub after_gap: Option<f64>,
+	detections: usize,
+	last_seen: Option<f64>,
+}
+
+impl StopCondition {
+	pub fn new(after_detections: Option<usize>, after_gap: Option<f64>) -> Self {
+		StopCondition { after_detections, after_gap, ..Default::default() }
+	}
+
+	/// Count the record's markers, and say why to stop if that's enough.
+	pub fn update(&mut self, record: &FrameRecord) -> Option<String> {
+		if !record.markers.is_empty() {
+			self.detections += record.markers.len();
+			self.last_seen = Some(record.timestamp);
+		}
+		if let Some(limit) = self.after_detections && self.detections >= limit {
+			return Some(format!("found {} markers", self.detections));
+		}
+		match (self.after_gap, self.last_seen) {
+			(Some(gap), Some(last_seen)) if record.timestamp - last_seen >= gap => Some(format!("no markers for {:.2} s", record.timestamp - last_seen)),
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::record::MarkerRecord;
+
+	#[test]
+	fn test_stop_conditions() {
+		let frame = |frame_id: usize, markers: usize| FrameRecord {
+			frame_id,
+			timestamp: frame_id as f64 / 10.0,
+			markers: vec![MarkerRecord::default(); markers],
+			..Default::default()
+		};
+		let mut detections = StopCondition::new(Some(5), None);
+		assert!(detections.update(&frame(0, 2)).is_none());
+		assert!(detections.update(&frame(1, 0)).is_none());
+		assert!(detections.update(&frame(2, 3)).is_some());
+
+		let mut gap = StopCondition::new(None, Some(0.5));
+		// Nothing seen yet, so no gap to measure.
+		assert!((0..10).all(|i| gap.update(&frame(i, 0)).is_none()));
+		assert!(gap.update(&frame(10, 1)).is_none());
+		assert!((11..15).all(|i| gap.update(&frame(i, 0)).is_none()));
+		assert!(gap.update(&frame(15, 0)).is_some());
+	}
+}
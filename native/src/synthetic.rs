@@ -0,0 +1,238 @@
+// Rendered frames of markers at known poses, so benchmarks and accuracy tests don't depend on footage.
+//
+// Markers come from the original ARUCO dictionary: 5x5 data bits inside a one cell black border, so the marker size
+// is the length of the border's outside edge. Each is drawn with a one cell white margin (the quiet zone a printed
+// marker gets from its paper) and antialiased by supersampling. Poses take marker space to camera space, the same as
+// the detector's, so a rendered pose is directly the pose the tracker should find.
+//
+// The rest is for the synth subcommand: a moving scene, the camera's faults layered over a render, and the record
+// the tracker would write if it were perfect.
+
+use crate::geometry::{Mat3, Pinhole, Vec3, add, dot, marker_corners, mat_mul, mat_mul_vec, rodrigues, scale, sub, transpose};
+use crate::record::{FrameRecord, MarkerRecord, PoseRecord};
+use image::{GrayImage, Luma};
+
+pub const DICTIONARY: &str = "ARUCO";
+
+/// A marker squarely facing the camera, upright: its +Y is up the image and its +Z points back at the lens.
+pub const FACING: Mat3 = [[1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, -1.0]];
+
+/// Samples per pixel along each axis.
+const SUPERSAMPLE: u32 = 4;
+
+/// The data bits of an ARUCO marker, true for white, row by row from the top. Each row is one of four words,
+/// picked by two bits of the id from the most significant end.
+pub fn aruco_bits(id: usize) -> Option<[[bool; 5]; 5]> {
+	const WORDS: [u8; 4] = [0b10000, 0b10111, 0b01001, 0b01110];
+	(id < 1024).then(|| std::array::from_fn(|row| {
+		let word = WORDS[(id >> (2 * (4 - row))) & 3];
+		std::array::from_fn(|col| (word >> (4 - col)) & 1 == 1)
+	}))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlacedMarker {
+	pub id: usize,
+	pub rotation: Mat3,
+	pub translation: Vec3,
+}
+
+/// How bright the marker is at a point in marker space (mm, +Y up), from 0 to 1, or None off its margin.
+fn shade(bits: &[[bool; 5]; 5], size: f32, (x, y): (f32, f32)) -> Option<f32> {
+	let cell = size / 7.0;
+	let col = ((x + size / 2.0) / cell).floor() as i32;
+	let row = ((size / 2.0 - y) / cell).floor() as i32;
+	match (row, col) {
+		(-1..=7, -1..=7) if row == -1 || row == 7 || col == -1 || col == 7 => Some(1.0),
+		(0 | 6, 0..=6) | (0..=6, 0 | 6) => Some(0.0),
+		(1..=5, 1..=5) => Some(if bits[row as usize - 1][col as usize - 1] { 1.0 } else { 0.0 }),
+		_ => None,
+	}
+}
+
+/// Draw the markers over a plain background. Markers partly behind the camera are left out.
+pub fn render(pinhole: &Pinhole, markers: &[PlacedMarker], marker_size_mm: f32, background: u8) -> GrayImage {
+	let mut img = GrayImage::from_pixel(pinhole.width, pinhole.height, Luma([background]));
+	for marker in markers {
+		let Some(bits) = aruco_bits(marker.id) else {
+			continue;
+		};
+		// The margin's corners bound everything we draw.
+		let half = marker_size_mm / 2.0 * 9.0 / 7.0;
+		let corners = [[-half, half, 0.0], [half, half, 0.0], [half, -half, 0.0], [-half, -half, 0.0]]
+			.map(|p| add(&mat_mul_vec(&marker.rotation, &p), &marker.translation));
+		if corners.iter().any(|p| p[2] <= 0.0) {
+			continue;
+		}
+		let projected = corners.map(|p| pinhole.project(&p));
+		let (min_x, max_x) = projected.iter().fold((f32::MAX, f32::MIN), |(lo, hi), p| (lo.min(p.0), hi.max(p.0)));
+		let (min_y, max_y) = projected.iter().fold((f32::MAX, f32::MIN), |(lo, hi), p| (lo.min(p.1), hi.max(p.1)));
+		let (x0, x1) = (min_x.floor().max(0.0) as u32, (max_x.ceil() as i64).clamp(0, pinhole.width as i64) as u32);
+		let (y0, y1) = (min_y.floor().max(0.0) as u32, (max_y.ceil() as i64).clamp(0, pinhole.height as i64) as u32);
+
+		// Each sample's ray meets the marker's plane where it's as far along the normal as the marker's center.
+		let to_marker = transpose(&marker.rotation);
+		let normal = [marker.rotation[0][2], marker.rotation[1][2], marker.rotation[2][2]];
+		let depth = dot(&normal, &marker.translation);
+		for y in y0..y1 {
+			for x in x0..x1 {
+				let (mut sum, mut hits) = (0.0, 0);
+				for sy in 0..SUPERSAMPLE {
+					for sx in 0..SUPERSAMPLE {
+						let pixel = (x as f32 + (sx as f32 + 0.5) / SUPERSAMPLE as f32, y as f32 + (sy as f32 + 0.5) / SUPERSAMPLE as f32);
+						let ray = pinhole.ray(pixel);
+						let along = dot(&normal, &ray);
+						if along.abs() < 1e-9 {
+							continue;
+						}
+						let local = mat_mul_vec(&to_marker, &sub(&scale(&ray, depth / along), &marker.translation));
+						if let Some(value) = shade(&bits, marker_size_mm, (local[0], local[1])) {
+							sum += value;
+							hits += 1;
+						}
+					}
+				}
+				if hits > 0 {
+					let samples = (SUPERSAMPLE * SUPERSAMPLE) as f32;
+					let under = img.get_pixel(x, y)[0] as f32;
+					let value = (sum * 255.0 + under * (samples - hits as f32)) / samples;
+					img.put_pixel(x, y, Luma([value.round().clamp(0.0, 255.0) as u8]));
+				}
+			}
+		}
+	}
+	img
+}
+
+/// The most markers a scene holds, so their ids stay in the dictionary.
+pub const MAX_SCENE_MARKERS: usize = 10;
+
+/// `count` markers in a grid about 650mm out, each drifting, bobbing in depth, and tilting on its own smooth path. The
+/// seed shifts the phases, so different seeds give different but equally repeatable motion.
+pub fn scene(count: usize, seed: u64, t: f32) -> Vec<PlacedMarker> {
+	let count = count.min(MAX_SCENE_MARKERS);
+	let columns = (count as f32).sqrt().ceil().max(1.0) as usize;
+	let rows = count.div_ceil(columns);
+	(0..count).map(|idx| {
+		let phase = idx as f32 * 1.7 + (seed % 1000) as f32 * 0.61;
+		let x = (idx % columns) as f32 - (columns - 1) as f32 / 2.0;
+		let y = (rows - 1) as f32 / 2.0 - (idx / columns) as f32;
+		let tilt = rodrigues(&[0.4 * (0.8 * t + phase).sin(), 0.4 * (0.6 * t + phase).cos(), 0.3 * (0.4 * t + phase).sin()]);
+		PlacedMarker {
+			id: idx * 100 + 7,
+			rotation: mat_mul(&tilt, &FACING),
+			translation: [120.0 * x + 30.0 * (0.9 * t + phase).sin(), 120.0 * y + 20.0 * (1.3 * t + 2.0 * phase).sin(), 650.0 + 120.0 * (0.5 * t + phase).sin()],
+		}
+	}).collect()
+}
+
+/// The per-pixel mean of renders over the time the shutter was open, for motion blur.
+pub fn average(frames: &[GrayImage]) -> GrayImage {
+	let (width, height) = frames.first().map_or((0, 0), |f| f.dimensions());
+	let mut sums = vec![0u32; width as usize * height as usize];
+	for frame in frames {
+		for (sum, v) in sums.iter_mut().zip(frame.as_raw()) {
+			*sum += *v as u32;
+		}
+	}
+	let n = frames.len().max(1) as u32;
+	GrayImage::from_raw(width, height, sums.iter().map(|sum| ((sum + n / 2) / n) as u8).collect()).expect("One sum per pixel.")
+}
+
+/// Uneven lighting: scale the whole frame by `gain`, falling off by `gradient` (0 to 1) from the left edge to the right.
+pub fn light(img: &mut GrayImage, gain: f32, gradient: f32) {
+	let width = img.width().max(2) as f32 - 1.0;
+	for (x, _, pixel) in img.enumerate_pixels_mut() {
+		let factor = gain * (1.0 - gradient * x as f32 / width);
+		pixel[0] = (pixel[0] as f32 * factor).round().clamp(0.0, 255.0) as u8;
+	}
+}
+
+/// A small seeded generator (splitmix64), so the same seed gives the same footage on every machine.
+pub struct Rng(u64);
+
+impl Rng {
+	pub fn new(seed: u64) -> Self {
+		Rng(seed)
+	}
+
+	fn next_u64(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+		z ^ (z >> 31)
+	}
+
+	/// Uniform in (0, 1].
+	fn unit(&mut self) -> f32 {
+		((self.next_u64() >> 40) as f32 + 1.0) / (1u64 << 24) as f32
+	}
+
+	/// Standard normal, by Box-Muller.
+	pub fn normal(&mut self) -> f32 {
+		(-2.0 * self.unit().ln()).sqrt() * (std::f32::consts::TAU * self.unit()).cos()
+	}
+}
+
+/// Sensor noise: add Gaussian noise with a standard deviation of `sigma` gray levels.
+pub fn add_noise(img: &mut GrayImage, sigma: f32, rng: &mut Rng) {
+	for pixel in img.pixels_mut() {
+		pixel[0] = (pixel[0] as f32 + sigma * rng.normal()).round().clamp(0.0, 255.0) as u8;
+	}
+}
+
+/// What a perfect tracker would write for a frame: every marker entirely in view, with its exact corners and pose.
+pub fn truth_record(frame_id: usize, timestamp: f64, pinhole: &Pinhole, markers: &[PlacedMarker], marker_size_mm: f32) -> FrameRecord {
+	let markers = markers.iter().filter_map(|marker| {
+		let points = marker_corners(marker_size_mm).map(|p| add(&mat_mul_vec(&marker.rotation, &p), &marker.translation));
+		if points.iter().any(|p| p[2] <= 0.0) {
+			return None;
+		}
+		let corners = points.map(|p| pinhole.project(&p));
+		let inside = |(u, v): (f32, f32)| u >= 0.0 && v >= 0.0 && u < pinhole.width as f32 && v < pinhole.height as f32;
+		corners.iter().all(|c| inside(*c)).then(|| MarkerRecord {
+			marker_id: marker.id,
+			corners,
+			poses: vec![PoseRecord { translation: marker.translation, rotation: marker.rotation, error: 0.0, reprojection_error: Some(0.0) }],
+			..Default::default()
+		})
+	}).collect();
+	FrameRecord { frame_id, timestamp, intrinsics: Some(*pinhole), markers, ..Default::default() }
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_renders_marker_upright() {
+		// 70mm at 1000mm with f = 1000 is 70 pixels, so each cell is 10 pixels.
+		let pinhole = Pinhole { width: 200, height: 200, fx: 1000.0, fy: 1000.0, cx: 100.0, cy: 100.0 };
+		let marker = PlacedMarker { id: 0b11_00_01_10_11, rotation: FACING, translation: [0.0, 0.0, 1000.0] };
+		let img = render(&pinhole, &[marker], 70.0, 128);
+		let at = |row: u32, col: u32| img.get_pixel(65 + col * 10 + 5, 65 + row * 10 + 5)[0];
+		// Border, margin, and the background past it.
+		assert_eq!((at(0, 0), at(0, 3), at(6, 6)), (0, 0, 0));
+		assert_eq!(img.get_pixel(60, 100)[0], 255);
+		assert_eq!(img.get_pixel(20, 20)[0], 128);
+		// The top data row is word 3 (01110) and the bottom word 3 again, the second word 0 (10000).
+		let row = |r: u32| (1..=5).map(|c| at(r, c) == 255).collect::<Vec<_>>();
+		assert_eq!(row(1), [false, true, true, true, false]);
+		assert_eq!(row(2), [true, false, false, false, false]);
+		assert_eq!(row(5), [false, true, true, true, false]);
+	}
+
+	#[test]
+	fn test_truth_matches_render() {
+		let pinhole = Pinhole { width: 200, height: 200, fx: 1000.0, fy: 1000.0, cx: 100.0, cy: 100.0 };
+		let facing = PlacedMarker { id: 7, rotation: FACING, translation: [0.0, 0.0, 1000.0] };
+		let beside = PlacedMarker { translation: [150.0, 0.0, 1000.0], ..facing };
+		let record = truth_record(3, 0.1, &pinhole, &[facing, beside], 70.0);
+		// Only the marker in view, with its corners on the rendered border's outside edge, clockwise from top left.
+		assert_eq!(record.markers.len(), 1);
+		assert_eq!(record.markers[0].corners, [(65.0, 65.0), (135.0, 65.0), (135.0, 135.0), (65.0, 135.0)]);
+		let img = render(&pinhole, &[facing], 70.0, 128);
+		assert_eq!((img.get_pixel(66, 66)[0], img.get_pixel(64, 66)[0]), (0, 255));
+	}
+}
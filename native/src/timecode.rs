@@ -0,0 +1,108 @@
+// SMPTE timecode on every frame record, so editorial can conform the tracks against the edit.
+//
+// The start comes from --timecode-start, or else whatever the camera embedded (ffmpeg shows a MOV's tmcd track, or
+// an MXF's or MPEG-TS's start timecode, as a "timecode" tag). Each frame's timecode is then the start plus its time
+// since the start of the stream, counted at the nominal rate, so dropped frames and retiming don't make it drift.
+// Drop frame timecode ("hh:mm:ss;ff") at 29.97 and 59.94 skips frame numbers the way a deck does: the first two (or
+// four) of every minute, except every tenth minute.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimecodeStart {
+	pub hours: u32,
+	pub minutes: u32,
+	pub seconds: u32,
+	pub frames: u32,
+	pub drop_frame: bool,
+}
+
+pub fn parse_timecode(text: &str) -> Result<TimecodeStart, String> {
+	let text = text.trim();
+	let drop_frame = text.contains(';');
+	let fields: Vec<&str> = text.split([':', ';']).collect();
+	let [hours, minutes, seconds, frames] = fields[..] else {
+		return Err(format!("Expected a timecode like 01:00:00:00, got '{text}'."));
+	};
+	let number = |field: &str, max: u32| -> Result<u32, String> {
+		let value = field.parse::<u32>().map_err(|e| format!("Bad timecode '{text}': {e}"))?;
+		if value < max { Ok(value) } else { Err(format!("Bad timecode '{text}': {value} is out of range.")) }
+	};
+	Ok(TimecodeStart {
+		hours: number(hours, 24)?,
+		minutes: number(minutes, 60)?,
+		seconds: number(seconds, 60)?,
+		frames: number(frames, 1000)?,
+		drop_frame,
+	})
+}
+
+pub struct Timecode {
+	/// Frames since midnight at the first frame, counted without gaps.
+	start: u64,
+	/// The real frame rate, e.g. 29.97, and the one the timecode counts in, e.g. 30.
+	rate: f64,
+	nominal: u64,
+	/// Frame numbers skipped at the start of each minute, for drop frame timecode.
+	dropped: u64,
+	/// Stream time of the first frame, in seconds.
+	origin: f64,
+}
+
+impl Timecode {
+	/// None if there's no sensible rate to count frames at.
+	pub fn new(start: TimecodeStart, rate: f64, origin: f64) -> Option<Self> {
+		let nominal = rate.round() as u64;
+		if nominal == 0 {
+			return None;
+		}
+		// Only 30 and 60 have a drop frame flavour. Anything else counts every frame.
+		let dropped = if start.drop_frame && nominal.is_multiple_of(30) { nominal / 15 } else { 0 };
+		let minutes = (start.hours * 60 + start.minutes) as u64;
+		let labelled = (minutes * 60 + start.seconds as u64) * nominal + start.frames as u64;
+		Some(Timecode { start: labelled - dropped * (minutes - minutes / 10), rate, nominal, dropped, origin })
+	}
+
+	/// The timecode of whatever frame was presented at `timestamp` seconds.
+	pub fn at(&self, timestamp: f64) -> String {
+		let elapsed = ((timestamp - self.origin) * self.rate).round().max(0.0) as u64;
+		let (nominal, dropped) = (self.nominal, self.dropped);
+		// Wrap at 24 hours, like the real thing.
+		let per_day = 24 * 3600 * nominal - dropped * (24 * 60 - 24 * 6);
+		let mut frame = (self.start + elapsed) % per_day;
+		if dropped > 0 {
+			// Put back the numbers skipped in every full ten minutes, then in each full minute since.
+			let per_ten_minutes = 600 * nominal - 9 * dropped;
+			let per_minute = 60 * nominal - dropped;
+			let (tens, rest) = (frame / per_ten_minutes, frame % per_ten_minutes);
+			frame += 9 * dropped * tens + if rest > dropped { dropped * ((rest - dropped) / per_minute) } else { 0 };
+		}
+		let (frames, seconds) = (frame % nominal, frame / nominal);
+		let separator = if dropped > 0 { ';' } else { ':' };
+		format!("{:02}:{:02}:{:02}{separator}{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60, frames)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_timecode() {
+		let start = parse_timecode("01:00:00:00").unwrap();
+		assert!(parse_timecode("01:00:00").is_err());
+		assert!(parse_timecode("01:61:00:00").is_err());
+		let timecode = Timecode::new(start, 25.0, 10.0).unwrap();
+		assert_eq!(timecode.at(10.0), "01:00:00:00");
+		assert_eq!(timecode.at(10.0 + 61.0 + 3.0 / 25.0), "01:01:01:03");
+
+		// 29.97 drop frame skips ;00 and ;01 at the top of every minute but the tenth.
+		let timecode = Timecode::new(parse_timecode("00:00:59;29").unwrap(), 30000.0 / 1001.0, 0.0).unwrap();
+		let frame = |n: f64| timecode.at(n * 1001.0 / 30000.0);
+		assert_eq!(frame(0.0), "00:00:59;29");
+		assert_eq!(frame(1.0), "00:01:00;02");
+		let timecode = Timecode::new(parse_timecode("00:09:59;29").unwrap(), 30000.0 / 1001.0, 0.0).unwrap();
+		assert_eq!(timecode.at(1001.0 / 30000.0), "00:10:00;00");
+		// An hour of drop frame timecode is 107892 frames.
+		let timecode = Timecode::new(parse_timecode("00:00:00;00").unwrap(), 30000.0 / 1001.0, 0.0).unwrap();
+		assert_eq!(timecode.at(107892.0 * 1001.0 / 30000.0), "01:00:00;00");
+	}
+}
@@ -0,0 +1,263 @@
+// Frame timing for variable frame rate footage.
+// Phones and screen recorders don't promise a constant frame interval, so the frame index alone can't be mapped to time.
+
+use crate::confidence::Confidence;
+use crate::geometry::{Mat3, lerp, lerp_vec3, slerp_mat3};
+use crate::record::{FrameRecord, MarkerRecord, PoseRecord};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTiming {
+	pub timestamp: f64,
+	pub dropped_frames: u32,
+	pub duplicate: bool,
+}
+
+/// Converts presentation timestamps into seconds and watches the gaps between them.
+pub struct FrameClock {
+	time_base: f64,
+	nominal_interval: Option<f64>,
+	previous: Option<f64>,
+}
+
+impl FrameClock {
+	/// `time_base` is seconds per tick of the stream. `nominal_fps` is the advertised (average) rate, if there is one.
+	pub fn new(time_base: f64, nominal_fps: Option<f64>) -> Self {
+		FrameClock {
+			time_base,
+			nominal_interval: nominal_fps.filter(|fps| *fps > 0.0).map(|fps| 1.0 / fps),
+			previous: None,
+		}
+	}
+
+	pub fn tick(&mut self, pts: Option<i64>) -> FrameTiming {
+		let interval = self.nominal_interval.unwrap_or(0.0);
+		// Some containers omit timestamps on a few frames. Assume those landed on schedule.
+		let timestamp = match (pts, self.previous) {
+			(Some(pts), _) => pts as f64 * self.time_base,
+			(None, Some(previous)) => previous + interval,
+			(None, None) => 0.0,
+		};
+
+		let mut timing = FrameTiming { timestamp, ..Default::default() };
+		if let (Some(previous), Some(interval)) = (self.previous, self.nominal_interval) {
+			let delta = timestamp - previous;
+			if delta < interval * 0.5 {
+				timing.duplicate = true;
+			} else if delta > interval * 1.5 {
+				timing.dropped_frames = ((delta / interval).round() as u32).saturating_sub(1);
+			}
+		}
+		self.previous = Some(timestamp);
+		timing
+	}
+}
+
+/// A frame this close to a sample's time, in seconds, counts as landing on it. Time bases like 1001/24000 don't divide
+/// evenly, so without some slack footage already at the target rate would come out a frame late, interpolated.
+const GRID_TOLERANCE: f64 = 1e-6;
+
+/// Resamples a stream of frame records onto a constant frame rate grid, interpolating poses between the neighbours.
+pub struct Resampler {
+	fps: f64,
+	/// Where sample 0 falls in the stream's timestamps, once it's known.
+	zero: Option<f64>,
+	/// How far the stream's start is into the synced timeline, in seconds.
+	offset: f64,
+	next_sample: Option<u64>,
+	previous: Option<FrameRecord>,
+}
+
+impl Resampler {
+	/// The grid counts from `start`, the stream's start time, or from its first frame without one. A stream that
+	/// starts `offset` seconds into the synced timeline (a --camera's frame_offset) counts from that much earlier, so
+	/// cameras resampled at the same rate number their samples alike.
+	pub fn new(fps: f64, start: Option<f64>, offset: f64) -> Self {
+		Resampler {
+			fps,
+			zero: start.map(|start| start - offset),
+			offset,
+			next_sample: None,
+			previous: None,
+		}
+	}
+
+	/// Feed the next decoded frame in and get back any samples that fall between it and the frame before.
+	pub fn push(&mut self, record: FrameRecord) -> Vec<FrameRecord> {
+		let mut out = vec![];
+		let zero = *self.zero.get_or_insert(record.timestamp - self.offset);
+		let next_sample = self.next_sample.get_or_insert(((record.timestamp - zero - GRID_TOLERANCE) * self.fps).ceil().max(0.0) as u64);
+
+		loop {
+			let t = zero + *next_sample as f64 / self.fps;
+			if t > record.timestamp + GRID_TOLERANCE {
+				break;
+			}
+			let mut sample = match &self.previous {
+				Some(previous) if previous.timestamp < record.timestamp && t >= previous.timestamp => {
+					let alpha = ((t - previous.timestamp) / (record.timestamp - previous.timestamp)).min(1.0) as f32;
+					interpolate_frames(previous, &record, alpha)
+				},
+				// The first frame, or a clock that went backwards. Don't try to interpolate.
+				_ => FrameRecord { source_frame: Some(record.frame_id), ..record.clone() },
+			};
+			sample.frame_id = *next_sample as usize;
+			sample.timestamp = t;
+			sample.dropped_frames = 0;
+			sample.duplicate = false;
+			sample.timings = None;
+			out.push(sample);
+			*next_sample += 1;
+		}
+		// Only count the work for each decoded frame once, however many samples it turned into.
+		if let Some(first) = out.first_mut() {
+			first.timings = record.timings;
+		}
+
+		self.previous = Some(record);
+		out
+	}
+}
+
+/// Blend two frames. Markers seen in both are interpolated; the rest come from whichever frame is closer.
+fn interpolate_frames(a: &FrameRecord, b: &FrameRecord, alpha: f32) -> FrameRecord {
+	let (nearest, other) = if alpha < 0.5 { (a, b) } else { (b, a) };
+	let markers = nearest.markers.iter().map(|m| {
+		match other.markers.iter().find(|o| o.marker_id == m.marker_id) {
+			Some(o) => {
+				let (from, to) = if alpha < 0.5 { (m, o) } else { (o, m) };
+				interpolate_markers(from, to, alpha)
+			},
+			None => m.clone(),
+		}
+	}).collect();
+	FrameRecord {
+		source_frame: Some(nearest.frame_id),
+		markers,
+		..nearest.clone()
+	}
+}
+
+fn interpolate_markers(a: &MarkerRecord, b: &MarkerRecord, alpha: f32) -> MarkerRecord {
+	let mut corners = a.corners;
+	for (c, other) in corners.iter_mut().zip(b.corners.iter()) {
+		*c = (lerp(c.0, other.0, alpha), lerp(c.1, other.1, alpha));
+	}
+	// The solver's two candidates can come back in either order, so each of a's is paired with whichever of b's is
+	// nearest in rotation, not the one in the same place. Otherwise we'd slerp from one solution to the other.
+	let mut unpaired: Vec<&PoseRecord> = b.poses.iter().collect();
+	let poses = a.poses.iter().filter_map(|pa| {
+		let nearest = (0..unpaired.len()).max_by(|&i, &j| similarity(&pa.rotation, &unpaired[i].rotation).total_cmp(&similarity(&pa.rotation, &unpaired[j].rotation)))?;
+		let pb = unpaired.swap_remove(nearest);
+		Some(PoseRecord {
+			translation: lerp_vec3(&pa.translation, &pb.translation, alpha),
+			rotation: slerp_mat3(&pa.rotation, &pb.rotation, alpha),
+			error: lerp(pa.error, pb.error, alpha),
+			reprojection_error: pa.reprojection_error.zip(pb.reprojection_error).map(|(ea, eb)| lerp(ea, eb, alpha)),
+		})
+	}).collect();
+	MarkerRecord {
+		marker_id: a.marker_id,
+		instance: a.instance,
+		// Worked out again on the way out.
+		corners_normalized: None,
+		corners,
+		corners_3d: a.corners_3d,
+		poses,
+		payload: a.payload.clone(),
+		diamond: a.diamond,
+		hamming_distance: a.hamming_distance.max(b.hamming_distance),
+		confidence: a.confidence.zip(b.confidence).map(|(ca, cb)| Confidence {
+			score: lerp(ca.score, cb.score, alpha),
+			border_contrast: lerp(ca.border_contrast, cb.border_contrast, alpha),
+			corner_sharpness: lerp(ca.corner_sharpness, cb.corner_sharpness, alpha),
+			decode_margin: ca.decode_margin.zip(cb.decode_margin).map(|(ma, mb)| lerp(ma, mb, alpha)),
+		}),
+		// Worked out again from the resampled track.
+		velocity: None,
+		angular_velocity: None,
+		// Only the corners seen at both ends count as seen.
+		partial: match (a.partial, b.partial) {
+			(Some(va), Some(vb)) => Some(std::array::from_fn(|i| va[i] && vb[i])),
+			(partial, None) | (None, partial) => partial,
+		},
+		// Measured, not interpolated, so keep the nearer frame's.
+		edges: if alpha < 0.5 { a.edges.clone() } else { b.edges.clone() },
+		// Worked out again on the way out.
+		axes: None,
+	}
+}
+
+/// The trace of a^T b: 3 for the same rotation, down to -1 for a half turn apart.
+fn similarity(a: &Mat3, b: &Mat3) -> f32 {
+	(0..3).flat_map(|i| (0..3).map(move |j| a[i][j] * b[i][j])).sum()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::geometry;
+
+	#[test]
+	fn test_clock_flags_drops_and_duplicates() {
+		let mut clock = FrameClock::new(1.0 / 1000.0, Some(25.0));
+		assert_eq!(clock.tick(Some(0)).dropped_frames, 0);
+		assert_eq!(clock.tick(Some(40)).dropped_frames, 0);
+		assert_eq!(clock.tick(Some(160)).dropped_frames, 2);
+		assert!(clock.tick(Some(160)).duplicate);
+	}
+
+	#[test]
+	fn test_resampler_fills_grid() {
+		let mut resampler = Resampler::new(10.0, Some(0.0), 0.0);
+		let frame = |frame_id: usize, timestamp: f64| FrameRecord { frame_id, timestamp, ..Default::default() };
+		assert_eq!(resampler.push(frame(0, 0.0)).len(), 1);
+		// 0.1, 0.2, and 0.3 all lie inside (0.0, 0.35].
+		let samples = resampler.push(frame(1, 0.35));
+		assert_eq!(samples.iter().map(|s| s.frame_id).collect::<Vec<_>>(), vec![1, 2, 3]);
+		assert_eq!(samples[0].source_frame, Some(0));
+		assert_eq!(samples[2].source_frame, Some(1));
+
+		// A frame a hair before its sample still lands on it, rather than the sample waiting to be interpolated from the next.
+		let mut resampler = Resampler::new(10.0, Some(0.0), 0.0);
+		let samples: Vec<_> = (0..5).flat_map(|i| resampler.push(frame(i, i as f64 / 10.0 - 1e-9))).collect();
+		assert_eq!(samples.iter().map(|s| (s.frame_id, s.source_frame)).collect::<Vec<_>>(), (0..5).map(|i| (i, Some(i))).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn test_resampler_counts_from_the_start() {
+		let frame = |frame_id: usize, timestamp: f64| FrameRecord { frame_id, timestamp, ..Default::default() };
+		let ids_and_times = |samples: Vec<FrameRecord>| samples.iter().map(|s| (s.frame_id, (s.timestamp * 1000.0).round() as i64)).collect::<Vec<_>>();
+		// A stream whose clock starts at 10s, or has no start time and just happens to. Either way the first sample is
+		// sample 0, on the first frame, not sample 100.
+		for start in [Some(10.0), None] {
+			let mut resampler = Resampler::new(10.0, start, 0.0);
+			assert_eq!(ids_and_times(resampler.push(frame(0, 10.0))), [(0, 10000)]);
+			assert_eq!(ids_and_times(resampler.push(frame(1, 10.25))), [(1, 10100), (2, 10200)]);
+		}
+		// No start time, and a first frame that isn't on any grid of ours.
+		let mut resampler = Resampler::new(10.0, None, 0.0);
+		assert_eq!(ids_and_times(resampler.push(frame(0, 3.333))), [(0, 3333)]);
+	}
+
+	#[test]
+	fn test_resampler_takes_the_offset_in_seconds() {
+		let frame = |frame_id: usize, timestamp: f64| FrameRecord { frame_id, timestamp, ..Default::default() };
+		// A 25 fps camera with a frame_offset of 5 starts 0.2s into the synced timeline, which is sample 2 at 10 fps,
+		// not sample 5.
+		let mut resampler = Resampler::new(10.0, Some(0.0), 5.0 / 25.0);
+		let samples = resampler.push(frame(0, 0.0));
+		assert_eq!(samples.iter().map(|s| s.frame_id).collect::<Vec<_>>(), [2]);
+		assert_eq!(resampler.push(frame(4, 0.16)).iter().map(|s| s.frame_id).collect::<Vec<_>>(), [3]);
+	}
+
+	#[test]
+	fn test_interpolate_swapped_candidates() {
+		let pose = |angle: f32| PoseRecord { rotation: geometry::rodrigues(&[angle, 0.0, 0.0]), ..Default::default() };
+		let a = MarkerRecord { poses: vec![pose(0.3), pose(-0.3)], ..Default::default() };
+		// The same two solutions a little later, in the other order.
+		let b = MarkerRecord { poses: vec![pose(-0.32), pose(0.32)], ..Default::default() };
+		let mid = interpolate_markers(&a, &b, 0.5);
+		let angles: Vec<f32> = mid.poses.iter().map(|p| geometry::rotation_vector(&p.rotation)[0]).collect();
+		assert!((angles[0] - 0.31).abs() < 1e-3 && (angles[1] + 0.31).abs() < 1e-3, "{angles:?}");
+	}
+}
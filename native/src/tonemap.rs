@@ -0,0 +1,140 @@
+// Bit-depth reduction for 10/12-bit and HDR footage.
+// The detector works on 8-bit luminance. Squashing a PQ or log signal into that linearly leaves markers as a band of
+// mid-greys, so we linearize the transfer function, compress it, and then stretch whatever range the frame actually uses.
+
+use image::{GrayImage, ImageBuffer, Luma};
+
+pub type Gray16Image = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transfer {
+	/// BT.709/sRGB-ish gamma, or anything we don't recognize. Log footage also ends up here.
+	Sdr,
+	/// SMPTE ST 2084.
+	Pq,
+	/// ARIB STD-B67.
+	Hlg,
+}
+
+// Nominal diffuse white in nits, per BT.2408.
+const REFERENCE_WHITE_NITS: f32 = 203.0;
+
+// Fraction of pixels allowed to clip at either end when stretching.
+const CLIP_FRACTION: f32 = 0.005;
+
+const HISTOGRAM_BINS: usize = 1024;
+
+pub struct ToneMapper {
+	// Display-referred value in [0, 1] for every 16-bit code value.
+	lut: Vec<f32>,
+}
+
+impl ToneMapper {
+	pub fn new(transfer: Transfer) -> Self {
+		let lut = (0..=u16::MAX).map(|v| display_value(v as f32 / u16::MAX as f32, transfer)).collect();
+		ToneMapper { lut }
+	}
+
+	pub fn map(&self, luma: &Gray16Image) -> GrayImage {
+		let (low, high) = self.stretch_range(luma);
+		let scale = if high > low { 1.0 / (high - low) } else { 1.0 };
+		let mut out = GrayImage::new(luma.width(), luma.height());
+		for (dst, src) in out.pixels_mut().zip(luma.pixels()) {
+			let v = (self.lut[src.0[0] as usize] - low) * scale;
+			dst.0[0] = (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+		}
+		out
+	}
+
+	// Find the display values at the bottom and top CLIP_FRACTION of the frame.
+	fn stretch_range(&self, luma: &Gray16Image) -> (f32, f32) {
+		let mut histogram = vec![0u32; HISTOGRAM_BINS];
+		for p in luma.pixels() {
+			let bin = (self.lut[p.0[0] as usize] * (HISTOGRAM_BINS - 1) as f32) as usize;
+			histogram[bin.min(HISTOGRAM_BINS - 1)] += 1;
+		}
+		let total = (luma.width() * luma.height()) as f32;
+		let clip = (total * CLIP_FRACTION) as u32;
+		let bin_value = |bin: usize| bin as f32 / (HISTOGRAM_BINS - 1) as f32;
+
+		let mut seen = 0;
+		let mut low = 0.0;
+		for (bin, count) in histogram.iter().enumerate() {
+			seen += count;
+			if seen > clip {
+				low = bin_value(bin);
+				break;
+			}
+		}
+		seen = 0;
+		let mut high = 1.0;
+		for (bin, count) in histogram.iter().enumerate().rev() {
+			seen += count;
+			if seen > clip {
+				high = bin_value(bin);
+				break;
+			}
+		}
+		(low, high)
+	}
+}
+
+fn display_value(code: f32, transfer: Transfer) -> f32 {
+	match transfer {
+		Transfer::Sdr => code,
+		Transfer::Pq => compress(pq_eotf(code) * 10000.0 / REFERENCE_WHITE_NITS),
+		// HLG's nominal peak is 1000 nits, about 4.9x reference white.
+		Transfer::Hlg => compress(hlg_inverse_oetf(code) * 1000.0 / REFERENCE_WHITE_NITS),
+	}
+}
+
+// Reinhard, then back to a roughly perceptual encoding.
+fn compress(relative_luminance: f32) -> f32 {
+	let x = relative_luminance.max(0.0);
+	(x / (1.0 + x)).powf(1.0 / 2.2)
+}
+
+/// Normalized PQ code value to linear light, where 1.0 is 10,000 nits.
+fn pq_eotf(code: f32) -> f32 {
+	const M1: f32 = 0.159_301_76;
+	const M2: f32 = 78.84375;
+	const C1: f32 = 0.8359375;
+	const C2: f32 = 18.851_563;
+	const C3: f32 = 18.6875;
+	let e = code.max(0.0).powf(1.0 / M2);
+	((e - C1).max(0.0) / (C2 - C3 * e)).powf(1.0 / M1)
+}
+
+/// Normalized HLG code value to scene-linear light in [0, 1].
+fn hlg_inverse_oetf(code: f32) -> f32 {
+	const A: f32 = 0.178_832_77;
+	const B: f32 = 0.284_668_92;
+	const C: f32 = 0.559_910_7;
+	if code <= 0.5 {
+		code * code / 3.0
+	} else {
+		(((code - C) / A).exp() + B) / 12.0
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_transfer_endpoints() {
+		assert!(pq_eotf(0.0).abs() < 1e-6);
+		assert!((pq_eotf(1.0) - 1.0).abs() < 1e-3);
+		assert!((hlg_inverse_oetf(0.5) - 1.0 / 12.0).abs() < 1e-6);
+		assert!((hlg_inverse_oetf(1.0) - 1.0).abs() < 1e-3);
+	}
+
+	#[test]
+	fn test_narrow_range_is_stretched() {
+		// A 10-bit log-ish frame that only spans a sliver of the code values should still come out black and white.
+		let luma = Gray16Image::from_fn(64, 64, |x, _| Luma([if x < 32 { 20000 } else { 26000 }]));
+		let mapped = ToneMapper::new(Transfer::Sdr).map(&luma);
+		assert!(mapped.get_pixel(0, 0).0[0] < 8);
+		assert!(mapped.get_pixel(63, 0).0[0] > 247);
+	}
+}
@@ -0,0 +1,350 @@
+// Per-corner 2D tracks collected over a whole run, for handing the detections to other tools' trackers and solvers.
+
+use crate::output::Sink;
+use crate::record::FrameRecord;
+use crate::value::Value;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Where one marker was in one frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackSample {
+	pub frame_id: usize,
+	/// Pixel coordinates, top-left origin, in the detector's corner order.
+	pub corners: [(f32, f32); 4],
+}
+
+impl TrackSample {
+	pub fn center(&self) -> (f32, f32) {
+		let (x, y) = self.corners.iter().fold((0.0, 0.0), |acc, c| (acc.0 + c.0, acc.1 + c.1));
+		(x / 4.0, y / 4.0)
+	}
+}
+
+/// Every marker's samples, keyed by camera, marker id, and instance so multi-camera runs and printed copies of an id
+/// don't mix their tracks.
+#[derive(Clone, Debug, Default)]
+pub struct Tracks2d {
+	pub width: u32,
+	pub height: u32,
+	/// The first and latest (frame_id, timestamp) seen, to work out the frame rate.
+	span: Option<((usize, f64), (usize, f64))>,
+	pub tracks: BTreeMap<(Option<String>, usize, u32), Vec<TrackSample>>,
+}
+
+impl Tracks2d {
+	pub fn push(&mut self, record: &FrameRecord) {
+		// Triangulated records repeat the left camera's corners.
+		if record.camera_id.as_deref() == Some("stereo") {
+			return;
+		}
+		if let Some(intrinsics) = &record.intrinsics && self.width == 0 {
+			self.width = intrinsics.width;
+			self.height = intrinsics.height;
+		}
+		let now = (record.frame_id, record.timestamp);
+		self.span = Some(self.span.map_or((now, now), |(first, _)| (first, now)));
+		for m in &record.markers {
+			let key = (record.camera_id.clone(), m.marker_id, m.instance.unwrap_or_default());
+			self.tracks.entry(key).or_default().push(TrackSample { frame_id: record.frame_id, corners: m.corners });
+		}
+	}
+
+	/// Frames per second according to the timestamps, or None if there weren't enough frames to tell.
+	pub fn fps(&self) -> Option<f64> {
+		let ((first_frame, first_time), (last_frame, last_time)) = self.span?;
+		(last_frame > first_frame && last_time > first_time).then(|| (last_frame - first_frame) as f64 / (last_time - first_time))
+	}
+
+	/// A readable track name like BFM_7, or BFM_left_7 in multi-camera runs. A second printed copy of marker 7 is
+	/// BFM_7.1, and so on.
+	pub fn track_name(camera_id: &Option<String>, marker_id: usize, instance: u32) -> String {
+		let marker = if instance > 0 { format!("{marker_id}.{instance}") } else { marker_id.to_string() };
+		match camera_id {
+			Some(camera_id) => format!("BFM_{camera_id}_{marker}"),
+			None => format!("BFM_{marker}"),
+		}
+	}
+}
+
+/// Tracks for a Blender MovieClip, which the addon turns into motion-tracking markers.
+/// Positions are normalized with a bottom-left origin and frames are 1-based, which is how MovieTrackingMarker wants them.
+/// Each fiducial gives one track at its center, with its pattern set to the marker's outline (good for plane tracks),
+/// and a track for each corner. A track gets a disabled marker on the frame after it's lost, so Blender doesn't hold
+/// it in place through the gap.
+pub fn blender_tracks_to_value(tracks: &Tracks2d) -> Value {
+	let (w, h) = (tracks.width.max(1) as f32, tracks.height.max(1) as f32);
+	let normalize = |(x, y): (f32, f32)| [x / w, 1.0 - y / h];
+	let mut out = vec![];
+	for ((camera_id, marker_id, instance), samples) in &tracks.tracks {
+		let name = Tracks2d::track_name(camera_id, *marker_id, *instance);
+		let mut center = vec![];
+		let mut corners: [Vec<Value>; 4] = Default::default();
+		for (idx, sample) in samples.iter().enumerate() {
+			let co = normalize(sample.center());
+			// Blender's pattern corners go counter-clockwise from the bottom left, relative to the marker position.
+			let pattern: Vec<f32> = [3, 2, 1, 0].iter().flat_map(|&c| {
+				let p = normalize(sample.corners[c]);
+				[p[0] - co[0], p[1] - co[1]]
+			}).collect();
+			center.push(Value::Map(vec![
+				("frame".to_string(), Value::Int(sample.frame_id as i64 + 1)),
+				("co".to_string(), Value::floats(&co)),
+				("pattern_corners".to_string(), Value::floats(&pattern)),
+			]));
+			for (c, track) in corners.iter_mut().enumerate() {
+				track.push(Value::Map(vec![
+					("frame".to_string(), Value::Int(sample.frame_id as i64 + 1)),
+					("co".to_string(), Value::floats(&normalize(sample.corners[c]))),
+				]));
+			}
+			let lost = samples.get(idx + 1).is_none_or(|next| next.frame_id > sample.frame_id + 1);
+			if lost {
+				let gap = Value::Map(vec![
+					("frame".to_string(), Value::Int(sample.frame_id as i64 + 2)),
+					("mute".to_string(), Value::Bool(true)),
+				]);
+				center.push(gap.clone());
+				for track in corners.iter_mut() {
+					track.push(gap.clone());
+				}
+			}
+		}
+		let track = |name: String, corner: Option<usize>, markers: Vec<Value>| Value::Map(vec![
+			("name".to_string(), Value::Str(name)),
+			("marker_id".to_string(), Value::Int(*marker_id as i64)),
+			("corner".to_string(), corner.map_or(Value::Null, |c| Value::Int(c as i64))),
+			("markers".to_string(), Value::Array(markers)),
+		]);
+		out.push(track(name.clone(), None, center));
+		for (c, markers) in corners.into_iter().enumerate() {
+			out.push(track(format!("{name}.{c}"), Some(c), markers));
+		}
+	}
+	Value::Map(vec![
+		("width".to_string(), Value::Int(tracks.width as i64)),
+		("height".to_string(), Value::Int(tracks.height as i64)),
+		("tracks".to_string(), Value::Array(out)),
+	])
+}
+
+// Nuke curves are in pixels with a bottom-left origin. Frames start at 1 like a default Read node.
+fn nuke_curve(samples: &[TrackSample], value: impl Fn(&TrackSample) -> f32) -> String {
+	let mut out = "{curve".to_string();
+	for sample in samples {
+		let _ = write!(out, " x{} {}", sample.frame_id + 1, value(sample));
+	}
+	out.push('}');
+	out
+}
+
+/// A Tracker4 node per fiducial with a track on each corner, ready to paste into Nuke or load with File > Import Script.
+/// Each track is switched off (with a constant key) on the frames where its marker wasn't seen.
+pub fn nuke_tracker_script(tracks: &Tracks2d) -> String {
+	const COLUMNS: [(u8, u8, u8, &str); 31] = [
+		(5, 1, 20, "enable"), (3, 1, 75, "name"), (2, 1, 58, "track_x"), (2, 1, 58, "track_y"),
+		(2, 1, 63, "offset_x"), (2, 1, 63, "offset_y"), (4, 1, 27, "T"), (4, 1, 27, "R"), (4, 1, 27, "S"),
+		(2, 0, 45, "error"), (1, 1, 0, "error_min"), (1, 1, 0, "error_max"),
+		(1, 1, 0, "pattern_x"), (1, 1, 0, "pattern_y"), (1, 1, 0, "pattern_r"), (1, 1, 0, "pattern_t"),
+		(1, 1, 0, "search_x"), (1, 1, 0, "search_y"), (1, 1, 0, "search_r"), (1, 1, 0, "search_t"),
+		(2, 1, 0, "key_track"), (2, 1, 0, "key_search_x"), (2, 1, 0, "key_search_y"), (2, 1, 0, "key_search_r"), (2, 1, 0, "key_search_t"),
+		(2, 1, 0, "key_track_x"), (2, 1, 0, "key_track_y"), (2, 1, 0, "key_track_r"), (2, 1, 0, "key_track_t"),
+		(3, 1, 0, "key_centre_offset_x"), (3, 1, 0, "key_centre_offset_y"),
+	];
+	const CORNER_NAMES: [&str; 4] = ["top left", "top right", "bottom right", "bottom left"];
+	let height = tracks.height as f32;
+
+	let mut out = String::new();
+	let _ = writeln!(out, "set cut_paste_input [stack 0]");
+	for (idx, ((camera_id, marker_id, instance), samples)) in tracks.tracks.iter().enumerate() {
+		// Enabled when a run of frames starts, disabled on the frame after it ends.
+		let mut enable = "{curve K".to_string();
+		for (i, sample) in samples.iter().enumerate() {
+			if i == 0 || samples[i - 1].frame_id + 1 != sample.frame_id {
+				let _ = write!(enable, " x{} 1", sample.frame_id + 1);
+			}
+			if samples.get(i + 1).is_none_or(|next| next.frame_id != sample.frame_id + 1) {
+				let _ = write!(enable, " x{} 0", sample.frame_id + 2);
+			}
+		}
+		enable.push('}');
+
+		let _ = writeln!(out, "Tracker4 {{");
+		let _ = writeln!(out, " tracks {{ {{ 1 31 4 }}");
+		out.push_str("{ ");
+		for (kind, visible, width, name) in COLUMNS {
+			let _ = writeln!(out, "{{ {kind} {visible} {width} {name} {name} 1 }} ");
+		}
+		out.push_str("} \n{ \n");
+		for (corner, corner_name) in CORNER_NAMES.iter().enumerate() {
+			let x = nuke_curve(samples, |s| s.corners[corner].0);
+			let y = nuke_curve(samples, |s| height - s.corners[corner].1);
+			let _ = writeln!(out, " {{ {enable} \"{corner_name}\" {x} {y} {{curve K x1 0}} {{curve K x1 0}} 1 0 0 {{curve x1 0}} 1 0 -16 -16 16 16 -32 -32 32 32 {{}} {{}} {{}} {{}} {{}} {{}} {{}} {{}} {{}} {{}} {{}} }}");
+		}
+		out.push_str("} \n}\n");
+		let _ = writeln!(out, " name {}", Tracks2d::track_name(camera_id, *marker_id, *instance));
+		let _ = writeln!(out, " xpos {}", idx * 110);
+		let _ = writeln!(out, " ypos 0");
+		let _ = writeln!(out, "}}");
+	}
+	out
+}
+
+/// After Effects keyframe clipboard text for one fiducial: the center as Transform > Position and the corners as a Corner Pin.
+/// Copy the file contents and paste onto a layer. AE frames are 0-based, like ours.
+pub fn after_effects_keyframes(tracks: &Tracks2d, samples: &[TrackSample]) -> String {
+	let mut out = String::new();
+	let _ = writeln!(out, "Adobe After Effects 8.0 Keyframe Data\n");
+	let _ = writeln!(out, "\tUnits Per Second\t{}", tracks.fps().unwrap_or(24.0));
+	let _ = writeln!(out, "\tSource Width\t{}", tracks.width);
+	let _ = writeln!(out, "\tSource Height\t{}", tracks.height);
+	let _ = writeln!(out, "\tSource Pixel Aspect Ratio\t1");
+	let _ = writeln!(out, "\tComp Pixel Aspect Ratio\t1\n");
+	let _ = writeln!(out, "Transform\tPosition");
+	let _ = writeln!(out, "\tFrame\tX pixels\tY pixels\tZ pixels\t");
+	for sample in samples {
+		let (x, y) = sample.center();
+		let _ = writeln!(out, "\t{}\t{x}\t{y}\t0\t", sample.frame_id);
+	}
+	out.push('\n');
+	// Corner Pin wants upper left, upper right, lower left, lower right.
+	for (param, (name, corner)) in [("Upper Left", 0), ("Upper Right", 1), ("Lower Left", 3), ("Lower Right", 2)].iter().enumerate() {
+		let _ = writeln!(out, "Effects\tCorner Pin #1\t{name} #{}", param + 2);
+		let _ = writeln!(out, "\tFrame\tX pixels\tY pixels\t");
+		for sample in samples {
+			let (x, y) = sample.corners[*corner];
+			let _ = writeln!(out, "\t{}\t{x}\t{y}\t", sample.frame_id);
+		}
+		out.push('\n');
+	}
+	let _ = writeln!(out, "\nEnd of Keyframe Data");
+	out
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackFormat {
+	/// A JSON file for the Blender addon.
+	Blender,
+	/// A .nk script with a Tracker4 node per fiducial.
+	Nuke,
+	/// A directory of keyframe clipboard text files, one per fiducial.
+	AfterEffects,
+}
+
+/// Collects the run and writes the track files at the end.
+pub struct Track2dSink {
+	format: TrackFormat,
+	path: PathBuf,
+	tracks: Tracks2d,
+}
+
+impl Track2dSink {
+	pub fn new(format: TrackFormat, path: PathBuf) -> Self {
+		Track2dSink { format, path, tracks: Tracks2d::default() }
+	}
+}
+
+fn write_file(path: &Path, contents: &str) -> io::Result<()> {
+	let mut writer = BufWriter::new(File::create(path)?);
+	writer.write_all(contents.as_bytes())?;
+	writer.flush()
+}
+
+impl Sink for Track2dSink {
+	fn write(&mut self, record: &FrameRecord) -> io::Result<()> {
+		self.tracks.push(record);
+		Ok(())
+	}
+
+	fn finish(&mut self) -> io::Result<()> {
+		match self.format {
+			TrackFormat::Blender => write_file(&self.path, &(blender_tracks_to_value(&self.tracks).to_json() + "\n")),
+			TrackFormat::Nuke => write_file(&self.path, &nuke_tracker_script(&self.tracks)),
+			TrackFormat::AfterEffects => {
+				std::fs::create_dir_all(&self.path)?;
+				for ((camera_id, marker_id, instance), samples) in &self.tracks.tracks {
+					let path = self.path.join(format!("{}.txt", Tracks2d::track_name(camera_id, *marker_id, *instance)));
+					write_file(&path, &after_effects_keyframes(&self.tracks, samples))?;
+				}
+				Ok(())
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::geometry::Pinhole;
+	use crate::record::MarkerRecord;
+
+	fn record(frame_id: usize) -> FrameRecord {
+		FrameRecord {
+			frame_id,
+			intrinsics: Some(Pinhole { width: 100, height: 50, fx: 100.0, fy: 100.0, cx: 50.0, cy: 25.0 }),
+			markers: vec![MarkerRecord { marker_id: 3, corners: [(10.0, 10.0), (20.0, 10.0), (20.0, 20.0), (10.0, 20.0)], ..Default::default() }],
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn test_blender_tracks() {
+		let mut tracks = Tracks2d::default();
+		for frame_id in [0, 1, 5] {
+			tracks.push(&record(frame_id));
+		}
+		let Value::Map(file) = blender_tracks_to_value(&tracks) else { panic!() };
+		let Value::Array(out) = &file[2].1 else { panic!() };
+		// The center track plus four corners.
+		assert_eq!(out.len(), 5);
+		let Value::Map(center) = &out[0] else { panic!() };
+		assert_eq!(center[0].1, Value::Str("BFM_3".to_string()));
+		let Value::Array(markers) = &center[3].1 else { panic!() };
+		// Frames 1, 2, a mute at 3, then 6 and a mute at 7.
+		let frames: Vec<&Value> = markers.iter().map(|m| match m { Value::Map(m) => &m[0].1, _ => panic!() }).collect();
+		assert_eq!(frames, [&Value::Int(1), &Value::Int(2), &Value::Int(3), &Value::Int(6), &Value::Int(7)]);
+		let Value::Map(first) = &markers[0] else { panic!() };
+		assert_eq!(first[1].1, Value::floats(&[0.15, 0.7]));
+	}
+
+	#[test]
+	fn test_nuke_enable_curve() {
+		let mut tracks = Tracks2d::default();
+		for frame_id in [0, 1, 5] {
+			tracks.push(&record(frame_id));
+		}
+		let script = nuke_tracker_script(&tracks);
+		assert!(script.contains("{curve K x1 1 x3 0 x6 1 x7 0} \"top left\" {curve x1 10 x2 10 x6 10} {curve x1 40 x2 40 x6 40}"), "{script}");
+		assert!(script.contains(" name BFM_3\n"));
+	}
+
+	#[test]
+	fn test_after_effects_fps() {
+		let mut tracks = Tracks2d::default();
+		for frame_id in 0..3 {
+			tracks.push(&FrameRecord { timestamp: frame_id as f64 / 30.0, ..record(frame_id) });
+		}
+		let text = after_effects_keyframes(&tracks, &tracks.tracks[&(None, 3, 0)]);
+		assert!(text.contains("\tUnits Per Second\t30\n"), "{text}");
+		assert!(text.contains("Effects\tCorner Pin #1\tLower Right #5\n\tFrame\tX pixels\tY pixels\t\n\t0\t20\t20\t\n"));
+	}
+
+	#[test]
+	fn test_instances_get_their_own_tracks() {
+		let mut tracks = Tracks2d::default();
+		for frame_id in 0..2 {
+			let mut record = record(frame_id);
+			let mut copy = MarkerRecord { instance: Some(1), ..record.markers[0].clone() };
+			copy.corners = copy.corners.map(|(x, y)| (x + 50.0, y));
+			record.markers[0].instance = Some(0);
+			record.markers.push(copy);
+			tracks.push(&record);
+		}
+		assert_eq!(tracks.tracks.len(), 2);
+		assert_eq!(tracks.tracks[&(None, 3, 1)][1].corners[0], (60.0, 10.0));
+		assert!(nuke_tracker_script(&tracks).contains(" name BFM_3.1\n"));
+	}
+}
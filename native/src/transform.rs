@@ -0,0 +1,170 @@
+// Re-expressing the 3D side of the records in another frame, just before they go to the outputs.
+// Everything 3D moves together: pose rotations and translations, triangulated corners, and velocities. The marker's
+// own axes (see geometry::marker_corners) never change, only the frame they're measured from.
+
+use crate::geometry::{self, Mat3, Vec3};
+use crate::record::FrameRecord;
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+
+/// Which way the camera's axes point in the output.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Convention {
+	/// +X right, +Y down, +Z forward. What the solver gives us.
+	#[default]
+	Opencv,
+	/// +X right, +Y up, -Z forward. Also a Blender camera's local axes.
+	Opengl,
+	/// Blender's Z-up world with the camera at the origin looking down +Y: +X right, +Y forward, +Z up.
+	Blender,
+}
+
+/// The length unit for translations in the output. Marker sizes on the command line are always mm.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Units {
+	#[default]
+	Mm,
+	Cm,
+	M,
+}
+
+impl Units {
+	pub fn per_mm(&self) -> f32 {
+		match self {
+			Units::Mm => 1.0,
+			Units::Cm => 0.1,
+			Units::M => 0.001,
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Units::Mm => "mm",
+			Units::Cm => "cm",
+			Units::M => "m",
+		}
+	}
+}
+
+impl Convention {
+	/// The rotation taking OpenCV camera-space vectors into this convention.
+	pub fn basis(&self) -> Mat3 {
+		match self {
+			Convention::Opencv => geometry::IDENTITY,
+			Convention::Opengl => [[1.0, 0.0, 0.0], [0.0, -1.0, 0.0], [0.0, 0.0, -1.0]],
+			Convention::Blender => [[1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, -1.0, 0.0]],
+		}
+	}
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Convention::Opencv => "opencv",
+			Convention::Opengl => "opengl",
+			Convention::Blender => "blender",
+		}
+	}
+}
+
+/// Apply x' = rotation * x + translation to every 3D quantity in the record. Velocities only rotate.
+pub fn transform_record(record: &mut FrameRecord, rotation: &Mat3, translation: &Vec3) {
+	let point = |p: &Vec3| geometry::add(&geometry::mat_mul_vec(rotation, p), translation);
+	// The camera pose goes the other way, from camera space to the map's world, so it picks up the inverse.
+	if let Some(camera) = record.camera_pose.as_mut() {
+		camera.rotation = geometry::mat_mul(&camera.rotation, &geometry::transpose(rotation));
+		camera.position = geometry::sub(&camera.position, &geometry::mat_mul_vec(&camera.rotation, translation));
+	}
+	for m in record.markers.iter_mut() {
+		for pose in m.poses.iter_mut() {
+			pose.rotation = geometry::mat_mul(rotation, &pose.rotation);
+			pose.translation = point(&pose.translation);
+		}
+		if let Some(corners) = m.corners_3d.as_mut() {
+			for c in corners.iter_mut() {
+				*c = point(c);
+			}
+		}
+		if let Some(v) = m.velocity.as_mut() {
+			*v = geometry::mat_mul_vec(rotation, v);
+		}
+		if let Some(w) = m.angular_velocity.as_mut() {
+			*w = geometry::mat_mul_vec(rotation, w);
+		}
+	}
+}
+
+/// Multiply every length in the record: translations, triangulated corners, and velocities. Rotations don't change.
+pub fn scale_record(record: &mut FrameRecord, factor: f32) {
+	if factor == 1.0 {
+		return;
+	}
+	if let Some(camera) = record.camera_pose.as_mut() {
+		camera.position = geometry::scale(&camera.position, factor);
+	}
+	for m in record.markers.iter_mut() {
+		for pose in m.poses.iter_mut() {
+			pose.translation = geometry::scale(&pose.translation, factor);
+		}
+		if let Some(corners) = m.corners_3d.as_mut() {
+			for c in corners.iter_mut() {
+				*c = geometry::scale(c, factor);
+			}
+		}
+		if let Some(v) = m.velocity.as_mut() {
+			*v = geometry::scale(v, factor);
+		}
+	}
+}
+
+/// A per-camera move into some other frame that's only measurable in some records, like --anchor-marker's. Records
+/// without a fresh measurement use the camera's last one, and until a camera has one at all its records go out with
+/// no 3D data, rather than camera-space numbers that look like they're in the other frame.
+#[derive(Default)]
+pub struct HeldTransform {
+	last: BTreeMap<Option<String>, (Mat3, Vec3)>,
+}
+
+impl HeldTransform {
+	pub fn apply(&mut self, record: &mut FrameRecord, measured: Option<(Mat3, Vec3)>) {
+		if let Some(measured) = measured {
+			self.last.insert(record.camera_id.clone(), measured);
+		}
+		match self.last.get(&record.camera_id) {
+			Some((rotation, translation)) => transform_record(record, rotation, translation),
+			None => {
+				record.camera_pose = None;
+				for m in record.markers.iter_mut() {
+					m.poses.clear();
+					m.corners_3d = None;
+					m.velocity = None;
+					m.angular_velocity = None;
+				}
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::record::{MarkerRecord, PoseRecord};
+
+	#[test]
+	fn test_blender_convention() {
+		// A marker straight ahead and a little below the camera, facing it.
+		let mut record = FrameRecord {
+			markers: vec![MarkerRecord {
+				poses: vec![PoseRecord { translation: [0.0, 100.0, 1000.0], rotation: geometry::IDENTITY, ..Default::default() }],
+				velocity: Some([0.0, 0.0, -10.0]),
+				..Default::default()
+			}],
+			..Default::default()
+		};
+		transform_record(&mut record, &Convention::Blender.basis(), &[0.0; 3]);
+		let m = &record.markers[0];
+		// Ahead is +Y and below is -Z, and it's coming toward us.
+		assert_eq!(m.poses[0].translation, [0.0, 1000.0, -100.0]);
+		assert_eq!(m.velocity, Some([0.0, -10.0, 0.0]));
+		// With no rotation the marker's +Z pointed forward, so now it points along +Y.
+		assert_eq!(geometry::mat_mul_vec(&m.poses[0].rotation, &[0.0, 0.0, 1.0]), [0.0, 1.0, 0.0]);
+	}
+}
@@ -0,0 +1,309 @@
+// A minimal structured value so the same record can be written as JSON or MessagePack without the two drifting apart,
+// and read back from either for the subcommands that work on track files.
+// We could use serde, but it feels like overkill for a handful of record types.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+	Null,
+	Bool(bool),
+	Int(i64),
+	/// Kept separate from F64 so f32 data prints (and packs) the way it was computed.
+	F32(f32),
+	F64(f64),
+	Str(String),
+	Array(Vec<Value>),
+	/// Keys stay in insertion order so output is stable and readable.
+	Map(Vec<(String, Value)>),
+}
+
+impl Value {
+	pub fn floats(values: &[f32]) -> Value {
+		Value::Array(values.iter().map(|v| Value::F32(*v)).collect())
+	}
+
+	/// The value under `key`, if this is a map that has one.
+	pub fn get(&self, key: &str) -> Option<&Value> {
+		match self {
+			Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+			_ => None,
+		}
+	}
+
+	pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+		match self {
+			Value::Map(entries) => entries.iter_mut().find(|(k, _)| k == key).map(|(_, v)| v),
+			_ => None,
+		}
+	}
+
+	/// Replace the value under `key`, or add it at the end.
+	pub fn set(&mut self, key: &str, value: Value) {
+		if let Some(existing) = self.get_mut(key) {
+			*existing = value;
+		} else if let Value::Map(entries) = self {
+			entries.push((key.to_string(), value));
+		}
+	}
+
+	pub fn as_f64(&self) -> Option<f64> {
+		match self {
+			Value::Int(i) => Some(*i as f64),
+			Value::F32(f) => Some(*f as f64),
+			Value::F64(f) => Some(*f),
+			_ => None,
+		}
+	}
+
+	/// An array of numbers as f32s, the way the records are computed.
+	pub fn as_floats(&self) -> Option<Vec<f32>> {
+		match self {
+			Value::Array(items) => items.iter().map(|v| v.as_f64().map(|f| f as f32)).collect(),
+			_ => None,
+		}
+	}
+
+	/// Read back one JSON document, such as a line of our own output. Numbers with a fraction or exponent come back
+	/// as F64, others as Int.
+	pub fn parse_json(text: &str) -> Result<Value, String> {
+		let mut parser = JsonParser { text: text.as_bytes(), at: 0 };
+		let value = parser.value()?;
+		parser.skip_whitespace();
+		if parser.at < text.len() {
+			return Err(format!("unexpected {:?} after the value at byte {}", parser.text[parser.at] as char, parser.at));
+		}
+		Ok(value)
+	}
+
+	pub fn to_json(&self) -> String {
+		let mut out = String::with_capacity(256);
+		self.write_json(&mut out);
+		out
+	}
+
+	fn write_json(&self, out: &mut String) {
+		match self {
+			Value::Null => out.push_str("null"),
+			Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+			Value::Int(i) => out.push_str(&i.to_string()),
+			// JSON has no NaN or infinity.
+			Value::F32(f) if !f.is_finite() => out.push_str("null"),
+			Value::F32(f) => out.push_str(&f.to_string()),
+			Value::F64(f) if !f.is_finite() => out.push_str("null"),
+			Value::F64(f) => out.push_str(&f.to_string()),
+			Value::Str(s) => out.push_str(&json_string(s)),
+			Value::Array(items) => {
+				out.push('[');
+				for (idx, item) in items.iter().enumerate() {
+					if idx > 0 {
+						out.push(',');
+					}
+					item.write_json(out);
+				}
+				out.push(']');
+			},
+			Value::Map(entries) => {
+				out.push('{');
+				for (idx, (key, value)) in entries.iter().enumerate() {
+					if idx > 0 {
+						out.push(',');
+					}
+					out.push_str(&json_string(key));
+					out.push(':');
+					value.write_json(out);
+				}
+				out.push('}');
+			},
+		}
+	}
+}
+
+struct JsonParser<'a> {
+	text: &'a [u8],
+	at: usize,
+}
+
+impl JsonParser<'_> {
+	fn skip_whitespace(&mut self) {
+		while self.text.get(self.at).is_some_and(u8::is_ascii_whitespace) {
+			self.at += 1;
+		}
+	}
+
+	fn expect(&mut self, literal: &str, value: Value) -> Result<Value, String> {
+		if !self.text[self.at..].starts_with(literal.as_bytes()) {
+			return Err(format!("expected {literal} at byte {}", self.at));
+		}
+		self.at += literal.len();
+		Ok(value)
+	}
+
+	fn value(&mut self) -> Result<Value, String> {
+		self.skip_whitespace();
+		match self.text.get(self.at) {
+			None => Err("unexpected end of input".to_string()),
+			Some(b'n') => self.expect("null", Value::Null),
+			Some(b't') => self.expect("true", Value::Bool(true)),
+			Some(b'f') => self.expect("false", Value::Bool(false)),
+			Some(b'"') => Ok(Value::Str(self.string()?)),
+			Some(b'[') => {
+				self.at += 1;
+				let mut items = vec![];
+				self.skip_whitespace();
+				if self.text.get(self.at) == Some(&b']') {
+					self.at += 1;
+					return Ok(Value::Array(items));
+				}
+				loop {
+					items.push(self.value()?);
+					self.skip_whitespace();
+					match self.text.get(self.at) {
+						Some(b',') => self.at += 1,
+						Some(b']') => {
+							self.at += 1;
+							return Ok(Value::Array(items));
+						},
+						_ => return Err(format!("expected , or ] at byte {}", self.at)),
+					}
+				}
+			},
+			Some(b'{') => {
+				self.at += 1;
+				let mut entries = vec![];
+				self.skip_whitespace();
+				if self.text.get(self.at) == Some(&b'}') {
+					self.at += 1;
+					return Ok(Value::Map(entries));
+				}
+				loop {
+					self.skip_whitespace();
+					if self.text.get(self.at) != Some(&b'"') {
+						return Err(format!("expected a key at byte {}", self.at));
+					}
+					let key = self.string()?;
+					self.skip_whitespace();
+					if self.text.get(self.at) != Some(&b':') {
+						return Err(format!("expected : at byte {}", self.at));
+					}
+					self.at += 1;
+					entries.push((key, self.value()?));
+					self.skip_whitespace();
+					match self.text.get(self.at) {
+						Some(b',') => self.at += 1,
+						Some(b'}') => {
+							self.at += 1;
+							return Ok(Value::Map(entries));
+						},
+						_ => return Err(format!("expected , or }} at byte {}", self.at)),
+					}
+				}
+			},
+			Some(_) => self.number(),
+		}
+	}
+
+	fn number(&mut self) -> Result<Value, String> {
+		let start = self.at;
+		while self.text.get(self.at).is_some_and(|c| c.is_ascii_digit() || matches!(c, b'-' | b'+' | b'.' | b'e' | b'E')) {
+			self.at += 1;
+		}
+		let text = std::str::from_utf8(&self.text[start..self.at]).expect("ASCII digits.");
+		if text.contains(['.', 'e', 'E']) {
+			text.parse().map(Value::F64).map_err(|_| format!("bad number {text:?} at byte {start}"))
+		} else {
+			text.parse().map(Value::Int).map_err(|_| format!("bad number {text:?} at byte {start}"))
+		}
+	}
+
+	fn string(&mut self) -> Result<String, String> {
+		let start = self.at;
+		self.at += 1;
+		let mut bytes = vec![];
+		loop {
+			let Some(&c) = self.text.get(self.at) else {
+				return Err(format!("unterminated string from byte {start}"));
+			};
+			self.at += 1;
+			match c {
+				b'"' => break,
+				b'\\' => {
+					let escape = self.text.get(self.at).copied();
+					self.at += 1;
+					let c = match escape {
+						Some(b'"') => '"',
+						Some(b'\\') => '\\',
+						Some(b'/') => '/',
+						Some(b'b') => '\u{8}',
+						Some(b'f') => '\u{c}',
+						Some(b'n') => '\n',
+						Some(b'r') => '\r',
+						Some(b't') => '\t',
+						Some(b'u') => {
+							let code = self.text.get(self.at..self.at + 4).and_then(|hex| u32::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+							self.at += 4;
+							// Surrogate pairs never come out of json_string, so a lone one is just replaced.
+							code.map_or(char::REPLACEMENT_CHARACTER, |code| char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER))
+						},
+						_ => return Err(format!("bad escape at byte {}", self.at - 1)),
+					};
+					bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+				},
+				c => bytes.push(c),
+			}
+		}
+		String::from_utf8(bytes).map_err(|_| format!("invalid UTF-8 in the string from byte {start}"))
+	}
+}
+
+/// Quote and escape a string for JSON.
+pub fn json_string(s: &str) -> String {
+	let mut out = String::with_capacity(s.len() + 2);
+	out.push('"');
+	for c in s.chars() {
+		match c {
+			'"' => out.push_str("\\\""),
+			'\\' => out.push_str("\\\\"),
+			'\n' => out.push_str("\\n"),
+			'\r' => out.push_str("\\r"),
+			'\t' => out.push_str("\\t"),
+			c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+			c => out.push(c),
+		}
+	}
+	out.push('"');
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_json_string_escapes() {
+		assert_eq!(json_string("cam \"A\"\\1\n"), "\"cam \\\"A\\\"\\\\1\\n\"");
+	}
+
+	#[test]
+	fn test_to_json() {
+		let v = Value::Map(vec![
+			("a".to_string(), Value::Int(1)),
+			("b".to_string(), Value::floats(&[0.5, f32::NAN])),
+			("c".to_string(), Value::Str("x".to_string())),
+		]);
+		assert_eq!(v.to_json(), "{\"a\":1,\"b\":[0.5,null],\"c\":\"x\"}");
+	}
+
+	#[test]
+	fn test_parse_json_round_trip() {
+		let v = Value::Map(vec![
+			("id".to_string(), Value::Int(-3)),
+			("xs".to_string(), Value::Array(vec![Value::F64(0.25), Value::F64(1e-7), Value::Null])),
+			("name".to_string(), Value::Str("cam \"A\"\\1\n\u{1}é".to_string())),
+			("ok".to_string(), Value::Bool(false)),
+			("empty".to_string(), Value::Map(vec![])),
+		]);
+		assert_eq!(Value::parse_json(&v.to_json()), Ok(v.clone()));
+		assert_eq!(Value::parse_json(" [1, {\"a\": [] }] "), Ok(Value::Array(vec![Value::Int(1), Value::Map(vec![("a".to_string(), Value::Array(vec![]))])])));
+		assert!(Value::parse_json("{\"a\": 1,}").is_err());
+		assert!(Value::parse_json("[1] 2").is_err());
+	}
+}
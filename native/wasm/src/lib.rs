@@ -0,0 +1,88 @@
+// Browser bindings for the detection core, so a web page can preview a track live from a camera with the exact
+// detector and pose solver the offline run uses.
+//
+// Build with `wasm-pack build --target web` in this directory, then from JavaScript:
+//   const tracker = new Tracker("ARUCO", 50.0, 900.0);
+//   const pixels = context.getImageData(0, 0, width, height);
+//   const record = JSON.parse(tracker.detect(pixels.data, width, height, performance.now() / 1000));
+// Each record is the same JSON as one line of the command line tool's output.
+
+use fiducial_track_video::detect::{FrameDetector, intrinsics_for};
+use fiducial_track_video::dictionary::parse_dictionaries;
+use fiducial_track_video::frame::FrameView;
+use fiducial_track_video::geometry::Pinhole;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub struct Tracker {
+	detector: FrameDetector,
+	focal_length_px: f32,
+	frame_id: usize,
+}
+
+#[wasm_bindgen]
+impl Tracker {
+	/// `dictionary` is named as on the command line, e.g. "APRILTAG_36H11+QR". The focal length is in pixels of the
+	/// frames passed to detect().
+	#[wasm_bindgen(constructor)]
+	pub fn new(dictionary: &str, marker_size_mm: f32, focal_length_px: f32) -> Result<Tracker, JsError> {
+		Tracker::create(dictionary, marker_size_mm, focal_length_px).map_err(|e| JsError::new(&e))
+	}
+
+	/// Find the markers in one frame of RGBA pixels, as from a canvas's getImageData(), and return its frame record as
+	/// JSON. Frames are numbered in the order they're passed in.
+	pub fn detect(&mut self, rgba: &[u8], width: u32, height: u32, timestamp: f64) -> Result<String, JsError> {
+		self.detect_frame(rgba, width, height, timestamp).map_err(|e| JsError::new(&e))
+	}
+}
+
+// A JsError can only be made inside a browser, so the work is done with plain errors and only turned into one on the
+// way out. That keeps it testable natively.
+impl Tracker {
+	fn create(dictionary: &str, marker_size_mm: f32, focal_length_px: f32) -> Result<Tracker, String> {
+		let dictionaries = parse_dictionaries(dictionary)?;
+		if !focal_length_px.is_finite() || focal_length_px <= 0.0 {
+			return Err("The focal length must be greater than zero.".to_string());
+		}
+		Ok(Tracker { detector: FrameDetector::new(dictionaries, marker_size_mm, marker_size_mm, None), focal_length_px, frame_id: 0 })
+	}
+
+	fn detect_frame(&mut self, rgba: &[u8], width: u32, height: u32, timestamp: f64) -> Result<String, String> {
+		let view = FrameView::new(rgba, width, height, width as usize * 4, 4)
+			.ok_or_else(|| format!("Expected {} bytes of RGBA for {width}x{height}, got {}.", width as usize * height as usize * 4, rgba.len()))?;
+		// The command line tool hands the detector luma unless asked for RGB, so do the same.
+		let gray = view.to_luma8();
+		let f = self.focal_length_px;
+		let pinhole = Pinhole { width, height, fx: f, fy: f, cx: width as f32 / 2.0, cy: height as f32 / 2.0 };
+		let found = self.detector.find(None, &gray, (0.0, 0.0), &pinhole);
+		let record = self.detector.record(found, self.frame_id, timestamp, &gray, (0.0, 0.0), &intrinsics_for(&pinhole), &pinhole, false);
+		self.frame_id += 1;
+		Ok(record.to_json())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use fiducial_track_video::synthetic::{self, PlacedMarker};
+
+	#[test]
+	fn test_detect() {
+		let pinhole = Pinhole { width: 320, height: 240, fx: 500.0, fy: 500.0, cx: 160.0, cy: 120.0 };
+		let marker = PlacedMarker { id: 7, rotation: synthetic::FACING, translation: [0.0, 0.0, 700.0] };
+		let gray = synthetic::render(&pinhole, &[marker], 100.0, 128);
+		let rgba: Vec<u8> = gray.as_raw().iter().flat_map(|&v| [v, v, v, 255]).collect();
+		let mut tracker = Tracker::create(synthetic::DICTIONARY, 100.0, 500.0).unwrap();
+		let first = tracker.detect_frame(&rgba, 320, 240, 0.0).unwrap();
+		assert!(first.contains("\"marker_id\":7"), "{first}");
+		let second = tracker.detect_frame(&rgba, 320, 240, 0.04).unwrap();
+		assert!(second.contains("\"frame_id\":1"), "{second}");
+	}
+
+	#[test]
+	fn test_wrong_size() {
+		let mut tracker = Tracker::create(synthetic::DICTIONARY, 100.0, 500.0).unwrap();
+		assert_eq!(tracker.detect_frame(&[0; 100], 320, 240, 0.0), Err("Expected 307200 bytes of RGBA for 320x240, got 100.".to_string()));
+		assert!(Tracker::create(synthetic::DICTIONARY, 100.0, 0.0).is_err());
+	}
+}